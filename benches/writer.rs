@@ -0,0 +1,21 @@
+//! Benchmarks for writing SOR files, e.g. when re-serialising an archive
+//! after bulk edits. Run with `cargo bench --bench writer`.
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use otdrs::parser::parse_file;
+
+fn writer(c: &mut Criterion) {
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let sor = parse_file(data).unwrap().1;
+    let mut group = c.benchmark_group("writer");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("to_bytes", |b| b.iter(|| black_box(&sor).to_bytes().unwrap()));
+    let mut buffer = Vec::new();
+    group.bench_function("serialize_into", |b| b.iter(|| {
+        buffer.clear();
+        black_box(&sor).serialize_into(&mut buffer).unwrap();
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, writer);
+criterion_main!(benches);
@@ -0,0 +1,99 @@
+/// This module parses SOR files from async readers, such as an upload's body
+/// stream in a web service, so that services needn't wrap the library in
+/// `spawn_blocking`. It is only available with the `tokio` feature.
+///
+/// Only reading is asynchronous: parsing a file already in memory takes well
+/// under a millisecond, so is done in place. Errors are `std::io::Error`s,
+/// with parse failures reported as `InvalidData`.
+use std::future::Future;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use crate::batch::{Metric, TimeSeries};
+use crate::types::SORFile;
+
+fn unparsable() -> Error {
+    Error::new(ErrorKind::InvalidData, "Could not parse SOR file")
+}
+
+async fn read_all<R: AsyncRead + Unpin>(mut reader: R) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data).await?;
+    Ok(data)
+}
+
+/// Read a whole SOR file from a reader and parse it
+pub async fn parse_from<R: AsyncRead + Unpin>(reader: R) -> Result<SORFile> {
+    let data = read_all(reader).await?;
+    crate::parser::parse_file(&data).map(|(_, sor)| sor).map_err(|_| unparsable())
+}
+
+/// Read a whole SOR file from a reader and parse only its metadata, as
+/// `parser::parse_metadata` does
+pub async fn parse_metadata_from<R: AsyncRead + Unpin>(reader: R) -> Result<SORFile> {
+    let data = read_all(reader).await?;
+    crate::parser::parse_metadata(&data).map(|(_, sor)| sor).map_err(|_| unparsable())
+}
+
+/// Run futures concurrently, returning their outputs in the order given
+async fn join_in_order<F>(futures: Vec<F>) -> Vec<F::Output>
+where F: Future + Send + 'static, F::Output: Send + 'static {
+    let mut tasks = tokio::task::JoinSet::new();
+    let count = futures.len();
+    for (i, future) in futures.into_iter().enumerate() {
+        tasks.spawn(async move { (i, future.await) });
+    }
+    let mut outputs: Vec<Option<F::Output>> = (0..count).map(|_| None).collect();
+    while let Some(joined) = tasks.join_next().await {
+        match joined {
+            Ok((i, output)) => outputs[i] = Some(output),
+            Err(err) => std::panic::resume_unwind(err.into_panic()),
+        }
+    }
+    outputs.into_iter().map(|output| output.unwrap()).collect()
+}
+
+/// Read and parse many files concurrently, returning the result for each in
+/// the order given
+pub async fn parse_files<P: AsRef<Path>>(paths: &[P]) -> Vec<Result<SORFile>> {
+    join_in_order(paths.iter().map(|path| {
+        let path = path.as_ref().to_owned();
+        async move { parse_from(tokio::fs::File::open(path).await?).await }
+    }).collect()).await
+}
+
+/// Measure a metric in each of many files, as `batch::timeseries` does,
+/// reading them concurrently
+pub async fn timeseries<P: AsRef<Path>>(paths: &[P], metric: &Metric) -> TimeSeries {
+    let reads = join_in_order(paths.iter().map(|path| {
+        let path = path.as_ref().to_owned();
+        async move { tokio::fs::read(path).await.map_err(|err| err.to_string()) }
+    }).collect()).await;
+    let mut series = TimeSeries::default();
+    for (path, data) in paths.iter().zip(reads) {
+        series.add(path.as_ref(), data, metric);
+    }
+    series.points.sort_by_key(|p| p.timestamp);
+    series
+}
+
+#[test]
+fn test_aio() {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    let data: &[u8] = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let sor = runtime.block_on(parse_from(data)).unwrap();
+    assert_eq!(sor, crate::parser::parse_file(data).unwrap().1);
+    let metadata = runtime.block_on(parse_metadata_from(data)).unwrap();
+    assert!(metadata.data_points.is_none());
+    let err = runtime.block_on(parse_from(&b"not a SOR file"[..])).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+    let paths = ["data/example4-exfo-ftb4ftbx730c-mfdgainer-1550nm.sor", "data/missing.sor", "data/example1-noyes-ofl280.sor"];
+    let results = runtime.block_on(parse_files(&paths));
+    assert_eq!(results[0].as_ref().unwrap().general_parameters.as_ref().unwrap().nominal_wavelength, 1550);
+    assert_eq!(results[1].as_ref().unwrap_err().kind(), ErrorKind::NotFound);
+    assert!(results[2].is_ok());
+
+    let series = runtime.block_on(timeseries(&paths, &Metric::TotalLoss));
+    assert_eq!(series, crate::batch::timeseries(&paths, &Metric::TotalLoss));
+}
@@ -0,0 +1,2480 @@
+/// This module provides the trace in physical units - power in dB against
+/// distance in metres - from which analyses of a SOR file are built.
+pub mod acceptance;
+pub mod aggregates;
+pub mod budget;
+pub mod filter;
+
+use crate::types::{DataPoints, DataPointsAtScaleFactor, KeyEvent, KeyEvents, Landmark, LastKeyEvent, SORFile};
+
+pub use crate::units::{DEFAULT_GROUP_INDEX, SPEED_OF_LIGHT};
+use crate::units;
+
+/// One-way distance in metres covered by a time of 100ps, which is the unit
+/// of every time in a SOR file, given the file's group index
+pub fn metres_per_100ps(sor: &SORFile) -> f64 {
+    units::metres_per_100ps(sor.fixed_parameters.as_ref().map_or(0, |fp| fp.group_index))
+}
+
+/// The optical return loss stored with the last key event, in dB, or None
+/// if it wasn't measured, which instruments record as zero
+pub fn measured_orl(sor: &SORFile) -> Option<f64> {
+    let lke = &sor.key_events.as_ref()?.last_key_event;
+    (lke.optical_return_loss != 0).then(|| lke.optical_return_loss as f64 / 1000.0)
+}
+
+impl SORFile {
+    /// Every data point as (distance from the front panel in metres, power
+    /// in dB), with its scale factor applied. Where several pulse widths were
+    /// used, each one's points follow the last's, taking its own data
+    /// spacing, so distances start again from the acquisition offset at the
+    /// first point of each. A pulse width with no count of points is taken to
+    /// have all of those remaining.
+    pub fn samples(&self) -> Result<impl Iterator<Item = (f64, f64)> + '_, &'static str> {
+        let fp = self.fixed_parameters.as_ref().ok_or("File has no fixed parameters block")?;
+        let dp = self.data_points.as_ref().ok_or("File has no data points block")?;
+        if fp.data_spacing.is_empty() {
+            return Err("File has no data spacing");
+        }
+        let offset_m = units::time_to_metres(fp.acquisition_offset as f64, fp.group_index);
+        let group_index = fp.group_index;
+        let counts = fp.n_data_points_for_pulse_widths_used.iter().map(|&n| if n > 0 { n as usize } else { usize::MAX })
+            .chain(std::iter::repeat(usize::MAX));
+        let distances = fp.data_spacing.iter().zip(counts)
+            .flat_map(move |(&spacing, n)| {
+                let spacing_m = units::spacing_m(spacing, group_index);
+                (0..n).map(move |i| offset_m + i as f64 * spacing_m)
+            });
+        // Power is stored as -dB*1000, scaled by scale_factor/1000
+        let powers = dp.scale_factors.iter()
+            .flat_map(|sf| sf.data.iter().map(move |&pt| -(pt as f64) * sf.scale_factor as f64 / 1e6));
+        Ok(distances.zip(powers))
+    }
+}
+
+/// The backscatter trace of a SOR file
+#[derive(Debug, PartialEq, Clone)]
+pub struct Trace {
+    points_db: Vec<f64>,
+    distance_m: Vec<f64>,
+    metres_per_100ps: f64,
+    user_offset_m: f64,
+    /// Backscatter coefficient for a 1ns pulse, in dB
+    backscatter_db: f64,
+    /// Width of the pulse used for these points, in ns
+    pulse_ns: f64,
+}
+
+impl Trace {
+    /// Build the trace from a file's data points. Where data is stored with
+    /// several scale factors, the scale factors apply to consecutive runs of
+    /// points. Where several pulse widths were used, only the first pulse
+    /// width's points are included.
+    pub fn new(sor: &SORFile) -> Result<Trace, &'static str> {
+        let samples = sor.samples()?;
+        let fp = sor.fixed_parameters.as_ref().unwrap();
+        let n_points = fp.n_data_points_for_pulse_widths_used.first()
+            .map_or(usize::MAX, |&n| if n > 0 { n as usize } else { usize::MAX });
+        let (distance_m, points_db): (Vec<f64>, Vec<f64>) = samples.take(n_points).unzip();
+        if points_db.is_empty() {
+            return Err("File has no data points");
+        }
+        let metres_per_100ps = metres_per_100ps(sor);
+        let user_offset = sor.general_parameters.as_ref().map_or(0, |gp| gp.user_offset);
+        Ok(Trace {
+            points_db,
+            distance_m,
+            metres_per_100ps,
+            user_offset_m: user_offset as f64 * metres_per_100ps,
+            backscatter_db: -(fp.backscatter_coefficient as f64) / 10.0,
+            pulse_ns: fp.pulse_widths_used.first().copied().unwrap_or(0) as f64,
+        })
+    }
+
+    /// Power at each point, in dB
+    pub fn points_db(&self) -> &[f64] {
+        &self.points_db
+    }
+
+    /// Distance of each point from the front panel, in metres
+    pub fn distance_m(&self) -> &[f64] {
+        &self.distance_m
+    }
+
+    /// One-way distance in metres covered by 100ps
+    pub fn metres_per_100ps(&self) -> f64 {
+        self.metres_per_100ps
+    }
+
+    /// Distance of the user offset from the front panel, in metres. Key
+    /// event distances are measured from here.
+    pub fn user_offset_m(&self) -> f64 {
+        self.user_offset_m
+    }
+
+    /// Distance from the front panel, in metres, of a time such as an event
+    /// propagation time, which is measured from the user offset
+    pub fn event_distance_m(&self, time: i32) -> f64 {
+        self.user_offset_m + time as f64 * self.metres_per_100ps
+    }
+
+    /// Power in dB at a distance from the front panel, interpolating between
+    /// points
+    pub fn power_at(&self, distance_m: f64) -> Result<f64, &'static str> {
+        let (x, y) = (&self.distance_m, &self.points_db);
+        if !(x[0]..=x[x.len() - 1]).contains(&distance_m) {
+            return Err("Distance is outside the trace");
+        }
+        let i = x.partition_point(|&x| x <= distance_m).clamp(1, x.len() - 1);
+        let t = if x[i] > x[i - 1] { (distance_m - x[i - 1]) / (x[i] - x[i - 1]) } else { 0.0 };
+        Ok(y[i - 1] + t * (y[i] - y[i - 1]))
+    }
+
+    /// Two-point loss in dB between two distances from the front panel: the
+    /// difference in power between them, which includes any events between
+    pub fn loss_two_point(&self, a_m: f64, b_m: f64) -> Result<f64, &'static str> {
+        Ok(self.power_at(a_m)? - self.power_at(b_m)?)
+    }
+
+    /// Optical return loss in dB of the fibre between two distances from the
+    /// front panel, as positive dB like the last key event's ORL. Only the
+    /// part of a reflection within the span is included, so to include a
+    /// reflective event at the end of the fibre the span must extend past it.
+    ///
+    /// The trace is converted back to linear power, relative to the
+    /// backscatter at the start of the span, and integrated over the round
+    /// trip time. The backscatter coefficient (which is for a 1ns pulse)
+    /// converts this to a fraction of the launched power. Reflections are
+    /// integrated along with the backscatter, so one which saturates the
+    /// receiver will be underestimated.
+    pub fn optical_return_loss(&self, a_m: f64, b_m: f64) -> Result<f64, &'static str> {
+        let (from, to) = if a_m <= b_m { (a_m, b_m) } else { (b_m, a_m) };
+        let (x, y) = (&self.distance_m, &self.points_db);
+        if from < x[0] || to > x[x.len() - 1] {
+            return Err("Distance is outside the trace");
+        }
+        if self.backscatter_db == 0.0 {
+            return Err("File has no backscatter coefficient");
+        }
+        let start = x.partition_point(|&x| x < from);
+        let end = x.partition_point(|&x| x <= to);
+        if end < start + 2 {
+            return Err("Too few points between the distances");
+        }
+        let reference = self.backscatter_level(start, end);
+        // Round trip time per point, in ns
+        let dt_ns = 2.0 * (x[1] - x[0]) / self.metres_per_100ps / 10.0;
+        let returned: f64 = y[start..end].iter().map(|y| 10f64.powf((y - reference) / 5.0) * dt_ns).sum();
+        Ok(-(self.backscatter_db / 10.0 + returned.log10()) * 10.0)
+    }
+
+    /// Estimate the level of the backscatter at the start of a range of
+    /// points, from a line fitted to its first tenth, ignoring reflections
+    fn backscatter_level(&self, start: usize, end: usize) -> f64 {
+        let (x, y) = (&self.distance_m, &self.points_db);
+        let end = (start + (end - start) / 10).max(start + 20).min(end);
+        let mut points: Vec<usize> = (start..end).collect();
+        let mut fit = fit_line(&x[start..end], &y[start..end]);
+        for _ in 0..3 {
+            points.retain(|&i| y[i] - fit.at(x[i]) <= 2.0 * fit.rms);
+            if points.len() < 2 {
+                break;
+            }
+            let (px, py): (Vec<f64>, Vec<f64>) = points.iter().map(|&i| (x[i], y[i])).unzip();
+            fit = fit_line(&px, &py);
+        }
+        fit.at(x[start])
+    }
+
+    /// Measure the loss of an event at a distance from the front panel, by
+    /// fitting lines to the fibre either side and extrapolating both to the
+    /// event. Each window is cut short at the ends of the trace.
+    pub fn event_loss(&self, distance_m: f64, windows: &FitWindows) -> Result<EventLoss, &'static str> {
+        let (first, last) = (self.distance_m[0], self.distance_m[self.distance_m.len() - 1]);
+        let before_from = (distance_m - windows.length_m).max(first);
+        let after_from = distance_m + windows.gap_m;
+        let after_to = (after_from + windows.length_m).min(last);
+        if before_from >= distance_m || after_from >= after_to {
+            return Err("No room to fit the fibre either side of the event");
+        }
+        let line = |from: f64, to: f64| {
+            let (x, y) = (&self.distance_m, &self.points_db);
+            let start = x.partition_point(|&x| x < from);
+            let end = x.partition_point(|&x| x <= to);
+            if end < start + 2 {
+                return Err("Too few points either side of the event");
+            }
+            Ok(fit_line(&x[start..end], &y[start..end]))
+        };
+        let before = line(before_from, distance_m)?;
+        let after = line(after_from, after_to)?;
+        let attenuation = |fit: &Fit, from: f64, to: f64| Attenuation {
+            db_per_km: -fit.slope * 1000.0,
+            loss_db: -fit.slope * (to - from),
+            rms_db: fit.rms,
+            points: fit.n,
+        };
+        Ok(EventLoss {
+            loss_db: before.at(distance_m) - after.at(distance_m),
+            before: attenuation(&before, before_from, distance_m),
+            after: attenuation(&after, after_from, after_to),
+        })
+    }
+
+    /// Reflectance in dB of an event at a distance from the front panel, as
+    /// negative dB like the key event table. The height of the highest point
+    /// within the gap after the event, above a line fitted to the backscatter
+    /// before it, is converted to reflectance using the backscatter
+    /// coefficient and pulse width, per SR-4731. A reflection which
+    /// saturates the receiver will be underestimated.
+    pub fn reflectance(&self, distance_m: f64, windows: &FitWindows) -> Result<f64, &'static str> {
+        let (x, y) = (&self.distance_m, &self.points_db);
+        if distance_m < x[0] || distance_m > x[x.len() - 1] {
+            return Err("Distance is outside the trace");
+        }
+        if self.backscatter_db == 0.0 || self.pulse_ns <= 0.0 {
+            return Err("File has no backscatter coefficient or pulse width");
+        }
+        let before = x.partition_point(|&x| x < distance_m - windows.length_m);
+        let start = x.partition_point(|&x| x < distance_m);
+        let end = x.partition_point(|&x| x <= distance_m + windows.gap_m);
+        if start < before + 2 {
+            return Err("Too few points before the event");
+        }
+        let fit = fit_line(&x[before..start], &y[before..start]);
+        let height = (start..end).map(|i| y[i] - fit.at(x[i])).fold(f64::NEG_INFINITY, f64::max);
+        if height <= 0.0 {
+            return Err("No reflection at the event");
+        }
+        Ok(reflectance_db(self.backscatter_db, self.pulse_ns, height))
+    }
+
+    /// Event and attenuation dead zones of a reflective event at a distance
+    /// from the front panel, per IEC 61746. The event dead zone is the width
+    /// of the reflection 1.5dB below its peak; the attenuation dead zone
+    /// runs from the start of the reflection, where it rises 0.5dB above a
+    /// line fitted to the backscatter before it, to where the trace settles
+    /// within 0.5dB of a line fitted to the backscatter after it, if there's
+    /// room to fit one.
+    pub fn dead_zones(&self, distance_m: f64, windows: &FitWindows) -> Result<DeadZones, &'static str> {
+        const EVENT_DB: f64 = 1.5;
+        const ATTENUATION_DB: f64 = 0.5;
+        let (x, y) = (&self.distance_m, &self.points_db);
+        if distance_m < x[0] || distance_m > x[x.len() - 1] {
+            return Err("Distance is outside the trace");
+        }
+        // Event times mark the start of the reflection, so the peak is just
+        // after it
+        let start = x.partition_point(|&x| x < distance_m);
+        let end = x.partition_point(|&x| x <= distance_m + windows.gap_m);
+        let peak = (start..end).max_by(|&a, &b| y[a].partial_cmp(&y[b]).unwrap()).ok_or("No points at the event")?;
+        // Distance at which the trace crosses a level between points i and j
+        let crossing = |i: usize, j: usize, level: f64| x[i] + (x[j] - x[i]) * (level - y[i]) / (y[j] - y[i]);
+        let level = y[peak] - EVENT_DB;
+        let rise = (0..peak).rev().find(|&i| y[i] < level).ok_or("The reflection starts before the trace")?;
+        let fall = (peak + 1..y.len()).find(|&i| y[i] < level).ok_or("The reflection ends after the trace")?;
+        let event_m = crossing(fall - 1, fall, level) - crossing(rise, rise + 1, level);
+
+        let loss = self.event_loss(distance_m, windows).ok();
+        let before_from = (distance_m - windows.length_m).max(x[0]);
+        let first = x.partition_point(|&x| x < before_from);
+        if start < first + 2 {
+            return Err("Too few points before the event");
+        }
+        let before = fit_line(&x[first..start], &y[first..start]);
+        let leading = (first..=peak).find(|&i| y[i] - before.at(x[i]) > ATTENUATION_DB).unwrap_or(peak);
+        let attenuation_m = loss.and_then(|_| {
+            let after_to = (distance_m + windows.gap_m + windows.length_m).min(x[x.len() - 1]);
+            let after_from = x.partition_point(|&x| x < distance_m + windows.gap_m);
+            let after_end = x.partition_point(|&x| x <= after_to);
+            let after = fit_line(&x[after_from..after_end], &y[after_from..after_end]);
+            (peak..after_end).find(|&i| (y[i] - after.at(x[i])).abs() <= ATTENUATION_DB).map(|i| x[i] - x[leading])
+        });
+        Ok(DeadZones { event_m, attenuation_m })
+    }
+
+    /// Noise floor in dB: the level below which 98% of the noise lies, as in
+    /// the fixed parameters block. The noise is taken to be the last 5% of
+    /// the trace, which should be well past the end of the fibre, ignoring
+    /// points pinned at the trace's lowest level where the instrument had no
+    /// data. Instruments don't all report their noise floor level on the
+    /// trace's scale, so this may differ from the stored value.
+    pub fn noise_floor(&self) -> Result<f64, &'static str> {
+        let y = &self.points_db;
+        let lowest = y.iter().copied().fold(f64::INFINITY, f64::min);
+        let mut noise: Vec<f64> = y[y.len() - y.len() / 20..].iter().copied().filter(|&y| y > lowest).collect();
+        if noise.len() < 20 {
+            return Err("Too few points at the end of the trace to measure the noise");
+        }
+        noise.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        Ok(noise[noise.len() * 98 / 100])
+    }
+
+    /// Dynamic range in dB: the height of the backscatter at the start of
+    /// the fibre above the noise floor. The backscatter is measured from
+    /// three pulse widths past the user offset, clear of the front
+    /// connector's reflection.
+    pub fn dynamic_range(&self) -> Result<f64, &'static str> {
+        Ok(self.front_level()? - self.noise_floor()?)
+    }
+
+    /// The theoretical resolution of the acquisition the trace came from
+    pub fn resolution(&self) -> Resolution {
+        let spacing_m = self.distance_m.get(1).map_or(0.0, |x1| x1 - self.distance_m[0]);
+        Resolution::new(self.pulse_ns, spacing_m, self.metres_per_100ps)
+    }
+
+    /// Backscatter level at the start of the fibre, three pulse widths past
+    /// the user offset, clear of the front connector's reflection
+    fn front_level(&self) -> Result<f64, &'static str> {
+        let x = &self.distance_m;
+        let gap_m = FitWindows::for_trace(self).gap_m;
+        let start = x.partition_point(|&x| x < self.user_offset_m + gap_m);
+        if start + 2 > x.len() {
+            return Err("The trace ends before the fibre starts");
+        }
+        Ok(self.backscatter_level(start, x.len()))
+    }
+
+    /// The trace ready for display: shifted so that the backscatter just
+    /// after the front connector is at 0 dB, and so reads as loss from
+    /// there, and optionally with the noise below the noise floor clipped
+    /// to it, so that charts aren't scaled to fit noise. The noise is left
+    /// as it is if the floor can't be measured. Levels are no longer on the
+    /// instrument's scale, so the noise floor and dynamic range of the
+    /// result don't mean what they did.
+    pub fn normalized(&self, clip_at_noise_floor: bool) -> Result<Trace, &'static str> {
+        let front = self.front_level()?;
+        let floor = if clip_at_noise_floor { self.noise_floor().unwrap_or(f64::NEG_INFINITY) } else { f64::NEG_INFINITY };
+        let points_db = self.points_db.iter().map(|&y| y.max(floor) - front).collect();
+        Ok(Trace { points_db, ..self.clone() })
+    }
+
+    /// A copy of a file with its data points replaced by the trace's, e.g.
+    /// once it has been filtered, so that it can be written out, plotted or
+    /// have events detected in it. Only one pulse width is kept.
+    pub fn to_sor(&self, sor: &SORFile) -> Result<SORFile, &'static str> {
+        let mut sor = sor.clone();
+        replace_points(&mut sor, &self.distance_m, &self.points_db)?;
+        Ok(sor)
+    }
+
+    /// The trace interpolated onto evenly spaced points spacing_m apart,
+    /// starting from its first point. Coarser spacings than the original
+    /// will miss the peaks of narrow reflections; decimate keeps them.
+    pub fn resample(&self, spacing_m: f64) -> Result<Trace, &'static str> {
+        if spacing_m.is_nan() || spacing_m <= 0.0 {
+            return Err("The spacing must be positive");
+        }
+        let (first, last) = (self.distance_m[0], self.distance_m[self.distance_m.len() - 1]);
+        let n = ((last - first) / spacing_m).floor() as usize + 1;
+        let distance_m: Vec<f64> = (0..n).map(|i| first + i as f64 * spacing_m).collect();
+        let points_db = distance_m.iter().map(|&d| self.power_at(d.min(last))).collect::<Result<_, _>>()?;
+        Ok(Trace { points_db, distance_m, ..self.clone() })
+    }
+
+    /// Reduce the trace to at most max_points points for display, e.g. in a
+    /// web preview. The trace is split into max_points/2 runs of points, and
+    /// the lowest and highest point of each kept in order, so reflections
+    /// and noise still show at their full height. The points are no longer
+    /// evenly spaced, so can't be stored with to_sor.
+    pub fn decimate(&self, max_points: usize) -> Result<Trace, &'static str> {
+        if max_points < 2 {
+            return Err("At least two points must be kept");
+        }
+        let len = self.points_db.len();
+        if len <= max_points {
+            return Ok(self.clone());
+        }
+        let runs = max_points / 2;
+        let (mut points_db, mut distance_m) = (Vec::with_capacity(max_points), Vec::with_capacity(max_points));
+        for run in 0..runs {
+            let (start, end) = (run * len / runs, (run + 1) * len / runs);
+            let y = &self.points_db[start..end];
+            let min = (0..y.len()).min_by(|&a, &b| y[a].partial_cmp(&y[b]).unwrap()).unwrap();
+            let max = (0..y.len()).max_by(|&a, &b| y[a].partial_cmp(&y[b]).unwrap()).unwrap();
+            let (first, second) = (start + min.min(max), start + min.max(max));
+            points_db.push(self.points_db[first]);
+            distance_m.push(self.distance_m[first]);
+            if second != first {
+                points_db.push(self.points_db[second]);
+                distance_m.push(self.distance_m[second]);
+            }
+        }
+        Ok(Trace { points_db, distance_m, ..self.clone() })
+    }
+
+    /// The trace as at most n_points (distance from the user offset in
+    /// metres, level in dB) pairs, decimated as by decimate, for thumbnails
+    /// and charts in web UIs which don't need every point. Distances are
+    /// measured as for events, so that the two can be drawn together.
+    pub fn preview(&self, n_points: usize) -> Result<Vec<(f64, f64)>, &'static str> {
+        let trace = self.decimate(n_points)?;
+        Ok(trace.distance_m.iter().map(|d| d - self.user_offset_m).zip(trace.points_db).collect())
+    }
+
+    /// Least-squares attenuation between two distances from the front panel.
+    /// A line is fitted to the points between them, so the measurement is
+    /// only meaningful over a stretch of fibre without events.
+    pub fn attenuation_lsa(&self, a_m: f64, b_m: f64) -> Result<Attenuation, &'static str> {
+        let (from, to) = if a_m <= b_m { (a_m, b_m) } else { (b_m, a_m) };
+        let (x, y) = (&self.distance_m, &self.points_db);
+        if from < x[0] || to > x[x.len() - 1] {
+            return Err("Distance is outside the trace");
+        }
+        let start = x.partition_point(|&x| x < from);
+        let end = x.partition_point(|&x| x <= to);
+        if end < start + 2 {
+            return Err("Too few points between the distances");
+        }
+        let fit = fit_line(&x[start..end], &y[start..end]);
+        Ok(Attenuation {
+            db_per_km: -fit.slope * 1000.0,
+            loss_db: -fit.slope * (to - from),
+            rms_db: fit.rms,
+            points: fit.n,
+        })
+    }
+}
+
+/// Where to fit lines either side of an event to measure its loss
+#[derive(Debug, PartialEq, Clone)]
+pub struct FitWindows {
+    /// Length of fibre fitted on each side, in metres
+    pub length_m: f64,
+    /// Length of fibre after the event which isn't fitted, in metres, to
+    /// leave room for the event itself and the receiver's recovery from it
+    pub gap_m: f64,
+}
+
+impl FitWindows {
+    /// Windows suited to the trace's pulse width, like those used for
+    /// detecting events
+    pub fn for_trace(trace: &Trace) -> FitWindows {
+        let spacing_m = trace.distance_m.get(1).map_or(0.0, |x| x - trace.distance_m[0]);
+        let gap_m = (3.0 * trace.pulse_ns * 10.0 * trace.metres_per_100ps).max(5.0 * spacing_m);
+        FitWindows { length_m: (4.0 * gap_m).max(100.0 * spacing_m), gap_m }
+    }
+}
+
+/// The loss of an event measured by fitting lines either side of it
+#[derive(Debug, PartialEq, Clone)]
+pub struct EventLoss {
+    /// Loss in dB, where the two lines meet the event
+    pub loss_db: f64,
+    /// The fibre leading into the event
+    pub before: Attenuation,
+    /// The fibre leading out of the event
+    pub after: Attenuation,
+}
+
+/// The dead zones of a reflective event, in metres
+#[derive(Debug, PartialEq, Clone)]
+pub struct DeadZones {
+    /// Event dead zone, within which a second reflection can't be told
+    /// apart from the first
+    pub event_m: f64,
+    /// Attenuation dead zone, within which a splice's loss can't be measured
+    pub attenuation_m: Option<f64>,
+}
+
+/// Measure the dead zones of each reflective key event, e.g. to compare an
+/// instrument against its specification using a reflection of known
+/// reflectance. Events whose dead zones can't be measured, such as those
+/// too close to the start of the trace, are left out. There's no
+/// attenuation dead zone at the end of the fibre, with no backscatter after
+/// it to return to.
+pub fn dead_zones(sor: &SORFile) -> Result<Vec<(i16, DeadZones)>, &'static str> {
+    let trace = Trace::new(sor)?;
+    let windows = FitWindows::for_trace(&trace);
+    let ke = sor.key_events.as_ref().ok_or("File has no key events")?;
+    let lke = &ke.last_key_event;
+    Ok(ke.key_events.iter()
+        .map(|e| (e.event_number, e.event_propogation_time, &e.event_code))
+        .chain(std::iter::once((lke.event_number, lke.event_propogation_time, &lke.event_code)))
+        .filter(|(_, _, code)| code.starts_with('1') || code.starts_with('2'))
+        .filter_map(|(number, time, code)| {
+            let mut zones = trace.dead_zones(trace.event_distance_m(time), &windows).ok()?;
+            if code.chars().nth(1) == Some('E') {
+                zones.attenuation_m = None;
+            }
+            Some((number, zones))
+        })
+        .collect())
+}
+
+/// The result of a least-squares attenuation measurement
+#[derive(Debug, PartialEq, Clone)]
+pub struct Attenuation {
+    /// Attenuation of the fibre in dB/km
+    pub db_per_km: f64,
+    /// Loss over the measured span in dB, according to the fitted line
+    pub loss_db: f64,
+    /// RMS difference between the trace and the fitted line in dB, as a
+    /// measure of the fit's quality
+    pub rms_db: f64,
+    /// Number of points fitted
+    pub points: usize,
+}
+
+/// Thresholds for detecting events, in dB
+#[derive(Debug, PartialEq, Clone)]
+pub struct EventThresholds {
+    /// Smallest loss (or gain) reported as a non-reflective event
+    pub loss_db: f64,
+    /// Smallest reflectance reported as a reflective event, e.g. -55.0
+    pub reflectance_db: f64,
+    /// Smallest loss taken to be the end of the fibre
+    pub end_of_fibre_db: f64,
+}
+
+impl Default for EventThresholds {
+    /// The defaults given by SR-4731
+    fn default() -> Self {
+        EventThresholds { loss_db: 0.2, reflectance_db: -55.0, end_of_fibre_db: 3.0 }
+    }
+}
+
+impl EventThresholds {
+    /// Take the thresholds from a file's fixed parameters, using the
+    /// defaults for any which are zero
+    pub fn from_sor(sor: &SORFile) -> EventThresholds {
+        let default = EventThresholds::default();
+        let fp = match &sor.fixed_parameters {
+            Some(fp) => fp,
+            None => return default,
+        };
+        let or_default = |v: u16, scale: f64, d: f64| if v > 0 { v as f64 * scale } else { d };
+        EventThresholds {
+            loss_db: or_default(fp.loss_threshold, 0.001, default.loss_db),
+            reflectance_db: or_default(fp.reflectance_threshold, -0.001, default.reflectance_db),
+            end_of_fibre_db: or_default(fp.end_of_fibre_threshold, 0.001, default.end_of_fibre_db),
+        }
+    }
+}
+
+/// A straight line fitted to part of the trace, with power in dB against
+/// distance in metres
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct Fit {
+    slope: f64,
+    intercept: f64,
+    /// RMS residual, in dB
+    rms: f64,
+    /// Number of points fitted
+    n: usize,
+}
+
+impl Fit {
+    fn at(&self, x: f64) -> f64 {
+        self.intercept + self.slope * x
+    }
+
+    /// Standard error of the fitted line at either end of the fitted points
+    fn end_error(&self) -> f64 {
+        // For evenly spaced points the variance at an end is 4σ²/n
+        2.0 * self.rms / (self.n as f64).sqrt()
+    }
+}
+
+/// Least-squares fit of a line to the points
+fn fit_line(x: &[f64], y: &[f64]) -> Fit {
+    let n = x.len() as f64;
+    let mean_x = x.iter().sum::<f64>() / n;
+    let mean_y = y.iter().sum::<f64>() / n;
+    let sxx: f64 = x.iter().map(|x| (x - mean_x).powi(2)).sum();
+    let sxy: f64 = x.iter().zip(y).map(|(x, y)| (x - mean_x) * (y - mean_y)).sum();
+    let slope = if sxx > 0.0 { sxy / sxx } else { 0.0 };
+    let intercept = mean_y - slope * mean_x;
+    let ss: f64 = x.iter().zip(y).map(|(x, y)| (y - intercept - slope * x).powi(2)).sum();
+    Fit { slope, intercept, rms: (ss / n).sqrt(), n: x.len() }
+}
+
+/// Reflectance in dB of a reflection rising height_db above the backscatter,
+/// given the backscatter coefficient for a 1ns pulse and the pulse width in
+/// ns. The trace is in one-way dB, hence the factor of 5.
+fn reflectance_db(backscatter_db: f64, pulse_ns: f64, height_db: f64) -> f64 {
+    backscatter_db + 10.0 * ((10f64.powf(height_db / 5.0) - 1.0) * pulse_ns).log10()
+}
+
+/// An event found in the trace, with indices into its points
+struct Found {
+    index: usize,
+    loss: f64,
+    reflectance: Option<f64>,
+    /// Attenuation of the fibre leading into the event, in dB/km
+    attenuation: f64,
+    /// Start and end of the fits either side of the event
+    markers: [usize; 4],
+    end: bool,
+}
+
+/// How distances along one trace map onto another trace of the same fibre
+#[derive(Debug, PartialEq, Clone)]
+pub struct Alignment {
+    /// Distance in metres added after scaling
+    pub offset_m: f64,
+    /// Stretch of the second trace relative to the first, e.g. from a
+    /// different group index
+    pub scale: f64,
+    /// Normalised cross-correlation of the aligned traces, from -1 to 1
+    pub correlation: f64,
+}
+
+impl Alignment {
+    /// Map a distance from the front panel in the first trace to the same
+    /// point in the second
+    pub fn map(&self, distance_m: f64) -> f64 {
+        self.scale * distance_m + self.offset_m
+    }
+}
+
+/// Estimate how two traces of the same fibre line up, e.g. when taken with
+/// different launch leads or group indices, by cross-correlating them.
+///
+/// The traces are compared by their edges - the change in level across each
+/// point, averaged over a window either side - so that events line up
+/// regardless of differences in launch power, and the noise past the end of
+/// the fibre is ignored. The search starts on a coarse grid, over offsets
+/// which overlap the traces and stretches of up to 2%, then is refined at the
+/// traces' own resolution.
+pub fn align(a: &Trace, b: &Trace) -> Result<Alignment, &'static str> {
+    const MAX_STRETCH: f64 = 0.02;
+    const COARSE_POINTS: f64 = 1024.0;
+    let (ea, eb) = (Edges::new(a)?, Edges::new(b)?);
+    let (a_from, a_to) = (ea.x0, ea.x0 + ea.spacing * (ea.prefix.len() - 2) as f64);
+    let (b_from, b_to) = (eb.x0, eb.x0 + eb.spacing * (eb.prefix.len() - 2) as f64);
+
+    // Correlate a's edges at a_from + k*step with b's at
+    // b_from + m*step*scale, for each shift m - k within the given range
+    let correlate = |step: f64, scale: f64, shifts: std::ops::RangeInclusive<i64>| {
+        let da: Vec<f64> = (0..((a_to - a_from) / step) as usize).map(|k| ea.edge(a_from + k as f64 * step, step)).collect();
+        let db: Vec<f64> = (0..((b_to - b_from) / (step * scale)) as usize)
+            .map(|m| eb.edge(b_from + m as f64 * step * scale, step * scale)).collect();
+        // Normalising by the whole of both traces, rather than just the
+        // overlap, stops a small overlap from matching perfectly
+        let norm = (da.iter().map(|v| v * v).sum::<f64>() * db.iter().map(|v| v * v).sum::<f64>()).sqrt();
+        let mut best = (f64::NEG_INFINITY, 0);
+        for shift in shifts {
+            let from = (-shift).clamp(0, da.len() as i64) as usize;
+            let to = (db.len() as i64 - shift).clamp(from as i64, da.len() as i64) as usize;
+            if from >= to {
+                continue;
+            }
+            let sab: f64 = da[from..to].iter().zip(&db[(from as i64 + shift) as usize..]).map(|(va, vb)| va * vb).sum();
+            let correlation = if norm > 0.0 { sab / norm } else { 0.0 };
+            if correlation > best.0 {
+                best = (correlation, shift);
+            }
+        }
+        // a_from + k*step maps to b_from + (k + shift)*step*scale
+        let offset_m = b_from + best.1 as f64 * step * scale - scale * a_from;
+        Alignment { offset_m, scale, correlation: best.0 }
+    };
+    let search = |step: f64, scales: &[f64], shifts: &dyn Fn(f64) -> std::ops::RangeInclusive<i64>| {
+        scales.iter()
+            .map(|&scale| correlate(step, scale, shifts(scale)))
+            .fold(None, |best: Option<Alignment>, a| match best {
+                Some(best) if best.correlation >= a.correlation => Some(best),
+                _ => Some(a),
+            })
+            .ok_or("No overlap between the traces")
+    };
+
+    // Coarse search over every overlapping offset. The stretch is searched
+    // in steps small enough to keep the far end within a grid step
+    let coarse = (a_to - a_from).max(b_to - b_from) / COARSE_POINTS;
+    let coarse = coarse.max(ea.spacing).max(eb.spacing);
+    let scale_step = 1.0 / COARSE_POINTS;
+    let n_scales = (MAX_STRETCH / scale_step) as i64;
+    let scales: Vec<f64> = (-n_scales..=n_scales).map(|i| 1.0 + i as f64 * scale_step).collect();
+    let span = ((a_to - a_from).max(b_to - b_from) / coarse) as i64;
+    let rough = search(coarse, &scales, &|_| -span..=span)?;
+
+    // Then refine around the best coarse alignment
+    let fine = ea.spacing.max(eb.spacing);
+    let scales: Vec<f64> = (-10..=10).map(|i| rough.scale + i as f64 * scale_step / 10.0).collect();
+    let reach = (2.0 * coarse / fine).ceil() as i64;
+    search(fine, &scales, &|scale| {
+        // The shift which reproduces the rough offset at this scale
+        let centre = ((rough.offset_m + scale * a_from - b_from) / (fine * scale)).round() as i64;
+        centre - reach..=centre + reach
+    })
+}
+
+/// Running sums of a trace's points, for averaging over spans in constant
+/// time
+struct Edges {
+    prefix: Vec<f64>,
+    x0: f64,
+    spacing: f64,
+    /// Level below which the trace is taken to be noise
+    floor: f64,
+}
+
+impl Edges {
+    fn new(trace: &Trace) -> Result<Edges, &'static str> {
+        let x = trace.distance_m();
+        if x.len() < 2 {
+            return Err("Too few data points to align");
+        }
+        let mut prefix = vec![0.0];
+        for y in trace.points_db() {
+            prefix.push(prefix[prefix.len() - 1] + y);
+        }
+        Ok(Edges {
+            prefix,
+            x0: x[0],
+            spacing: x[1] - x[0],
+            floor: trace.noise_floor().map_or(f64::NEG_INFINITY, |nf| nf + 3.0),
+        })
+    }
+
+    /// Mean level between two distances, if there are points between them
+    fn mean(&self, from: f64, to: f64) -> Option<f64> {
+        let n = self.prefix.len() - 1;
+        let start = ((from - self.x0) / self.spacing).ceil().max(0.0) as usize;
+        let end = (((to - self.x0) / self.spacing).floor() + 1.0).clamp(0.0, n as f64) as usize;
+        if start >= end {
+            return None;
+        }
+        Some((self.prefix[end] - self.prefix[start]) / (end - start) as f64)
+    }
+
+    /// Change in mean level across a distance, over a window either side,
+    /// or zero where that's outside the trace or in the noise
+    fn edge(&self, distance_m: f64, window_m: f64) -> f64 {
+        match (self.mean(distance_m - window_m, distance_m), self.mean(distance_m, distance_m + window_m)) {
+            (Some(before), Some(after)) if before > self.floor && after > self.floor => after - before,
+            _ => 0.0,
+        }
+    }
+}
+
+/// The difference between a trace and a reference trace of the same fibre
+#[derive(Debug, PartialEq, Clone)]
+pub struct Difference {
+    /// Distance of each point from the current trace's front panel
+    pub distance_m: Vec<f64>,
+    /// Current less reference level at each point, in dB, zeroed at the
+    /// start of the fibre
+    pub delta_db: Vec<f64>,
+    /// Where the difference exceeds the threshold
+    pub deviations: Vec<Deviation>,
+    /// How the reference trace lines up with the current one
+    pub alignment: Alignment,
+}
+
+impl Difference {
+    /// Build a difference trace, with trace type DT, from the current
+    /// trace's file. Data points can't hold levels above 0 dB, so the
+    /// difference is shifted down by its largest value rounded up to a whole
+    /// dB. The key events, which describe the current trace, are removed.
+    pub fn to_sor(&self, current: &SORFile) -> Result<SORFile, &'static str> {
+        let mut sor = current.clone();
+        let top = self.delta_db.iter().copied().fold(0.0, f64::max).ceil();
+        let points_db: Vec<f64> = self.delta_db.iter().map(|d| d - top).collect();
+        replace_points(&mut sor, &self.distance_m, &points_db)?;
+        if let Some(fp) = sor.fixed_parameters.as_mut() {
+            fp.trace_type = "DT".into();
+        }
+        sor.key_events = None;
+        Ok(sor)
+    }
+}
+
+/// Replace a file's data points with levels in dB at evenly spaced
+/// distances from the front panel, as a single pulse width stored with a
+/// scale factor of 1. Levels above 0dB can't be stored, so are clipped
+fn replace_points(sor: &mut SORFile, distance_m: &[f64], points_db: &[f64]) -> Result<(), &'static str> {
+    let fp = sor.fixed_parameters.as_mut().ok_or("File has no fixed parameters block")?;
+    if distance_m.len() < 2 {
+        return Err("Too few points to store");
+    }
+    let spacing_m = distance_m[1] - distance_m[0];
+    let span_m = distance_m[distance_m.len() - 1] - distance_m[0];
+    if (span_m - spacing_m * (distance_m.len() - 1) as f64).abs() > spacing_m / 2.0 {
+        return Err("Points must be evenly spaced to store");
+    }
+    let distance_per_100ps = units::distance_per_100ps(&fp.units_of_distance, fp.group_index);
+    let data: Vec<u16> = points_db.iter().map(|y| (-y * 1000.0).round().clamp(0.0, u16::MAX as f64) as u16).collect();
+    let acquisition_offset = units::metres_to_time(distance_m[0], fp.group_index);
+    fp.acquisition_offset_distance = crate::edit::rescale_distance(fp.acquisition_offset_distance, fp.acquisition_offset,
+                                                                  acquisition_offset, distance_per_100ps);
+    fp.acquisition_offset = acquisition_offset;
+    fp.total_n_pulse_widths_used = 1;
+    fp.pulse_widths_used.truncate(1);
+    fp.data_spacing = vec![units::data_spacing(spacing_m, fp.group_index)];
+    fp.n_data_points_for_pulse_widths_used = vec![data.len() as i32];
+    sor.data_points = Some(DataPoints {
+        number_of_data_points: data.len() as i32,
+        total_number_scale_factors_used: 1,
+        scale_factors: vec![DataPointsAtScaleFactor { n_points: data.len() as i32, scale_factor: 1000, data }],
+    });
+    Ok(())
+}
+
+/// A run of points whose difference exceeds the threshold
+#[derive(Debug, PartialEq, Clone)]
+pub struct Deviation {
+    /// Distance from the current trace's front panel
+    pub from_m: f64,
+    pub to_m: f64,
+    /// The largest difference, with its sign, in dB
+    pub peak_db: f64,
+}
+
+/// Subtract a reference trace from the current trace of the same fibre, e.g.
+/// to verify a repair against a measurement from before it, listing where
+/// the two differ by more than delta_db.
+///
+/// The traces are lined up with align, and compared from the current trace's
+/// user offset until either falls into its noise. Launch power varies from
+/// one measurement to the next, so the difference is zeroed over the first
+/// stretch of fibre; a new loss then shows as a step down which continues
+/// to the end of the fibre. A deviation starts where the difference exceeds
+/// delta_db, and ends where it falls below half that.
+pub fn subtract(reference: &Trace, current: &Trace, delta_db: f64) -> Result<Difference, &'static str> {
+    let alignment = align(reference, current)?;
+    let floor = |trace: &Trace| trace.noise_floor().map_or(f64::NEG_INFINITY, |nf| nf + 3.0);
+    let (reference_floor, current_floor) = (floor(reference), floor(current));
+    let x = current.distance_m();
+    let start = x.partition_point(|&x| x < current.user_offset_m);
+    let mut distance_m = Vec::new();
+    let mut delta = Vec::new();
+    for (&x, &c) in x[start..].iter().zip(&current.points_db[start..]) {
+        let r = match reference.power_at((x - alignment.offset_m) / alignment.scale) {
+            Ok(r) => r,
+            // Before the reference starts, or after it ends
+            Err(_) if distance_m.is_empty() => continue,
+            Err(_) => break,
+        };
+        if r <= reference_floor || c <= current_floor {
+            if distance_m.is_empty() {
+                continue;
+            }
+            break;
+        }
+        distance_m.push(x);
+        delta.push(c - r);
+    }
+    if distance_m.is_empty() {
+        return Err("The traces don't overlap above the noise");
+    }
+
+    // Zero the difference over the fibre following the first event
+    let windows = FitWindows::for_trace(current);
+    let from = distance_m.partition_point(|&x| x < distance_m[0] + windows.gap_m);
+    let to = distance_m.partition_point(|&x| x <= distance_m[0] + windows.gap_m + windows.length_m);
+    if to > from {
+        let zero = delta[from..to].iter().sum::<f64>() / (to - from) as f64;
+        for d in delta.iter_mut() {
+            *d -= zero;
+        }
+    }
+
+    // A deviation continues until the difference falls to half the
+    // threshold, so that noise doesn't break it up
+    let mut deviations: Vec<Deviation> = Vec::new();
+    let mut within = false;
+    for (&x, &d) in distance_m.iter().zip(&delta) {
+        match deviations.last_mut() {
+            Some(deviation) if within && d.abs() > delta_db / 2.0 && d * deviation.peak_db > 0.0 => {
+                deviation.to_m = x;
+                if d.abs() > deviation.peak_db.abs() {
+                    deviation.peak_db = d;
+                }
+            }
+            _ if d.abs() > delta_db => {
+                deviations.push(Deviation { from_m: x, to_m: x, peak_db: d });
+                within = true;
+            }
+            _ => within = false,
+        }
+    }
+    Ok(Difference { distance_m, delta_db: delta, deviations, alignment })
+}
+
+/// An event's loss at two wavelengths
+#[derive(Debug, PartialEq, Clone)]
+pub struct WavelengthLoss {
+    /// Distance from the user offset of the shorter wavelength's trace
+    pub distance_m: f64,
+    /// Loss at the shorter wavelength, in dB
+    pub short_db: f64,
+    /// Loss at the longer wavelength, in dB
+    pub long_db: f64,
+    /// True if the loss at the longer wavelength is significantly more, as
+    /// from a macrobend
+    pub macrobend: bool,
+}
+
+/// Compare the loss of each event in two files of the same fibre at
+/// different wavelengths, e.g. 1310nm and 1550nm, flagging those whose loss
+/// is much greater at the longer wavelength. Bends lose more at longer
+/// wavelengths, while splices and connectors lose about the same.
+///
+/// Events are taken from the key events of both files, since a bend may only
+/// be found at the longer wavelength, and lined up with align. Each event's
+/// loss is measured from both traces with event_loss; events where either
+/// measurement fails, such as at the very start of a trace, are left out. An event is
+/// flagged if the difference is at least 0.1dB, and well outside the noise
+/// on the two measurements.
+pub fn find_macrobends(a: &SORFile, b: &SORFile) -> Result<Vec<WavelengthLoss>, &'static str> {
+    const MIN_DIFFERENCE_DB: f64 = 0.1;
+    // Differences smaller than this many standard errors are taken to be
+    // noise
+    const SIGNIFICANCE: f64 = 3.0;
+    let wavelength = |sor: &SORFile| sor.general_parameters.as_ref().map(|gp| gp.nominal_wavelength)
+        .ok_or("File has no general parameters block");
+    let (short, long) = match wavelength(a)?.cmp(&wavelength(b)?) {
+        std::cmp::Ordering::Less => (a, b),
+        std::cmp::Ordering::Greater => (b, a),
+        std::cmp::Ordering::Equal => return Err("Both files are at the same wavelength"),
+    };
+    let (short_trace, long_trace) = (Trace::new(short)?, Trace::new(long)?);
+    let alignment = align(&short_trace, &long_trace)?;
+    let (short_windows, long_windows) = (FitWindows::for_trace(&short_trace), FitWindows::for_trace(&long_trace));
+
+    // Every event but the end of the fibre, as distances along the shorter
+    // wavelength's trace, and those at the longer wavelength which aren't
+    // at the shorter
+    let along = |sor: &SORFile, trace: &Trace, map: &dyn Fn(f64) -> f64| {
+        let mut events = events(sor);
+        events.pop();
+        for e in events.iter_mut() {
+            e.distance_m = map(trace.user_offset_m + e.distance_m);
+        }
+        events
+    };
+    let short_events = along(short, &short_trace, &|d| d);
+    let long_events = along(long, &long_trace, &|d| (d - alignment.offset_m) / alignment.scale);
+    let matches = match_events(&short_events, &long_events, short_windows.gap_m, f64::INFINITY);
+    let mut positions: Vec<f64> = short_events.iter().map(|e| e.distance_m)
+        .chain(matches.new.iter().map(|&i| long_events[i].distance_m))
+        .collect();
+    positions.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    // Standard error of a loss, from the fits either side extrapolated to
+    // the event
+    let error = |loss: &EventLoss| 2.0 * (loss.before.rms_db.powi(2) / loss.before.points as f64
+        + loss.after.rms_db.powi(2) / loss.after.points as f64).sqrt();
+    Ok(positions.into_iter().filter_map(|distance_m| {
+        let short_loss = short_trace.event_loss(distance_m, &short_windows).ok()?;
+        let long_loss = long_trace.event_loss(alignment.map(distance_m), &long_windows).ok()?;
+        let difference = long_loss.loss_db - short_loss.loss_db;
+        let noise = (error(&short_loss).powi(2) + error(&long_loss).powi(2)).sqrt();
+        Some(WavelengthLoss {
+            distance_m: distance_m - short_trace.user_offset_m,
+            short_db: short_loss.loss_db,
+            long_db: long_loss.loss_db,
+            macrobend: difference >= MIN_DIFFERENCE_DB && difference >= SIGNIFICANCE * noise,
+        })
+    }).collect())
+}
+
+/// A key event reduced to what's needed to match it with another file's
+#[derive(Debug, PartialEq, Clone)]
+pub struct Event {
+    pub number: i16,
+    /// Distance from the user offset, in metres
+    pub distance_m: f64,
+    pub loss_db: f64,
+    pub reflectance_db: f64,
+    pub code: String,
+}
+
+/// List a file's key events, including the last key event
+pub fn events(sor: &SORFile) -> Vec<Event> {
+    let metres_per_100ps = metres_per_100ps(sor);
+    let ke = match &sor.key_events {
+        Some(ke) => ke,
+        None => return Vec::new(),
+    };
+    let lke = &ke.last_key_event;
+    ke.key_events.iter()
+        .map(|e| (e.event_number, e.event_propogation_time, e.event_loss, e.event_reflectance, &e.event_code))
+        .chain(std::iter::once((lke.event_number, lke.event_propogation_time, lke.event_loss, lke.event_reflectance, &lke.event_code)))
+        .map(|(number, time, loss, reflectance, code)| Event {
+            number,
+            distance_m: time as f64 * metres_per_100ps,
+            loss_db: loss as f64 / 1000.0,
+            reflectance_db: reflectance as f64 / 1000.0,
+            code: code.to_string(),
+        })
+        .collect()
+}
+
+/// An event found in both of two lists
+#[derive(Debug, PartialEq, Clone)]
+pub struct EventPair {
+    /// Index of the event in the first list
+    pub a: usize,
+    /// Index of the event in the second list
+    pub b: usize,
+    /// Distance of the second event less the first, in metres
+    pub distance_error_m: f64,
+    /// Loss of the second event less the first, in dB
+    pub loss_change_db: f64,
+    /// True if the loss has changed by more than the tolerance
+    pub changed: bool,
+}
+
+/// How the events in two lists pair up
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct EventMatches {
+    pub matched: Vec<EventPair>,
+    /// Indices of events only in the second list
+    pub new: Vec<usize>,
+    /// Indices of events only in the first list
+    pub missing: Vec<usize>,
+}
+
+/// Pair up the events in two lists, e.g. a baseline and a later measurement
+/// of the same fibre. Events pair up if they're within distance_tol_m of
+/// each other; any distance offset or scaling between the two must already
+/// have been applied. Pairs keep to the order of events along the fibre, so
+/// an event inserted or deleted between two others doesn't throw out the
+/// rest, and among the ways of pairing up the most events, the one with
+/// the least total distance error is chosen. Pairs whose loss differs by
+/// more than loss_tol_db are marked as changed.
+pub fn match_events(a: &[Event], b: &[Event], distance_tol_m: f64, loss_tol_db: f64) -> EventMatches {
+    let order = |events: &[Event]| {
+        let mut order: Vec<usize> = (0..events.len()).collect();
+        order.sort_by(|&i, &j| events[i].distance_m.partial_cmp(&events[j].distance_m).unwrap());
+        order
+    };
+    let (oa, ob) = (order(a), order(b));
+    let (n, m) = (oa.len(), ob.len());
+    let error = |i: usize, j: usize| (b[ob[j]].distance_m - a[oa[i]].distance_m).abs();
+    // best[i][j] is the (pairs, -error) of the best pairing of the first i
+    // events of a with the first j of b
+    let mut best = vec![vec![(0usize, 0.0f64); m + 1]; n + 1];
+    let better = |x: (usize, f64), y: (usize, f64)| x.0 > y.0 || (x.0 == y.0 && x.1 > y.1);
+    for i in 1..=n {
+        for j in 1..=m {
+            let mut score = if better(best[i - 1][j], best[i][j - 1]) { best[i - 1][j] } else { best[i][j - 1] };
+            if error(i - 1, j - 1) <= distance_tol_m {
+                let (pairs, e) = best[i - 1][j - 1];
+                let paired = (pairs + 1, e - error(i - 1, j - 1));
+                if better(paired, score) {
+                    score = paired;
+                }
+            }
+            best[i][j] = score;
+        }
+    }
+
+    let mut matches = EventMatches::default();
+    let (mut i, mut j) = (n, m);
+    while i > 0 && j > 0 {
+        if best[i][j] == best[i - 1][j] {
+            i -= 1;
+            matches.missing.push(oa[i]);
+        } else if best[i][j] == best[i][j - 1] {
+            j -= 1;
+            matches.new.push(ob[j]);
+        } else {
+            i -= 1;
+            j -= 1;
+            let loss_change_db = b[ob[j]].loss_db - a[oa[i]].loss_db;
+            matches.matched.push(EventPair {
+                a: oa[i],
+                b: ob[j],
+                distance_error_m: b[ob[j]].distance_m - a[oa[i]].distance_m,
+                loss_change_db,
+                changed: loss_change_db.abs() > loss_tol_db,
+            });
+        }
+    }
+    matches.missing.extend(oa[..i].iter().rev());
+    matches.new.extend(ob[..j].iter().rev());
+    matches.matched.reverse();
+    matches.new.reverse();
+    matches.missing.reverse();
+    matches
+}
+
+/// Where a fibre has most probably broken, or otherwise gained loss
+#[derive(Debug, PartialEq, Clone)]
+pub struct BreakLocation {
+    /// Distance from the current trace's user offset, in metres
+    pub distance_m: f64,
+    /// How far either way the true location may lie, in metres, given the
+    /// pulse width and data spacing. Uncertainty in the group index, and in
+    /// how much fibre is coiled in the cable, come on top
+    pub uncertainty_m: f64,
+    /// True if the fibre now ends here, rather than there being a new or
+    /// worsened event
+    pub end_of_fibre: bool,
+    /// The current file's landmark nearest the break, if it has link
+    /// parameters, and the distance from it to the break in metres -
+    /// positive if the break is beyond it
+    pub landmark: Option<(Landmark, f64)>,
+}
+
+/// Find where a fibre has broken since a baseline measurement, for
+/// responding to an outage. The two files' events are lined up and matched
+/// as by compare; if the fibre now ends short of where it used to, that is
+/// the break, and otherwise it's the first event which is new or has
+/// worsened by more than 0.1dB. If the current file has no key events, they
+/// are detected in its trace.
+pub fn locate_break(baseline: &SORFile, current: &SORFile) -> Result<BreakLocation, &'static str> {
+    use crate::compare::{compare, Change, ToleranceProfile};
+    const LOSS_TOLERANCE_DB: f64 = 0.1;
+    let fp = current.fixed_parameters.as_ref().ok_or("File has no fixed parameters block")?;
+    let uncertainty_m = resolutions(current)?.first().map_or(0.0, |r| r.event_dead_zone_m);
+
+    let detected;
+    let current = match current.key_events {
+        Some(_) => current,
+        None => {
+            let mut sor = current.clone();
+            sor.key_events = Some(detect_events(current, &EventThresholds::from_sor(current))?);
+            detected = sor;
+            &detected
+        }
+    };
+    let (base_end, cur_end) = match (events(baseline).last(), events(current).last()) {
+        (Some(b), Some(c)) => (b.distance_m, c.distance_m),
+        _ => return Err("Both files must have key events"),
+    };
+    let tolerance_m = (2.0 * uncertainty_m).max(2.0);
+    // Only losses point to a break
+    let tolerances = ToleranceProfile {
+        loss_db: LOSS_TOLERANCE_DB,
+        distance_m: tolerance_m,
+        reflectance_db: f64::INFINITY,
+        ..ToleranceProfile::default()
+    };
+    let comparison = compare(baseline, current, &tolerances);
+    let end_of_fibre = cur_end < base_end + comparison.offset_m - tolerance_m;
+    let distance_m = if end_of_fibre {
+        Some(cur_end)
+    } else {
+        comparison.changes.iter()
+            .filter_map(|change| match change {
+                Change::New(e) | Change::Worsened { current: e, .. } => Some(e.distance_m),
+                Change::Missing(_) => None,
+            })
+            .min_by(|a, b| a.partial_cmp(b).unwrap())
+    }.ok_or("No break or new loss found")?;
+
+    let landmark = current.link_parameters.as_ref().and_then(|lp| {
+        lp.landmarks.iter()
+            .map(|l| (l, distance_m - units::time_to_metres(l.landmark_location as f64, fp.group_index)))
+            .min_by(|a, b| a.1.abs().partial_cmp(&b.1.abs()).unwrap())
+            .map(|(l, from_m)| (l.clone(), from_m))
+    });
+    Ok(BreakLocation { distance_m, uncertainty_m, end_of_fibre, landmark })
+}
+
+/// An event's loss measured from both ends of the fibre
+#[derive(Debug, PartialEq, Clone)]
+pub struct BidirectionalLoss {
+    /// Number of the event in the forward measurement
+    pub event_number: i16,
+    /// Loss measured from the forward end, in dB
+    pub forward_db: f64,
+    /// Loss measured from the far end, in dB
+    pub backward_db: f64,
+    /// The mean of the two, in dB
+    pub average_db: f64,
+}
+
+/// Pair up the events in measurements of a fibre from each end and average
+/// their losses. Where the mode field diameter changes at a splice, the
+/// backscatter changes with it, adding to the loss measured from one end
+/// and taking as much from the other - so a gainer in one direction shows
+/// extra loss in the other, and the mean of the two is the true loss.
+///
+/// Backward distances are measured from the end of the fibre, scaled so the
+/// two measurements' lengths agree, and events pair up within tolerance_m.
+/// Only key events are paired, not the end of the fibre.
+pub fn bidirectional_losses(forward: &SORFile, backward: &SORFile, tolerance_m: f64) -> Result<Vec<BidirectionalLoss>, &'static str> {
+    let (mut forward_events, mut backward_events) = (events(forward), events(backward));
+    // The last key event is the end of the fibre, which has no loss
+    let (forward_end, backward_end) = match (forward_events.pop(), backward_events.pop()) {
+        (Some(f), Some(b)) => (f, b),
+        _ => return Err("Both files must have key events"),
+    };
+    let (forward_length, backward_length) = (forward_end.distance_m, backward_end.distance_m);
+    if forward_length <= 0.0 || backward_length <= 0.0 {
+        return Err("The end of the fibre must be beyond the user offset");
+    }
+    for e in backward_events.iter_mut() {
+        e.distance_m = (backward_length - e.distance_m) * forward_length / backward_length;
+    }
+    let matches = match_events(&forward_events, &backward_events, tolerance_m, f64::INFINITY);
+    Ok(matches.matched.iter().map(|pair| {
+        let (f, b) = (&forward_events[pair.a], &backward_events[pair.b]);
+        BidirectionalLoss {
+            event_number: f.number,
+            forward_db: f.loss_db,
+            backward_db: b.loss_db,
+            average_db: (f.loss_db + b.loss_db) / 2.0,
+        }
+    }).collect())
+}
+
+/// Which way the second of two stitched acquisitions was taken
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Direction {
+    /// From further along the fibre, in the same direction as the first,
+    /// e.g. from an access point part way along
+    Same,
+    /// From the far end of the fibre, back towards the first
+    Opposite,
+}
+
+/// How two acquisitions to be stitched together overlap
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Overlap {
+    /// Length of fibre covered by both, in metres, taking each acquisition
+    /// to cover the fibre from its user offset to its last point
+    pub length_m: f64,
+    pub direction: Direction,
+}
+
+/// Join two acquisitions of a fibre too long to test in one, each covering
+/// part of it with an overlap between them, into one file covering the
+/// whole, e.g. to report on a long-haul span tested from both ends.
+///
+/// The combined trace is the first acquisition's up to the middle of the
+/// overlap and the second's after it, shifted to meet the first's level
+/// across the overlap, at the first's spacing. A trace taken from the far
+/// end is reversed, and its level negated so that losses fall away from the
+/// first acquisition's end as they should; reflections in that half show as
+/// dips. Key events are taken from each acquisition on its side of the
+/// join, and a trace from the far end contributes its user offset as the end
+/// of the fibre. The end-to-end loss is measured across the combined trace;
+/// the optical return loss is not computed.
+///
+/// The result is a copy of the first file with the combined trace and
+/// events, and a note of the join in its comment.
+pub fn stitch(a: &SORFile, b: &SORFile, overlap: &Overlap) -> Result<SORFile, &'static str> {
+    let (ta, tb) = (Trace::new(a)?, Trace::new(b)?);
+    let reach = |t: &Trace| t.distance_m[t.distance_m.len() - 1] - t.user_offset_m;
+    let (a_reach, b_reach) = (reach(&ta), reach(&tb));
+    if !(overlap.length_m > 0.0 && overlap.length_m < a_reach.min(b_reach)) {
+        return Err("The overlap must be shorter than both traces");
+    }
+    let start_m = a_reach - overlap.length_m;
+    let end_m = match overlap.direction {
+        Direction::Same => start_m + b_reach,
+        Direction::Opposite => a_reach + b_reach - overlap.length_m,
+    };
+    // Distances from here on are from the first acquisition's user offset,
+    // and from the second's for d
+    let to_b = |x: f64| match overlap.direction {
+        Direction::Same => x - start_m,
+        Direction::Opposite => end_m - x,
+    };
+    let b_level = |x: f64| {
+        let level = tb.power_at((tb.user_offset_m + to_b(x)).clamp(tb.distance_m[0], tb.distance_m[tb.distance_m.len() - 1]))?;
+        Ok::<f64, &'static str>(if overlap.direction == Direction::Opposite { -level } else { level })
+    };
+    let mut differences = Vec::new();
+    for (&d, &y) in ta.distance_m.iter().zip(&ta.points_db) {
+        let x = d - ta.user_offset_m;
+        if x >= start_m {
+            differences.push(y - b_level(x)?);
+        }
+    }
+    if differences.is_empty() {
+        return Err("No points lie within the overlap");
+    }
+    let shift = differences.iter().sum::<f64>() / differences.len() as f64;
+
+    let join_m = a_reach - overlap.length_m / 2.0;
+    let spacing_m = ta.distance_m[1] - ta.distance_m[0];
+    let first = ta.distance_m[0];
+    let n = ((end_m + ta.user_offset_m - first) / spacing_m).floor() as usize + 1;
+    let distance_m: Vec<f64> = (0..n).map(|i| first + i as f64 * spacing_m).collect();
+    let points_db = distance_m.iter().map(|&d| {
+        let x = d - ta.user_offset_m;
+        if x <= join_m { ta.power_at(d) } else { Ok(b_level(x)? + shift) }
+    }).collect::<Result<Vec<f64>, _>>()?;
+
+    let mut sor = a.clone();
+    replace_points(&mut sor, &distance_m, &points_db)?;
+    let fp = sor.fixed_parameters.as_mut().unwrap();
+    let range = units::metres_to_time(distance_m[n - 1] - first, fp.group_index);
+    fp.acquisition_range_distance = crate::edit::rescale_distance(fp.acquisition_range_distance, fp.acquisition_range, range,
+                                                                  units::distance_per_100ps(&fp.units_of_distance, fp.group_index));
+    fp.acquisition_range = range;
+
+    // Key events either side of the join, with times in the first file's
+    let (ma, mb) = (ta.metres_per_100ps, tb.metres_per_100ps);
+    let b_to_a = |t: i32| match overlap.direction {
+        Direction::Same => ((start_m + t as f64 * mb) / ma).round() as i32,
+        Direction::Opposite => ((end_m - t as f64 * mb) / ma).round() as i32,
+    };
+    let all = |ke: &KeyEvents| {
+        let mut all = ke.key_events.clone();
+        all.push(demote(&ke.last_key_event));
+        all
+    };
+    let mut events: Vec<KeyEvent> = a.key_events.as_ref().map_or(Vec::new(), all).into_iter()
+        .filter(|e| (e.event_propogation_time as f64 * ma) <= join_m)
+        .collect();
+    let mut theirs: Vec<KeyEvent> = b.key_events.as_ref().map_or(Vec::new(), all).into_iter()
+        .map(|mut e| {
+            e.event_propogation_time = b_to_a(e.event_propogation_time);
+            for marker in [&mut e.marker_location_1, &mut e.marker_location_2, &mut e.marker_location_3,
+                           &mut e.marker_location_4, &mut e.marker_location_5] {
+                if *marker != 0 {
+                    *marker = b_to_a(*marker);
+                }
+            }
+            e
+        })
+        .filter(|e| (e.event_propogation_time as f64 * ma) > join_m)
+        .collect();
+    if overlap.direction == Direction::Opposite {
+        theirs.reverse();
+        // The far end's user offset is the end of the fibre
+        if let Some(end) = theirs.last_mut().filter(|e| e.event_code.len() >= 2 && e.event_code.is_ascii()) {
+            end.event_code = format!("{}E{}", &end.event_code[..1], &end.event_code[2..]).into();
+        }
+    }
+    events.extend(theirs);
+    let end = events.pop().ok_or("Neither file has key events beyond the join")?;
+    for (n, e) in events.iter_mut().enumerate() {
+        e.event_number = n as i16 + 1;
+    }
+    let stitched = Trace::new(&sor)?;
+    let last = stitched.distance_m[stitched.distance_m.len() - 1];
+    let end_to_end_loss = stitched.loss_two_point(stitched.user_offset_m.max(first), stitched.event_distance_m(end.event_propogation_time).min(last))?;
+    sor.key_events = Some(KeyEvents {
+        number_of_key_events: events.len() as i16 + 1,
+        last_key_event: LastKeyEvent {
+            event_number: events.len() as i16 + 1,
+            event_propogation_time: end.event_propogation_time,
+            attenuation_coefficient_lead_in_fiber: end.attenuation_coefficient_lead_in_fiber,
+            event_loss: end.event_loss,
+            event_reflectance: end.event_reflectance,
+            event_code: end.event_code,
+            loss_measurement_technique: end.loss_measurement_technique,
+            marker_location_1: end.marker_location_1,
+            marker_location_2: end.marker_location_2,
+            marker_location_3: end.marker_location_3,
+            marker_location_4: end.marker_location_4,
+            marker_location_5: end.marker_location_5,
+            comment: end.comment,
+            end_to_end_loss: (end_to_end_loss * 1000.0).round() as i32,
+            end_to_end_marker_position_1: 0,
+            end_to_end_marker_position_2: end.event_propogation_time,
+            optical_return_loss: 0,
+            optical_return_loss_marker_position_1: 0,
+            optical_return_loss_marker_position_2: 0,
+        },
+        key_events: events,
+    });
+    if let Some(gp) = sor.general_parameters.as_mut() {
+        let note = format!("Stitched at {:.1} m", join_m);
+        gp.comment = match gp.comment.trim() {
+            "" => note.into(),
+            comment => format!("{}; {}", comment, note).into(),
+        };
+    }
+    Ok(sor)
+}
+
+/// The last key event as an ordinary one
+pub(crate) fn demote(lke: &LastKeyEvent) -> KeyEvent {
+    KeyEvent {
+        event_number: lke.event_number,
+        event_propogation_time: lke.event_propogation_time,
+        attenuation_coefficient_lead_in_fiber: lke.attenuation_coefficient_lead_in_fiber,
+        event_loss: lke.event_loss,
+        event_reflectance: lke.event_reflectance,
+        event_code: lke.event_code.clone(),
+        loss_measurement_technique: lke.loss_measurement_technique.clone(),
+        marker_location_1: lke.marker_location_1,
+        marker_location_2: lke.marker_location_2,
+        marker_location_3: lke.marker_location_3,
+        marker_location_4: lke.marker_location_4,
+        marker_location_5: lke.marker_location_5,
+        comment: lke.comment.clone(),
+    }
+}
+
+/// A span of fibre between two consecutive events
+#[derive(Debug, PartialEq, Clone)]
+pub struct Section {
+    /// Number of the event at the start, or 0 for the user offset
+    pub from_event: i16,
+    /// Number of the event at the end
+    pub to_event: i16,
+    /// Distance of the start from the user offset, in metres
+    pub start_m: f64,
+    /// Length of the section in metres
+    pub length_m: f64,
+    /// Loss of the fibre over the whole section in dB, not counting the
+    /// events at either end
+    pub loss_db: Option<f64>,
+    /// Attenuation of the fibre in dB/km
+    pub db_per_km: Option<f64>,
+}
+
+/// Tabulate the sections of fibre between consecutive key events, starting
+/// from the user offset, with each one's length, loss and attenuation.
+///
+/// Attenuation is fitted to the trace over the section, leaving out the
+/// dead zone after the event at its start. Where there's no trace, or the
+/// section is too short to fit, the lead-in attenuation the file records
+/// for the event at its end is used instead if there is one, and otherwise
+/// the loss is left unknown.
+pub fn section_table(sor: &SORFile) -> Result<Vec<Section>, &'static str> {
+    let ke = sor.key_events.as_ref().ok_or("File has no key events")?;
+    let metres_per_100ps = metres_per_100ps(sor);
+    let trace = Trace::new(sor).ok();
+    let gap_m = trace.as_ref().map_or(0.0, |t| FitWindows::for_trace(t).gap_m);
+    let lke = &ke.last_key_event;
+    let events = ke.key_events.iter()
+        .map(|e| (e.event_number, e.event_propogation_time, e.attenuation_coefficient_lead_in_fiber))
+        .chain(std::iter::once((lke.event_number, lke.event_propogation_time, lke.attenuation_coefficient_lead_in_fiber)));
+    let mut sections = Vec::new();
+    let (mut from_event, mut from_time) = (0, 0);
+    for (to_event, to_time, recorded) in events {
+        let (start_m, end_m) = (from_time as f64 * metres_per_100ps, to_time as f64 * metres_per_100ps);
+        if end_m > start_m {
+            let length_m = end_m - start_m;
+            let fitted = trace.as_ref().and_then(|t| {
+                let offset_m = t.user_offset_m;
+                t.attenuation_lsa(offset_m + start_m + gap_m, offset_m + end_m).ok()
+            });
+            let db_per_km = match fitted {
+                Some(a) => Some(a.db_per_km),
+                None if recorded != 0 => Some(recorded as f64 / 1000.0),
+                None => None,
+            };
+            sections.push(Section {
+                from_event,
+                to_event,
+                start_m,
+                length_m,
+                loss_db: db_per_km.map(|a| a * length_m / 1000.0),
+                db_per_km,
+            });
+        }
+        from_event = to_event;
+        from_time = to_time;
+    }
+    Ok(sections)
+}
+
+/// Typical insertion loss in dB of a 1xN splitter, for each N: the ideal
+/// 10log(N) dB split plus a fraction of a dB of excess loss
+const SPLITTER_LOSSES: [(u32, f64); 6] = [(2, 3.6), (4, 7.0), (8, 10.3), (16, 13.5), (32, 16.8), (64, 20.1)];
+
+/// The N of the 1xN splitter whose typical loss is within 1dB of a
+/// non-reflective event's loss, if there is one, e.g. for testing a PON
+/// through its splitters. Losses between the typical values are more likely
+/// to be faults.
+pub fn splitter_ratio(loss_db: f64) -> Option<u32> {
+    SPLITTER_LOSSES.iter().find(|(_, typical)| (loss_db - typical).abs() <= 1.0).map(|(n, _)| *n)
+}
+
+/// A problem with an acquisition found by assess
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Flag {
+    /// The backscatter is noisy enough to hide splices at the default loss
+    /// threshold, so more averaging (or a wider pulse) is needed
+    InsufficientAveraging,
+    /// The trace ends before the fibre does, or too soon after it to measure
+    /// the noise floor
+    RangeTooShort,
+    /// A reflection reached the top of the receiver's range, so its
+    /// reflectance can't be measured
+    SaturatedEvent,
+    /// The backscatter fades into the noise without a clear end of fibre,
+    /// so the dynamic range isn't enough for the fibre's length
+    EndNotReached,
+}
+
+impl Flag {
+    /// How much the flag takes off the score
+    fn penalty(self) -> u8 {
+        match self {
+            Flag::InsufficientAveraging => 30,
+            Flag::RangeTooShort => 40,
+            Flag::SaturatedEvent => 10,
+            Flag::EndNotReached => 40,
+        }
+    }
+}
+
+/// The best an acquisition with a given pulse width and data spacing can
+/// resolve, in metres. Real dead zones are longer, particularly after strong
+/// reflections which saturate the receiver.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Resolution {
+    pub pulse_width_ns: f64,
+    /// Length of fibre the pulse occupies
+    pub pulse_width_m: f64,
+    /// Distance between data points
+    pub spacing_m: f64,
+    /// How close two events can be and still be told apart: half the
+    /// pulse's length, as light makes the round trip
+    pub spatial_resolution_m: f64,
+    /// How soon after a reflective event the next can be seen: the spatial
+    /// resolution, plus a point to see it in
+    pub event_dead_zone_m: f64,
+}
+
+impl Resolution {
+    fn new(pulse_width_ns: f64, spacing_m: f64, metres_per_100ps: f64) -> Resolution {
+        let pulse_width_m = pulse_width_ns * 10.0 * metres_per_100ps;
+        Resolution {
+            pulse_width_ns,
+            pulse_width_m,
+            spacing_m,
+            spatial_resolution_m: pulse_width_m / 2.0,
+            event_dead_zone_m: pulse_width_m / 2.0 + spacing_m,
+        }
+    }
+}
+
+/// The resolution of each of the file's acquisitions, one per pulse width,
+/// in the order they are stored
+pub fn resolutions(sor: &SORFile) -> Result<Vec<Resolution>, &'static str> {
+    let fp = sor.fixed_parameters.as_ref().ok_or("File has no fixed parameters block")?;
+    let metres_per_100ps = units::metres_per_100ps(fp.group_index);
+    Ok(fp.pulse_widths_used.iter().enumerate().map(|(i, &pulse_ns)| {
+        let spacing_m = units::spacing_m(fp.data_spacing.get(i).copied().unwrap_or(0), fp.group_index);
+        Resolution::new(pulse_ns as f64, spacing_m, metres_per_100ps)
+    }).collect())
+}
+
+/// The quality of an acquisition
+#[derive(Debug, PartialEq, Clone)]
+pub struct Assessment {
+    /// Out of 100, less a penalty for each flag
+    pub score: u8,
+    pub flags: Vec<Flag>,
+    /// Noise floor in dB, if it could be measured
+    pub noise_floor_db: Option<f64>,
+    /// Dynamic range in dB, if it could be measured
+    pub dynamic_range_db: Option<f64>,
+    pub resolution: Resolution,
+}
+
+/// Assess the quality of an acquisition from its trace, e.g. to reject bad
+/// measurements from the field automatically. The end of the fibre is found
+/// as detect_events does, with the default thresholds.
+pub fn assess(trace: &Trace) -> Result<Assessment, &'static str> {
+    let thresholds = EventThresholds::default();
+    let found = find_events(trace, &thresholds)?;
+    let (x, y) = (trace.distance_m(), trace.points_db());
+    let noise_floor_db = trace.noise_floor().ok();
+    let mut flags = Vec::new();
+
+    // The fibre's backscatter is noisiest just before its end
+    let end = &found[found.len() - 1];
+    let [left_from, left_to, ..] = end.markers;
+    let before_end = if left_to >= left_from + 2 { Some(fit_line(&x[left_from..left_to], &y[left_from..left_to])) } else { None };
+    if before_end.as_ref().is_some_and(|fit| fit.rms > thresholds.loss_db) {
+        flags.push(Flag::InsufficientAveraging);
+    }
+    // Noise doesn't fit a line as closely as backscatter does
+    let tail = x.len() - x.len() / 20;
+    let ends_in_fibre = x.len() - tail >= 2 && fit_line(&x[tail..], &y[tail..]).rms <= thresholds.loss_db;
+    if ends_in_fibre || end.index == x.len() - 1 || noise_floor_db.is_none() || x.len() - end.index < x.len() / 20 {
+        flags.push(Flag::RangeTooShort);
+    } else if let (Some(fit), Some(noise_floor_db)) = (&before_end, noise_floor_db) {
+        if fit.at(x[end.index]) - noise_floor_db < thresholds.end_of_fibre_db {
+            flags.push(Flag::EndNotReached);
+        }
+    }
+    // A saturated receiver clips the top of the reflection flat
+    let highest = y.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    if y.windows(3).any(|w| w.iter().all(|&y| y == highest)) {
+        flags.push(Flag::SaturatedEvent);
+    }
+
+    let penalty: u8 = flags.iter().map(|f| f.penalty()).sum();
+    Ok(Assessment {
+        score: 100u8.saturating_sub(penalty),
+        flags,
+        noise_floor_db,
+        dynamic_range_db: trace.dynamic_range().ok(),
+        resolution: trace.resolution(),
+    })
+}
+
+/// Find the events in a trace, as detect_events does, starting with the user
+/// offset and finishing with the end of the fibre or of the trace
+fn find_events(trace: &Trace, thresholds: &EventThresholds) -> Result<Vec<Found>, &'static str> {
+    // Steps smaller than this many standard errors are taken to be noise
+    const SIGNIFICANCE: f64 = 5.0;
+    // Backscatter falling faster than this, in dB/km, isn't from fibre
+    const MAX_ATTENUATION: f64 = 5.0;
+    let (x, y) = (trace.distance_m(), trace.points_db());
+    let spacing_m = x.get(1).map_or(0.0, |x1| x1 - x[0]);
+    if spacing_m <= 0.0 {
+        return Err("Too few data points to detect events");
+    }
+    // The pulse width is in ns; 1ns is ten 100ps units
+    let pulse_ns = trace.pulse_ns.max(1.0);
+    let pulse_m = pulse_ns * 10.0 * trace.metres_per_100ps();
+    // The gap must cover the event and the receiver recovering from it
+    let gap = ((3.0 * pulse_m / spacing_m).ceil() as usize).max(5);
+    let window = (4 * gap).max(100);
+    let min_window = (window / 4).max(20);
+    // Vendors don't agree on the reference for the noise floor level in
+    // FxdParams, so take the level of the end of the trace, plus a margin
+    let tail = &y[y.len() - (y.len() / 50).max(1)..];
+    let noise_floor_db = tail.iter().sum::<f64>() / tail.len() as f64 + 1.0;
+    let fit = |from: usize, to: usize| fit_line(&x[from..to], &y[from..to]);
+    let reflectance = |height: f64| reflectance_db(trace.backscatter_db, pulse_ns, height);
+    let peak = |from: usize, to: usize, left: &Fit| {
+        y[from..to].iter().zip(&x[from..to]).map(|(y, x)| y - left.at(*x)).fold(f64::NEG_INFINITY, f64::max)
+    };
+    // Follow a steeply falling trace until it either levels out into
+    // backscatter again, or falls far enough below the given level that the
+    // fibre must have ended
+    let tail_ends = |mut from: usize, level: f64| loop {
+        let to = (from + window).min(x.len());
+        if to < from + min_window {
+            return true;
+        }
+        let tail = fit(from, to);
+        if level - tail.at(x[to - 1]) >= thresholds.end_of_fibre_db || tail.at(x[from]) <= noise_floor_db {
+            return true;
+        }
+        if -tail.slope * 1000.0 <= MAX_ATTENUATION {
+            return false;
+        }
+        from = to;
+    };
+    // Measure an event at an index, with the fit to its left starting at
+    // left_from if there's room for one
+    let measure = |index: usize, left_from: usize| -> Option<Found> {
+        let left_from = left_from.max(index.saturating_sub(window));
+        let left = if index >= left_from + min_window { Some(fit(left_from, index)) } else { None };
+        let mut right_from = index + gap;
+        if let Some(left) = &left {
+            // The receiver takes a while to recover from a large reflection,
+            // so skip on until the trace is back down to the backscatter
+            let tolerance = SIGNIFICANCE * left.rms + thresholds.loss_db;
+            if peak(index, right_from.min(x.len()), left) > tolerance {
+                let limit = (right_from + window).min(x.len());
+                while right_from < limit && y[right_from] - left.at(x[right_from]) > tolerance {
+                    right_from += 1;
+                }
+            }
+        }
+        let right_to = (right_from + window).min(x.len());
+        if right_to < right_from + min_window {
+            return None;
+        }
+        let right = fit(right_from, right_to);
+        let (loss, height, attenuation, noise) = match &left {
+            Some(left) => (left.at(x[index]) - right.at(x[index]), peak(index, index + gap, left), -left.slope * 1000.0,
+                           (left.end_error().powi(2) + right.end_error().powi(2)).sqrt().max(left.rms / 2.0)),
+            // Without a fit to the left we can only compare with the right
+            None => (0.0, peak(index, index + gap, &right), 0.0, right.rms),
+        };
+        let reflectance = Some(height)
+            .filter(|h| *h > SIGNIFICANCE * noise)
+            .map(reflectance)
+            .filter(|r| *r >= thresholds.reflectance_db);
+        let loss = if loss.abs() >= thresholds.loss_db && loss.abs() >= SIGNIFICANCE * noise { loss } else { 0.0 };
+        Some(Found {
+            index,
+            loss,
+            reflectance,
+            attenuation,
+            markers: [if left.is_some() { left_from } else { index }, index, right_from, right_to - 1],
+            // Past the end there's just noise, or the receiver recovering
+            // from the reflection at the end
+            end: loss >= thresholds.end_of_fibre_db || right.at(x[right_from]) <= noise_floor_db
+                || (-right.slope * 1000.0 > MAX_ATTENUATION && left.is_some_and(|left| tail_ends(right_from, left.at(x[index])))),
+        })
+    };
+
+    // Start with the user offset, then scan along the fibre from there
+    let start = x.iter().position(|&x| x >= trace.user_offset_m()).unwrap_or(0);
+    let mut found = vec![measure(start, 0).ok_or("Too few data points to detect events")?];
+    let mut clear_from = found[0].markers[2];
+    let mut i = clear_from + min_window;
+    while !found[found.len() - 1].end {
+        let event = match measure(i, clear_from) {
+            Some(event) => event,
+            None => {
+                // The trace ended before the fibre did
+                let index = x.len() - 1;
+                found.push(Found { index, loss: 0.0, reflectance: None, attenuation: 0.0, markers: [index; 4], end: true });
+                break;
+            }
+        };
+        if event.loss == 0.0 && event.reflectance.is_none() && !event.end {
+            i += 1;
+            continue;
+        }
+        // A step is first seen as it enters the fit to the right, and is
+        // measured best once it's within the gap, so move along to where it
+        // looks largest
+        if event.reflectance.is_none() {
+            let mut best = event.loss.abs();
+            for j in i + 1..(i + window).min(x.len()) {
+                match measure(j, clear_from) {
+                    Some(e) if e.loss.abs() > best => {
+                        best = e.loss.abs();
+                        i = j;
+                    }
+                    Some(_) => (),
+                    None => break,
+                }
+            }
+        }
+        // Then place it at the steepest rise (for a reflection) or fall
+        // within the gap
+        let steps = (i..i + gap - 1).map(|k| (k, y[k + 1] - y[k]));
+        let index = if event.reflectance.is_some() {
+            steps.fold((i, f64::NEG_INFINITY), |best, s| if s.1 > best.1 { s } else { best }).0
+        } else {
+            steps.fold((i, f64::INFINITY), |best, s| if s.1 < best.1 { s } else { best }).0
+        };
+        match measure(index, clear_from) {
+            Some(event) if event.loss != 0.0 || event.reflectance.is_some() || event.end => {
+                clear_from = event.markers[2];
+                i = clear_from + min_window;
+                found.push(event);
+            }
+            _ => i += 1,
+        }
+    }
+    Ok(found)
+}
+
+/// Find events in a file's trace, returning a key events block in place of
+/// any the file has.
+///
+/// A least-squares line is fitted to the trace either side of each point,
+/// leaving a gap of a few pulse widths for the event itself. Where the lines
+/// differ by more than the loss threshold (and by more than the noise on the
+/// trace allows for) there is a non-reflective event; where the trace within
+/// the gap peaks high enough to exceed the reflectance threshold there is a
+/// reflective one. The fibre ends at the first event whose loss exceeds the
+/// end of fibre threshold, or where the trace falls to the noise floor. If
+/// the trace ends first, the last event is marked out of range.
+///
+/// There is always an event at the user offset. Event losses are
+/// least-squares measurements, as is the end-to-end loss between the user
+/// offset and the end of the fibre; the optical return loss is not computed.
+/// Reflections which are ghosts of others, as found by find_ghosts, are
+/// noted in their comments.
+pub fn detect_events(sor: &SORFile, thresholds: &EventThresholds) -> Result<KeyEvents, &'static str> {
+    let trace = Trace::new(sor)?;
+    let found = find_events(&trace, thresholds)?;
+    let (x, y) = (trace.distance_m(), trace.points_db());
+    let fit = |from: usize, to: usize| fit_line(&x[from..to], &y[from..to]);
+    let time = |i: usize| ((x[i] - trace.user_offset_m()) / trace.metres_per_100ps()).round() as i32;
+    let start = found[0].index;
+    let out_of_range = found[found.len() - 1].index == x.len() - 1;
+
+    let mut events: Vec<KeyEvent> = found.iter().enumerate().map(|(n, f)| {
+        let last = n == found.len() - 1;
+        let code = match (f.reflectance.is_some(), last, out_of_range) {
+            (_, true, true) => "0O9999",
+            (true, true, false) => "1E9999",
+            (false, true, false) => "0E9999",
+            (true, false, _) => "1F9999",
+            (false, false, _) => "0F9999",
+        };
+        KeyEvent {
+            event_number: n as i16 + 1,
+            event_propogation_time: time(f.index),
+            attenuation_coefficient_lead_in_fiber: (f.attenuation * 1000.0).round().clamp(i16::MIN as f64, i16::MAX as f64) as i16,
+            // Loss at the end of the fibre is meaningless
+            event_loss: if last { 0 } else { (f.loss * 1000.0).round() as i16 },
+            event_reflectance: f.reflectance.map_or(0, |r| (r * 1000.0).round() as i32),
+            event_code: code.into(),
+            loss_measurement_technique: "LS".into(),
+            marker_location_1: time(f.markers[0]),
+            marker_location_2: time(f.markers[1]),
+            marker_location_3: time(f.markers[2]),
+            marker_location_4: time(f.markers[3]),
+            marker_location_5: if f.reflectance.is_some() { time(f.index) } else { 0 },
+            comment: "".into(),
+        }
+    }).collect();
+
+    // The end-to-end loss is the drop in backscatter from the user offset to
+    // the end, including the events between
+    let end = &found[found.len() - 1];
+    let end_to_end_loss = match found.len() {
+        1 => 0.0,
+        _ => {
+            let first = fit(found[0].markers[2], found[0].markers[3] + 1);
+            let before_end = fit(found[found.len() - 2].markers[2], end.index.max(found[found.len() - 2].markers[2] + 1));
+            first.at(x[start]) - before_end.at(x[end.index]) + found[0].loss
+        }
+    };
+    let last = events.pop().ok_or("No events found")?;
+    let mut ke = KeyEvents {
+        number_of_key_events: events.len() as i16 + 1,
+        last_key_event: LastKeyEvent {
+            event_number: last.event_number,
+            event_propogation_time: last.event_propogation_time,
+            attenuation_coefficient_lead_in_fiber: last.attenuation_coefficient_lead_in_fiber,
+            event_loss: last.event_loss,
+            event_reflectance: last.event_reflectance,
+            event_code: last.event_code,
+            loss_measurement_technique: last.loss_measurement_technique,
+            marker_location_1: last.marker_location_1,
+            marker_location_2: last.marker_location_2,
+            marker_location_3: last.marker_location_3,
+            marker_location_4: last.marker_location_4,
+            marker_location_5: last.marker_location_5,
+            comment: last.comment,
+            end_to_end_loss: (end_to_end_loss * 1000.0).round() as i32,
+            end_to_end_marker_position_1: 0,
+            end_to_end_marker_position_2: last.event_propogation_time,
+            optical_return_loss: 0,
+            optical_return_loss_marker_position_1: 0,
+            optical_return_loss_marker_position_2: 0,
+        },
+        key_events: events,
+    };
+    let user_offset = sor.general_parameters.as_ref().map_or(0, |gp| gp.user_offset);
+    let ghosts = ghosts(&ke, user_offset, trace.pulse_ns, thresholds.loss_db);
+    mark_ghosts(&mut ke, &ghosts);
+    Ok(ke)
+}
+
+/// A reflection which is an echo of other reflections, rather than an event
+#[derive(Debug, PartialEq, Clone)]
+pub struct Ghost {
+    /// Number of the ghost event
+    pub event_number: i16,
+    /// Numbers of the two reflections the light bounced between, where 0 is
+    /// the front panel
+    pub between: (i16, i16),
+    /// Number of the reflection it echoes
+    pub echo_of: i16,
+}
+
+/// Find key events which are ghosts: reflections without loss, lying where
+/// light bouncing between two stronger reflections would make a third
+/// reflection appear. Light which bounces back from reflection k to
+/// reflection i (or the front panel) and out again sees each reflection j
+/// beyond i further away by the distance from i to k, most often as a ghost
+/// of k itself at twice its distance from i.
+pub fn find_ghosts(sor: &SORFile) -> Vec<Ghost> {
+    let ke = match &sor.key_events {
+        Some(ke) => ke,
+        None => return Vec::new(),
+    };
+    let user_offset = sor.general_parameters.as_ref().map_or(0, |gp| gp.user_offset);
+    let pulse_ns = sor.fixed_parameters.as_ref().and_then(|fp| fp.pulse_widths_used.first().copied()).unwrap_or(0) as f64;
+    ghosts(ke, user_offset, pulse_ns, EventThresholds::from_sor(sor).loss_db)
+}
+
+/// Find the ghosts among a file's key events, and say so in their comments
+pub fn flag_ghosts(sor: &mut SORFile) -> Vec<Ghost> {
+    let ghosts = find_ghosts(sor);
+    if let Some(ke) = sor.key_events.as_mut() {
+        mark_ghosts(ke, &ghosts);
+    }
+    ghosts
+}
+
+fn mark_ghosts(ke: &mut KeyEvents, ghosts: &[Ghost]) {
+    let comments = ke.key_events.iter_mut().map(|e| (e.event_number, &mut e.comment))
+        .chain(std::iter::once((ke.last_key_event.event_number, &mut ke.last_key_event.comment)));
+    for (number, comment) in comments {
+        if let Some(ghost) = ghosts.iter().find(|g| g.event_number == number) {
+            let note = format!("ghost of event {}", ghost.echo_of);
+            *comment = if comment.trim().is_empty() { note.into() } else { format!("{}; {}", comment.trim(), note).into() };
+        }
+    }
+}
+
+fn ghosts(ke: &KeyEvents, user_offset: i32, pulse_ns: f64, loss_threshold_db: f64) -> Vec<Ghost> {
+    // (number, time from the front panel, reflectance in dB*1000, loss in
+    // dB) of each reflection, in order along the fibre
+    let lke = &ke.last_key_event;
+    let mut reflections: Vec<(i16, i64, i32, f64)> = ke.key_events.iter()
+        .map(|e| (e.event_number, e.event_propogation_time, e.event_reflectance, e.event_loss, &e.event_code))
+        .chain(std::iter::once((lke.event_number, lke.event_propogation_time, lke.event_reflectance, lke.event_loss, &lke.event_code)))
+        .filter(|e| e.4.starts_with('1') || e.4.starts_with('2'))
+        .map(|(number, time, reflectance, loss, _)| (number, (time + user_offset) as i64, reflectance, loss as f64 / 1000.0))
+        .collect();
+    reflections.sort_by_key(|r| r.1);
+    // Events are located to within about a pulse width; the pulse width is
+    // in ns, and one ns is ten 100ps units
+    let tolerance = (pulse_ns * 10.0).max(50.0) as i64;
+    let front_panel = (0, 0, 0, 0.0);
+    let mut sources = vec![front_panel];
+    let mut ghosts = Vec::new();
+    for &g in &reflections {
+        let mut echo = None;
+        if g.3.abs() < loss_threshold_db {
+            'search: for (n, i) in sources.iter().enumerate() {
+                for k in &sources[n + 1..] {
+                    for j in &sources[n + 1..] {
+                        if (j.1 + k.1 - i.1 - g.1).abs() <= tolerance && g.2 < i.2.max(j.2).max(k.2) {
+                            echo = Some(Ghost { event_number: g.0, between: (i.0, k.0), echo_of: j.0 });
+                            break 'search;
+                        }
+                    }
+                }
+            }
+        }
+        match echo {
+            Some(ghost) => ghosts.push(ghost),
+            None => sources.push(g),
+        }
+    }
+    ghosts
+}
+
+#[test]
+fn test_trace() {
+    let data = include_bytes!("../data/example1-noyes-ofl280.sor");
+    let sor = crate::parser::parse_file(data).unwrap().1;
+    let trace = Trace::new(&sor).unwrap();
+    assert_eq!(trace.points_db().len(), 30000);
+    assert_eq!(trace.distance_m().len(), 30000);
+    assert_eq!(trace.points_db()[0], -22.153);
+    // The first data point is 214.7ns behind the front panel
+    assert!((trace.distance_m()[0] + 43.86).abs() < 0.01);
+    assert!((trace.distance_m()[1] - trace.distance_m()[0] - 0.2043).abs() < 0.0001);
+    // The last key event is 3734.4m beyond the 503.4m user offset
+    assert!((trace.event_distance_m(182802) - 4237.8).abs() < 0.1);
+}
+
+#[test]
+fn test_samples() {
+    let data = include_bytes!("../data/example1-noyes-ofl280.sor");
+    let mut sor = crate::parser::parse_file(data).unwrap().1;
+    let trace = Trace::new(&sor).unwrap();
+    let samples: Vec<(f64, f64)> = sor.samples().unwrap().collect();
+    assert_eq!(samples.len(), 30000);
+    assert_eq!(samples[0], (trace.distance_m()[0], trace.points_db()[0]));
+    assert_eq!(samples[29999], (trace.distance_m()[29999], trace.points_db()[29999]));
+
+    // A second pulse width's points at twice the spacing start again from
+    // the acquisition offset
+    let fp = sor.fixed_parameters.as_mut().unwrap();
+    fp.total_n_pulse_widths_used = 2;
+    fp.pulse_widths_used = vec![30, 100];
+    fp.data_spacing = vec![100000, 200000];
+    fp.n_data_points_for_pulse_widths_used = vec![20000, 10000];
+    let samples: Vec<(f64, f64)> = sor.samples().unwrap().collect();
+    assert_eq!(samples.len(), 30000);
+    assert_eq!(samples[20000].0, samples[0].0);
+    assert!((samples[20001].0 - samples[20000].0 - 2.0 * (samples[1].0 - samples[0].0)).abs() < 1e-9);
+    assert_eq!(Trace::new(&sor).unwrap().points_db().len(), 20000);
+    sor.data_points = None;
+    assert!(sor.samples().is_err());
+}
+
+#[test]
+fn test_detect_events() {
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let sor = crate::parser::parse_file(data).unwrap().1;
+    let ke = detect_events(&sor, &EventThresholds::from_sor(&sor)).unwrap();
+    let m = metres_per_100ps(&sor);
+    let found: Vec<(f64, f64, &str)> = ke.key_events.iter()
+        .map(|e| (e.event_propogation_time as f64 * m, e.event_loss as f64 / 1000.0, &e.event_code[..2]))
+        .collect();
+    // The instrument found these, among others, at 0.0, 477.6, 778.6 and
+    // 1447.7m, with losses of 0.203, -0.336, 0.342 and 0.511 dB
+    for (distance, loss, code) in [(0.0, 0.2, "1F"), (477.6, -0.336, "0F"), (778.6, 0.342, "0F"), (1447.7, 0.511, "1F")] {
+        assert!(found.iter().any(|f| (f.0 - distance).abs() < 5.0 && (f.1 - loss).abs() < 0.05 && f.2 == code),
+                "no event like {} dB at {}m in {:?}", loss, distance, found);
+    }
+    let lke = &ke.last_key_event;
+    assert_eq!(&*lke.event_code, "1E9999");
+    assert!((lke.event_propogation_time as f64 * m - 3628.6).abs() < 1.0);
+    assert!((lke.end_to_end_loss - 2224).abs() < 50);
+    assert_eq!(ke.number_of_key_events as usize, ke.key_events.len() + 1);
+
+    // Stricter thresholds find fewer events
+    let strict = EventThresholds { loss_db: 0.3, ..EventThresholds::from_sor(&sor) };
+    let fewer = detect_events(&sor, &strict).unwrap();
+    assert!(fewer.key_events.len() < ke.key_events.len());
+    assert_eq!(fewer.last_key_event.event_propogation_time, lke.event_propogation_time);
+}
+
+#[test]
+fn test_cursor_measurements() {
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let sor = crate::parser::parse_file(data).unwrap().1;
+    let trace = Trace::new(&sor).unwrap();
+    let x = trace.distance_m();
+    assert_eq!(trace.power_at(x[10]).unwrap(), trace.points_db()[10]);
+    let midway = trace.power_at((x[10] + x[11]) / 2.0).unwrap();
+    assert!((midway - (trace.points_db()[10] + trace.points_db()[11]) / 2.0).abs() < 1e-9);
+    assert!(trace.power_at(-1.0).is_err());
+    assert_eq!(trace.loss_two_point(x[10], x[10]).unwrap(), 0.0);
+
+    // Between the 1447.7m connector and the end of the fibre at 3628.6m,
+    // which are measured from the 151.5m user offset
+    let (a, b) = (trace.user_offset_m() + 1600.0, trace.user_offset_m() + 3500.0);
+    let lsa = trace.attenuation_lsa(a, b).unwrap();
+    assert!(lsa.db_per_km > 0.2 && lsa.db_per_km < 0.5, "{:?}", lsa);
+    assert!((lsa.loss_db - lsa.db_per_km * 1.9).abs() < 1e-9);
+    assert!(lsa.rms_db < 0.1);
+    assert_eq!(trace.attenuation_lsa(b, a).unwrap(), lsa);
+    // A two-point measurement agrees over a long span, within the noise
+    assert!((trace.loss_two_point(a, b).unwrap() - lsa.loss_db).abs() < 0.2);
+    // The connector's loss is included in a two-point measurement across it
+    let across = trace.loss_two_point(trace.user_offset_m() + 1400.0, trace.user_offset_m() + 1500.0).unwrap();
+    assert!(across > 0.3, "{}", across);
+}
+
+#[test]
+fn test_optical_return_loss() {
+    // Vendors disagree over whether a reflection at the end marker counts;
+    // where the span is unambiguous we agree with the stored value. For
+    // example2 the span ends at the start of the end reflection, which the
+    // instrument included, so we extend it a little.
+    for (file, extend_m, expected) in [("example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor", 0.0, 36.018),
+                                       ("example4-exfo-ftb4ftbx730c-mfdgainer-1550nm.sor", 0.0, 37.78),
+                                       ("example1-noyes-ofl280-fastreporter-save.sor", 0.0, 17.841),
+                                       ("example2-exfo-maxtester730c.sor", 5.0, 19.852)] {
+        let data = std::fs::read(format!("data/{}", file)).unwrap();
+        let sor = crate::parser::parse_file(&data).unwrap().1;
+        let trace = Trace::new(&sor).unwrap();
+        let lke = &sor.key_events.as_ref().unwrap().last_key_event;
+        let x = trace.distance_m();
+        let a = trace.event_distance_m(lke.optical_return_loss_marker_position_1).max(x[0]);
+        let b = (trace.event_distance_m(lke.optical_return_loss_marker_position_2) + extend_m).min(x[x.len() - 1]);
+        let orl = trace.optical_return_loss(a, b).unwrap();
+        assert!((orl - expected).abs() < 0.5, "{}: {} dB, expected {} dB", file, orl, expected);
+    }
+}
+
+#[test]
+fn test_event_loss() {
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let sor = crate::parser::parse_file(data).unwrap().1;
+    let trace = Trace::new(&sor).unwrap();
+    let windows = FitWindows::for_trace(&trace);
+    let ke = sor.key_events.as_ref().unwrap();
+    // Event 4 is a 0.342 dB splice, and event 8 a 0.511 dB connector
+    for e in [&ke.key_events[3], &ke.key_events[7]] {
+        let measured = trace.event_loss(trace.event_distance_m(e.event_propogation_time), &windows).unwrap();
+        assert!((measured.loss_db - e.event_loss as f64 / 1000.0).abs() < 0.05, "{:?} for {:?}", measured, e);
+        assert_eq!(measured.before.points, measured.after.points);
+    }
+    // Longer windows reach further along the fibre
+    let long = FitWindows { length_m: 200.0, ..windows.clone() };
+    let measured = trace.event_loss(trace.event_distance_m(ke.key_events[3].event_propogation_time), &long).unwrap();
+    assert!(measured.before.points > 1000);
+    assert!(trace.event_loss(trace.distance_m()[0], &windows).is_err());
+}
+
+#[test]
+fn test_reflectance() {
+    // A reflection 10dB above -80dB backscatter with a 10ns pulse
+    assert!((reflectance_db(-80.0, 10.0, 10.0) - (-80.0 + 10.0 * 990f64.log10())).abs() < 1e-9);
+    let files: [(&[u8], usize); 3] = [
+        (include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor"), 7),
+        (include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1550nm.sor"), 7),
+        (include_bytes!("../data/example2-exfo-maxtester730c.sor"), 1),
+    ];
+    for (data, n) in files {
+        let sor = crate::parser::parse_file(data).unwrap().1;
+        let trace = Trace::new(&sor).unwrap();
+        let windows = FitWindows::for_trace(&trace);
+        let e = &sor.key_events.as_ref().unwrap().key_events[n];
+        let distance_m = trace.event_distance_m(e.event_propogation_time);
+        let measured = trace.reflectance(distance_m, &windows).unwrap();
+        assert!((measured - e.event_reflectance as f64 / 1000.0).abs() < 1.0, "{} for {:?}", measured, e);
+    }
+}
+
+#[test]
+fn test_normalized() {
+    let data = include_bytes!("../data/example1-noyes-ofl280.sor");
+    let trace = Trace::new(&crate::parser::parse_file(data).unwrap().1).unwrap();
+    let normalized = trace.normalized(false).unwrap();
+    let start = trace.user_offset_m() + FitWindows::for_trace(&trace).gap_m;
+    assert!(normalized.power_at(start).unwrap().abs() < 0.1);
+    let shift = trace.points_db()[0] - normalized.points_db()[0];
+    assert!(trace.points_db().iter().zip(normalized.points_db()).all(|(a, b)| (a - b - shift).abs() < 1e-9));
+    assert_eq!(normalized.distance_m(), trace.distance_m());
+
+    let clipped = trace.normalized(true).unwrap();
+    let floor = trace.noise_floor().unwrap() - shift;
+    let lowest = clipped.points_db().iter().copied().fold(f64::INFINITY, f64::min);
+    assert!((lowest - floor).abs() < 1e-9);
+    assert!(normalized.points_db().iter().any(|&y| y < floor));
+}
+
+#[test]
+fn test_noise_floor() {
+    // The end of this trace is pinned at -63.999dB, and the noise above that
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let trace = Trace::new(&crate::parser::parse_file(data).unwrap().1).unwrap();
+    let noise_floor = trace.noise_floor().unwrap();
+    assert!(noise_floor > -63.0 && noise_floor < -50.0, "{}", noise_floor);
+    let dynamic_range = trace.dynamic_range().unwrap();
+    assert!((trace.power_at(trace.user_offset_m() + 100.0).unwrap() - noise_floor - dynamic_range).abs() < 0.2);
+
+    let data = include_bytes!("../data/example1-noyes-ofl280.sor");
+    let trace = Trace::new(&crate::parser::parse_file(data).unwrap().1).unwrap();
+    assert!((trace.noise_floor().unwrap() - -31.5).abs() < 1.0);
+    assert!(trace.dynamic_range().unwrap() > 5.0);
+}
+
+
+#[test]
+fn test_assess() {
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let trace = Trace::new(&crate::parser::parse_file(data).unwrap().1).unwrap();
+    let assessment = assess(&trace).unwrap();
+    assert_eq!(assessment, Assessment {
+        score: 100,
+        flags: vec![],
+        noise_floor_db: trace.noise_floor().ok(),
+        dynamic_range_db: trace.dynamic_range().ok(),
+        resolution: trace.resolution(),
+    });
+    assert_eq!(assessment.resolution, resolutions(&crate::parser::parse_file(data).unwrap().1).unwrap()[0]);
+
+    // The end reflection of this trace is clipped
+    let data = include_bytes!("../data/example1-noyes-ofl280.sor");
+    let assessment = assess(&Trace::new(&crate::parser::parse_file(data).unwrap().1).unwrap()).unwrap();
+    assert_eq!(assessment.flags, vec![Flag::SaturatedEvent]);
+    assert_eq!(assessment.score, 90);
+
+    // This monitoring trace is barely above the noise
+    let data = include_bytes!("../data/example5-exfo-rtu2ftbx735c-sm7r-ea-hrd.sor");
+    let assessment = assess(&Trace::new(&crate::parser::parse_file(data).unwrap().1).unwrap()).unwrap();
+    assert!(assessment.flags.contains(&Flag::InsufficientAveraging));
+
+    // Cutting the trace short of the end of the fibre
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let mut sor = crate::parser::parse_file(data).unwrap().1;
+    sor.crop(None, Some(2000.0)).unwrap();
+    let assessment = assess(&Trace::new(&sor).unwrap()).unwrap();
+    assert!(assessment.flags.contains(&Flag::RangeTooShort), "{:?}", assessment);
+}
+
+#[test]
+fn test_align() {
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let sor = crate::parser::parse_file(data).unwrap().1;
+    let a = Trace::new(&sor).unwrap();
+
+    // A trace starting 100m further from the front panel, as though taken
+    // through a longer launch lead
+    let mut shifted = sor.clone();
+    shifted.fixed_parameters.as_mut().unwrap().acquisition_offset += (100.0 / a.metres_per_100ps()).round() as i32;
+    let alignment = align(&a, &Trace::new(&shifted).unwrap()).unwrap();
+    assert!((alignment.offset_m - 100.0).abs() < 0.5, "{:?}", alignment);
+    assert!((alignment.scale - 1.0).abs() < 0.0005, "{:?}", alignment);
+    assert!(alignment.correlation > 0.9);
+
+    // The same fibre at another wavelength
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1550nm.sor");
+    let other = crate::parser::parse_file(data).unwrap().1;
+    let b = Trace::new(&other).unwrap();
+    let alignment = align(&a, &b).unwrap();
+    let (ea, eb) = (&sor.key_events.as_ref().unwrap().key_events[7], &other.key_events.as_ref().unwrap().key_events[7]);
+    let mapped = alignment.map(a.event_distance_m(ea.event_propogation_time));
+    assert!((mapped - b.event_distance_m(eb.event_propogation_time)).abs() < 2.0, "{:?}", alignment);
+}
+
+#[test]
+fn test_subtract() {
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let reference = crate::parser::parse_file(data).unwrap().1;
+    let trace = Trace::new(&reference).unwrap();
+    let same = subtract(&trace, &trace, 0.3).unwrap();
+    assert!(same.deviations.is_empty(), "{:?}", same.deviations);
+
+    // Add a 0.5dB loss 1km along the fibre
+    let mut current = reference.clone();
+    let at = trace.distance_m().partition_point(|&x| x < trace.user_offset_m() + 1000.0);
+    for p in current.data_points.as_mut().unwrap().scale_factors[0].data[at..].iter_mut() {
+        *p += 500;
+    }
+    let difference = subtract(&trace, &Trace::new(&current).unwrap(), 0.3).unwrap();
+    assert_eq!(difference.deviations.len(), 1);
+    let deviation = &difference.deviations[0];
+    assert!((deviation.from_m - trace.distance_m()[at]).abs() < 1.0, "{:?}", deviation);
+    assert!((deviation.peak_db + 0.5).abs() < 0.2, "{:?}", deviation);
+
+    let sor = difference.to_sor(&current).unwrap();
+    let written = crate::parser::parse_file(&sor.to_bytes().unwrap()).unwrap().1;
+    assert_eq!(&*written.fixed_parameters.as_ref().unwrap().trace_type, "DT");
+    let dt = Trace::new(&written).unwrap();
+    assert_eq!(dt.points_db().len(), difference.delta_db.len());
+    assert!((dt.distance_m()[0] - difference.distance_m[0]).abs() < 0.05);
+}
+
+#[test]
+fn test_find_macrobends() {
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let short = crate::parser::parse_file(data).unwrap().1;
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1550nm.sor");
+    let mut long = crate::parser::parse_file(data).unwrap().1;
+    // The splices and connectors lose about the same at both wavelengths
+    let losses = find_macrobends(&long, &short).unwrap();
+    assert_eq!(losses.len(), 8);
+    assert!(losses.iter().all(|l| !l.macrobend), "{:?}", losses);
+
+    // Bend the fibre 1km along, only visibly at 1550nm
+    let trace = Trace::new(&long).unwrap();
+    let at = trace.distance_m().partition_point(|&x| x < trace.user_offset_m() + 1000.0);
+    let sf = &mut long.data_points.as_mut().unwrap().scale_factors[0];
+    for p in sf.data[at..].iter_mut() {
+        *p += 400;
+    }
+    let ke = long.key_events.as_mut().unwrap();
+    let mut bend = ke.key_events[4].clone();
+    bend.event_propogation_time = ((trace.distance_m()[at] - trace.user_offset_m()) / trace.metres_per_100ps()).round() as i32;
+    ke.key_events.insert(5, bend);
+    let losses = find_macrobends(&short, &long).unwrap();
+    let bends: Vec<&WavelengthLoss> = losses.iter().filter(|l| l.macrobend).collect();
+    assert_eq!(bends.len(), 1, "{:?}", losses);
+    assert!((bends[0].distance_m - 1000.0).abs() < 1.0);
+    assert!((bends[0].long_db - bends[0].short_db - 0.4).abs() < 0.1);
+    assert!(find_macrobends(&short, &short).is_err());
+}
+
+#[test]
+fn test_find_ghosts() {
+    let data = include_bytes!("../data/example2-exfo-maxtester730c.sor");
+    let mut sor = crate::parser::parse_file(data).unwrap().1;
+    // Light bouncing between events 2 and 3 shows event 3 again as event 5,
+    // and event 4 as event 6
+    assert_eq!(flag_ghosts(&mut sor), vec![
+        Ghost { event_number: 5, between: (2, 3), echo_of: 3 },
+        Ghost { event_number: 6, between: (2, 3), echo_of: 4 },
+    ]);
+    let ke = sor.key_events.as_ref().unwrap();
+    assert_eq!(&*ke.key_events[4].comment, "ghost of event 3");
+    assert_eq!(&*ke.last_key_event.comment, "ghost of event 4");
+
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    assert!(find_ghosts(&crate::parser::parse_file(data).unwrap().1).is_empty());
+}
+
+#[test]
+fn test_bidirectional_losses() {
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let forward = crate::parser::parse_file(data).unwrap().1;
+    // Make a measurement from the far end, where the gainer at event 2 shows
+    // as extra loss
+    let mut backward = forward.clone();
+    let ke = backward.key_events.as_mut().unwrap();
+    let length = ke.last_key_event.event_propogation_time;
+    for e in ke.key_events.iter_mut() {
+        e.event_propogation_time = length - e.event_propogation_time;
+    }
+    ke.key_events[1].event_loss = 900;
+    let losses = bidirectional_losses(&forward, &backward, 2.0).unwrap();
+    assert_eq!(losses.len(), 8);
+    assert_eq!(losses[0], BidirectionalLoss { event_number: 1, forward_db: 0.203, backward_db: 0.203, average_db: 0.203 });
+    assert_eq!(losses[1].event_number, 2);
+    assert!((losses[1].average_db - (0.9 - 0.336) / 2.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_stitch() {
+    let data = include_bytes!("../data/example1-noyes-ofl280.sor");
+    let original = crate::parser::parse_file(data).unwrap().1;
+    let trace = Trace::new(&original).unwrap();
+    let end_m = original.key_events.as_ref().unwrap().last_key_event.event_propogation_time as f64 * trace.metres_per_100ps;
+    let mut a = original.clone();
+    a.crop(None, Some(2500.0)).unwrap();
+    let a_reach = {
+        let t = Trace::new(&a).unwrap();
+        t.distance_m[t.distance_m.len() - 1] - t.user_offset_m
+    };
+    // Both traces should agree with the original over the fibre
+    let check = |stitched: &SORFile| {
+        let t = Trace::new(stitched).unwrap();
+        let errors: Vec<f64> = (1..35).map(|i| i as f64 * 100.0)
+            .map(|x| (t.power_at(t.user_offset_m + x).unwrap() - trace.power_at(trace.user_offset_m + x).unwrap()).abs())
+            .collect();
+        assert!(errors.iter().sum::<f64>() / (errors.len() as f64) < 0.05, "{:?}", errors);
+        let ke = stitched.key_events.as_ref().unwrap();
+        assert_eq!(ke.number_of_key_events, 3);
+        assert!((ke.last_key_event.event_propogation_time as f64 * t.metres_per_100ps - end_m).abs() < 1.0);
+        assert!(stitched.general_parameters.as_ref().unwrap().comment.contains("Stitched at"));
+    };
+
+    // Tested again from an access point 1500m along
+    let mut b = original.clone();
+    b.crop(Some(1500.0), None).unwrap();
+    let stitched = stitch(&a, &b, &Overlap { length_m: a_reach - 1500.0, direction: Direction::Same }).unwrap();
+    check(&stitched);
+    assert_eq!(stitched.key_events.as_ref().unwrap().last_key_event.event_code,
+               original.key_events.as_ref().unwrap().last_key_event.event_code);
+
+    // Tested from the far end, as far back as 1530m from the start, with the
+    // levels reversed, negated and offset
+    let mut b = original.clone();
+    let distance_m: Vec<f64> = (0..=(2200.0 / 0.2) as usize).map(|i| i as f64 * 0.2).collect();
+    let points_db: Vec<f64> = distance_m.iter()
+        .map(|&d| -30.0 - trace.power_at(trace.user_offset_m + end_m - d).unwrap())
+        .collect();
+    replace_points(&mut b, &distance_m, &points_db).unwrap();
+    b.general_parameters.as_mut().unwrap().user_offset = 0;
+    let ke = b.key_events.as_mut().unwrap();
+    ke.key_events.clear();
+    ke.last_key_event.event_propogation_time = 0;
+    ke.last_key_event.event_code = "1F9999".into();
+    let b_reach = distance_m[distance_m.len() - 1];
+    let stitched = stitch(&a, &b, &Overlap { length_m: a_reach + b_reach - end_m, direction: Direction::Opposite }).unwrap();
+    check(&stitched);
+    assert_eq!(&*stitched.key_events.as_ref().unwrap().last_key_event.event_code, "1E9999");
+
+    assert!(stitch(&a, &b, &Overlap { length_m: 5000.0, direction: Direction::Same }).is_err());
+}
+
+#[test]
+fn test_splitter_ratio() {
+    assert_eq!(splitter_ratio(3.4), Some(2));
+    assert_eq!(splitter_ratio(10.9), Some(8));
+    assert_eq!(splitter_ratio(17.5), Some(32));
+    // Between a 1x2 and 1x4, or too small for any
+    assert_eq!(splitter_ratio(5.3), None);
+    assert_eq!(splitter_ratio(0.5), None);
+}
+
+#[test]
+fn test_section_table() {
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let mut sor = crate::parser::parse_file(data).unwrap().1;
+    let sections = section_table(&sor).unwrap();
+    // The first event is at the user offset, so starts the first section
+    assert_eq!(sections.len(), 8);
+    assert_eq!((sections[0].from_event, sections[0].to_event), (1, 2));
+    let total: f64 = sections.iter().map(|s| s.length_m).sum();
+    let end = sor.key_events.as_ref().unwrap().last_key_event.event_propogation_time as f64 * metres_per_100ps(&sor);
+    assert!((total - end).abs() < 1e-6);
+    // The trace is almost flat leading into event 4, as the file's own
+    // attenuation of 0.008dB/km there agrees
+    assert!(sections[2].db_per_km.unwrap() < 0.05);
+    for s in &sections {
+        let a = s.db_per_km.unwrap();
+        assert!(a > 0.0 && a < 0.6, "{:?}", s);
+        assert!((s.loss_db.unwrap() - a * s.length_m / 1000.0).abs() < 1e-9);
+    }
+    // Without a trace, the recorded lead-in attenuation is used
+    sor.data_points = None;
+    let sections = section_table(&sor).unwrap();
+    assert_eq!(sections[0].db_per_km, Some(0.384));
+}
+
+#[test]
+fn test_dead_zones() {
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let sor = crate::parser::parse_file(data).unwrap().1;
+    let zones = dead_zones(&sor).unwrap();
+    // The reflective events are two connectors and the end of the fibre
+    assert_eq!(zones.iter().map(|(n, _)| *n).collect::<Vec<_>>(), vec![1, 8, 9]);
+    // A 10ns pulse is about 1m long
+    for (_, dz) in &zones {
+        assert!(dz.event_m > 1.0 && dz.event_m < 1.5, "{:?}", dz);
+    }
+    let adz = zones[1].1.attenuation_m.unwrap();
+    assert!(adz > zones[1].1.event_m && adz < 5.0);
+    assert_eq!(zones[2].1.attenuation_m, None);
+}
+
+#[test]
+fn test_resample_and_decimate() {
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let sor = crate::parser::parse_file(data).unwrap().1;
+    let trace = Trace::new(&sor).unwrap();
+    let coarse = trace.resample(1.0).unwrap();
+    let span = trace.distance_m()[trace.distance_m().len() - 1] - trace.distance_m()[0];
+    assert_eq!(coarse.distance_m().len(), span.floor() as usize + 1);
+    assert!((coarse.power_at(500.0).unwrap() - trace.power_at(500.0).unwrap()).abs() < 0.05);
+    // Resampled traces can be stored
+    let stored = Trace::new(&coarse.to_sor(&sor).unwrap()).unwrap();
+    assert_eq!(stored.distance_m().len(), coarse.distance_m().len());
+    assert!(trace.resample(0.0).is_err());
+
+    let preview = trace.decimate(1000).unwrap();
+    assert!(preview.points_db().len() <= 1000);
+    // The highest and lowest points survive
+    let max = |y: &[f64]| y.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let min = |y: &[f64]| y.iter().copied().fold(f64::INFINITY, f64::min);
+    assert_eq!(max(preview.points_db()), max(trace.points_db()));
+    assert_eq!(min(preview.points_db()), min(trace.points_db()));
+    assert!(preview.distance_m().windows(2).all(|w| w[0] < w[1]));
+    assert!(preview.to_sor(&sor).is_err());
+    assert_eq!(trace.decimate(usize::MAX).unwrap(), trace);
+
+    let points = trace.preview(1000).unwrap();
+    assert_eq!(points.len(), preview.points_db().len());
+    assert_eq!(points[0], (preview.distance_m()[0] - trace.user_offset_m(), preview.points_db()[0]));
+    assert!(trace.preview(1).is_err());
+}
+
+#[test]
+fn test_match_events() {
+    let event = |number: i16, distance_m: f64, loss_db: f64| Event { number, distance_m, loss_db, reflectance_db: 0.0, code: "0F9999".to_owned() };
+    let a = vec![event(1, 100.0, 0.1), event(2, 200.0, 0.1), event(3, 300.0, 0.2), event(4, 302.0, 0.3)];
+    // A new event between the first two, event 2 gone, and event 4 worse
+    let b = vec![event(1, 101.0, 0.1), event(2, 150.0, 0.5), event(3, 301.5, 0.2), event(4, 303.5, 0.6)];
+    let matches = match_events(&a, &b, 2.0, 0.05);
+    // Event 3 in b is nearest event 4 in a, but pairing it with event 3
+    // lets event 4 pair up too
+    assert_eq!(matches.matched.iter().map(|p| (p.a, p.b)).collect::<Vec<_>>(), vec![(0, 0), (2, 2), (3, 3)]);
+    assert_eq!(matches.new, vec![1]);
+    assert_eq!(matches.missing, vec![1]);
+    assert!(!matches.matched[0].changed);
+    assert!(matches.matched[2].changed);
+    assert!((matches.matched[2].loss_change_db - 0.3).abs() < 1e-9);
+    assert_eq!(match_events(&a, &[], 2.0, 0.05).missing, vec![0, 1, 2, 3]);
+}
+
+#[test]
+fn test_locate_break() {
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let baseline = crate::parser::parse_file(data).unwrap().1;
+    // The fibre breaks at 1000m, so the trace falls into the noise there,
+    // and the instrument doesn't analyse it
+    let mut current = baseline.clone();
+    let trace = Trace::new(&baseline).unwrap();
+    let at = trace.distance_m().partition_point(|&x| x < trace.user_offset_m() + 1000.0);
+    let data = &mut current.data_points.as_mut().unwrap().scale_factors[0].data;
+    let noise = data[data.len() * 19 / 20..].to_vec();
+    for (i, point) in data[at..].iter_mut().enumerate() {
+        *point = noise[i % noise.len()];
+    }
+    current.key_events = None;
+    let landmark = |number: i16, distance_m: f64| Landmark {
+        landmark_number: number,
+        landmark_code: "MH".into(),
+        landmark_location: (distance_m / metres_per_100ps(&baseline)).round() as i32,
+        related_event_number: 0,
+        gps_longitude: 0,
+        gps_latitude: 0,
+        fiber_correction_factor_lead_in_fiber: 0,
+        sheath_marker_entering_landmark: 0,
+        sheath_marker_leaving_landmark: 0,
+        units_of_sheath_marks_leaving_landmark: "mt".into(),
+        mode_field_diameter_leaving_landmark: 0,
+        comment: format!("Manhole {}", number).into(),
+    };
+    current.link_parameters = Some(crate::types::LinkParameters {
+        number_of_landmarks: 2,
+        landmarks: vec![landmark(1, 950.0), landmark(2, 1500.0)],
+    });
+    let location = locate_break(&baseline, &current).unwrap();
+    assert!(location.end_of_fibre);
+    // Detection finds the foot of the fall a few metres early
+    assert!((location.distance_m - 1000.0).abs() < 5.0, "{:?}", location);
+    assert!(location.uncertainty_m > 0.0);
+    let (nearest, from_m) = location.landmark.unwrap();
+    assert_eq!(nearest.landmark_number, 1);
+    assert!((from_m - 50.0).abs() < 5.0);
+    // Nothing has changed between a file and itself
+    assert!(locate_break(&baseline, &baseline).is_err());
+}
@@ -0,0 +1,221 @@
+/// This module judges a SOR file against an acceptance profile - limits on
+/// event losses and reflectances, and on the link as a whole - as set out
+/// in a commissioning specification.
+///
+/// Profiles can be loaded from TOML, e.g.
+///
+/// ```toml
+/// max_splice_loss = 0.1
+/// max_connector_loss = 0.5
+/// min_orl = 27.0
+///
+/// [max_attenuation]
+/// 1310 = 0.35
+/// 1550 = 0.25
+/// ```
+///
+/// with any limits left out taking their defaults.
+use std::collections::BTreeMap;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+use crate::analysis::{measured_orl, section_table, splitter_ratio};
+use crate::types::SORFile;
+
+/// Limits against which events and links are judged. Losses and
+/// reflectances are in dB.
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    /// Maximum loss of a non-reflective event, e.g. a fusion splice
+    pub max_splice_loss: f64,
+    /// Maximum loss of a reflective event, e.g. a connector
+    pub max_connector_loss: f64,
+    /// Maximum reflectance of a reflective event, e.g. -35.0
+    pub max_reflectance: f64,
+    /// Maximum end-to-end loss of the link, if one applies
+    pub max_total_loss: Option<f64>,
+    /// Minimum optical return loss of the link, if one applies
+    pub min_orl: Option<f64>,
+    /// Maximum attenuation of the fibre between events in dB/km, by
+    /// nominal wavelength in nm. Wavelengths not listed aren't limited
+    #[serde(deserialize_with = "wavelength_map")]
+    pub max_attenuation: BTreeMap<i16, f64>,
+    /// Take non-reflective losses typical of a 1xN splitter to be splitters
+    /// rather than faults, as when testing a PON
+    pub splitters: bool,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            max_splice_loss: 0.3,
+            max_connector_loss: 0.75,
+            max_reflectance: -35.0,
+            max_total_loss: None,
+            min_orl: None,
+            max_attenuation: BTreeMap::new(),
+            splitters: false,
+        }
+    }
+}
+
+impl Profile {
+    /// Judge an event's loss and reflectance (both in dB) given its event
+    /// code. Loss at the end of the fibre is meaningless, and a reflectance
+    /// of zero means none was measured
+    pub fn judge(&self, loss_db: f64, reflectance_db: f64, code: &str) -> bool {
+        end_of_fibre(code) || if reflective(code) {
+            loss_db <= self.max_connector_loss && (reflectance_db == 0.0 || reflectance_db <= self.max_reflectance)
+        } else {
+            loss_db <= self.max_splice_loss
+        }
+    }
+}
+
+/// The verdict on one key event, including the last
+#[derive(Debug, PartialEq, Clone)]
+pub struct EventResult {
+    pub event_number: i16,
+    /// The N of a probable 1xN splitter, if the profile looks for splitters
+    pub splitter: Option<u32>,
+    pub pass: bool,
+}
+
+/// The verdict on the fibre between two consecutive events
+#[derive(Debug, PartialEq, Clone)]
+pub struct SectionResult {
+    /// Number of the event at the start, or 0 for the user offset
+    pub from_event: i16,
+    /// Number of the event at the end
+    pub to_event: i16,
+    /// Attenuation in dB/km, if it could be measured
+    pub db_per_km: Option<f64>,
+    /// False if the attenuation is over the limit for the wavelength
+    pub pass: bool,
+}
+
+/// The result of judging a file against a profile
+#[derive(Debug, PartialEq, Clone)]
+pub struct Evaluation {
+    pub events: Vec<EventResult>,
+    pub sections: Vec<SectionResult>,
+    /// End-to-end loss in dB
+    pub total_loss_db: f64,
+    pub total_loss_pass: bool,
+    /// Optical return loss in dB, or zero if not measured
+    pub orl_db: f64,
+    /// True if the ORL is within the profile, or wasn't measured
+    pub orl_pass: bool,
+    /// True if the link as a whole - its total loss, ORL and the attenuation
+    /// of each section - is within the profile
+    pub link_pass: bool,
+    /// True if the link and every event are within the profile
+    pub pass: bool,
+}
+
+/// Judge a file's key events, and the link they make up, against a profile.
+/// Events are taken as the file records them; the attenuation of each
+/// section is measured with section_table where there's a limit for the
+/// file's wavelength.
+pub fn evaluate(sor: &SORFile, profile: &Profile) -> Evaluation {
+    let mut events = Vec::new();
+    let mut total_loss_db = 0.0;
+    if let Some(ke) = &sor.key_events {
+        let lke = &ke.last_key_event;
+        let all = ke.key_events.iter()
+            .map(|e| (e.event_number, e.event_loss, e.event_reflectance, &e.event_code))
+            .chain(std::iter::once((lke.event_number, lke.event_loss, lke.event_reflectance, &lke.event_code)));
+        for (event_number, loss, reflectance, code) in all {
+            let (loss_db, reflectance_db) = (loss as f64 / 1000.0, reflectance as f64 / 1000.0);
+            let splitter = if profile.splitters && !reflective(code) && !end_of_fibre(code) {
+                splitter_ratio(loss_db)
+            } else {
+                None
+            };
+            events.push(EventResult {
+                event_number,
+                splitter,
+                pass: splitter.is_some() || profile.judge(loss_db, reflectance_db, code),
+            });
+        }
+        total_loss_db = lke.end_to_end_loss as f64 / 1000.0;
+    }
+
+    let wavelength = sor.general_parameters.as_ref().map_or(0, |gp| gp.nominal_wavelength);
+    let sections = match profile.max_attenuation.get(&wavelength) {
+        Some(&max) => section_table(sor).unwrap_or_default().into_iter().map(|s| SectionResult {
+            from_event: s.from_event,
+            to_event: s.to_event,
+            db_per_km: s.db_per_km,
+            pass: s.db_per_km.is_none_or(|a| a <= max),
+        }).collect(),
+        None => Vec::new(),
+    };
+
+    let total_loss_pass = profile.max_total_loss.is_none_or(|max| total_loss_db <= max);
+    let orl = measured_orl(sor);
+    let orl_pass = orl.is_none_or(|orl_db| profile.min_orl.is_none_or(|min| orl_db >= min));
+    let link_pass = total_loss_pass && orl_pass && sections.iter().all(|s| s.pass);
+    Evaluation {
+        pass: link_pass && events.iter().all(|e| e.pass),
+        events,
+        sections,
+        total_loss_db,
+        total_loss_pass,
+        orl_db: orl.unwrap_or(0.0),
+        orl_pass,
+        link_pass,
+    }
+}
+
+/// TOML keys are always strings, so wavelengths are parsed from them
+fn wavelength_map<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<i16, f64>, D::Error> {
+    BTreeMap::<String, f64>::deserialize(deserializer)?.into_iter()
+        .map(|(k, v)| k.parse().map(|k| (k, v)).map_err(|_| D::Error::custom(format!("invalid wavelength {:?}", k))))
+        .collect()
+}
+
+/// Event codes starting 1 are reflective, and 2 saturated reflective
+fn reflective(code: &str) -> bool {
+    code.starts_with('1') || code.starts_with('2')
+}
+
+fn end_of_fibre(code: &str) -> bool {
+    code.chars().nth(1) == Some('E')
+}
+
+#[test]
+fn test_evaluate() {
+    let data = include_bytes!("../../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let sor = crate::parser::parse_file(data).unwrap().1;
+    let profile: Profile = toml::from_str("max_splice_loss = 0.35\nmin_orl = 27.0\n[max_attenuation]\n1310 = 0.6\n").unwrap();
+    assert_eq!(profile.max_connector_loss, Profile::default().max_connector_loss);
+    assert_eq!(profile.max_attenuation.get(&1310), Some(&0.6));
+    let result = evaluate(&sor, &profile);
+    assert_eq!(result.events.len(), 9);
+    assert!(result.events.iter().all(|e| e.pass));
+    assert_eq!(result.sections.len(), 8);
+    assert!(result.link_pass);
+    assert!(result.pass);
+
+    // A stricter profile fails the 0.342dB splice at event 4, and the
+    // attenuation of the fibre leading into event 5
+    let mut strict = Profile { max_splice_loss: 0.3, ..profile };
+    strict.max_attenuation.insert(1310, 0.5);
+    let result = evaluate(&sor, &strict);
+    assert!(!result.events[3].pass);
+    assert_eq!(result.sections.iter().filter(|s| !s.pass).map(|s| s.to_event).collect::<Vec<_>>(), vec![5]);
+    assert!(!result.link_pass);
+    assert!(!result.pass);
+    // The file's ORL is 36.018dB
+    assert!(result.orl_pass);
+    assert!(!evaluate(&sor, &Profile { min_orl: Some(40.0), ..Profile::default() }).orl_pass);
+    // An ORL of zero wasn't measured, so can't fail
+    let mut unmeasured = sor.clone();
+    unmeasured.key_events.as_mut().unwrap().last_key_event.optical_return_loss = 0;
+    let result = evaluate(&unmeasured, &Profile { min_orl: Some(40.0), ..Profile::default() });
+    assert_eq!(result.orl_db, 0.0);
+    assert!(result.orl_pass);
+    // Unknown limits are rejected rather than ignored
+    assert!(toml::from_str::<Profile>("max_splice = 0.1").is_err());
+}
@@ -0,0 +1,137 @@
+/// This module checks the end-to-end loss and ORL stored with the last key
+/// event against the same figures recomputed from the trace between their
+/// markers, to catch instrument firmware - or re-analysis software - which
+/// writes aggregates that don't match the rest of the file.
+use crate::analysis::{fit_line, Fit, FitWindows, Trace};
+use crate::types::SORFile;
+
+/// How far stored aggregates may be from those recomputed, in dB. Vendors
+/// measure in slightly different ways, so these are loose
+#[derive(Debug, PartialEq, Clone)]
+pub struct AggregateTolerances {
+    pub loss_db: f64,
+    pub orl_db: f64,
+}
+
+impl Default for AggregateTolerances {
+    fn default() -> Self {
+        AggregateTolerances { loss_db: 0.5, orl_db: 2.0 }
+    }
+}
+
+/// A stored aggregate and what the trace says it should be, in dB
+#[derive(Debug, PartialEq, Clone)]
+pub struct Aggregate {
+    pub stored_db: f64,
+    /// None if the span between the markers is too short to measure
+    pub recomputed_db: Option<f64>,
+    /// Whether the two agree within the tolerance, or None if the value
+    /// wasn't stored or couldn't be recomputed
+    pub consistent: Option<bool>,
+}
+
+/// The outcome of checking a file's aggregates
+#[derive(Debug, PartialEq, Clone)]
+pub struct AggregateCheck {
+    pub end_to_end_loss: Aggregate,
+    pub optical_return_loss: Aggregate,
+}
+
+/// Recompute the end-to-end loss and ORL between their markers and compare
+/// them with those stored.
+///
+/// The loss is the difference between the backscatter level at each marker,
+/// from lines fitted to the fibre after the first and before the second.
+/// Vendors differ over whether the ORL includes a reflection at the second
+/// marker, typically the end of the fibre, so it is computed both ways and
+/// the stored value is consistent if it is near either.
+pub fn check_aggregates(sor: &SORFile, tolerances: &AggregateTolerances) -> Result<AggregateCheck, &'static str> {
+    let lke = &sor.key_events.as_ref().ok_or("File has no key events")?.last_key_event;
+    let trace = Trace::new(sor)?;
+    let windows = FitWindows::for_trace(&trace);
+    let (first, last) = (trace.distance_m[0], trace.distance_m[trace.distance_m.len() - 1]);
+
+    let from_m = trace.event_distance_m(lke.end_to_end_marker_position_1).max(first);
+    let to_m = trace.event_distance_m(lke.end_to_end_marker_position_2).min(last);
+    let loss_db = if to_m - from_m >= 2.0 * (windows.length_m + windows.gap_m) {
+        let start = fit(&trace, from_m + windows.gap_m, from_m + windows.gap_m + windows.length_m);
+        let end = fit(&trace, to_m - windows.gap_m - windows.length_m, to_m - windows.gap_m);
+        start.zip(end).map(|(start, end)| start.at(from_m) - end.at(to_m))
+    } else {
+        None
+    };
+    let stored_loss_db = lke.end_to_end_loss as f64 / 1000.0;
+    let end_to_end_loss = Aggregate {
+        stored_db: stored_loss_db,
+        recomputed_db: loss_db,
+        consistent: loss_db.map(|loss| (loss - stored_loss_db).abs() <= tolerances.loss_db),
+    };
+
+    let from_m = trace.event_distance_m(lke.optical_return_loss_marker_position_1).max(first);
+    let to_m = trace.event_distance_m(lke.optical_return_loss_marker_position_2).min(last);
+    let including = trace.optical_return_loss(from_m, (to_m + windows.gap_m).min(last)).ok();
+    let excluding = trace.optical_return_loss(from_m, to_m).ok();
+    let stored_orl_db = lke.optical_return_loss as f64 / 1000.0;
+    let near = |orl: Option<f64>| orl.is_some_and(|orl| (orl - stored_orl_db).abs() <= tolerances.orl_db);
+    let optical_return_loss = Aggregate {
+        stored_db: stored_orl_db,
+        // Report whichever convention the stored value follows
+        recomputed_db: if near(excluding) && !near(including) { excluding } else { including.or(excluding) },
+        consistent: match (including, excluding) {
+            _ if lke.optical_return_loss == 0 => None,
+            (None, None) => None,
+            _ => Some(near(including) || near(excluding)),
+        },
+    };
+    Ok(AggregateCheck { end_to_end_loss, optical_return_loss })
+}
+
+fn fit(trace: &Trace, from_m: f64, to_m: f64) -> Option<Fit> {
+    let (x, y) = (&trace.distance_m, &trace.points_db);
+    let start = x.partition_point(|&x| x < from_m);
+    let end = x.partition_point(|&x| x <= to_m);
+    (end >= start + 2).then(|| fit_line(&x[start..end], &y[start..end]))
+}
+
+impl SORFile {
+    /// Replace stored aggregates which are inconsistent with the trace by
+    /// their recomputed values, returning the check made beforehand
+    pub fn correct_aggregates(&mut self, tolerances: &AggregateTolerances) -> Result<AggregateCheck, &'static str> {
+        let check = check_aggregates(self, tolerances)?;
+        let lke = &mut self.key_events.as_mut().ok_or("File has no key events")?.last_key_event;
+        if let (Some(false), Some(loss_db)) = (check.end_to_end_loss.consistent, check.end_to_end_loss.recomputed_db) {
+            lke.end_to_end_loss = (loss_db * 1000.0).round() as i32;
+        }
+        if let (Some(false), Some(orl_db)) = (check.optical_return_loss.consistent, check.optical_return_loss.recomputed_db) {
+            lke.optical_return_loss = (orl_db * 1000.0).round().clamp(0.0, u16::MAX as f64) as u16;
+        }
+        Ok(check)
+    }
+}
+
+#[test]
+fn test_check_aggregates() {
+    let parse = |data: &[u8]| crate::parser::parse_file(data).unwrap().1;
+    let tolerances = AggregateTolerances::default();
+    let check = check_aggregates(&parse(include_bytes!("../../data/example2-exfo-maxtester730c.sor")), &tolerances).unwrap();
+    assert_eq!(check.end_to_end_loss.consistent, Some(true));
+    assert!((check.end_to_end_loss.recomputed_db.unwrap() - 1.912).abs() < 0.05);
+    assert_eq!(check.optical_return_loss.consistent, Some(true));
+    // EXFO's FTB leaves the end reflection out of the ORL
+    let check = check_aggregates(&parse(include_bytes!("../../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor")), &tolerances).unwrap();
+    assert!((check.optical_return_loss.recomputed_db.unwrap() - 36.018).abs() < 0.5);
+    // Anritsu doesn't store an ORL
+    let check = check_aggregates(&parse(include_bytes!("../../data/example3-anritsu-accessmastermt9085.sor")), &tolerances).unwrap();
+    assert_eq!((check.end_to_end_loss.consistent, check.optical_return_loss.consistent), (Some(true), None));
+
+    // The OFL280 stores an ORL of 24.5dB, where FastReporter recomputes the
+    // 17.8dB we do
+    let mut sor = parse(include_bytes!("../../data/example1-noyes-ofl280.sor"));
+    let check = sor.correct_aggregates(&tolerances).unwrap();
+    assert_eq!(check.optical_return_loss.consistent, Some(false));
+    assert_eq!(check.end_to_end_loss.consistent, Some(true));
+    let lke = &sor.key_events.as_ref().unwrap().last_key_event;
+    assert!((lke.optical_return_loss as f64 / 1000.0 - 17.84).abs() < 0.1);
+    assert_eq!(lke.end_to_end_loss, 576);
+    assert_eq!(check_aggregates(&sor, &tolerances).unwrap().optical_return_loss.consistent, Some(true));
+}
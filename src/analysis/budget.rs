@@ -0,0 +1,112 @@
+/// This module checks whether a tested link will carry a given class of
+/// optics, by setting the loss measured on it against the transceivers'
+/// power budget.
+use crate::analysis::measured_orl;
+use crate::types::SORFile;
+
+/// The optical specification of a pair of transceivers, as given on their
+/// datasheets. Powers are in dBm.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Optics {
+    /// Minimum launch power of the transmitter
+    pub tx_power_dbm: f64,
+    /// Receiver sensitivity, the weakest signal it can receive
+    pub rx_sensitivity_dbm: f64,
+    /// Receiver overload, the strongest signal it can receive, if specified
+    pub rx_overload_dbm: Option<f64>,
+    /// Minimum ORL the transmitter tolerates in dB, if specified
+    pub min_orl_db: Option<f64>,
+}
+
+/// A link's loss set against the power budget of some optics
+#[derive(Debug, PartialEq, Clone)]
+pub struct LinkBudget {
+    /// Power budget of the optics, in dB
+    pub budget_db: f64,
+    /// Loss of the link in dB
+    pub loss_db: f64,
+    /// The part of the loss due to key events, in dB
+    pub event_loss_db: f64,
+    /// The key event with the greatest loss, and its loss in dB
+    pub worst_event: Option<(i16, f64)>,
+    /// Power reaching the receiver, in dBm
+    pub rx_power_dbm: f64,
+    /// Budget left over after the link's loss, in dB
+    pub margin_db: f64,
+    /// True if the receiver would be overloaded, so the link needs an
+    /// attenuator
+    pub overload: bool,
+    /// Optical return loss of the link in dB, or zero if not measured
+    pub orl_db: f64,
+    /// True if the ORL is within the optics' tolerance, or either is unknown
+    pub orl_pass: bool,
+    /// True if the margin is at least the reserve asked for, the receiver
+    /// isn't overloaded and the ORL is acceptable
+    pub pass: bool,
+}
+
+/// Work out a link's budget for some optics, keeping reserve_db of margin
+/// in hand for repairs and ageing.
+///
+/// The link's loss is the end-to-end loss the file records or, failing
+/// that, the sum of its key events' losses. Gainers count at their measured
+/// (negative) loss, so a link with gainers should be judged on a
+/// bidirectional average.
+pub fn link_budget(sor: &SORFile, optics: &Optics, reserve_db: f64) -> Result<LinkBudget, &'static str> {
+    let ke = sor.key_events.as_ref().ok_or("File has no key events")?;
+    let lke = &ke.last_key_event;
+    let event_loss_db = ke.key_events.iter().map(|e| e.event_loss as f64 / 1000.0).sum();
+    let worst_event = ke.key_events.iter()
+        .max_by_key(|e| e.event_loss)
+        .map(|e| (e.event_number, e.event_loss as f64 / 1000.0));
+    let loss_db = if lke.end_to_end_loss != 0 { lke.end_to_end_loss as f64 / 1000.0 } else { event_loss_db };
+
+    let budget_db = optics.tx_power_dbm - optics.rx_sensitivity_dbm;
+    let rx_power_dbm = optics.tx_power_dbm - loss_db;
+    let margin_db = rx_power_dbm - optics.rx_sensitivity_dbm;
+    let overload = optics.rx_overload_dbm.is_some_and(|max| rx_power_dbm > max);
+    let orl = measured_orl(sor);
+    let orl_pass = orl.is_none_or(|orl_db| optics.min_orl_db.is_none_or(|min| orl_db >= min));
+    Ok(LinkBudget {
+        budget_db,
+        loss_db,
+        event_loss_db,
+        worst_event,
+        rx_power_dbm,
+        margin_db,
+        overload,
+        orl_db: orl.unwrap_or(0.0),
+        orl_pass,
+        pass: margin_db >= reserve_db && !overload && orl_pass,
+    })
+}
+
+#[test]
+fn test_link_budget() {
+    let data = include_bytes!("../../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let mut sor = crate::parser::parse_file(data).unwrap().1;
+    // Typical 10km LR optics
+    let optics = Optics { tx_power_dbm: -8.2, rx_sensitivity_dbm: -14.4, rx_overload_dbm: Some(0.5), min_orl_db: Some(12.0) };
+    let budget = link_budget(&sor, &optics, 3.0).unwrap();
+    assert!((budget.budget_db - 6.2).abs() < 1e-9);
+    assert_eq!(budget.loss_db, 2.224);
+    assert!((budget.margin_db - (6.2 - 2.224)).abs() < 1e-9);
+    assert_eq!(budget.worst_event, Some((8, 0.511)));
+    assert!(!budget.overload);
+    assert!(budget.orl_pass);
+    assert!(budget.pass);
+    // Not enough reserve left
+    assert!(!link_budget(&sor, &optics, 4.0).unwrap().pass);
+    // Too much power for the receiver
+    let hot = Optics { tx_power_dbm: 3.0, rx_overload_dbm: Some(-1.0), ..optics.clone() };
+    assert!(link_budget(&sor, &hot, 3.0).unwrap().overload);
+    // Without an end-to-end loss, the events' losses are summed
+    sor.key_events.as_mut().unwrap().last_key_event.end_to_end_loss = 0;
+    let budget = link_budget(&sor, &optics, 3.0).unwrap();
+    assert_eq!(budget.loss_db, budget.event_loss_db);
+    // An ORL of zero wasn't measured, so can't fail
+    sor.key_events.as_mut().unwrap().last_key_event.optical_return_loss = 0;
+    let budget = link_budget(&sor, &Optics { min_orl_db: Some(40.0), ..optics }, 3.0).unwrap();
+    assert_eq!(budget.orl_db, 0.0);
+    assert!(budget.orl_pass);
+}
@@ -0,0 +1,162 @@
+/// This module smooths a trace, to tame the noise on long-range
+/// acquisitions before detecting events in it or plotting it.
+///
+/// Windows are a number of points centred on each point, so should be odd;
+/// even windows are widened by one. Near the ends of the trace, where the
+/// full window doesn't fit, the widest window centred on the point that
+/// does fit is used instead, so the first and last points are left as they
+/// are.
+use crate::analysis::Trace;
+
+/// A filter to apply to a trace's points
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Filter {
+    /// The mean of the points in the window
+    MovingAverage { window: usize },
+    /// The median of the points in the window, which smooths noise while
+    /// keeping the edges of events sharp
+    Median { window: usize },
+    /// A polynomial of the given order fitted to the points in the window,
+    /// which keeps the height and shape of reflections better than a moving
+    /// average of the same width
+    SavitzkyGolay { window: usize, order: usize },
+}
+
+impl Trace {
+    /// A copy of the trace with the filter applied to its points
+    pub fn filter(&self, filter: &Filter) -> Result<Trace, &'static str> {
+        let points_db = match *filter {
+            Filter::MovingAverage { window } => moving_average(&self.points_db, half_width(window)?),
+            Filter::Median { window } => median(&self.points_db, half_width(window)?),
+            Filter::SavitzkyGolay { window, order } => {
+                let half = half_width(window)?;
+                if order > 2 * half {
+                    return Err("The polynomial's order must be less than the window");
+                }
+                savitzky_golay(&self.points_db, half, order)
+            }
+        };
+        Ok(Trace { points_db, ..self.clone() })
+    }
+}
+
+/// Points either side of the centre of a window
+fn half_width(window: usize) -> Result<usize, &'static str> {
+    if window == 0 {
+        return Err("The window must be at least one point");
+    }
+    Ok(window / 2)
+}
+
+/// The largest half-width up to half which fits around point i
+fn fit(i: usize, len: usize, half: usize) -> usize {
+    half.min(i).min(len - 1 - i)
+}
+
+fn moving_average(y: &[f64], half: usize) -> Vec<f64> {
+    let mut prefix = Vec::with_capacity(y.len() + 1);
+    prefix.push(0.0);
+    for v in y {
+        prefix.push(prefix[prefix.len() - 1] + v);
+    }
+    (0..y.len()).map(|i| {
+        let h = fit(i, y.len(), half);
+        (prefix[i + h + 1] - prefix[i - h]) / (2 * h + 1) as f64
+    }).collect()
+}
+
+fn median(y: &[f64], half: usize) -> Vec<f64> {
+    let mut window = Vec::with_capacity(2 * half + 1);
+    (0..y.len()).map(|i| {
+        let h = fit(i, y.len(), half);
+        window.clear();
+        window.extend_from_slice(&y[i - h..=i + h]);
+        window.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        window[h]
+    }).collect()
+}
+
+fn savitzky_golay(y: &[f64], half: usize, order: usize) -> Vec<f64> {
+    // Coefficients for each half-width used, the narrower ones only being
+    // needed near the ends
+    let coefficients: Vec<Vec<f64>> = (0..=half).map(|h| sg_coefficients(h, order.min(2 * h))).collect();
+    (0..y.len()).map(|i| {
+        let h = fit(i, y.len(), half);
+        coefficients[h].iter().zip(&y[i - h..=i + h]).map(|(c, v)| c * v).sum()
+    }).collect()
+}
+
+/// Weights giving the value at the centre of a least-squares polynomial fit
+/// to 2h+1 points: the first row of (JᵀJ)⁻¹Jᵀ, where J is the Vandermonde
+/// matrix of the offsets -h..=h
+fn sg_coefficients(h: usize, order: usize) -> Vec<f64> {
+    let n = order + 1;
+    let offsets: Vec<f64> = (-(h as i64)..=h as i64).map(|x| x as f64).collect();
+    // JᵀJ is symmetric, with entries the sums of powers of the offsets.
+    // Solving JᵀJ a = e₀ gives the first row of its inverse
+    let mut m: Vec<Vec<f64>> = (0..n).map(|r| {
+        let mut row: Vec<f64> = (0..n).map(|c| offsets.iter().map(|x| x.powi((r + c) as i32)).sum()).collect();
+        row.push(if r == 0 { 1.0 } else { 0.0 });
+        row
+    }).collect();
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&a, &b| m[a][col].abs().partial_cmp(&m[b][col].abs()).unwrap()).unwrap();
+        m.swap(col, pivot);
+        let pivot_row = m[col].clone();
+        for (r, row) in m.iter_mut().enumerate() {
+            if r != col {
+                let factor = row[col] / pivot_row[col];
+                for (v, p) in row[col..].iter_mut().zip(&pivot_row[col..]) {
+                    *v -= factor * p;
+                }
+            }
+        }
+    }
+    let a: Vec<f64> = (0..n).map(|r| m[r][n] / m[r][r]).collect();
+    offsets.iter().map(|x| a.iter().enumerate().map(|(k, a)| a * x.powi(k as i32)).sum()).collect()
+}
+
+#[test]
+fn test_filters() {
+    let data = include_bytes!("../../data/example1-noyes-ofl280.sor");
+    let sor = crate::parser::parse_file(data).unwrap().1;
+    let trace = Trace::new(&sor).unwrap();
+    // A straight line of fibre with white noise on it
+    let mut seed = 1u32;
+    let mut noise = || {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        (seed >> 16) as f64 / 65536.0 - 0.5
+    };
+    let points_db = trace.distance_m().iter().map(|x| -0.0003 * x + noise()).collect();
+    let noisy = Trace { points_db, ..trace.clone() };
+    let rms = |t: &Trace| {
+        let residuals = t.points_db().iter().zip(t.distance_m()).map(|(y, x)| (y + 0.0003 * x).powi(2));
+        (residuals.sum::<f64>() / t.points_db().len() as f64).sqrt()
+    };
+    for filter in &[Filter::MovingAverage { window: 15 }, Filter::Median { window: 15 }, Filter::SavitzkyGolay { window: 15, order: 2 }] {
+        let smoothed = noisy.filter(filter).unwrap();
+        assert_eq!(smoothed.distance_m(), noisy.distance_m());
+        assert!(rms(&smoothed) < rms(&noisy) / 2.0, "{:?}", filter);
+        // The ends are left alone
+        assert_eq!(smoothed.points_db()[0], noisy.points_db()[0]);
+    }
+    // Smoothed traces can be stored back in the file
+    let smoothed = noisy.filter(&Filter::MovingAverage { window: 15 }).unwrap();
+    let stored = Trace::new(&smoothed.to_sor(&sor).unwrap()).unwrap();
+    assert_eq!(stored.distance_m().len(), smoothed.distance_m().len());
+    assert!((stored.distance_m()[100] - smoothed.distance_m()[100]).abs() < 1e-3);
+    assert!((stored.points_db()[100] - smoothed.points_db()[100]).abs() <= 0.0005);
+    // A window of one point changes nothing
+    assert_eq!(trace.filter(&Filter::Median { window: 1 }).unwrap(), trace);
+    assert!(trace.filter(&Filter::SavitzkyGolay { window: 5, order: 5 }).is_err());
+
+    // Savitzky-Golay fits polynomials of its order exactly, and with order
+    // 0 is a moving average
+    let cubic: Vec<f64> = (0..20).map(|x| (x as f64).powi(3) - 4.0 * x as f64).collect();
+    for (a, b) in savitzky_golay(&cubic, 3, 3).iter().zip(&cubic) {
+        assert!((a - b).abs() < 1e-6);
+    }
+    for (a, b) in savitzky_golay(&cubic, 3, 0).iter().zip(moving_average(&cubic, 3)) {
+        assert!((a - b).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,157 @@
+/// This module is the stable face of otdrs: parsing, writing, validating
+/// and summarising files through functions whose signatures don't mention
+/// nom, or any other dependency, so that crates built on them aren't broken
+/// when the parser's internals change. The parser module's combinators
+/// remain public for those who need them, but may change in any release.
+///
+/// ```
+/// let data = include_bytes!("../data/example1-noyes-ofl280.sor");
+/// let sor = otdrs::api::parse(data).unwrap();
+/// let bytes = otdrs::api::write(&sor).unwrap();
+/// assert_eq!(otdrs::api::parse(&bytes).unwrap().fixed_parameters, sor.fixed_parameters);
+/// ```
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use crate::types::SORFile;
+#[cfg(feature = "std")]
+use crate::analysis::acceptance::Profile;
+#[cfg(feature = "std")]
+pub use crate::validate::{Finding, Severity};
+
+/// What went wrong
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The bytes aren't a SOR file otdrs can read
+    Parse,
+    /// The file can't be encoded, e.g. because its map is missing a block
+    Write,
+}
+
+/// The error from parsing or writing a file
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Error {
+    kind: ErrorKind,
+    offset: Option<usize>,
+    message: String,
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// How far into the input parsing failed, in bytes, if known
+    pub fn offset(&self) -> Option<usize> {
+        self.offset
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ErrorKind::Parse => write!(f, "Could not parse SOR file: {}", self.message),
+            ErrorKind::Write => write!(f, "Could not write SOR file: {}", self.message),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+/// Parse a whole SOR file, ignoring anything after its last block
+pub fn parse(bytes: &[u8]) -> Result<SORFile, Error> {
+    match crate::parser::parse_file(bytes) {
+        Ok((_, sor)) => Ok(sor),
+        // Rather than nom's debug output, which includes the whole of the
+        // remaining input, say where and in which parser it failed
+        Err(nom::Err::Error(err)) | Err(nom::Err::Failure(err)) => {
+            let offset = bytes.len() - err.input.len();
+            Err(Error { kind: ErrorKind::Parse, offset: Some(offset), message: format!("{:?} failed at byte {}", err.code, offset) })
+        }
+        Err(nom::Err::Incomplete(_)) => {
+            Err(Error { kind: ErrorKind::Parse, offset: None, message: "unexpected end of file".to_string() })
+        }
+    }
+}
+
+/// Encode a file, with its map and checksum rebuilt to match
+pub fn write(sor: &SORFile) -> Result<Vec<u8>, Error> {
+    sor.to_bytes().map_err(|message| Error { kind: ErrorKind::Write, offset: None, message: message.to_string() })
+}
+
+/// Check that the file makes sense as a measurement, most severe findings
+/// first; see `otdrs::validate`
+#[cfg(feature = "std")]
+pub fn validate(sor: &SORFile) -> Vec<Finding> {
+    sor.validate()
+}
+
+/// A file's identity and headline results
+#[cfg(feature = "std")]
+#[derive(Debug, PartialEq, Clone, serde::Serialize)]
+#[non_exhaustive]
+pub struct Summary {
+    pub cable_id: String,
+    pub fiber_id: String,
+    pub wavelength_nm: i16,
+    /// Acquisition time as an ISO-8601 timestamp
+    pub date: String,
+    pub supplier: Option<String>,
+    pub mainframe_sn: Option<String>,
+    /// Distance to the last event, in metres
+    pub length_m: f64,
+    pub total_loss_db: f64,
+    pub orl_db: f64,
+    pub events: usize,
+    /// Theoretical spatial resolution and event dead zone of the first
+    /// pulse width used, in metres; see `otdrs::analysis::Resolution`
+    pub spatial_resolution_m: Option<f64>,
+    pub event_dead_zone_m: Option<f64>,
+}
+
+/// Summarise a file, as the service's `/summary` endpoint does
+#[cfg(feature = "std")]
+pub fn summary(sor: &SORFile) -> Summary {
+    let report = crate::report::build("", sor, &Profile::default());
+    let sp = sor.supplier_parameters.as_ref();
+    let resolution = crate::analysis::resolutions(sor).ok().and_then(|r| r.into_iter().next());
+    Summary {
+        cable_id: report.cable_id,
+        fiber_id: report.fiber_id,
+        wavelength_nm: report.wavelength,
+        date: report.date,
+        supplier: sp.map(|sp| sp.supplier_name.trim().to_owned()),
+        mainframe_sn: sp.map(|sp| sp.otdr_mainframe_sn.trim().to_owned()),
+        length_m: report.length_m,
+        total_loss_db: report.total_loss_db,
+        orl_db: report.orl_db,
+        events: report.events.len(),
+        spatial_resolution_m: resolution.as_ref().map(|r| r.spatial_resolution_m),
+        event_dead_zone_m: resolution.as_ref().map(|r| r.event_dead_zone_m),
+    }
+}
+
+#[test]
+fn test_api() {
+    let data = include_bytes!("../data/example2-exfo-maxtester730c.sor");
+    let sor = parse(data).unwrap();
+    assert_eq!(parse(&write(&sor).unwrap()).unwrap().key_events, sor.key_events);
+
+    let err = parse(&data[..100]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Parse);
+    assert!(err.to_string().starts_with("Could not parse SOR file: "));
+    let mut broken = sor.clone();
+    broken.map.block_info.clear();
+    assert_eq!(write(&broken).unwrap_err().kind(), ErrorKind::Write);
+
+    #[cfg(feature = "std")]
+    {
+        assert!(validate(&sor).is_empty());
+        let summary = summary(&sor);
+        assert_eq!((summary.wavelength_nm, summary.events), (1310, 6));
+        assert!(summary.event_dead_zone_m.unwrap() > summary.spatial_resolution_m.unwrap());
+    }
+}
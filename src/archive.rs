@@ -0,0 +1,103 @@
+/// This module reads SOR files straight out of ZIP archives, as exported in
+/// bulk by several OTDR mainframes, without extracting them first. It is only
+/// available with the `zip` feature.
+///
+/// A file inside an archive is addressed by a path through it, e.g.
+/// `bundle.zip/site-a/fibre1.sor`, which the batch functions and the command
+/// line accept wherever they take a path to a SOR file.
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use zip::result::{ZipError, ZipResult};
+use zip::ZipArchive;
+use crate::types::SORFile;
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case(extension))
+}
+
+/// True if a path is a ZIP archive, judged by its extension
+pub fn is_archive(path: &Path) -> bool {
+    has_extension(path, "zip") && path.is_file()
+}
+
+/// Split a path through an archive, e.g. `bundle.zip/site-a/fibre1.sor`, into
+/// the archive's path and the name of the entry within it
+pub fn split(path: &Path) -> Option<(&Path, String)> {
+    let archive = path.ancestors().skip(1).find(|ancestor| is_archive(ancestor))?;
+    let entry: Vec<_> = path.strip_prefix(archive).ok()?.iter().map(|c| c.to_string_lossy()).collect();
+    Some((archive, entry.join("/")))
+}
+
+/// Paths through an archive to each of the SOR files within it, in the
+/// order they were stored
+pub fn sor_entries(archive: &Path) -> ZipResult<Vec<PathBuf>> {
+    let zip = ZipArchive::new(File::open(archive)?)?;
+    Ok(zip.file_names()
+        .filter(|name| !name.ends_with('/') && has_extension(Path::new(name), "sor"))
+        .map(|name| archive.join(name))
+        .collect())
+}
+
+/// Read a file inside an archive, given the path through it
+pub fn read(path: &Path) -> ZipResult<Vec<u8>> {
+    let (archive, name) = split(path).ok_or(ZipError::FileNotFound)?;
+    let mut zip = ZipArchive::new(File::open(archive)?)?;
+    let mut entry = zip.by_name(&name)?;
+    let mut data = Vec::with_capacity(entry.size() as usize);
+    entry.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Parse each SOR file in an archive, giving each entry's name with its
+/// result, so that one bad file doesn't stop the rest being read
+pub fn parse_entries(archive: &Path) -> ZipResult<Vec<(String, Result<SORFile, &'static str>)>> {
+    let mut zip = ZipArchive::new(File::open(archive)?)?;
+    let mut results = Vec::new();
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i)?;
+        if entry.is_dir() || !has_extension(Path::new(entry.name()), "sor") {
+            continue;
+        }
+        let mut data = Vec::with_capacity(entry.size() as usize);
+        let result = match entry.read_to_end(&mut data) {
+            Ok(_) => crate::parser::parse_file(&data).map(|(_, sor)| sor).map_err(|_| "Could not parse SOR file"),
+            Err(_) => Err("Could not read entry from archive"),
+        };
+        results.push((entry.name().to_owned(), result));
+    }
+    Ok(results)
+}
+
+#[test]
+fn test_archive() {
+    use std::io::Write;
+    let dir = std::env::temp_dir().join(format!("otdrs-archive-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let archive = dir.join("bundle.zip");
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let mut zip = zip::ZipWriter::new(File::create(&archive).unwrap());
+    let options = zip::write::SimpleFileOptions::default();
+    zip.add_directory("site-a/", options).unwrap();
+    zip.start_file("site-a/fibre1.SOR", options).unwrap();
+    zip.write_all(data).unwrap();
+    zip.start_file("readme.txt", options).unwrap();
+    zip.write_all(b"not a trace").unwrap();
+    zip.start_file("broken.sor", options).unwrap();
+    zip.write_all(b"not a trace either").unwrap();
+    zip.finish().unwrap();
+
+    assert!(is_archive(&archive));
+    let entries = sor_entries(&archive).unwrap();
+    assert_eq!(entries, vec![archive.join("site-a/fibre1.SOR"), archive.join("broken.sor")]);
+    assert_eq!(split(&entries[0]), Some((archive.as_path(), "site-a/fibre1.SOR".to_owned())));
+    assert_eq!(split(Path::new("data/example1-noyes-ofl280.sor")), None);
+    assert_eq!(read(&entries[0]).unwrap(), data);
+    assert!(read(&archive.join("missing.sor")).is_err());
+
+    let parsed = parse_entries(&archive).unwrap();
+    assert_eq!(parsed.len(), 2);
+    assert_eq!(&*parsed[0].1.as_ref().unwrap().general_parameters.as_ref().unwrap().fiber_id, "Fiber1");
+    assert_eq!(parsed[1], ("broken.sor".to_owned(), Err("Could not parse SOR file")));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,116 @@
+/// This module converts a SORFile to Apache Arrow record batches, for
+/// handing to polars, pandas, DataFusion and the like without going through
+/// JSON. It is only available with the `arrow` feature.
+///
+/// Values are in engineering units: distances in metres from the user
+/// offset, levels and losses in dB, and the acquisition time as a UTC
+/// timestamp. Metadata from missing blocks is null.
+use std::sync::Arc;
+use arrow_array::{ArrayRef, Float64Array, Int16Array, Int32Array, RecordBatch, StringArray, TimestampSecondArray};
+use arrow_schema::ArrowError;
+use crate::analysis::{events, measured_orl, metres_per_100ps, Trace};
+use crate::types::SORFile;
+
+/// A file as three record batches
+#[derive(Debug, PartialEq, Clone)]
+pub struct RecordBatches {
+    /// One row describing the file and its acquisition
+    pub metadata: RecordBatch,
+    /// One row per key event
+    pub events: RecordBatch,
+    /// One row per data point; empty if the file has no data points
+    pub samples: RecordBatch,
+}
+
+fn text<'a>(values: impl IntoIterator<Item = &'a str>) -> ArrayRef {
+    Arc::new(StringArray::from_iter_values(values.into_iter().map(str::trim)))
+}
+
+/// A single, possibly null, string
+fn single_text(value: Option<&str>) -> ArrayRef {
+    Arc::new(StringArray::from(vec![value.map(str::trim)]))
+}
+
+impl SORFile {
+    /// Convert the file's metadata, key events and data points to Arrow
+    /// record batches
+    pub fn to_record_batches(&self) -> Result<RecordBatches, ArrowError> {
+        let gp = self.general_parameters.as_ref();
+        let sp = self.supplier_parameters.as_ref();
+        let fp = self.fixed_parameters.as_ref();
+        let lke = self.key_events.as_ref().map(|ke| &ke.last_key_event);
+        let m_per_100ps = metres_per_100ps(self);
+        let metadata = RecordBatch::try_from_iter(vec![
+            ("cable_id", single_text(gp.map(|gp| &*gp.cable_id))),
+            ("fiber_id", single_text(gp.map(|gp| &*gp.fiber_id))),
+            ("originating_location", single_text(gp.map(|gp| &*gp.originating_location))),
+            ("terminating_location", single_text(gp.map(|gp| &*gp.terminating_location))),
+            ("wavelength_nm", Arc::new(Int16Array::from(vec![gp.map(|gp| gp.nominal_wavelength)])) as ArrayRef),
+            ("supplier", single_text(sp.map(|sp| &*sp.supplier_name))),
+            ("mainframe_sn", single_text(sp.map(|sp| &*sp.otdr_mainframe_sn))),
+            ("acquired", Arc::new(TimestampSecondArray::from(vec![fp.map(|fp| fp.date_time_stamp as i64)]).with_timezone("UTC"))),
+            ("pulse_width_ns", Arc::new(Int16Array::from(vec![fp.and_then(|fp| fp.pulse_widths_used.first().copied())]))),
+            ("group_index", Arc::new(Float64Array::from(vec![fp.map(|fp| fp.group_index as f64 / 100000.0)]))),
+            ("length_m", Arc::new(Float64Array::from(vec![lke.map(|lke| lke.event_propogation_time as f64 * m_per_100ps)]))),
+            ("total_loss_db", Arc::new(Float64Array::from(vec![lke.map(|lke| lke.end_to_end_loss as f64 / 1000.0)]))),
+            ("orl_db", Arc::new(Float64Array::from(vec![measured_orl(self)]))),
+        ])?;
+
+        let events = events(self);
+        let comments: Vec<&str> = self.key_events.iter()
+            .flat_map(|ke| ke.key_events.iter().map(|e| &*e.comment).chain(std::iter::once(&*ke.last_key_event.comment)))
+            .collect();
+        let events = RecordBatch::try_from_iter(vec![
+            ("event_number", Arc::new(Int16Array::from_iter_values(events.iter().map(|e| e.number))) as ArrayRef),
+            ("distance_m", Arc::new(Float64Array::from_iter_values(events.iter().map(|e| e.distance_m)))),
+            ("loss_db", Arc::new(Float64Array::from_iter_values(events.iter().map(|e| e.loss_db)))),
+            ("reflectance_db", Arc::new(Float64Array::from_iter_values(events.iter().map(|e| e.reflectance_db)))),
+            ("code", text(events.iter().map(|e| e.code.as_str()))),
+            ("comment", text(comments)),
+        ])?;
+
+        let (distance_m, level_db) = match Trace::new(self) {
+            Ok(trace) => (trace.distance_m().iter().map(|d| d - trace.user_offset_m()).collect(), trace.points_db().to_vec()),
+            Err(_) => (Vec::new(), Vec::new()),
+        };
+        let samples = RecordBatch::try_from_iter(vec![
+            ("index", Arc::new(Int32Array::from_iter_values(0..distance_m.len() as i32)) as ArrayRef),
+            ("distance_m", Arc::new(Float64Array::from(distance_m))),
+            ("level_db", Arc::new(Float64Array::from(level_db))),
+        ])?;
+        Ok(RecordBatches { metadata, events, samples })
+    }
+}
+
+#[test]
+fn test_to_record_batches() {
+    use arrow_array::Array;
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let sor = crate::parser::parse_file(data).unwrap().1;
+    let batches = sor.to_record_batches().unwrap();
+    assert_eq!(batches.metadata.num_rows(), 1);
+    let fiber_id = batches.metadata.column_by_name("fiber_id").unwrap().as_any().downcast_ref::<StringArray>().unwrap();
+    assert_eq!(fiber_id.value(0), "Fiber1");
+    let wavelength = batches.metadata.column_by_name("wavelength_nm").unwrap().as_any().downcast_ref::<Int16Array>().unwrap();
+    assert_eq!(wavelength.value(0), 1310);
+
+    assert_eq!(batches.events.num_rows(), events(&sor).len());
+    let loss = batches.events.column_by_name("loss_db").unwrap().as_any().downcast_ref::<Float64Array>().unwrap();
+    assert_eq!(loss.value(1), -0.336);
+    let n = Trace::new(&sor).unwrap().points_db().len();
+    assert_eq!(batches.samples.num_rows(), n);
+
+    // Without data points or key events, the batches are empty
+    let metadata = crate::parser::parse_metadata(include_bytes!("../data/example1-noyes-ofl280.sor")).unwrap().1;
+    let batches = metadata.to_record_batches().unwrap();
+    assert_eq!(batches.samples.num_rows(), 0);
+    assert_eq!(batches.samples.num_columns(), 3);
+    let mut empty = metadata;
+    empty.key_events = None;
+    empty.fixed_parameters = None;
+    empty.general_parameters = None;
+    let batches = empty.to_record_batches().unwrap();
+    assert_eq!(batches.events.num_rows(), 0);
+    assert!(batches.metadata.column_by_name("length_m").unwrap().is_null(0));
+    assert!(batches.metadata.column_by_name("cable_id").unwrap().is_null(0));
+}
@@ -0,0 +1,255 @@
+/// This module works on many SOR files at once: converting them to other
+/// formats across a thread pool, and extracting a measurement from many files
+/// of the same fibre, such as an archive of periodic tests, as a time series
+/// for trending the fibre's degradation.
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use rayon::prelude::*;
+use crate::progress::{Cancelled, Hooks};
+use crate::analysis::events;
+pub use crate::format::Format;
+use crate::format::{FormatOptions, OutputFormat};
+use crate::types::SORFile;
+
+/// What to measure in each file
+#[derive(Debug, PartialEq, Clone)]
+pub enum Metric {
+    /// End-to-end loss in dB
+    TotalLoss,
+    /// Optical return loss in dB
+    Orl,
+    /// Loss in dB of the event nearest a distance in metres from the user
+    /// offset, if there is one within the tolerance. Events are found by
+    /// distance rather than number since numbers change as events appear
+    EventLoss { distance_m: f64, tolerance_m: f64 },
+}
+
+impl Metric {
+    /// The metric's value in a file, if it has one
+    pub fn measure(&self, sor: &SORFile) -> Option<f64> {
+        let lke = &sor.key_events.as_ref()?.last_key_event;
+        match self {
+            Metric::TotalLoss => Some(lke.end_to_end_loss as f64 / 1000.0),
+            Metric::Orl => crate::analysis::measured_orl(sor),
+            Metric::EventLoss { distance_m, tolerance_m } => events(sor).into_iter()
+                .map(|e| ((e.distance_m - distance_m).abs(), e.loss_db))
+                .filter(|&(error, _)| error <= *tolerance_m)
+                .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
+                .map(|(_, loss)| loss),
+        }
+    }
+}
+
+/// A metric's value in one file
+#[derive(Debug, PartialEq, Clone)]
+pub struct Point {
+    pub path: PathBuf,
+    /// Acquisition time, in seconds since the Unix epoch
+    pub timestamp: u32,
+    pub value: f64,
+}
+
+/// A metric across many files, in order of acquisition time
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct TimeSeries {
+    pub points: Vec<Point>,
+    /// Files which couldn't be read or parsed, or which lack the metric,
+    /// with the reason
+    pub skipped: Vec<(PathBuf, String)>,
+}
+
+/// Read a file, or with the object_store feature, an object given by URL, or
+/// with the zip feature, a file inside an archive
+pub(crate) fn read(path: &Path) -> Result<Vec<u8>, String> {
+    #[cfg(feature = "zip")]
+    {
+        if crate::archive::split(path).is_some() {
+            return crate::archive::read(path).map_err(|err| err.to_string());
+        }
+    }
+    #[cfg(feature = "object_store")]
+    {
+        if let Some(url) = path.to_str().filter(|path| crate::store::is_url(path)) {
+            return crate::store::read(url).map_err(|err| err.to_string());
+        }
+    }
+    std::fs::read(path).map_err(|err| err.to_string())
+}
+
+/// Measure a metric in each of many files, which may be object store URLs
+/// with the object_store feature, or paths through ZIP archives, e.g.
+/// `bundle.zip/fibre1.sor`, with the zip feature. Only the files' metadata
+/// is parsed, so this is quick even for large archives; files which can't be
+/// measured are skipped rather than failing the whole series.
+pub fn timeseries<P: AsRef<Path>>(paths: &[P], metric: &Metric) -> TimeSeries {
+    match timeseries_with(paths, metric, &Hooks::default()) {
+        Ok(series) => series,
+        Err(Cancelled) => unreachable!("Nothing can cancel the default hooks"),
+    }
+}
+
+/// Measure a metric as `timeseries` does, reporting progress and stopping if
+/// cancelled
+pub fn timeseries_with<P: AsRef<Path>>(paths: &[P], metric: &Metric, hooks: &Hooks) -> Result<TimeSeries, Cancelled> {
+    let mut series = TimeSeries::default();
+    for (i, path) in paths.iter().enumerate() {
+        if hooks.is_cancelled() {
+            return Err(Cancelled);
+        }
+        let path = path.as_ref();
+        series.add(path, read(path), metric);
+        hooks.progress(i + 1, paths.len());
+    }
+    series.points.sort_by_key(|p| p.timestamp);
+    Ok(series)
+}
+
+impl TimeSeries {
+    /// Measure a file that has been read, or note why it was skipped
+    pub(crate) fn add(&mut self, path: &Path, data: Result<Vec<u8>, String>, metric: &Metric) {
+        let sor = match data {
+            Ok(data) => match crate::parser::parse_metadata(&data) {
+                Ok((_, sor)) => sor,
+                Err(_) => return self.skipped.push((path.to_owned(), "Could not parse SOR file".to_owned())),
+            },
+            Err(err) => return self.skipped.push((path.to_owned(), err)),
+        };
+        let timestamp = sor.fixed_parameters.as_ref().map(|fp| fp.date_time_stamp);
+        match (timestamp, metric.measure(&sor)) {
+            (Some(timestamp), Some(value)) => self.points.push(Point { path: path.to_owned(), timestamp, value }),
+            (None, _) => self.skipped.push((path.to_owned(), "File has no fixed parameters block".to_owned())),
+            (_, None) => self.skipped.push((path.to_owned(), "File does not have this metric".to_owned())),
+        }
+    }
+}
+
+/// How to convert files
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ConvertOptions {
+    pub format: Format,
+    /// Give values in dB, metres, seconds and ISO-8601 timestamps rather than
+    /// raw SR-4731 encodings. Not available for the interchange format
+    pub engineering_units: bool,
+    /// Indent JSON and XML
+    pub pretty: bool,
+    /// Sort JSON object keys
+    pub canonical: bool,
+    /// Number of threads to convert with; by default, one per CPU
+    pub jobs: Option<usize>,
+}
+
+/// The outcome of converting one file
+#[derive(Debug, PartialEq, Clone)]
+pub struct Conversion {
+    pub path: PathBuf,
+    /// The converted file, or why it couldn't be converted
+    pub result: Result<Vec<u8>, String>,
+}
+
+fn convert_one(path: &Path, format: &dyn OutputFormat, options: &FormatOptions) -> Result<Vec<u8>, String> {
+    let data = read(path)?;
+    let sor = crate::parser::parse_file(&data).map(|(_, sor)| sor).map_err(|_| "Could not parse SOR file".to_owned())?;
+    format.serialize(&sor, options)
+}
+
+/// Read, parse and convert many files across a thread pool, which may be
+/// object store URLs or paths through ZIP archives as for `timeseries`. A
+/// file which can't be converted doesn't stop the rest; each result is
+/// returned, in the order of the inputs.
+///
+/// Progress is reported from the pool's threads as each file finishes. If
+/// cancelled, files already being converted are finished but no more are
+/// started.
+pub fn convert<P>(inputs: &[P], options: &ConvertOptions, hooks: &Hooks) -> Result<Vec<Conversion>, Cancelled>
+where P: AsRef<Path> + Sync {
+    convert_to(inputs, &options.format, options, hooks)
+}
+
+/// Convert many files as `convert` does, to any format, such as one looked
+/// up with `otdrs::format::lookup`. The format in the options is ignored
+pub fn convert_to<P>(inputs: &[P], format: &dyn OutputFormat, options: &ConvertOptions, hooks: &Hooks)
+    -> Result<Vec<Conversion>, Cancelled>
+where P: AsRef<Path> + Sync {
+    let format_options = FormatOptions {
+        engineering_units: options.engineering_units,
+        pretty: options.pretty,
+        canonical: options.canonical,
+    };
+    let done = AtomicUsize::new(0);
+    let run = || inputs.par_iter().map(|path| {
+        if hooks.is_cancelled() {
+            return None;
+        }
+        let path = path.as_ref();
+        let result = convert_one(path, format, &format_options);
+        hooks.progress(done.fetch_add(1, Ordering::Relaxed) + 1, inputs.len());
+        Some(Conversion { path: path.to_owned(), result })
+    }).collect::<Option<Vec<_>>>();
+    let converted = match options.jobs.map(|jobs| rayon::ThreadPoolBuilder::new().num_threads(jobs).build()) {
+        Some(Ok(pool)) => pool.install(run),
+        // The global pool is always there to fall back on
+        _ => run(),
+    };
+    converted.ok_or(Cancelled)
+}
+
+#[test]
+fn test_timeseries() {
+    let paths = ["data/example4-exfo-ftb4ftbx730c-mfdgainer-1550nm.sor", "data/example1-noyes-ofl280.sor",
+                 "data/missing.sor", "Cargo.toml"];
+    let series = timeseries(&paths, &Metric::TotalLoss);
+    assert_eq!(series.points.len(), 2);
+    assert!(series.points[0].timestamp <= series.points[1].timestamp);
+    let skipped: Vec<&Path> = series.skipped.iter().map(|(path, _)| path.as_path()).collect();
+    assert_eq!(skipped, vec![Path::new("data/missing.sor"), Path::new("Cargo.toml")]);
+
+    let sor = crate::parser::parse_file(include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor")).unwrap().1;
+    let lke = &sor.key_events.as_ref().unwrap().last_key_event;
+    assert_eq!(Metric::Orl.measure(&sor), Some(lke.optical_return_loss as f64 / 1000.0));
+    // Anritsu doesn't store an ORL
+    let anritsu = crate::parser::parse_file(include_bytes!("../data/example3-anritsu-accessmastermt9085.sor")).unwrap().1;
+    assert_eq!(Metric::Orl.measure(&anritsu), None);
+    // The gainer at 477.6m
+    let at = |distance_m| Metric::EventLoss { distance_m, tolerance_m: 2.0 }.measure(&sor);
+    assert_eq!(at(478.0), Some(-0.336));
+    assert_eq!(at(300.0), None);
+
+    // Cancelling part way through stops before the next file
+    use crate::progress::CancellationToken;
+    let token = CancellationToken::new();
+    let hooks = Hooks::new().with_cancellation(token.clone()).with_progress(|done, _| if done == 2 { token.cancel() });
+    assert_eq!(timeseries_with(&paths, &Metric::TotalLoss, &hooks), Err(Cancelled));
+}
+
+#[test]
+fn test_convert() {
+    use crate::progress::CancellationToken;
+    let paths = ["data/example1-noyes-ofl280.sor", "data/missing.sor", "Cargo.toml", "data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor"];
+    let calls = AtomicUsize::new(0);
+    let options = ConvertOptions { jobs: Some(2), ..ConvertOptions::default() };
+    let hooks = Hooks::new().with_progress(|done, total| {
+        assert!(done <= total && total == 4);
+        calls.fetch_add(1, Ordering::Relaxed);
+    });
+    let converted = convert(&paths, &options, &hooks).unwrap();
+    drop(hooks);
+    assert_eq!(calls.into_inner(), 4);
+    let converted_paths: Vec<&Path> = converted.iter().map(|c| c.path.as_path()).collect();
+    assert_eq!(converted_paths, paths.iter().map(Path::new).collect::<Vec<_>>());
+    let json: serde_json::Value = serde_json::from_slice(converted[3].result.as_ref().unwrap()).unwrap();
+    assert_eq!(json["general_parameters"]["fiber_id"], "Fiber1");
+    assert!(converted[1].result.is_err());
+    assert_eq!(converted[2].result, Err("Could not parse SOR file".to_owned()));
+
+    let options = ConvertOptions { format: Format::Interchange, ..ConvertOptions::default() };
+    let converted = convert(&paths[3..], &options, &Hooks::default()).unwrap();
+    let sor = crate::types::SORFile::from_interchange(converted[0].result.as_ref().unwrap()).unwrap();
+    assert_eq!(&*sor.general_parameters.unwrap().fiber_id, "Fiber1");
+    let options = ConvertOptions { engineering_units: true, ..options };
+    assert!(convert(&paths[3..], &options, &Hooks::default()).unwrap()[0].result.is_err());
+    assert_eq!("msgpack".parse(), Ok(Format::MessagePack));
+
+    let token = CancellationToken::new();
+    token.cancel();
+    assert_eq!(convert(&paths, &options, &Hooks::new().with_cancellation(token)), Err(Cancelled));
+}
@@ -0,0 +1,257 @@
+/// This module builds a fixed parameters block from scratch, e.g. for a trace
+/// converted from another format or simulated, from settings in metres,
+/// nanoseconds and dB, keeping the fields which describe the same thing in
+/// different ways consistent with one another.
+use crate::types::FixedParametersBlock;
+use crate::units;
+
+/// Typical acquisition settings for a class of link
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Preset {
+    /// FTTx drops and campus links of up to 10km: a 30ns pulse, with points
+    /// every 0.5m
+    Access,
+    /// Metro rings of up to 40km: a 275ns pulse, with points every 2m
+    Metro,
+    /// Long-haul spans of up to 160km: a 10us pulse, with points every 8m
+    LongHaul,
+}
+
+impl Preset {
+    /// (pulse width in ns, range in metres, spacing in metres)
+    fn settings(self) -> (i16, f64, f64) {
+        match self {
+            Preset::Access => (30, 10_000.0, 0.5),
+            Preset::Metro => (275, 40_000.0, 2.0),
+            Preset::LongHaul => (10_000, 160_000.0, 8.0),
+        }
+    }
+}
+
+/// Group index as stored and backscatter coefficient in dB for a 1ns pulse
+/// of standard single-mode fibre (G.652) at the wavelengths it is usually
+/// tested at
+fn fibre_at(wavelength_nm: i16) -> Option<(i32, f64)> {
+    match wavelength_nm {
+        1310 => Some((146760, -79.4)),
+        1550 => Some((146820, -81.7)),
+        1625 => Some((146860, -82.3)),
+        _ => None,
+    }
+}
+
+/// Builds a FixedParametersBlock, see `FixedParametersBlock::builder`
+#[derive(Debug, PartialEq, Clone)]
+pub struct FixedParametersBuilder {
+    block: FixedParametersBlock,
+    range_m: Option<f64>,
+    spacing_m: Option<f64>,
+    n_points: Option<i32>,
+    offset_m: f64,
+    backscatter_db: Option<f64>,
+}
+
+impl FixedParametersBlock {
+    /// Start building a block for a single pulse width, in metres, with
+    /// SR-4731's default event thresholds. Two of the range, the spacing and
+    /// the number of points must be given, by hand or by a preset, and the
+    /// third is worked out; if all three are given they must agree.
+    pub fn builder() -> FixedParametersBuilder {
+        FixedParametersBuilder {
+            block: FixedParametersBlock {
+                date_time_stamp: 0,
+                units_of_distance: "mt".into(),
+                actual_wavelength: 0,
+                acquisition_offset: 0,
+                acquisition_offset_distance: 0,
+                total_n_pulse_widths_used: 1,
+                pulse_widths_used: Vec::new(),
+                data_spacing: Vec::new(),
+                n_data_points_for_pulse_widths_used: Vec::new(),
+                group_index: units::DEFAULT_GROUP_INDEX,
+                backscatter_coefficient: 0,
+                number_of_averages: 0,
+                averaging_time: 0,
+                acquisition_range: 0,
+                acquisition_range_distance: 0,
+                front_panel_offset: 0,
+                noise_floor_level: 0,
+                noise_floor_scale_factor: 1000,
+                power_offset_first_point: 0,
+                loss_threshold: 200,
+                reflectance_threshold: 55000,
+                end_of_fibre_threshold: 3000,
+                trace_type: "ST".into(),
+                window_coordinate_1: 0,
+                window_coordinate_2: 0,
+                window_coordinate_3: 0,
+                window_coordinate_4: 0,
+            },
+            range_m: None,
+            spacing_m: None,
+            n_points: None,
+            offset_m: 0.0,
+            backscatter_db: None,
+        }
+    }
+}
+
+impl FixedParametersBuilder {
+    /// Apply a preset's pulse width, range and spacing, and the group index
+    /// and backscatter coefficient of standard single-mode fibre at a
+    /// wavelength of 1310, 1550 or 1625nm, which also becomes the actual
+    /// wavelength. Anything set afterwards overrides the preset
+    pub fn preset(mut self, preset: Preset, wavelength_nm: i16) -> FixedParametersBuilder {
+        let (pulse_ns, range_m, spacing_m) = preset.settings();
+        self = self.pulse_width_ns(pulse_ns).range_m(range_m).spacing_m(spacing_m).wavelength_nm(wavelength_nm);
+        self.n_points = None;
+        if let Some((group_index, backscatter_db)) = fibre_at(wavelength_nm) {
+            self = self.group_index(group_index).backscatter_db(backscatter_db);
+        }
+        self
+    }
+
+    /// Acquisition time, in seconds since the Unix epoch
+    pub fn date_time_stamp(mut self, timestamp: u32) -> FixedParametersBuilder {
+        self.block.date_time_stamp = timestamp;
+        self
+    }
+
+    pub fn wavelength_nm(mut self, wavelength_nm: i16) -> FixedParametersBuilder {
+        self.block.actual_wavelength = wavelength_nm;
+        self
+    }
+
+    pub fn pulse_width_ns(mut self, pulse_ns: i16) -> FixedParametersBuilder {
+        self.block.pulse_widths_used = vec![pulse_ns];
+        self
+    }
+
+    /// Distance from the front panel to the last point, in metres
+    pub fn range_m(mut self, range_m: f64) -> FixedParametersBuilder {
+        self.range_m = Some(range_m);
+        self
+    }
+
+    /// Distance between points, in metres
+    pub fn spacing_m(mut self, spacing_m: f64) -> FixedParametersBuilder {
+        self.spacing_m = Some(spacing_m);
+        self
+    }
+
+    pub fn n_points(mut self, n_points: i32) -> FixedParametersBuilder {
+        self.n_points = Some(n_points);
+        self
+    }
+
+    /// Distance from the front panel to the first point, in metres, which
+    /// is negative if the first point is behind it
+    pub fn offset_m(mut self, offset_m: f64) -> FixedParametersBuilder {
+        self.offset_m = offset_m;
+        self
+    }
+
+    /// Group index as stored, e.g. 146800 for 1.468
+    pub fn group_index(mut self, group_index: i32) -> FixedParametersBuilder {
+        self.block.group_index = group_index;
+        self
+    }
+
+    /// Backscatter coefficient for a 1ns pulse, in dB, e.g. -81.7
+    pub fn backscatter_db(mut self, backscatter_db: f64) -> FixedParametersBuilder {
+        self.backscatter_db = Some(backscatter_db);
+        self
+    }
+
+    pub fn number_of_averages(mut self, averages: i32) -> FixedParametersBuilder {
+        self.block.number_of_averages = averages;
+        self
+    }
+
+    /// Event detection thresholds in dB: the smallest loss reported as an
+    /// event, the smallest reflectance reported, e.g. -55.0, and the loss
+    /// taken to be the end of the fibre
+    pub fn thresholds_db(mut self, loss_db: f64, reflectance_db: f64, end_of_fibre_db: f64) -> FixedParametersBuilder {
+        let threshold = |db: f64| (db * 1000.0).abs().round().min(u16::MAX as f64) as u16;
+        self.block.loss_threshold = threshold(loss_db);
+        self.block.reflectance_threshold = threshold(reflectance_db);
+        self.block.end_of_fibre_threshold = threshold(end_of_fibre_db);
+        self
+    }
+
+    /// Trace type, e.g. ST for a standard trace or BD for a bidirectional
+    /// one
+    pub fn trace_type(mut self, trace_type: &str) -> FixedParametersBuilder {
+        self.block.trace_type = trace_type.into();
+        self
+    }
+
+    /// Check the settings and fill in the fields which follow from them
+    pub fn build(self) -> Result<FixedParametersBlock, &'static str> {
+        let mut block = self.block;
+        if block.pulse_widths_used.is_empty() {
+            return Err("No pulse width was given");
+        }
+        if self.range_m.is_some_and(|r| !r.is_finite() || r <= 0.0) || self.spacing_m.is_some_and(|s| !s.is_finite() || s <= 0.0) {
+            return Err("The range and spacing must be positive");
+        }
+        if self.n_points.is_some_and(|n| n < 2) {
+            return Err("There must be at least two points");
+        }
+        // The range reaches from the first point to the last
+        let (range_m, spacing_m, n_points) = match (self.range_m, self.spacing_m, self.n_points) {
+            (Some(range), Some(spacing), None) => (range, spacing, (range / spacing).ceil() as i32 + 1),
+            (Some(range), None, Some(n)) => (range, range / (n - 1) as f64, n),
+            (None, Some(spacing), Some(n)) => (spacing * (n - 1) as f64, spacing, n),
+            (Some(range), Some(spacing), Some(n)) => {
+                if (spacing * (n - 1) as f64 - range).abs() > spacing.max(range * 0.01) {
+                    return Err("The range, spacing and number of points don't agree");
+                }
+                (range, spacing, n)
+            }
+            _ => return Err("Two of the range, spacing and number of points must be given"),
+        };
+        let group_index = block.group_index;
+        block.data_spacing = vec![units::data_spacing(spacing_m, group_index)];
+        block.n_data_points_for_pulse_widths_used = vec![n_points];
+        block.acquisition_range = units::metres_to_time(range_m, group_index);
+        block.acquisition_offset = units::metres_to_time(self.offset_m, group_index);
+        block.acquisition_range_distance = units::metres_to_distance(range_m, &block.units_of_distance).unwrap_or(0);
+        block.acquisition_offset_distance = units::metres_to_distance(self.offset_m, &block.units_of_distance).unwrap_or(0);
+        // Everything before the first point is front panel offset
+        block.front_panel_offset = (-block.acquisition_offset).max(0);
+        if let Some(db) = self.backscatter_db {
+            if !db.is_finite() || !(-3276.7..=0.0).contains(&db) {
+                return Err("The backscatter coefficient must be between -3276.7 and 0 dB");
+            }
+            block.backscatter_coefficient = (-db * 10.0).round() as i16;
+        }
+        Ok(block)
+    }
+}
+
+#[test]
+fn test_fixed_parameters_builder() {
+    let fp = FixedParametersBlock::builder().preset(Preset::Access, 1550).date_time_stamp(1592057570).build().unwrap();
+    assert_eq!((fp.actual_wavelength, fp.pulse_widths_used.clone(), fp.group_index, fp.backscatter_coefficient),
+               (1550, vec![30], 146820, 817));
+    assert_eq!(fp.n_data_points_for_pulse_widths_used, vec![20001]);
+    assert_eq!(fp.acquisition_range_distance, 100000);
+    let spacing_m = units::spacing_m(fp.data_spacing[0], fp.group_index);
+    assert!((spacing_m - 0.5).abs() < 1e-4);
+    assert!((units::time_to_metres(fp.acquisition_range as f64, fp.group_index) - 10_000.0).abs() < 0.02);
+
+    // The third of range, spacing and points is worked out, or checked
+    let fp = FixedParametersBlock::builder().pulse_width_ns(100).range_m(5000.0).n_points(5001).build().unwrap();
+    assert!((units::spacing_m(fp.data_spacing[0], fp.group_index) - 1.0).abs() < 1e-4);
+    let builder = FixedParametersBlock::builder().preset(Preset::Metro, 1310);
+    assert!(builder.clone().n_points(20001).build().is_ok());
+    assert!(builder.clone().n_points(5000).build().is_err());
+    assert!(FixedParametersBlock::builder().pulse_width_ns(100).range_m(5000.0).build().is_err());
+    assert!(FixedParametersBlock::builder().range_m(5000.0).spacing_m(1.0).build().is_err());
+
+    let fp = FixedParametersBlock::builder().preset(Preset::LongHaul, 1550).offset_m(-40.0).thresholds_db(0.05, -65.0, 5.0)
+        .build().unwrap();
+    assert!(fp.acquisition_offset < 0 && fp.front_panel_offset == -fp.acquisition_offset);
+    assert_eq!((fp.loss_threshold, fp.reflectance_threshold, fp.end_of_fibre_threshold), (50, 65000, 5000));
+}
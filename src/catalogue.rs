@@ -0,0 +1,211 @@
+/// This module maintains a SQLite catalogue of SOR file metadata, so that
+/// large collections of traces can be searched by cable, fibre, wavelength,
+/// date and loss without re-parsing every file. It is only available with the
+/// `sqlite` feature.
+use std::path::{Path, PathBuf};
+use rusqlite::{params, Connection, ToSql};
+use serde::Serialize;
+use crate::analysis::metres_per_100ps;
+use crate::engineering::iso8601;
+use crate::progress::Hooks;
+use crate::types::SORFile;
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS files (
+    path TEXT PRIMARY KEY,
+    cable_id TEXT NOT NULL,
+    fiber_id TEXT NOT NULL,
+    wavelength INTEGER NOT NULL,
+    date TEXT NOT NULL,
+    length_m REAL,
+    total_loss_db REAL
+);
+CREATE INDEX IF NOT EXISTS files_cable_fiber ON files (cable_id, fiber_id);
+CREATE INDEX IF NOT EXISTS files_date ON files (date);";
+
+/// A catalogue entry describing one file
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Entry {
+    pub path: String,
+    pub cable_id: String,
+    pub fiber_id: String,
+    /// Nominal wavelength in nm
+    pub wavelength: i16,
+    /// Acquisition time as an ISO-8601 timestamp
+    pub date: String,
+    /// Distance to the last key event in metres, if the file has key events
+    pub length_m: Option<f64>,
+    /// End-to-end loss in dB, if the file has key events
+    pub total_loss_db: Option<f64>,
+}
+
+/// Criteria for searching the catalogue. Cable and fibre IDs are matched as
+/// globs, e.g. `C0*`; dates are compared as ISO-8601 strings, so a prefix
+/// such as `2019-09` works as a bound.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Query {
+    pub cable_id: Option<String>,
+    pub fiber_id: Option<String>,
+    pub wavelength: Option<i16>,
+    /// Earliest acquisition date, inclusive
+    pub from: Option<String>,
+    /// Latest acquisition date, inclusive of anything starting with it
+    pub to: Option<String>,
+    pub min_total_loss_db: Option<f64>,
+    pub max_total_loss_db: Option<f64>,
+}
+
+impl Entry {
+    /// Build an entry from a parsed file, which need only contain metadata
+    /// (see `parser::parse_metadata`)
+    pub fn new(path: &str, sor: &SORFile) -> Entry {
+        let gp = sor.general_parameters.as_ref();
+        let fp = sor.fixed_parameters.as_ref();
+        let metres_per_100ps = metres_per_100ps(sor);
+        let lke = sor.key_events.as_ref().map(|ke| &ke.last_key_event);
+        Entry {
+            path: path.to_owned(),
+            cable_id: gp.map_or(String::new(), |gp| gp.cable_id.trim().to_owned()),
+            fiber_id: gp.map_or(String::new(), |gp| gp.fiber_id.trim().to_owned()),
+            wavelength: gp.map_or(0, |gp| gp.nominal_wavelength),
+            date: fp.map_or(String::new(), |fp| iso8601(fp.date_time_stamp)),
+            length_m: lke.map(|lke| lke.event_propogation_time as f64 * metres_per_100ps),
+            total_loss_db: lke.map(|lke| lke.end_to_end_loss as f64 / 1000.0),
+        }
+    }
+}
+
+/// Open a catalogue, creating it if need be
+pub fn open(path: &Path) -> rusqlite::Result<Connection> {
+    let conn = Connection::open(path)?;
+    conn.execute_batch(SCHEMA)?;
+    Ok(conn)
+}
+
+/// Add an entry to the catalogue, replacing any existing entry for the path
+pub fn insert(conn: &Connection, entry: &Entry) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO files (path, cable_id, fiber_id, wavelength, date, length_m, total_loss_db)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![entry.path, entry.cable_id, entry.fiber_id, entry.wavelength, entry.date, entry.length_m, entry.total_loss_db],
+    )?;
+    Ok(())
+}
+
+/// The outcome of indexing many files
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct IndexSummary {
+    pub indexed: usize,
+    /// Files which couldn't be read or parsed, with the reason
+    pub skipped: Vec<(PathBuf, String)>,
+}
+
+/// Add many files to the catalogue in one transaction, which is far faster
+/// than one per file, reading only their metadata. Files which can't be read
+/// or parsed are skipped. If cancelled, the transaction is rolled back,
+/// leaving the catalogue as it was, and None is returned
+pub fn index<P: AsRef<Path>>(conn: &mut Connection, paths: &[P], hooks: &Hooks) -> rusqlite::Result<Option<IndexSummary>> {
+    let tx = conn.transaction()?;
+    let mut summary = IndexSummary::default();
+    for (i, path) in paths.iter().enumerate() {
+        if hooks.is_cancelled() {
+            return Ok(None);
+        }
+        let path = path.as_ref();
+        let sor = crate::batch::read(path).and_then(|data| match crate::parser::parse_metadata(&data) {
+            Ok((_, sor)) => Ok(sor),
+            Err(_) => Err("Could not parse SOR file".to_owned()),
+        });
+        match sor {
+            Ok(sor) => {
+                insert(&tx, &Entry::new(&path.to_string_lossy(), &sor))?;
+                summary.indexed += 1;
+            }
+            Err(err) => summary.skipped.push((path.to_owned(), err)),
+        }
+        hooks.progress(i + 1, paths.len());
+    }
+    tx.commit()?;
+    Ok(Some(summary))
+}
+
+/// Find entries matching every given criterion, ordered by cable, fibre,
+/// wavelength and date
+pub fn search(conn: &Connection, query: &Query) -> rusqlite::Result<Vec<Entry>> {
+    let mut sql = String::from("SELECT path, cable_id, fiber_id, wavelength, date, length_m, total_loss_db FROM files WHERE 1");
+    let mut values: Vec<&dyn ToSql> = Vec::new();
+    // Anything after the "to" prefix, e.g. a time of day, is still within it
+    let to = query.to.as_ref().map(|to| format!("{}\u{10ffff}", to));
+    let criteria: [(&str, Option<&dyn ToSql>); 7] = [
+        ("cable_id GLOB ?", query.cable_id.as_ref().map(|v| v as &dyn ToSql)),
+        ("fiber_id GLOB ?", query.fiber_id.as_ref().map(|v| v as &dyn ToSql)),
+        ("wavelength = ?", query.wavelength.as_ref().map(|v| v as &dyn ToSql)),
+        ("date >= ?", query.from.as_ref().map(|v| v as &dyn ToSql)),
+        ("date <= ?", to.as_ref().map(|v| v as &dyn ToSql)),
+        ("total_loss_db >= ?", query.min_total_loss_db.as_ref().map(|v| v as &dyn ToSql)),
+        ("total_loss_db <= ?", query.max_total_loss_db.as_ref().map(|v| v as &dyn ToSql)),
+    ];
+    for (clause, value) in criteria.iter() {
+        if let Some(value) = value {
+            sql += " AND ";
+            sql += clause;
+            values.push(*value);
+        }
+    }
+    sql += " ORDER BY cable_id, fiber_id, wavelength, date";
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map(values.as_slice(), |row| {
+        Ok(Entry {
+            path: row.get(0)?,
+            cable_id: row.get(1)?,
+            fiber_id: row.get(2)?,
+            wavelength: row.get(3)?,
+            date: row.get(4)?,
+            length_m: row.get(5)?,
+            total_loss_db: row.get(6)?,
+        })
+    })?;
+    rows.collect()
+}
+
+#[test]
+fn test_catalogue() {
+    let conn = open(Path::new(":memory:")).unwrap();
+    for (path, data) in [
+        ("noyes.sor", &include_bytes!("../data/example1-noyes-ofl280.sor")[..]),
+        ("anritsu.sor", &include_bytes!("../data/example3-anritsu-accessmastermt9085.sor")[..]),
+    ] {
+        let sor = crate::parser::parse_metadata(data).unwrap().1;
+        insert(&conn, &Entry::new(path, &sor)).unwrap();
+    }
+    // Re-indexing a file replaces its entry
+    let sor = crate::parser::parse_metadata(include_bytes!("../data/example1-noyes-ofl280.sor")).unwrap().1;
+    insert(&conn, &Entry::new("noyes.sor", &sor)).unwrap();
+    assert_eq!(search(&conn, &Query::default()).unwrap().len(), 2);
+
+    let found = search(&conn, &Query { wavelength: Some(1550), ..Query::default() }).unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].path, "noyes.sor");
+    assert_eq!(found[0].cable_id, "C001");
+    assert_eq!(found[0].date, "2019-09-30T09:27:54Z");
+    assert_eq!(found[0].total_loss_db, Some(0.576));
+    let found = search(&conn, &Query { cable_id: Some("C0*".to_owned()), to: Some("2019-09-30".to_owned()), ..Query::default() }).unwrap();
+    assert_eq!(found.len(), 1);
+    let found = search(&conn, &Query { from: Some("2020".to_owned()), ..Query::default() }).unwrap();
+    assert_eq!(found[0].path, "anritsu.sor");
+}
+
+#[test]
+fn test_index() {
+    use crate::progress::CancellationToken;
+    let mut conn = open(Path::new(":memory:")).unwrap();
+    let paths = ["data/example1-noyes-ofl280.sor", "data/missing.sor", "data/example3-anritsu-accessmastermt9085.sor"];
+    let token = CancellationToken::new();
+    let hooks = Hooks::new().with_cancellation(token.clone()).with_progress(|done, _| if done == 2 { token.cancel() });
+    assert_eq!(index(&mut conn, &paths, &hooks).unwrap(), None);
+    assert!(search(&conn, &Query::default()).unwrap().is_empty());
+
+    let summary = index(&mut conn, &paths, &Hooks::default()).unwrap().unwrap();
+    assert_eq!(summary.indexed, 2);
+    assert_eq!(summary.skipped[0].0, Path::new("data/missing.sor"));
+    assert_eq!(search(&conn, &Query::default()).unwrap().len(), 2);
+}
@@ -0,0 +1,201 @@
+/// This module compares a SOR file against a baseline measurement of the same
+/// fibre, to find events which have appeared or worsened since - e.g. for
+/// monitoring dark fibre with an OTDR which just dumps SOR files.
+///
+/// The two traces may have been taken with different launch leads, so before
+/// matching events we find the distance offset which lines up the most
+/// events between the two.
+pub use crate::analysis::{events, Event};
+use crate::analysis::{match_events, measured_orl};
+use crate::types::SORFile;
+use serde::{Deserialize, Serialize};
+
+/// How much measurements of the same fibre may differ before the difference
+/// is reported, shared by compare, diff and bidirectional reports. Like
+/// acceptance profiles, these can be loaded from TOML, e.g.
+///
+/// ```toml
+/// distance_m = 5.0
+/// loss_db = 0.1
+/// ```
+///
+/// with any tolerances left out taking their defaults, so that a testing
+/// policy can be kept under version control.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ToleranceProfile {
+    /// Distance in metres within which two events are the same event
+    pub distance_m: f64,
+    /// Increase in an event's or the link's loss, in dB, above which it has
+    /// worsened
+    pub loss_db: f64,
+    /// Increase in an event's reflectance, in dB, above which it has
+    /// worsened
+    pub reflectance_db: f64,
+    /// Fall in the link's optical return loss, in dB, above which it has
+    /// worsened
+    pub orl_db: f64,
+    /// Difference in trace level, in dB, above which two traces differ, as
+    /// for analysis::subtract
+    pub level_db: f64,
+}
+
+impl Default for ToleranceProfile {
+    fn default() -> Self {
+        ToleranceProfile { distance_m: 2.0, loss_db: 0.05, reflectance_db: 2.0, orl_db: 1.0, level_db: 0.3 }
+    }
+}
+
+/// How an event differs from the baseline
+#[derive(Debug, PartialEq, Clone)]
+pub enum Change {
+    /// The event is not in the baseline
+    New(Event),
+    /// The event's loss or reflectance has increased by more than the
+    /// tolerance
+    Worsened { baseline: Event, current: Event },
+    /// A baseline event is no longer present, e.g. the end of the fibre has
+    /// moved because of a break
+    Missing(Event),
+}
+
+/// The result of comparing a file against its baseline
+#[derive(Debug, PartialEq, Clone)]
+pub struct Comparison {
+    /// Distance added to baseline events to line them up with the current
+    /// trace, in metres
+    pub offset_m: f64,
+    /// Number of events found in both traces
+    pub matched: usize,
+    pub changes: Vec<Change>,
+    /// Change in end-to-end loss, in dB, if both files record one
+    pub total_loss_change_db: Option<f64>,
+    /// Change in optical return loss, in dB, if both files record one; a
+    /// fall is a worsening
+    pub orl_change_db: Option<f64>,
+    /// True if nothing has changed by more than the tolerances
+    pub pass: bool,
+}
+
+/// Compare a file against a baseline measurement of the same fibre
+pub fn compare(baseline: &SORFile, current: &SORFile, tolerances: &ToleranceProfile) -> Comparison {
+    let base = events(baseline);
+    let cur = events(current);
+    let offset_m = alignment(&base, &cur, tolerances.distance_m);
+
+    // Line the baseline up with the current trace before matching
+    let shifted: Vec<Event> = base.iter().map(|b| Event { distance_m: b.distance_m + offset_m, ..b.clone() }).collect();
+    let matches = match_events(&shifted, &cur, tolerances.distance_m, tolerances.loss_db);
+    let mut changes = Vec::new();
+    for (i, c) in cur.iter().enumerate() {
+        match matches.matched.iter().find(|pair| pair.b == i) {
+            Some(pair) if (pair.changed && pair.loss_change_db > 0.0) || reflects_more(&base[pair.a], c, tolerances) => {
+                changes.push(Change::Worsened { baseline: base[pair.a].clone(), current: c.clone() });
+            }
+            Some(_) => {}
+            None => changes.push(Change::New(c.clone())),
+        }
+    }
+    for &i in &matches.missing {
+        changes.push(Change::Missing(base[i].clone()));
+    }
+
+    let total_loss = |sor: &SORFile| sor.key_events.as_ref().map(|ke| ke.last_key_event.end_to_end_loss as f64 / 1000.0);
+    let total_loss_change_db = match (total_loss(baseline), total_loss(current)) {
+        (Some(b), Some(c)) => Some(c - b),
+        _ => None,
+    };
+    let orl_change_db = match (measured_orl(baseline), measured_orl(current)) {
+        (Some(b), Some(c)) => Some(c - b),
+        _ => None,
+    };
+    let pass = changes.is_empty() && total_loss_change_db.is_none_or(|d| d <= tolerances.loss_db)
+        && orl_change_db.is_none_or(|d| -d <= tolerances.orl_db);
+    Comparison { offset_m, matched: matches.matched.len(), changes, total_loss_change_db, orl_change_db, pass }
+}
+
+/// Whether an event's reflectance has risen by more than the tolerance. A
+/// reflectance of zero wasn't measured
+fn reflects_more(baseline: &Event, current: &Event, tolerances: &ToleranceProfile) -> bool {
+    baseline.reflectance_db != 0.0 && current.reflectance_db != 0.0
+        && current.reflectance_db - baseline.reflectance_db > tolerances.reflectance_db
+}
+
+/// Find the offset, among those which line up some pair of events, which
+/// lines up the most events. Ties go to the smallest offset.
+fn alignment(base: &[Event], cur: &[Event], tolerance_m: f64) -> f64 {
+    let mut best = (0, 0.0);
+    for b in base {
+        for c in cur {
+            let offset = c.distance_m - b.distance_m;
+            let count = base.iter()
+                .filter(|b| cur.iter().any(|c| (b.distance_m + offset - c.distance_m).abs() <= tolerance_m))
+                .count();
+            if count > best.0 || (count == best.0 && offset.abs() < f64::abs(best.1)) {
+                best = (count, offset);
+            }
+        }
+    }
+    best.1
+}
+
+#[test]
+fn test_compare() {
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let baseline = crate::parser::parse_file(data).unwrap().1;
+    let same = compare(&baseline, &baseline, &ToleranceProfile::default());
+    assert!(same.pass);
+    assert_eq!(same.offset_m, 0.0);
+    assert_eq!(same.matched, 9);
+
+    // Worsen a splice, add a launch lead's worth of offset, and remove an event
+    let mut current = baseline.clone();
+    let ke = current.key_events.as_mut().unwrap();
+    ke.key_events[3].event_loss += 200;
+    let removed = ke.key_events.remove(5);
+    for e in ke.key_events.iter_mut() {
+        e.event_propogation_time += 4900;
+    }
+    ke.last_key_event.event_propogation_time += 4900;
+    let result = compare(&baseline, &current, &ToleranceProfile::default());
+    assert!(!result.pass);
+    assert!((result.offset_m - 100.0).abs() < 1.0);
+    assert_eq!(result.matched, 8);
+    assert_eq!(result.changes.len(), 2);
+    match &result.changes[0] {
+        Change::Worsened { baseline, current } => assert!((current.loss_db - baseline.loss_db - 0.2).abs() < 1e-9),
+        other => panic!("unexpected change {:?}", other),
+    }
+    match &result.changes[1] {
+        Change::Missing(e) => assert_eq!(e.loss_db, removed.event_loss as f64 / 1000.0),
+        other => panic!("unexpected change {:?}", other),
+    }
+}
+
+#[test]
+fn test_tolerance_profile() {
+    let profile: ToleranceProfile = toml::from_str("distance_m = 5.0\norl_db = 0.5").unwrap();
+    assert_eq!(profile, ToleranceProfile { distance_m: 5.0, orl_db: 0.5, ..ToleranceProfile::default() });
+    assert!(toml::from_str::<ToleranceProfile>("distance = 5.0").is_err());
+
+    // A connector reflecting more, and the ORL falling, are worsenings
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let baseline = crate::parser::parse_file(data).unwrap().1;
+    let mut current = baseline.clone();
+    let ke = current.key_events.as_mut().unwrap();
+    let reflective = ke.key_events.iter().position(|e| e.event_reflectance != 0).unwrap();
+    ke.key_events[reflective].event_reflectance += 3000;
+    let result = compare(&baseline, &current, &profile);
+    assert!(matches!(result.changes[..], [Change::Worsened { .. }]));
+    let relaxed = ToleranceProfile { reflectance_db: 5.0, ..profile.clone() };
+    assert!(compare(&baseline, &current, &relaxed).pass);
+
+    let mut current = baseline.clone();
+    current.key_events.as_mut().unwrap().last_key_event.optical_return_loss -= 1000;
+    let result = compare(&baseline, &current, &profile);
+    assert_eq!(result.orl_change_db, Some(-1.0));
+    assert!(!result.pass);
+    // An ORL of zero wasn't measured, so isn't compared
+    current.key_events.as_mut().unwrap().last_key_event.optical_return_loss = 0;
+    assert_eq!(compare(&baseline, &current, &profile).orl_change_db, None);
+}
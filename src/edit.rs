@@ -0,0 +1,1131 @@
+/// This module provides edits to a SORFile which keep its blocks consistent
+/// with one another, such as cropping a trace or adding landmarks.
+use std::sync::Arc;
+use crate::analysis::{demote, events, match_events, metres_per_100ps, EventThresholds, Trace};
+use crate::units;
+use crate::types::{BlockInfo, FixedParametersBlock, KeyEvent, KeyEvents, Landmark, LastKeyEvent, LinkParameters, ProprietaryBlock, SORFile};
+
+/// Header of the proprietary block reanalyze_events keeps the original key
+/// events in, encoded as the body of a KeyEvents block
+pub const BLOCK_ID_ORIGINAL_EVENTS: &str = "OtdrsOriginalEvents";
+
+impl Landmark {
+    /// A landmark with the given code, e.g. MH for a manhole, at a time in
+    /// 100ps from the user offset, with nothing else recorded
+    pub fn new(code: &str, location: i32) -> Landmark {
+        Landmark {
+            landmark_number: 0,
+            landmark_code: code.into(),
+            landmark_location: location,
+            related_event_number: 0,
+            gps_longitude: 0,
+            gps_latitude: 0,
+            fiber_correction_factor_lead_in_fiber: 0,
+            sheath_marker_entering_landmark: 0,
+            sheath_marker_leaving_landmark: 0,
+            units_of_sheath_marks_leaving_landmark: "mt".into(),
+            mode_field_diameter_leaving_landmark: 0,
+            comment: "".into(),
+        }
+    }
+
+    /// WGS84 latitude and longitude in decimal degrees, if recorded. Zero
+    /// for both means there is no position
+    pub fn position(&self) -> Option<(f64, f64)> {
+        if self.gps_latitude == 0 && self.gps_longitude == 0 {
+            None
+        } else {
+            Some((units::gps_to_degrees(self.gps_latitude), units::gps_to_degrees(self.gps_longitude)))
+        }
+    }
+
+    /// Set the WGS84 latitude and longitude in decimal degrees
+    pub fn set_position(&mut self, latitude: f64, longitude: f64) -> Result<(), &'static str> {
+        if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+            return Err("Latitude must be within 90 degrees and longitude within 180");
+        }
+        self.gps_latitude = units::degrees_to_gps(latitude);
+        self.gps_longitude = units::degrees_to_gps(longitude);
+        Ok(())
+    }
+}
+
+impl SORFile {
+    /// Crop the trace to the span between two distances in metres, measured
+    /// from the user offset as in the key event table. Either end may be left
+    /// open. This is typically used to remove launch and receive leads.
+    ///
+    /// Data points outside the span are discarded and the acquisition offset
+    /// moved to the first remaining point. The user offset is moved to the
+    /// start of the span, so distances in the cropped file are measured from
+    /// there. Key events outside the span are removed and the remainder
+    /// renumbered; if the last key event is removed, the last remaining event
+    /// takes its place. The end-to-end loss is re-measured from the trace
+    /// between its (adjusted) markers, but the optical return loss is not
+    /// recomputed. Landmarks outside the span are removed too, and those
+    /// remaining moved with the user offset and related to their events'
+    /// new numbers, or to none if their event was removed.
+    pub fn crop(&mut self, from_m: Option<f64>, to_m: Option<f64>) -> Result<(), &'static str> {
+        let m_per_100ps = metres_per_100ps(self);
+        let fp = self.fixed_parameters.as_mut().ok_or("File has no fixed parameters block")?;
+        if fp.data_spacing.len() > 1 {
+            return Err("Cropping files with several pulse widths is not supported");
+        }
+        let distance_per_100ps = units::distance_per_100ps(&fp.units_of_distance, fp.group_index);
+        let user_offset = self.general_parameters.as_ref().map_or(0, |gp| gp.user_offset);
+        // Times from here on are absolute, i.e. from the front panel
+        let start = from_m.map(|m| user_offset as f64 + m / m_per_100ps);
+        let end = to_m.map(|m| user_offset as f64 + m / m_per_100ps);
+        if let (Some(start), Some(end)) = (start, end) {
+            if start >= end {
+                return Err("The start of the span must be before its end");
+            }
+        }
+
+        // Crop the data points. Each scale factor covers a consecutive run of
+        // points, so we crop the runs as though they were one
+        let old_acquisition_offset = fp.acquisition_offset;
+        if let Some(dp) = self.data_points.as_mut() {
+            let spacing = fp.data_spacing.first().copied().unwrap_or(0) as f64 / 10000.0;
+            if spacing <= 0.0 {
+                return Err("File has no data spacing");
+            }
+            let total: usize = dp.scale_factors.iter().map(|sf| sf.data.len()).sum();
+            let acquisition_offset = fp.acquisition_offset as f64;
+            let first = start.map_or(0.0, |s| ((s - acquisition_offset) / spacing).ceil()).max(0.0) as usize;
+            let last = end.map_or(total as f64, |e| ((e - acquisition_offset) / spacing).floor() + 1.0).max(0.0) as usize;
+            let (first, last) = (first.min(total), last.min(total));
+            if first >= last {
+                return Err("No data points lie within the span");
+            }
+            let mut run_start = 0;
+            for sf in dp.scale_factors.iter_mut() {
+                let run_end = run_start + sf.data.len();
+                let keep = (first.clamp(run_start, run_end) - run_start)..(last.clamp(run_start, run_end) - run_start);
+                sf.data = sf.data[keep].to_vec();
+                sf.n_points = sf.data.len() as i32;
+                run_start = run_end;
+            }
+            dp.scale_factors.retain(|sf| !sf.data.is_empty());
+            dp.total_number_scale_factors_used = dp.scale_factors.len() as i16;
+            dp.number_of_data_points = (last - first) as i32;
+            fp.n_data_points_for_pulse_widths_used = vec![(last - first) as i32];
+            fp.acquisition_offset = (acquisition_offset + first as f64 * spacing).round() as i32;
+        } else if let Some(start) = start {
+            fp.acquisition_offset = fp.acquisition_offset.max(start.round() as i32);
+        }
+        fp.acquisition_offset_distance = rescale_distance(fp.acquisition_offset_distance, old_acquisition_offset,
+                                                          fp.acquisition_offset, distance_per_100ps);
+
+        // Move the user offset to the start of the span, and event times with
+        // it, since they are measured from the user offset
+        let new_user_offset = start.map_or(user_offset, |s| s.round() as i32);
+        let shift = new_user_offset - user_offset;
+        if let Some(gp) = self.general_parameters.as_mut() {
+            gp.user_offset_distance = rescale_distance(gp.user_offset_distance, gp.user_offset, new_user_offset, distance_per_100ps);
+            gp.user_offset = new_user_offset;
+        }
+        let within = |time: i32| {
+            let time = (time + user_offset) as f64;
+            start.is_none_or(|s| time >= s.floor()) && end.is_none_or(|e| time <= e.ceil())
+        };
+        let span_end = end.map(|e| e.round() as i32 - new_user_offset);
+        let move_marker = |m: i32| {
+            let m = (m - shift).max(0);
+            span_end.map_or(m, |e| m.min(e))
+        };
+        // Landmarks are placed from the user offset too, and related to
+        // events by number. The events kept are numbered in order, whichever
+        // of them becomes the last key event
+        let kept: Vec<i16> = self.key_events.iter()
+            .flat_map(|ke| ke.key_events.iter().map(|e| (e.event_number, e.event_propogation_time))
+                .chain(std::iter::once((ke.last_key_event.event_number, ke.last_key_event.event_propogation_time))))
+            .filter(|&(_, time)| within(time))
+            .map(|(number, _)| number)
+            .collect();
+        if let Some(lp) = self.link_parameters.as_mut() {
+            lp.landmarks.retain(|l| within(l.landmark_location));
+            for l in lp.landmarks.iter_mut() {
+                l.landmark_location -= shift;
+                l.related_event_number = kept.iter().position(|&n| n == l.related_event_number).map_or(0, |i| i as i16 + 1);
+            }
+            renumber(lp);
+        }
+        if let Some(ke) = self.key_events.take() {
+            let mut events: Vec<KeyEvent> = ke.key_events.into_iter().filter(|e| within(e.event_propogation_time)).collect();
+            let lke = ke.last_key_event;
+            let mut lke = if within(lke.event_propogation_time) {
+                lke
+            } else {
+                match events.pop() {
+                    Some(e) => promote(e, lke),
+                    // The block can't exist without a last key event
+                    None => {
+                        self.key_events = None;
+                        return Ok(());
+                    }
+                }
+            };
+            for e in events.iter_mut() {
+                e.event_propogation_time -= shift;
+                shift_markers(&mut e.marker_location_1, shift);
+                shift_markers(&mut e.marker_location_2, shift);
+                shift_markers(&mut e.marker_location_3, shift);
+                shift_markers(&mut e.marker_location_4, shift);
+                shift_markers(&mut e.marker_location_5, shift);
+            }
+            for (n, e) in events.iter_mut().enumerate() {
+                e.event_number = n as i16 + 1;
+            }
+            lke.event_number = events.len() as i16 + 1;
+            lke.event_propogation_time -= shift;
+            shift_markers(&mut lke.marker_location_1, shift);
+            shift_markers(&mut lke.marker_location_2, shift);
+            shift_markers(&mut lke.marker_location_3, shift);
+            shift_markers(&mut lke.marker_location_4, shift);
+            shift_markers(&mut lke.marker_location_5, shift);
+            let markers = (lke.end_to_end_marker_position_1, lke.end_to_end_marker_position_2);
+            lke.end_to_end_marker_position_1 = move_marker(lke.end_to_end_marker_position_1);
+            lke.end_to_end_marker_position_2 = move_marker(lke.end_to_end_marker_position_2);
+            lke.optical_return_loss_marker_position_1 = move_marker(lke.optical_return_loss_marker_position_1);
+            lke.optical_return_loss_marker_position_2 = move_marker(lke.optical_return_loss_marker_position_2);
+            if (lke.end_to_end_marker_position_1, lke.end_to_end_marker_position_2) != (markers.0 - shift, markers.1 - shift) {
+                if let Some(loss) = self.two_point_loss(lke.end_to_end_marker_position_1, lke.end_to_end_marker_position_2) {
+                    lke.end_to_end_loss = loss;
+                }
+            }
+            self.key_events = Some(crate::types::KeyEvents {
+                number_of_key_events: events.len() as i16 + 1,
+                key_events: events,
+                last_key_event: lke,
+            });
+        }
+        Ok(())
+    }
+
+    /// Distance in metres from the user offset to a landmark
+    pub fn landmark_distance_m(&self, landmark: &Landmark) -> f64 {
+        landmark.landmark_location as f64 * metres_per_100ps(self)
+    }
+
+    /// Add a landmark with the given code, e.g. MH for a manhole, at a
+    /// distance in metres from the user offset, creating the link parameters
+    /// block if need be. Landmarks are kept in order of distance and
+    /// numbered from 1. The new landmark is returned for its position,
+    /// comment and so on to be filled in.
+    pub fn add_landmark(&mut self, code: &str, distance_m: f64) -> Result<&mut Landmark, &'static str> {
+        if code.len() != 2 || !code.is_ascii() {
+            return Err("Landmark codes are two characters");
+        }
+        let location = (distance_m / metres_per_100ps(self)).round() as i32;
+        self.map_link_parameters();
+        let lp = self.link_parameters.get_or_insert(LinkParameters { number_of_landmarks: 0, landmarks: Vec::new() });
+        let at = lp.landmarks.partition_point(|l| l.landmark_location <= location);
+        lp.landmarks.insert(at, Landmark::new(code, location));
+        renumber(lp);
+        Ok(&mut lp.landmarks[at])
+    }
+
+    /// Replace the file's landmarks, e.g. with those built from a route by
+    /// `geo::landmarks_from_csv`, adding the link parameters block to the map
+    /// if need be
+    pub fn set_link_parameters(&mut self, lp: LinkParameters) {
+        self.map_link_parameters();
+        self.link_parameters = Some(lp);
+    }
+
+    /// Make sure the map lists a link parameters block, for the writer
+    fn map_link_parameters(&mut self) {
+        if !self.map.block_info.iter().any(|b| &*b.identifier == crate::parser::BLOCK_ID_LNKPARAMS) {
+            self.map.block_info.push(BlockInfo {
+                identifier: crate::parser::BLOCK_ID_LNKPARAMS.into(),
+                revision_number: self.map.revision_number,
+                size: 0,
+            });
+        }
+    }
+
+    /// Remove a landmark by number, renumbering the rest. The link parameters
+    /// block is kept, even if empty
+    pub fn remove_landmark(&mut self, number: i16) -> Option<Landmark> {
+        let lp = self.link_parameters.as_mut()?;
+        let at = lp.landmarks.iter().position(|l| l.landmark_number == number)?;
+        let landmark = lp.landmarks.remove(at);
+        renumber(lp);
+        Some(landmark)
+    }
+
+    /// Relate each landmark to the nearest key event within tolerance_m, or
+    /// to none (event number 0) if there isn't one, e.g. after importing a
+    /// route from a GIS. Returns the number of landmarks related to an event
+    pub fn relate_landmarks(&mut self, tolerance_m: f64) -> usize {
+        let events = events(self);
+        let m_per_100ps = metres_per_100ps(self);
+        let lp = match self.link_parameters.as_mut() {
+            Some(lp) => lp,
+            None => return 0,
+        };
+        let mut related = 0;
+        for l in lp.landmarks.iter_mut() {
+            let distance_m = l.landmark_location as f64 * m_per_100ps;
+            l.related_event_number = events.iter()
+                .map(|e| (e.number, (e.distance_m - distance_m).abs()))
+                .filter(|&(_, error)| error <= tolerance_m)
+                .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+                .map_or(0, |(number, _)| {
+                    related += 1;
+                    number
+                });
+        }
+        related
+    }
+
+    /// Make the distance fields which mirror a time - the user offset, the
+    /// acquisition offset and the acquisition range - agree with their times,
+    /// using the group index and units of distance. The times are taken as
+    /// right, since everything else in the file is placed by them, unless a
+    /// time is zero and its distance isn't, when the time is filled in from
+    /// the distance instead.
+    ///
+    /// Writers don't all fill in the distances as SR-4731 describes, so any
+    /// pair which disagreed by more than rounding is returned, with the
+    /// values it had, for the caller to check. Files whose units of distance
+    /// aren't recognised can't be synced.
+    pub fn sync_offsets(&mut self) -> Result<Vec<OffsetConflict>, &'static str> {
+        let fp = self.fixed_parameters.as_mut().ok_or("File has no fixed parameters block")?;
+        let distance_per_100ps = units::distance_per_100ps(&fp.units_of_distance, fp.group_index)
+            .ok_or("The units of distance are not recognised")?;
+        let mut conflicts = Vec::new();
+        let mut sync = |field: &'static str, time: &mut i32, distance: &mut i32| {
+            if *time == 0 {
+                *time = (*distance as f64 / distance_per_100ps).round() as i32;
+                return;
+            }
+            let synced = (*time as f64 * distance_per_100ps).round() as i32;
+            // Some writers truncate rather than round
+            if (synced - *distance).abs() > 1 {
+                conflicts.push(OffsetConflict { field, time: *time, distance: *distance });
+            }
+            *distance = synced;
+        };
+        sync("acquisition_offset", &mut fp.acquisition_offset, &mut fp.acquisition_offset_distance);
+        sync("acquisition_range", &mut fp.acquisition_range, &mut fp.acquisition_range_distance);
+        if let Some(gp) = self.general_parameters.as_mut() {
+            sync("user_offset", &mut gp.user_offset, &mut gp.user_offset_distance);
+        }
+        Ok(conflicts)
+    }
+
+    /// Change the group index, given as stored (the index x 100000), e.g. to
+    /// apply the cable's real index after testing. Times are what the OTDR
+    /// measured, so they are kept, and every distance in the file - event,
+    /// landmark and trace positions - moves with the new index. The distance
+    /// fields mirroring the offsets and range are scaled to match.
+    pub fn set_group_index(&mut self, group_index: i32) -> Result<(), &'static str> {
+        if group_index <= 0 {
+            return Err("The group index must be positive");
+        }
+        let fp = self.fixed_parameters.as_mut().ok_or("File has no fixed parameters block")?;
+        // Distances are proportional to the speed of light in the fibre
+        let ratio = units::metres_per_100ps(group_index) / units::metres_per_100ps(fp.group_index);
+        let scale = |distance: i32| (distance as f64 * ratio).round() as i32;
+        fp.group_index = group_index;
+        fp.acquisition_offset_distance = scale(fp.acquisition_offset_distance);
+        fp.acquisition_range_distance = scale(fp.acquisition_range_distance);
+        if let Some(gp) = self.general_parameters.as_mut() {
+            gp.user_offset_distance = scale(gp.user_offset_distance);
+        }
+        Ok(())
+    }
+
+    /// Retag the wavelength a file was recorded at, and the backscatter
+    /// coefficient that goes with it, e.g. where tests were taken with the
+    /// wrong wavelength or an approximate coefficient selected. The actual
+    /// wavelength follows the nominal unless given.
+    ///
+    /// The instrument worked out each event's reflectance from the
+    /// backscatter coefficient, and a reflectance moves dB for dB with it, so
+    /// reflective events are adjusted by the change in coefficient. The
+    /// optical return loss is not recomputed.
+    pub fn correct_wavelength(&mut self, correction: &WavelengthCorrection) -> Result<(), &'static str> {
+        let backscatter = match correction.backscatter_db {
+            Some(db) if !db.is_finite() || !(-3276.7..=0.0).contains(&db) => {
+                return Err("The backscatter coefficient must be between -3276.7 and 0 dB");
+            }
+            Some(db) => Some((-db * 10.0).round() as i16),
+            None => None,
+        };
+        if let Some(nominal) = correction.nominal_nm {
+            self.general_parameters.as_mut().ok_or("File has no general parameters block")?.nominal_wavelength = nominal;
+        }
+        let fp = self.fixed_parameters.as_mut().ok_or("File has no fixed parameters block")?;
+        if let Some(actual) = correction.actual_nm.or(correction.nominal_nm) {
+            fp.actual_wavelength = actual;
+        }
+        if let Some(backscatter) = backscatter {
+            // Coefficients are -dB*10 and reflectances dB*1000
+            let shift = (fp.backscatter_coefficient as i32 - backscatter as i32) * 100;
+            fp.backscatter_coefficient = backscatter;
+            if let Some(ke) = self.key_events.as_mut() {
+                let reflectances = ke.key_events.iter_mut().map(|e| &mut e.event_reflectance)
+                    .chain(std::iter::once(&mut ke.last_key_event.event_reflectance));
+                for reflectance in reflectances.filter(|r| **r != 0) {
+                    *reflectance += shift;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Find the events in the trace with analysis::detect_events and replace
+    /// the key events with them. The thresholds recorded in the fixed
+    /// parameters are used unless overridden, and the thresholds used are
+    /// recorded there.
+    ///
+    /// The key events the file had are kept, for audit, in a proprietary
+    /// block which original_events reads back. If the file has been
+    /// reanalysed before, the block already holds the events from before the
+    /// first time, and is left alone. Landmarks related to an event are
+    /// related to the new event nearest it, or to none if there isn't one
+    /// within the options' landmark tolerance.
+    pub fn reanalyze_events(&mut self, options: &ReanalyzeOptions) -> Result<(), &'static str> {
+        let file = EventThresholds::from_sor(self);
+        let thresholds = EventThresholds {
+            loss_db: options.loss_db.unwrap_or(file.loss_db),
+            reflectance_db: options.reflectance_db.unwrap_or(file.reflectance_db),
+            end_of_fibre_db: options.end_of_fibre_db.unwrap_or(file.end_of_fibre_db),
+        };
+        let key_events = crate::analysis::detect_events(self, &thresholds)?;
+        let fp = self.fixed_parameters.as_mut().ok_or("File has no fixed parameters block")?;
+        let threshold = |db: f64| (db * 1000.0).abs().round().min(u16::MAX as f64) as u16;
+        fp.loss_threshold = threshold(thresholds.loss_db);
+        fp.reflectance_threshold = threshold(thresholds.reflectance_db);
+        fp.end_of_fibre_threshold = threshold(thresholds.end_of_fibre_db);
+
+        let kept = self.proprietary_blocks.iter().any(|pb| &*pb.header == BLOCK_ID_ORIGINAL_EVENTS);
+        if !kept && self.key_events.is_some() {
+            let mut data = Vec::new();
+            self.gen_key_events(&mut data)?;
+            data.drain(..crate::parser::BLOCK_ID_KEYEVENTS.len() + 1);
+            self.map.block_info.push(BlockInfo {
+                identifier: BLOCK_ID_ORIGINAL_EVENTS.into(),
+                revision_number: self.map.revision_number,
+                size: 0,
+            });
+            self.proprietary_blocks.push(ProprietaryBlock { header: BLOCK_ID_ORIGINAL_EVENTS.into(), data: data.into() });
+        }
+        // Landmarks follow their events to the nearest new one, if any
+        let old_events = events(self);
+        self.key_events = Some(key_events);
+        let new_events = events(self);
+        let tolerance_m = options.landmark_tolerance_m.unwrap_or(5.0);
+        if let Some(lp) = self.link_parameters.as_mut() {
+            for l in lp.landmarks.iter_mut().filter(|l| l.related_event_number != 0) {
+                l.related_event_number = old_events.iter().find(|e| e.number == l.related_event_number)
+                    .and_then(|old| new_events.iter()
+                        .map(|e| (e.number, (e.distance_m - old.distance_m).abs()))
+                        .filter(|&(_, error)| error <= tolerance_m)
+                        .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap()))
+                    .map_or(0, |(number, _)| number);
+            }
+        }
+        Ok(())
+    }
+
+    /// The key events from before the file was first reanalysed with
+    /// reanalyze_events, if it has been
+    pub fn original_events(&self) -> Option<KeyEvents> {
+        let pb = self.proprietary_blocks.iter().find(|pb| &*pb.header == BLOCK_ID_ORIGINAL_EVENTS)?;
+        let mut block = crate::parser::BLOCK_ID_KEYEVENTS.as_bytes().to_vec();
+        block.push(0);
+        block.extend_from_slice(&pb.data);
+        crate::parser::key_events_block(&block).ok().map(|(_, ke)| ke)
+    }
+
+    /// Carry annotations over from another file of the same fibre, e.g. an
+    /// earlier test which was annotated in the field, to this one. Events are
+    /// matched by their distance from each file's user offset, and the other
+    /// file's comments are copied to their matches here. Events which were
+    /// added or moved by hand in the other file (with A or M as the second
+    /// character of their code) and have no match here are copied in too,
+    /// unless they lie beyond this file's last key event, and landmarks
+    /// related to later events are renumbered to match.
+    pub fn merge_events_from(&mut self, other: &SORFile, strategy: &MergeStrategy) -> Result<MergedEvents, &'static str> {
+        let theirs = other.key_events.as_ref().ok_or("The other file has no key events")?;
+        if self.key_events.is_none() {
+            return Err("File has no key events to merge into");
+        }
+        let matches = match_events(&events(other), &events(self), strategy.tolerance_m, f64::INFINITY);
+        let their_comment = |i: usize| theirs.key_events.get(i).map_or(&theirs.last_key_event.comment, |e| &e.comment);
+        let m_per_100ps = metres_per_100ps(self);
+        let their_m_per_100ps = metres_per_100ps(other);
+        let ke = self.key_events.as_mut().unwrap();
+        let mut merged = MergedEvents::default();
+        for pair in &matches.matched {
+            let incoming = their_comment(pair.a).trim();
+            let comment = ke.key_events.get_mut(pair.b).map_or(&mut ke.last_key_event.comment, |e| &mut e.comment);
+            let existing = comment.trim();
+            let new = match strategy.comments {
+                _ if incoming.is_empty() || incoming == existing => continue,
+                CommentMerge::Keep if !existing.is_empty() => continue,
+                CommentMerge::Append if !existing.is_empty() => format!("{}; {}", existing, incoming),
+                _ => incoming.to_owned(),
+            };
+            *comment = new.into();
+            merged.comments += 1;
+        }
+        if strategy.user_events {
+            let end = ke.last_key_event.event_propogation_time;
+            // The last key event is the end of the fibre, so is never copied
+            for e in matches.missing.iter().filter_map(|&i| theirs.key_events.get(i)) {
+                if !matches!(e.event_code.as_bytes().get(1), Some(b'A') | Some(b'M')) {
+                    continue;
+                }
+                let time = (e.event_propogation_time as f64 * their_m_per_100ps / m_per_100ps).round() as i32;
+                if time >= end {
+                    continue;
+                }
+                let shift = e.event_propogation_time - time;
+                let mut event = e.clone();
+                event.event_propogation_time = time;
+                shift_markers(&mut event.marker_location_1, shift);
+                shift_markers(&mut event.marker_location_2, shift);
+                shift_markers(&mut event.marker_location_3, shift);
+                shift_markers(&mut event.marker_location_4, shift);
+                shift_markers(&mut event.marker_location_5, shift);
+                let at = ke.key_events.partition_point(|k| k.event_propogation_time <= time);
+                ke.key_events.insert(at, event);
+                if let Some(lp) = self.link_parameters.as_mut() {
+                    let number = at as i16 + 1;
+                    for l in lp.landmarks.iter_mut().filter(|l| l.related_event_number >= number) {
+                        l.related_event_number += 1;
+                    }
+                }
+                merged.events += 1;
+            }
+            for (n, e) in ke.key_events.iter_mut().enumerate() {
+                e.event_number = n as i16 + 1;
+            }
+            ke.last_key_event.event_number = ke.key_events.len() as i16 + 1;
+            ke.number_of_key_events = ke.key_events.len() as i16 + 1;
+        }
+        Ok(merged)
+    }
+
+    /// Add an event by hand, as `KeyEvents::insert_event_at` does, also
+    /// renumbering the events landmarks are related to
+    pub fn insert_event_at(&mut self, distance_m: f64, template: EventTemplate) -> Result<&mut KeyEvent, &'static str> {
+        let fp = self.fixed_parameters.as_ref().ok_or("File has no fixed parameters block")?;
+        let ke = self.key_events.as_mut().ok_or("File has no key events")?;
+        let event = ke.insert_event_at(distance_m, template, fp)?;
+        let number = event.event_number;
+        if let Some(lp) = self.link_parameters.as_mut() {
+            for l in lp.landmarks.iter_mut().filter(|l| l.related_event_number >= number) {
+                l.related_event_number += 1;
+            }
+        }
+        let ke = self.key_events.as_mut().unwrap();
+        Ok(&mut ke.key_events[number as usize - 1])
+    }
+
+    /// The file as though the fibre had been tested from its far end, e.g.
+    /// to compare with a test which was, or to pair up with one in a
+    /// bidirectional analysis. The far end is the last key event, which
+    /// swaps places with the user offset: the data points run the other
+    /// way, the key events are listed from the far end with their markers
+    /// measured from it, and the first event becomes the end of the fibre.
+    /// Landmarks are reversed likewise, and the originating and terminating
+    /// locations swapped. The trace type becomes RT, or ST if the file was
+    /// already reversed, so reversing twice gives back the original.
+    ///
+    /// Losses and reflectances are left as they are, though they would
+    /// differ if measured from the far end, as would the trace's levels.
+    pub fn reversed(&self) -> Result<SORFile, &'static str> {
+        let mut sor = self.clone();
+        let ke = sor.key_events.take().ok_or("File has no key events to find the far end by")?;
+        let length = ke.last_key_event.event_propogation_time;
+        let fp = sor.fixed_parameters.as_mut().ok_or("File has no fixed parameters block")?;
+        if fp.data_spacing.len() > 1 {
+            return Err("Reversing files with several pulse widths is not supported");
+        }
+        fp.trace_type = if &*fp.trace_type == "RT" { "ST" } else { "RT" }.into();
+        let distance_per_100ps = units::distance_per_100ps(&fp.units_of_distance, fp.group_index);
+
+        // A time from the front panel T becomes 2U + L - T, for the user
+        // offset U and the time L from there to the far end
+        if let Some(gp) = sor.general_parameters.as_mut() {
+            core::mem::swap(&mut gp.originating_location, &mut gp.terminating_location);
+        }
+        let user_offset = sor.general_parameters.as_ref().map_or(0, |gp| gp.user_offset);
+        if let Some(dp) = sor.data_points.as_mut() {
+            let spacing = fp.data_spacing.first().copied().unwrap_or(0) as f64 / 10000.0;
+            if spacing <= 0.0 {
+                return Err("File has no data spacing");
+            }
+            let total: usize = dp.scale_factors.iter().map(|sf| sf.data.len()).sum();
+            dp.scale_factors.reverse();
+            for sf in dp.scale_factors.iter_mut() {
+                sf.data.reverse();
+            }
+            let last = fp.acquisition_offset as f64 + total.saturating_sub(1) as f64 * spacing;
+            let acquisition_offset = ((2 * user_offset + length) as f64 - last).round() as i32;
+            fp.acquisition_offset_distance = rescale_distance(fp.acquisition_offset_distance, fp.acquisition_offset,
+                                                              acquisition_offset, distance_per_100ps);
+            fp.acquisition_offset = acquisition_offset;
+        }
+
+        // Each event's lead-in fibre is now the section after it
+        let mut all = ke.key_events;
+        all.push(demote(&ke.last_key_event));
+        let lead_ins: Vec<i16> = all.iter().skip(1).map(|e| e.attenuation_coefficient_lead_in_fiber).chain(Some(0)).collect();
+        for (e, lead_in) in all.iter_mut().zip(lead_ins) {
+            e.attenuation_coefficient_lead_in_fiber = lead_in;
+            e.event_propogation_time = length - e.event_propogation_time;
+            flip_markers(e, length);
+        }
+        all.reverse();
+        let count = all.len() as i16;
+        for (n, e) in all.iter_mut().enumerate() {
+            e.event_number = n as i16 + 1;
+        }
+        if let Some(first) = all.first_mut() {
+            set_code_kind(&mut first.event_code, b'E', "F");
+        }
+        let last = all.pop().unwrap();
+        let old = ke.last_key_event;
+        let mut lke = promote(last, old.clone());
+        set_code_kind(&mut lke.event_code, b'F', "E");
+        lke.end_to_end_marker_position_1 = length - old.end_to_end_marker_position_2;
+        lke.end_to_end_marker_position_2 = length - old.end_to_end_marker_position_1;
+        lke.optical_return_loss_marker_position_1 = length - old.optical_return_loss_marker_position_2;
+        lke.optical_return_loss_marker_position_2 = length - old.optical_return_loss_marker_position_1;
+        sor.key_events = Some(KeyEvents { number_of_key_events: count, key_events: all, last_key_event: lke });
+
+        if let Some(lp) = sor.link_parameters.as_mut() {
+            for l in lp.landmarks.iter_mut() {
+                l.landmark_location = length - l.landmark_location;
+                if l.related_event_number != 0 {
+                    l.related_event_number = count + 1 - l.related_event_number;
+                }
+            }
+            lp.landmarks.reverse();
+            renumber(lp);
+        }
+        Ok(sor)
+    }
+
+    /// Two-point loss in dB*1000 between two times relative to the user
+    /// offset, which may fall a fraction of a point outside the trace
+    fn two_point_loss(&self, a: i32, b: i32) -> Option<i32> {
+        let trace = Trace::new(self).ok()?;
+        let x = trace.distance_m();
+        let clamp = |t: i32| trace.event_distance_m(t).clamp(x[0], x[x.len() - 1]);
+        trace.loss_two_point(clamp(a), clamp(b)).ok().map(|loss| (loss * 1000.0).round() as i32)
+    }
+}
+
+impl KeyEvents {
+    /// Add an event by hand, e.g. a splice the OTDR missed, at a distance in
+    /// metres from the user offset, converted to a propagation time with the
+    /// group index in the fixed parameters. The event is coded as added by
+    /// the user, takes the attenuation of the fibre it sits in from the event
+    /// after it, and is numbered in order of distance, along with the rest
+    /// and the last key event; the end-to-end and ORL markers are times, so
+    /// stay where they are. Events can't be added beyond the last key event,
+    /// which is the end of the fibre. The new event is returned for its
+    /// markers and so on to be filled in.
+    pub fn insert_event_at(&mut self, distance_m: f64, template: EventTemplate, fp: &FixedParametersBlock)
+                           -> Result<&mut KeyEvent, &'static str> {
+        if !distance_m.is_finite() || distance_m < 0.0 {
+            return Err("Events can't be added before the user offset");
+        }
+        let time = (distance_m / units::metres_per_100ps(fp.group_index)).round() as i32;
+        if time >= self.last_key_event.event_propogation_time {
+            return Err("Events can only be added before the end of the fibre");
+        }
+        let at = self.key_events.partition_point(|e| e.event_propogation_time <= time);
+        let attenuation = self.key_events.get(at)
+            .map_or(self.last_key_event.attenuation_coefficient_lead_in_fiber, |e| e.attenuation_coefficient_lead_in_fiber);
+        self.key_events.insert(at, KeyEvent {
+            event_number: 0,
+            event_propogation_time: time,
+            attenuation_coefficient_lead_in_fiber: attenuation,
+            event_loss: (template.loss_db * 1000.0).round().clamp(i16::MIN as f64, i16::MAX as f64) as i16,
+            event_reflectance: template.reflectance_db.map_or(0, |r| (r * 1000.0).round() as i32),
+            event_code: if template.reflectance_db.is_some() { "1A9999" } else { "0A9999" }.into(),
+            loss_measurement_technique: "OT".into(),
+            marker_location_1: 0,
+            marker_location_2: 0,
+            marker_location_3: 0,
+            marker_location_4: 0,
+            marker_location_5: if template.reflectance_db.is_some() { time } else { 0 },
+            comment: template.comment.into(),
+        });
+        for (n, e) in self.key_events.iter_mut().enumerate() {
+            e.event_number = n as i16 + 1;
+        }
+        self.last_key_event.event_number = self.key_events.len() as i16 + 1;
+        self.number_of_key_events = self.key_events.len() as i16 + 1;
+        Ok(&mut self.key_events[at])
+    }
+}
+
+/// An event to add by hand, for `KeyEvents::insert_event_at`
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct EventTemplate {
+    /// Loss in dB
+    pub loss_db: f64,
+    /// Reflectance in dB, e.g. -55.0, for a reflective event such as a
+    /// connector, or None for a non-reflective one such as a splice
+    pub reflectance_db: Option<f64>,
+    pub comment: String,
+}
+
+impl EventTemplate {
+    pub fn splice(loss_db: f64, comment: &str) -> EventTemplate {
+        EventTemplate { loss_db, reflectance_db: None, comment: comment.to_owned() }
+    }
+
+    pub fn connector(loss_db: f64, reflectance_db: f64, comment: &str) -> EventTemplate {
+        EventTemplate { loss_db, reflectance_db: Some(reflectance_db), comment: comment.to_owned() }
+    }
+}
+
+/// A correction to the wavelength of a file, for `SORFile::correct_wavelength`.
+/// Anything not given is left as it is
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct WavelengthCorrection {
+    /// Nominal wavelength, in nm
+    pub nominal_nm: Option<i16>,
+    /// Actual wavelength, in nm
+    pub actual_nm: Option<i16>,
+    /// Backscatter coefficient for a 1ns pulse, in dB, e.g. -81.8
+    pub backscatter_db: Option<f64>,
+}
+
+/// Thresholds for `SORFile::reanalyze_events`, in dB, overriding those
+/// recorded in the file
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ReanalyzeOptions {
+    /// Smallest loss (or gain) reported as a non-reflective event
+    pub loss_db: Option<f64>,
+    /// Smallest reflectance reported as a reflective event, e.g. -55.0
+    pub reflectance_db: Option<f64>,
+    /// Smallest loss taken to be the end of the fibre
+    pub end_of_fibre_db: Option<f64>,
+    /// How far, in metres, a new event can be from the one a landmark was
+    /// related to and take its place; by default 5m, as detection places
+    /// events a few metres from where an instrument might
+    pub landmark_tolerance_m: Option<f64>,
+}
+
+/// What `SORFile::merge_events_from` carries over, and how
+#[derive(Debug, PartialEq, Clone)]
+pub struct MergeStrategy {
+    /// How far apart events in the two files can be, in metres, and still be
+    /// taken to be the same
+    pub tolerance_m: f64,
+    /// What to do with a comment when the event here has one already
+    pub comments: CommentMerge,
+    /// Copy events added or moved by hand which have no match here
+    pub user_events: bool,
+}
+
+impl Default for MergeStrategy {
+    fn default() -> MergeStrategy {
+        MergeStrategy { tolerance_m: 2.0, comments: CommentMerge::Keep, user_events: true }
+    }
+}
+
+/// How to merge a comment into an event which has one already
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum CommentMerge {
+    /// Leave the existing comment alone
+    #[default]
+    Keep,
+    /// Replace it with the other file's
+    Replace,
+    /// Add the other file's after it, separated by a semicolon
+    Append,
+}
+
+/// What `SORFile::merge_events_from` changed
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct MergedEvents {
+    /// Events whose comment was set or changed
+    pub comments: usize,
+    /// Events copied from the other file
+    pub events: usize,
+}
+
+/// A time field and the distance field mirroring it which disagreed, as
+/// found by `SORFile::sync_offsets`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct OffsetConflict {
+    /// The time field's name, e.g. user_offset; the distance field's name
+    /// has _distance added
+    pub field: &'static str,
+    /// The time, in 100ps
+    pub time: i32,
+    /// The distance as it was, in tenths of the units of distance
+    pub distance: i32,
+}
+
+fn renumber(lp: &mut LinkParameters) {
+    for (n, l) in lp.landmarks.iter_mut().enumerate() {
+        l.landmark_number = n as i16 + 1;
+    }
+    lp.number_of_landmarks = lp.landmarks.len() as i16;
+}
+
+/// Marker locations of zero mean the marker is unused
+fn shift_markers(marker: &mut i32, shift: i32) {
+    if *marker != 0 {
+        *marker -= shift;
+    }
+}
+
+/// Measure an event's markers from the far end, where the markers on the
+/// OTDR side of the event - ML1 and ML2 for least squares, ML1 otherwise -
+/// trade places with those on the far side. Some writers put markers at the
+/// user offset, so zero is only taken to mean no markers if they all are
+fn flip_markers(e: &mut KeyEvent, length: i32) {
+    let markers = [e.marker_location_1, e.marker_location_2, e.marker_location_3, e.marker_location_4, e.marker_location_5];
+    if markers.iter().all(|&m| m == 0) {
+        return;
+    }
+    let flip = |m: i32| length - m;
+    let (m1, m2, m3, m4) = (e.marker_location_1, e.marker_location_2, e.marker_location_3, e.marker_location_4);
+    if &*e.loss_measurement_technique == "LS" {
+        e.marker_location_1 = flip(m4);
+        e.marker_location_2 = flip(m3);
+        e.marker_location_3 = flip(m2);
+        e.marker_location_4 = flip(m1);
+    } else {
+        e.marker_location_1 = flip(m2);
+        e.marker_location_2 = flip(m1);
+        e.marker_location_3 = flip(m3);
+        e.marker_location_4 = flip(m4);
+    }
+    e.marker_location_5 = flip(e.marker_location_5);
+}
+
+/// Replace the second character of an event code, e.g. E for the end of
+/// the fibre, if it is the one given
+fn set_code_kind(code: &mut Arc<str>, from: u8, to: &str) {
+    if code.as_bytes().get(1) == Some(&from) && code.is_ascii() {
+        *code = format!("{}{}{}", &code[..1], to, &code[2..]).into();
+    }
+}
+
+/// Turn a key event into the last key event, taking the end-to-end and ORL
+/// fields from the old last key event
+fn promote(e: KeyEvent, old: LastKeyEvent) -> LastKeyEvent {
+    LastKeyEvent {
+        event_number: e.event_number,
+        event_propogation_time: e.event_propogation_time,
+        attenuation_coefficient_lead_in_fiber: e.attenuation_coefficient_lead_in_fiber,
+        event_loss: e.event_loss,
+        event_reflectance: e.event_reflectance,
+        event_code: e.event_code,
+        loss_measurement_technique: e.loss_measurement_technique,
+        marker_location_1: e.marker_location_1,
+        marker_location_2: e.marker_location_2,
+        marker_location_3: e.marker_location_3,
+        marker_location_4: e.marker_location_4,
+        marker_location_5: e.marker_location_5,
+        comment: e.comment,
+        ..old
+    }
+}
+
+/// Scale a distance field along with the time field it mirrors. Writers
+/// disagree on how the two relate, so we preserve the existing ratio where
+/// there is one
+pub(crate) fn rescale_distance(distance: i32, old_time: i32, new_time: i32, distance_per_100ps: Option<f64>) -> i32 {
+    if old_time != 0 {
+        (distance as f64 * new_time as f64 / old_time as f64).round() as i32
+    } else {
+        distance_per_100ps.map_or(distance, |d| (new_time as f64 * d).round() as i32)
+    }
+}
+
+#[test]
+fn test_crop_launch_lead() {
+    let data = include_bytes!("../data/example1-noyes-ofl280.sor");
+    let original = crate::parser::parse_file(data).unwrap().1;
+    let mut sor = original.clone();
+    sor.crop(Some(5.0), None).unwrap();
+    let ke = sor.key_events.as_ref().unwrap();
+    assert_eq!(ke.number_of_key_events, 2);
+    assert_eq!(ke.key_events[0].event_number, 1);
+    // 5m is 244.8 units of 100ps at this group index
+    assert_eq!(ke.key_events[0].event_propogation_time, 532 - 245);
+    assert_eq!(ke.last_key_event.event_propogation_time, 182802 - 245);
+    let gp = sor.general_parameters.as_ref().unwrap();
+    assert_eq!(gp.user_offset, 24641 + 245);
+    // The first remaining point is at or just after the start of the span
+    let fp = sor.fixed_parameters.as_ref().unwrap();
+    assert!(fp.acquisition_offset >= gp.user_offset && fp.acquisition_offset - gp.user_offset < 11);
+    let n = sor.data_points.as_ref().unwrap().number_of_data_points;
+    assert_eq!(fp.n_data_points_for_pulse_widths_used, vec![n]);
+    assert_eq!(sor.data_points.as_ref().unwrap().scale_factors[0].data.len(), n as usize);
+    // Losing the first event's span reduces the end-to-end loss
+    assert!(ke.last_key_event.end_to_end_loss < original.key_events.unwrap().last_key_event.end_to_end_loss);
+    // The result is a valid file
+    let written = crate::parser::parse_file(&sor.to_bytes().unwrap()).unwrap().1;
+    assert_eq!(written.key_events, sor.key_events);
+    assert_eq!(written.data_points, sor.data_points);
+}
+
+#[test]
+fn test_crop_both_ends() {
+    let data = include_bytes!("../data/example1-noyes-ofl280.sor");
+    let mut sor = crate::parser::parse_file(data).unwrap().1;
+    sor.crop(Some(5.0), Some(2000.0)).unwrap();
+    let ke = sor.key_events.as_ref().unwrap();
+    // Only the 10.9m splice remains, and becomes the last key event
+    assert_eq!(ke.number_of_key_events, 1);
+    assert!(ke.key_events.is_empty());
+    assert_eq!(&*ke.last_key_event.event_code, "0F9999");
+    assert_eq!(ke.last_key_event.event_number, 1);
+    assert_eq!(ke.last_key_event.end_to_end_marker_position_2, (2000.0 / 0.02042878759795571f64).round() as i32 - 245);
+    assert!(sor.crop(Some(10.0), Some(5.0)).is_err());
+}
+
+#[test]
+fn test_crop_landmarks() {
+    let data = include_bytes!("../data/example1-noyes-ofl280.sor");
+    let mut sor = crate::parser::parse_file(data).unwrap().1;
+    sor.add_landmark("MH", 2.0).unwrap();
+    let m_per_100ps = metres_per_100ps(&sor);
+    sor.add_landmark("CL", 532.0 * m_per_100ps).unwrap();
+    sor.add_landmark("MH", 1000.0).unwrap();
+    sor.add_landmark("BD", 182802.0 * m_per_100ps).unwrap();
+    sor.relate_landmarks(1.0);
+    let related = |sor: &SORFile| sor.link_parameters.as_ref().unwrap().landmarks.iter()
+        .map(|l| (l.landmark_number, &*l.landmark_code, l.landmark_location, l.related_event_number))
+        .map(|(n, code, location, event)| (n, code.to_owned(), location, event))
+        .collect::<Vec<_>>();
+    assert_eq!(related(&sor)[1], (2, "CL".to_owned(), 532, 2));
+
+    // The landmark in the launch lead goes, and the rest move with the user
+    // offset and follow their events' numbers
+    sor.crop(Some(5.0), None).unwrap();
+    let mh = (1000.0 / 0.02042878759795571f64).round() as i32;
+    assert_eq!(related(&sor), vec![(1, "CL".to_owned(), 532 - 245, 1), (2, "MH".to_owned(), mh - 245, 0),
+                                   (3, "BD".to_owned(), 182802 - 245, 2)]);
+    // The far end's event is cropped away with its landmark, and the 1000m
+    // landmark stays unrelated
+    sor.crop(None, Some(1500.0)).unwrap();
+    assert_eq!(related(&sor), vec![(1, "CL".to_owned(), 532 - 245, 1), (2, "MH".to_owned(), mh - 245, 0)]);
+    let written = crate::parser::parse_file(&sor.to_bytes().unwrap()).unwrap().1;
+    assert_eq!(written.link_parameters, sor.link_parameters);
+}
+
+#[test]
+fn test_landmarks() {
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let mut sor = crate::parser::parse_file(data).unwrap().1;
+    assert!(sor.add_landmark("manhole", 10.0).is_err());
+    sor.add_landmark("MH", 1500.0).unwrap().comment = "Manhole 2".into();
+    let first = sor.add_landmark("MH", 478.0).unwrap();
+    first.set_position(51.507351, -0.127758).unwrap();
+    assert!(first.set_position(91.0, 0.0).is_err());
+    assert_eq!(first.landmark_number, 1);
+    // The first is at the gainer, event 2; there's no event near 1500m
+    assert_eq!(sor.relate_landmarks(2.0), 1);
+    let lp = sor.link_parameters.as_ref().unwrap();
+    assert_eq!(lp.number_of_landmarks, 2);
+    assert_eq!(lp.landmarks[0].related_event_number, 2);
+    assert_eq!(lp.landmarks[1].related_event_number, 0);
+    assert_eq!(lp.landmarks[1].landmark_number, 2);
+    assert!((sor.landmark_distance_m(&lp.landmarks[1]) - 1500.0).abs() < 0.02);
+    assert_eq!(lp.landmarks[0].position(), Some((51.507351, -0.127758)));
+    assert_eq!(lp.landmarks[1].position(), None);
+
+    // Landmarks survive being written out and read back
+    let written = crate::parser::parse_file(&sor.to_bytes().unwrap()).unwrap().1;
+    assert_eq!(written.link_parameters, sor.link_parameters);
+    assert_eq!(&*sor.remove_landmark(1).unwrap().comment, "");
+    assert_eq!(sor.link_parameters.as_ref().unwrap().landmarks[0].landmark_number, 1);
+    assert!(sor.remove_landmark(2).is_none());
+}
+
+#[test]
+fn test_sync_offsets() {
+    // Noyes gives the offset distances in whole metres, not tenths
+    let data = include_bytes!("../data/example1-noyes-ofl280.sor");
+    let mut sor = crate::parser::parse_file(data).unwrap().1;
+    let conflicts = sor.sync_offsets().unwrap();
+    let fields: Vec<&str> = conflicts.iter().map(|c| c.field).collect();
+    assert_eq!(fields, ["acquisition_offset", "acquisition_range", "user_offset"]);
+    assert_eq!(conflicts[2], OffsetConflict { field: "user_offset", time: 24641, distance: 503 });
+    // 503.4m, in tenths of a metre
+    assert_eq!(sor.general_parameters.as_ref().unwrap().user_offset_distance, 5034);
+    assert_eq!(sor.sync_offsets().unwrap(), vec![]);
+
+    // A missing time is filled in from its distance
+    sor.general_parameters.as_mut().unwrap().user_offset = 0;
+    assert_eq!(sor.sync_offsets().unwrap(), vec![]);
+    assert!((sor.general_parameters.as_ref().unwrap().user_offset - 24641).abs() <= 1);
+
+    sor.fixed_parameters.as_mut().unwrap().units_of_distance = "xx".into();
+    assert!(sor.sync_offsets().is_err());
+}
+
+#[test]
+fn test_set_group_index() {
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let original = crate::parser::parse_file(data).unwrap().1;
+    let mut sor = original.clone();
+    sor.set_group_index(146770 * 2).unwrap();
+    assert_eq!(sor.fixed_parameters.as_ref().unwrap().group_index, 146770 * 2);
+    // Twice the index halves every distance
+    let (before, after) = (events(&original), events(&sor));
+    assert!((after[3].distance_m * 2.0 - before[3].distance_m).abs() < 1e-9);
+    assert_eq!(sor.general_parameters.as_ref().unwrap().user_offset_distance, 758);
+    assert_eq!(sor.key_events, original.key_events);
+    assert!(sor.set_group_index(0).is_err());
+}
+
+#[test]
+fn test_correct_wavelength() {
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let original = crate::parser::parse_file(data).unwrap().1;
+    let mut sor = original.clone();
+    let correction = WavelengthCorrection { nominal_nm: Some(1550), backscatter_db: Some(-81.8), ..Default::default() };
+    sor.correct_wavelength(&correction).unwrap();
+    assert_eq!(sor.general_parameters.as_ref().unwrap().nominal_wavelength, 1550);
+    let fp = sor.fixed_parameters.as_ref().unwrap();
+    assert_eq!((fp.actual_wavelength, fp.backscatter_coefficient), (1550, 818));
+    // Reflectances follow the coefficient; non-reflective events stay at 0
+    let shift = (original.fixed_parameters.as_ref().unwrap().backscatter_coefficient as i32 - 818) * 100;
+    let pairs = original.key_events.as_ref().unwrap().key_events.iter().zip(&sor.key_events.as_ref().unwrap().key_events);
+    for (before, after) in pairs {
+        let expected = if before.event_reflectance == 0 { 0 } else { before.event_reflectance + shift };
+        assert_eq!(after.event_reflectance, expected);
+    }
+    assert!(sor.correct_wavelength(&WavelengthCorrection { backscatter_db: Some(10.0), ..Default::default() }).is_err());
+}
+
+#[test]
+fn test_reanalyze_events() {
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let original = crate::parser::parse_file(data).unwrap().1;
+    let mut sor = original.clone();
+    assert_eq!(sor.original_events(), None);
+    for (distance_m, event) in [(477.6, 2), (577.7, 3), (778.6, 4), (1447.7, 8), (2000.0, 0)] {
+        sor.add_landmark("MH", distance_m).unwrap().related_event_number = event;
+    }
+    sor.reanalyze_events(&ReanalyzeOptions { loss_db: Some(0.1), ..Default::default() }).unwrap();
+    assert_eq!(sor.fixed_parameters.as_ref().unwrap().loss_threshold, 100);
+    assert_ne!(sor.key_events, original.key_events);
+    // The event at 577.7m isn't found again, and the rest are renumbered
+    let related: Vec<i16> = sor.link_parameters.as_ref().unwrap().landmarks.iter().map(|l| l.related_event_number).collect();
+    assert_eq!(related, [2, 0, 3, 5, 0]);
+    // The original events survive being written out, and reanalysing again
+    let mut sor = crate::parser::parse_file(&sor.to_bytes().unwrap()).unwrap().1;
+    assert_eq!(sor.original_events(), original.key_events);
+    sor.reanalyze_events(&ReanalyzeOptions::default()).unwrap();
+    assert_eq!(sor.original_events(), original.key_events);
+    assert_eq!(sor.proprietary_blocks.len(), original.proprietary_blocks.len() + 1);
+}
+
+#[test]
+fn test_merge_events_from() {
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let fresh = crate::parser::parse_file(data).unwrap().1;
+    let mut annotated = fresh.clone();
+    let ke = annotated.key_events.as_mut().unwrap();
+    ke.key_events[1].comment = "splice tray 3".into();
+    ke.key_events[2].comment = "gainer".into();
+    // An event the technician added between two others
+    let mut added = ke.key_events[3].clone();
+    added.event_propogation_time = (ke.key_events[3].event_propogation_time + ke.key_events[4].event_propogation_time) / 2;
+    added.event_code = "0A9999".into();
+    added.comment = "manhole 12".into();
+    ke.key_events.insert(4, added);
+
+    let mut sor = fresh.clone();
+    sor.key_events.as_mut().unwrap().key_events[2].comment = "checked".into();
+    sor.add_landmark("MH", 478.0).unwrap().related_event_number = 3;
+    sor.add_landmark("MH", 1500.0).unwrap().related_event_number = 5;
+    let merged = sor.merge_events_from(&annotated, &MergeStrategy::default()).unwrap();
+    assert_eq!(merged, MergedEvents { comments: 1, events: 1 });
+    // The added event is now number 5, so the landmark related to the old
+    // event 5 follows it to 6
+    let related: Vec<i16> = sor.link_parameters.as_ref().unwrap().landmarks.iter().map(|l| l.related_event_number).collect();
+    assert_eq!(related, [3, 6]);
+    let ke = sor.key_events.as_ref().unwrap();
+    assert_eq!(&*ke.key_events[1].comment, "splice tray 3");
+    assert_eq!(&*ke.key_events[2].comment, "checked");
+    assert_eq!(&*ke.key_events[4].comment, "manhole 12");
+    assert_eq!(ke.key_events[4].event_number, 5);
+    assert_eq!(ke.number_of_key_events, fresh.key_events.as_ref().unwrap().number_of_key_events + 1);
+    assert_eq!(ke.last_key_event.event_number, ke.number_of_key_events);
+
+    // Merging again changes nothing
+    assert_eq!(sor.merge_events_from(&annotated, &MergeStrategy::default()).unwrap(), MergedEvents::default());
+
+    let mut sor = fresh.clone();
+    sor.key_events.as_mut().unwrap().key_events[2].comment = "checked".into();
+    let strategy = MergeStrategy { comments: CommentMerge::Append, user_events: false, ..Default::default() };
+    assert_eq!(sor.merge_events_from(&annotated, &strategy).unwrap(), MergedEvents { comments: 2, events: 0 });
+    assert_eq!(&*sor.key_events.as_ref().unwrap().key_events[2].comment, "checked; gainer");
+}
+
+#[test]
+fn test_insert_event_at() {
+    let data = include_bytes!("../data/example1-noyes-ofl280.sor");
+    let original = crate::parser::parse_file(data).unwrap().1;
+    let mut sor = original.clone();
+    sor.set_link_parameters(LinkParameters { number_of_landmarks: 0, landmarks: Vec::new() });
+    sor.add_landmark("MH", 3000.0).unwrap().related_event_number = 3;
+    let event = sor.insert_event_at(1000.0, EventTemplate::splice(0.08, "splice closure 4")).unwrap();
+    assert_eq!((event.event_number, event.event_loss, &*event.event_code), (3, 80, "0A9999"));
+    let ke = sor.key_events.as_ref().unwrap();
+    let old = original.key_events.as_ref().unwrap();
+    // Between the second event and the end of the fibre
+    assert!((ke.key_events[2].event_propogation_time as f64 * metres_per_100ps(&sor) - 1000.0).abs() < 0.02);
+    assert_eq!(ke.key_events[2].attenuation_coefficient_lead_in_fiber, old.last_key_event.attenuation_coefficient_lead_in_fiber);
+    assert_eq!((ke.number_of_key_events, ke.last_key_event.event_number), (4, 4));
+    assert_eq!(ke.last_key_event.end_to_end_marker_position_2, old.last_key_event.end_to_end_marker_position_2);
+    assert_eq!(sor.link_parameters.as_ref().unwrap().landmarks[0].related_event_number, 4);
+
+    let reparsed = crate::parser::parse_file(&sor.to_bytes().unwrap()).unwrap().1;
+    assert_eq!(reparsed.key_events, sor.key_events);
+    assert!(sor.insert_event_at(1e6, EventTemplate::connector(0.3, -50.0, "")).is_err());
+    assert!(sor.insert_event_at(-1.0, EventTemplate::default()).is_err());
+}
+
+#[test]
+fn test_reversed() {
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let original = crate::parser::parse_file(data).unwrap().1;
+    let reversed = original.reversed().unwrap();
+    assert_eq!(&*reversed.fixed_parameters.as_ref().unwrap().trace_type, "RT");
+    let (ke, old) = (reversed.key_events.as_ref().unwrap(), original.key_events.as_ref().unwrap());
+    let length = old.last_key_event.event_propogation_time;
+    assert_eq!(ke.number_of_key_events, old.number_of_key_events);
+    // The far end is now the first event, and the first the end
+    assert_eq!((ke.key_events[0].event_propogation_time, ke.key_events[0].event_code.as_bytes()[1]), (0, b'F'));
+    assert_eq!(ke.last_key_event.event_propogation_time, length - old.key_events[0].event_propogation_time);
+    assert_eq!(ke.last_key_event.event_code.as_bytes()[1], b'E');
+    assert_eq!(ke.key_events[1].event_propogation_time, length - old.key_events[old.key_events.len() - 1].event_propogation_time);
+    assert_eq!(ke.key_events[1].event_loss, old.key_events[old.key_events.len() - 1].event_loss);
+
+    // The trace runs the other way, about the same points
+    let (forward, backward) = (Trace::new(&original).unwrap(), Trace::new(&reversed).unwrap());
+    let end = forward.event_distance_m(length);
+    for x in [100.0, 1000.0, 3000.0] {
+        let there = forward.power_at(forward.event_distance_m(0) + x).unwrap();
+        let back = backward.power_at(end - x).unwrap();
+        assert!((there - back).abs() < 0.05, "{} {} {}", x, there, back);
+    }
+
+    let again = reversed.reversed().unwrap();
+    assert_eq!(again.fixed_parameters, original.fixed_parameters);
+    assert_eq!(again.data_points, original.data_points);
+    assert_eq!(again.key_events, original.key_events);
+}
@@ -0,0 +1,147 @@
+/// This module converts a SORFile into a JSON value in which raw SR-4731
+/// encodings are replaced with engineering units - dB, metres, seconds and
+/// ISO-8601 timestamps - for consumers that don't want to learn the format.
+///
+/// Converted fields are renamed with a unit suffix (e.g. `event_loss` becomes
+/// `event_loss_db`) so that they can't be mistaken for raw values. Conversions
+/// follow the specification; vendor quirks (such as averaging times stored
+/// in the wrong units) are not corrected.
+use serde_json::{Map, Value};
+use crate::analysis::metres_per_100ps;
+use crate::types::SORFile;
+use crate::units::metres_per_unit;
+
+/// Convert a SORFile to a JSON value with fields in engineering units
+pub fn to_value(sor: &SORFile) -> Result<Value, serde_json::Error> {
+    let mut value = serde_json::to_value(sor)?;
+    // Times are one-way, in 100ps units
+    let m_per_100ps = metres_per_100ps(sor);
+    let to_m = |t: f64| t * m_per_100ps;
+    // Distances are in 10x units_of_distance; leave them be if the unit is
+    // one we don't recognise
+    let unit_m = sor.fixed_parameters.as_ref().and_then(|fp| metres_per_unit(&fp.units_of_distance));
+    let to_db = |v: f64| v / 1000.0;
+
+    if let Some(gp) = value.get_mut("general_parameters").and_then(Value::as_object_mut) {
+        convert(gp, "user_offset", "user_offset_m", to_m);
+        if let Some(unit_m) = unit_m {
+            convert(gp, "user_offset_distance", "user_offset_distance_m", |d| d / 10.0 * unit_m);
+        }
+    }
+    if let Some(fp) = value.get_mut("fixed_parameters").and_then(Value::as_object_mut) {
+        if let Some(ts) = sor.fixed_parameters.as_ref().map(|fp| fp.date_time_stamp) {
+            fp.insert("date_time_stamp".to_owned(), Value::String(iso8601(ts)));
+        }
+        convert(fp, "acquisition_offset", "acquisition_offset_m", to_m);
+        convert(fp, "acquisition_range", "acquisition_range_m", to_m);
+        convert(fp, "front_panel_offset", "front_panel_offset_m", to_m);
+        // Data spacing is the time taken to acquire 10,000 points; we give
+        // the distance between adjacent points
+        convert(fp, "data_spacing", "data_spacing_m", |t| to_m(t / 10000.0));
+        if let Some(unit_m) = unit_m {
+            convert(fp, "acquisition_offset_distance", "acquisition_offset_distance_m", |d| d / 10.0 * unit_m);
+            convert(fp, "acquisition_range_distance", "acquisition_range_distance_m", |d| d / 10.0 * unit_m);
+        }
+        convert(fp, "group_index", "group_index", |g| g / 100000.0);
+        convert(fp, "backscatter_coefficient", "backscatter_coefficient_db", |b| -b / 10.0);
+        convert(fp, "averaging_time", "averaging_time_s", |t| t / 10.0);
+        let nf_scale = sor.fixed_parameters.as_ref().map_or(1000, |fp| fp.noise_floor_scale_factor) as f64 / 1000.0;
+        convert(fp, "noise_floor_level", "noise_floor_level_db", |l| -l / 1000.0 * nf_scale);
+        convert(fp, "power_offset_first_point", "power_offset_first_point_db", to_db);
+        convert(fp, "loss_threshold", "loss_threshold_db", to_db);
+        convert(fp, "reflectance_threshold", "reflectance_threshold_db", |r| -r / 1000.0);
+        convert(fp, "end_of_fibre_threshold", "end_of_fibre_threshold_db", to_db);
+    }
+    if let Some(ke) = value.get_mut("key_events").and_then(Value::as_object_mut) {
+        if let Some(events) = ke.get_mut("key_events").and_then(Value::as_array_mut) {
+            for event in events.iter_mut().filter_map(Value::as_object_mut) {
+                convert_event(event, to_m);
+            }
+        }
+        if let Some(lke) = ke.get_mut("last_key_event").and_then(Value::as_object_mut) {
+            convert_event(lke, to_m);
+            convert(lke, "end_to_end_loss", "end_to_end_loss_db", to_db);
+            convert(lke, "optical_return_loss", "optical_return_loss_db", to_db);
+            for key in &["end_to_end_marker_position_1", "end_to_end_marker_position_2",
+                         "optical_return_loss_marker_position_1", "optical_return_loss_marker_position_2"] {
+                convert(lke, key, &format!("{}_m", key), to_m);
+            }
+        }
+    }
+    if let Some(dp) = value.get_mut("data_points").and_then(Value::as_object_mut) {
+        if let Some(sfs) = dp.get_mut("scale_factors").and_then(Value::as_array_mut) {
+            for sf in sfs.iter_mut().filter_map(Value::as_object_mut) {
+                let scale = sf.get("scale_factor").and_then(Value::as_f64).unwrap_or(1000.0) / 1000.0;
+                convert(sf, "data", "data_db", |pt| -pt * scale / 1000.0);
+            }
+        }
+    }
+    Ok(value)
+}
+
+/// Convert the fields common to KeyEvent and LastKeyEvent
+fn convert_event(event: &mut Map<String, Value>, to_m: impl Fn(f64) -> f64) {
+    convert(event, "event_propogation_time", "event_propogation_time_m", &to_m);
+    convert(event, "attenuation_coefficient_lead_in_fiber", "attenuation_coefficient_lead_in_fiber_db_per_km", |a| a / 1000.0);
+    convert(event, "event_loss", "event_loss_db", |l| l / 1000.0);
+    convert(event, "event_reflectance", "event_reflectance_db", |r| r / 1000.0);
+    for n in 1..=5 {
+        convert(event, &format!("marker_location_{}", n), &format!("marker_location_{}_m", n), &to_m);
+    }
+}
+
+/// Replace a numeric field (or array of numbers) with its converted value
+/// under a new name
+fn convert(map: &mut Map<String, Value>, key: &str, new_key: &str, f: impl Fn(f64) -> f64) {
+    let converted = match map.remove(key) {
+        Some(Value::Array(values)) => Value::Array(values.iter().map(|v| number(v.as_f64().map(&f))).collect()),
+        Some(v) => number(v.as_f64().map(&f)),
+        None => return,
+    };
+    map.insert(new_key.to_owned(), converted);
+}
+
+fn number(v: Option<f64>) -> Value {
+    v.and_then(serde_json::Number::from_f64).map_or(Value::Null, Value::Number)
+}
+
+/// Format seconds since the unix epoch as an ISO-8601 UTC timestamp
+pub fn iso8601(timestamp: u32) -> String {
+    let days = (timestamp / 86400) as i64;
+    let secs = timestamp % 86400;
+    // Civil-from-days, per Howard Hinnant's date algorithms
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+#[test]
+fn test_iso8601() {
+    assert_eq!(iso8601(0), "1970-01-01T00:00:00Z");
+    assert_eq!(iso8601(1569835674), "2019-09-30T09:27:54Z");
+    assert_eq!(iso8601(951782400), "2000-02-29T00:00:00Z");
+}
+
+#[test]
+fn test_engineering_units() {
+    let data = include_bytes!("../data/example1-noyes-ofl280.sor");
+    let sor = crate::parser::parse_file(data).unwrap().1;
+    let value = to_value(&sor).unwrap();
+    let fp = &value["fixed_parameters"];
+    assert_eq!(fp["date_time_stamp"], "2019-09-30T09:27:54Z");
+    assert_eq!(fp["noise_floor_level_db"], -30.342);
+    assert_eq!(fp["backscatter_coefficient_db"], -80.2);
+    assert!(fp.get("acquisition_range").is_none());
+    assert!((fp["acquisition_range_m"].as_f64().unwrap() - 6128.6).abs() < 0.1);
+    assert!((value["general_parameters"]["user_offset_m"].as_f64().unwrap() - 503.4).abs() < 0.1);
+    let event = &value["key_events"]["key_events"][0];
+    assert_eq!(event["event_loss_db"].as_f64().unwrap(), sor.key_events.as_ref().unwrap().key_events[0].event_loss as f64 / 1000.0);
+    assert_eq!(value["data_points"]["scale_factors"][0]["data_db"][0], -22.153);
+}
@@ -0,0 +1,184 @@
+/// This module writes traces out for bulk numeric work: as NumPy `.npy` and
+/// `.npz` files, which need no extra dependencies, and, with the `parquet`,
+/// `sqlite` and `hdf5` features, as Parquet datasets, SQLite databases or
+/// HDF5 files of many files.
+use crc::{Crc, CRC_32_ISO_HDLC};
+use crate::analysis::Trace;
+use crate::types::SORFile;
+
+#[cfg(feature = "parquet")]
+mod parquet;
+#[cfg(feature = "parquet")]
+pub use self::parquet::to_parquet;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::to_sqlite;
+#[cfg(feature = "hdf5")]
+mod hdf5;
+#[cfg(feature = "hdf5")]
+pub use self::hdf5::to_hdf5;
+
+/// Encode little-endian doubles as a NumPy `.npy` file, in C order with the
+/// given shape
+pub fn to_npy(values: &[f64], shape: &[usize]) -> Result<Vec<u8>, &'static str> {
+    if shape.iter().product::<usize>() != values.len() {
+        return Err("The shape doesn't match the number of values");
+    }
+    let dims: Vec<String> = shape.iter().map(|d| d.to_string()).collect();
+    // One-dimensional shapes need the trailing comma to be a Python tuple
+    let shape = if dims.len() == 1 { format!("({},)", dims[0]) } else { format!("({})", dims.join(", ")) };
+    let mut header = format!("{{'descr': '<f8', 'fortran_order': False, 'shape': {}, }}", shape);
+    // The magic, version and header length take 10 bytes, and the whole
+    // preamble is padded with spaces to a multiple of 64, ending in a newline
+    let padded = (10 + header.len() + 1).div_ceil(64) * 64;
+    header += &" ".repeat(padded - 10 - header.len() - 1);
+    header.push('\n');
+    let mut bytes = Vec::with_capacity(padded + values.len() * 8);
+    bytes.extend(b"\x93NUMPY\x01\x00");
+    bytes.extend(&(header.len() as u16).to_le_bytes());
+    bytes.extend(header.as_bytes());
+    for v in values {
+        bytes.extend(&v.to_le_bytes());
+    }
+    Ok(bytes)
+}
+
+/// A trace's distances from the user offset in metres and levels in dB, as
+/// a 2xN `.npy` array, e.g. for `distance, level = numpy.load(...)`
+pub fn trace_to_npy(sor: &SORFile) -> Result<Vec<u8>, &'static str> {
+    let trace = Trace::new(sor)?;
+    let user_offset_m = trace.user_offset_m();
+    let mut values: Vec<f64> = trace.distance_m().iter().map(|d| d - user_offset_m).collect();
+    values.extend(trace.points_db());
+    to_npy(&values, &[2, trace.points_db().len()])
+}
+
+/// A trace as a NumPy `.npz` archive holding `distance_m`, the distance of
+/// each point from the user offset in metres, and `level_db`, its level in
+/// dB. The archive is uncompressed, as `numpy.savez` writes.
+pub fn to_npz(sor: &SORFile) -> Result<Vec<u8>, &'static str> {
+    let trace = Trace::new(sor)?;
+    let user_offset_m = trace.user_offset_m();
+    let distance_m: Vec<f64> = trace.distance_m().iter().map(|d| d - user_offset_m).collect();
+    let n = distance_m.len();
+    zip_stored(&[
+        ("distance_m.npy", to_npy(&distance_m, &[n])?),
+        ("level_db.npy", to_npy(trace.points_db(), &[n])?),
+    ])
+}
+
+/// The data points exactly as stored, without scale factors applied, as a
+/// stream of little-endian u16s: those of one run of points sharing a scale
+/// factor if one is given by index, otherwise every run in file order
+pub fn to_raw_u16(sor: &SORFile, run: Option<usize>) -> Result<Vec<u8>, &'static str> {
+    let scale_factors = &sor.data_points.as_ref().ok_or("No data points in file")?.scale_factors;
+    let runs = match run {
+        Some(n) => scale_factors.get(n..=n).ok_or("No such scale factor")?,
+        None => &scale_factors[..],
+    };
+    Ok(runs.iter().flat_map(|sf| &sf.data).flat_map(|point| point.to_le_bytes()).collect())
+}
+
+/// A ZIP archive of the given files, stored without compression
+fn zip_stored(files: &[(&str, Vec<u8>)]) -> Result<Vec<u8>, &'static str> {
+    // Stored entries are dated 1980-01-01, the earliest DOS date
+    const DOS_DATE: u16 = 0x21;
+    // Without the ZIP64 extensions, sizes and offsets must fit in 32 bits
+    let total: usize = files.iter().map(|(name, data)| 76 + 2 * name.len() + data.len()).sum();
+    if total + 22 > u32::MAX as usize {
+        return Err("Arrays over 4GB can't be written to an archive");
+    }
+    let crc = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+    let mut bytes = Vec::new();
+    let mut directory = Vec::new();
+    for (name, data) in files {
+        let (size, offset) = (data.len() as u32, bytes.len() as u32);
+        let checksum = crc.checksum(data);
+        // Fields shared by the local header and the central directory:
+        // version needed, flags, method, time, date, CRC, sizes, name length
+        let mut common = Vec::new();
+        common.extend(&20u16.to_le_bytes());
+        common.extend(&0u16.to_le_bytes());
+        common.extend(&0u16.to_le_bytes());
+        common.extend(&0u16.to_le_bytes());
+        common.extend(&DOS_DATE.to_le_bytes());
+        common.extend(&checksum.to_le_bytes());
+        common.extend(&size.to_le_bytes());
+        common.extend(&size.to_le_bytes());
+        common.extend(&(name.len() as u16).to_le_bytes());
+
+        bytes.extend(&0x04034b50u32.to_le_bytes());
+        bytes.extend(&common);
+        bytes.extend(&0u16.to_le_bytes());
+        bytes.extend(name.as_bytes());
+        bytes.extend(data);
+
+        directory.extend(&0x02014b50u32.to_le_bytes());
+        directory.extend(&20u16.to_le_bytes());
+        directory.extend(&common);
+        // Extra field and comment lengths, disk number, internal and
+        // external attributes
+        directory.extend(&[0u8; 12]);
+        directory.extend(&offset.to_le_bytes());
+        directory.extend(name.as_bytes());
+    }
+    let offset = bytes.len() as u32;
+    let count = files.len() as u16;
+    bytes.extend(&directory);
+    bytes.extend(&0x06054b50u32.to_le_bytes());
+    bytes.extend(&0u16.to_le_bytes());
+    bytes.extend(&0u16.to_le_bytes());
+    bytes.extend(&count.to_le_bytes());
+    bytes.extend(&count.to_le_bytes());
+    bytes.extend(&(directory.len() as u32).to_le_bytes());
+    bytes.extend(&offset.to_le_bytes());
+    bytes.extend(&0u16.to_le_bytes());
+    Ok(bytes)
+}
+
+#[test]
+fn test_to_npy() {
+    let npy = to_npy(&[1.0, 2.5, -3.0], &[3]).unwrap();
+    assert_eq!(&npy[..8], b"\x93NUMPY\x01\x00");
+    // As numpy writes it, the preamble is 128 bytes
+    assert_eq!(u16::from_le_bytes([npy[8], npy[9]]), 118);
+    assert_eq!(npy.len(), 128 + 24);
+    let header = std::str::from_utf8(&npy[10..128]).unwrap();
+    assert!(header.starts_with("{'descr': '<f8', 'fortran_order': False, 'shape': (3,), }"));
+    assert!(header.ends_with(" \n"));
+    assert_eq!(&npy[136..144], &2.5f64.to_le_bytes());
+    assert!(to_npy(&[1.0, 2.0], &[3]).is_err());
+
+    let sor = crate::parser::parse_file(include_bytes!("../data/example1-noyes-ofl280.sor")).unwrap().1;
+    let n = Trace::new(&sor).unwrap().points_db().len();
+    let npy = trace_to_npy(&sor).unwrap();
+    assert!(std::str::from_utf8(&npy[10..128]).unwrap().contains(&format!("'shape': (2, {}),", n)));
+}
+
+#[test]
+fn test_to_npz() {
+    let sor = crate::parser::parse_file(include_bytes!("../data/example1-noyes-ofl280.sor")).unwrap().1;
+    let n = Trace::new(&sor).unwrap().points_db().len();
+    let npz = to_npz(&sor).unwrap();
+    // Two local headers, each with its array, and two directory entries
+    let entry = |name: &str| 30 + name.len() + 128 + n * 8;
+    let directory = |name: &str| 46 + name.len();
+    assert_eq!(npz.len(), entry("distance_m.npy") + entry("level_db.npy")
+               + directory("distance_m.npy") + directory("level_db.npy") + 22);
+    assert_eq!(&npz[..4], b"PK\x03\x04");
+    assert_eq!(&npz[30..44], b"distance_m.npy");
+    assert_eq!(&npz[npz.len() - 22..npz.len() - 18], b"PK\x05\x06");
+    assert!(to_npz(&crate::parser::parse_metadata(include_bytes!("../data/example1-noyes-ofl280.sor")).unwrap().1).is_err());
+}
+
+#[test]
+fn test_to_raw_u16() {
+    let sor = crate::parser::parse_file(include_bytes!("../data/example1-noyes-ofl280.sor")).unwrap().1;
+    let points = &sor.data_points.as_ref().unwrap().scale_factors[0].data;
+    let raw = to_raw_u16(&sor, None).unwrap();
+    assert_eq!(raw.len(), points.len() * 2);
+    assert_eq!(&raw[2..4], &points[1].to_le_bytes());
+    assert_eq!(to_raw_u16(&sor, Some(0)).unwrap(), raw);
+    assert!(to_raw_u16(&sor, Some(1)).is_err());
+}
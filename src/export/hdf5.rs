@@ -0,0 +1,621 @@
+/// HDF5 files of many traces, the format several research groups working on
+/// fibre sensing keep OTDR data in. The file is written directly rather than
+/// through the HDF5 bindings, so no native library is needed.
+///
+/// Files are grouped by their nominal wavelength, e.g. `/1550nm/fibre1.sor`,
+/// with a `wavelength_nm` attribute on each wavelength's group. Each file's
+/// group is named by its file name, has a `filename` attribute giving the
+/// name it was exported with, and holds:
+///
+/// - `distance_m` and `level_db`, the trace, with distances from the user
+///   offset, if the file has data points
+/// - `events`, a group of key event columns, `number`, `distance_m`,
+///   `loss_db`, `reflectance_db`, `code` and `comment`, if it has key events
+/// - `general_parameters`, `supplier_parameters` and `fixed_parameters`,
+///   groups with each field of the block as an attribute
+///
+/// Only what's needed is written: version 2 object headers, and contiguous
+/// datasets of doubles, 64-bit integers and fixed-length UTF-8 strings, all
+/// of which HDF5 1.8 and later read. Links and attributes are always held
+/// compactly in their object headers, never in dense storage, so each header
+/// raises HDF5's limit on how many it can hold compactly (by default 8) to
+/// however many it has.
+use std::borrow::Borrow;
+use std::collections::BTreeMap;
+use std::path::Path;
+use crate::analysis::{events, Trace};
+use crate::types::SORFile;
+
+const SIGNATURE: &[u8] = b"\x89HDF\r\n\x1a\n";
+/// Signature, versions, sizes and flags, four addresses and the checksum
+const SUPERBLOCK_SIZE: usize = 48;
+const UNDEFINED: u64 = u64::MAX;
+
+const DATASPACE: u8 = 0x01;
+const LINK_INFO: u8 = 0x02;
+const DATATYPE: u8 = 0x03;
+const FILL_VALUE: u8 = 0x05;
+const LINK: u8 = 0x06;
+const DATA_LAYOUT: u8 = 0x08;
+const GROUP_INFO: u8 = 0x0A;
+const ATTRIBUTE: u8 = 0x0C;
+
+/// An attribute's or a dataset's values, which are all of one type
+#[derive(Debug, PartialEq, Clone)]
+enum Values {
+    Float(Vec<f64>),
+    Int(Vec<i64>),
+    Text(Vec<String>),
+}
+
+impl Values {
+    fn len(&self) -> usize {
+        match self {
+            Values::Float(values) => values.len(),
+            Values::Int(values) => values.len(),
+            Values::Text(values) => values.len(),
+        }
+    }
+
+    /// Bytes per value; strings are as long as the longest
+    fn size(&self) -> usize {
+        match self {
+            Values::Float(_) | Values::Int(_) => 8,
+            Values::Text(values) => values.iter().map(String::len).max().unwrap_or(0).max(1),
+        }
+    }
+
+    fn datatype(&self) -> Vec<u8> {
+        let mut message = Vec::new();
+        match self {
+            Values::Float(_) => {
+                // Version 1, class 1: little-endian IEEE 754, with an implied
+                // leading mantissa bit and the sign in bit 63
+                message.extend(&[0x11, 0x20, 63, 0]);
+                message.extend(&8u32.to_le_bytes());
+                // Bit offset and precision, then the exponent's and
+                // mantissa's locations and sizes, and the exponent bias
+                message.extend(&0u16.to_le_bytes());
+                message.extend(&64u16.to_le_bytes());
+                message.extend(&[52, 11, 0, 52]);
+                message.extend(&1023u32.to_le_bytes());
+            }
+            Values::Int(_) => {
+                // Class 0, signed and little-endian
+                message.extend(&[0x10, 0x08, 0, 0]);
+                message.extend(&8u32.to_le_bytes());
+                message.extend(&0u16.to_le_bytes());
+                message.extend(&64u16.to_le_bytes());
+            }
+            Values::Text(_) => {
+                // Class 3, null-padded UTF-8
+                message.extend(&[0x13, 0x11, 0, 0]);
+                message.extend(&(self.size() as u32).to_le_bytes());
+            }
+        }
+        message
+    }
+
+    fn raw(&self) -> Vec<u8> {
+        match self {
+            Values::Float(values) => values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+            Values::Int(values) => values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+            Values::Text(values) => {
+                let size = self.size();
+                values.iter().flat_map(|s| s.bytes().chain(std::iter::repeat(0)).take(size)).collect()
+            }
+        }
+    }
+}
+
+struct Attribute {
+    name: String,
+    values: Values,
+    /// A single value rather than an array of them
+    scalar: bool,
+}
+
+impl Attribute {
+    fn text(name: &str, value: &str) -> Attribute {
+        Attribute { name: name.to_owned(), values: Values::Text(vec![value.to_owned()]), scalar: true }
+    }
+}
+
+#[derive(Default)]
+struct Group {
+    attributes: Vec<Attribute>,
+    groups: Vec<(String, Group)>,
+    datasets: Vec<(String, Values)>,
+}
+
+/// A block's fields as attributes. Strings are trimmed, arrays of numbers,
+/// such as the pulse widths used, are stored as arrays, and anything else
+/// as JSON text.
+fn fields<T: serde::Serialize>(block: &T) -> Vec<Attribute> {
+    use serde_json::Value;
+    let object = match serde_json::to_value(block) {
+        Ok(Value::Object(object)) => object,
+        _ => return Vec::new(),
+    };
+    object.into_iter().filter_map(|(name, value)| {
+        let (values, scalar) = match &value {
+            Value::Null => return None,
+            Value::Bool(b) => (Values::Int(vec![*b as i64]), true),
+            Value::Number(n) => (n.as_i64().map_or_else(|| Values::Float(vec![n.as_f64().unwrap_or(f64::NAN)]), |i| Values::Int(vec![i])), true),
+            Value::String(s) => (Values::Text(vec![s.trim().to_owned()]), true),
+            Value::Array(array) => match array.iter().map(Value::as_i64).collect::<Option<Vec<_>>>() {
+                Some(ints) => (Values::Int(ints), false),
+                None => match array.iter().map(Value::as_f64).collect::<Option<Vec<_>>>() {
+                    Some(floats) => (Values::Float(floats), false),
+                    None => (Values::Text(vec![value.to_string()]), true),
+                },
+            },
+            Value::Object(_) => (Values::Text(vec![value.to_string()]), true),
+        };
+        Some(Attribute { name, values, scalar })
+    }).collect()
+}
+
+/// A file's group, with its trace, events and parameters
+fn file_group(filename: &str, sor: &SORFile) -> Group {
+    let mut group = Group { attributes: vec![Attribute::text("filename", filename)], ..Group::default() };
+    if let Ok(trace) = Trace::new(sor) {
+        let user_offset_m = trace.user_offset_m();
+        group.datasets.push(("distance_m".to_owned(), Values::Float(trace.distance_m().iter().map(|d| d - user_offset_m).collect())));
+        group.datasets.push(("level_db".to_owned(), Values::Float(trace.points_db().to_vec())));
+    }
+    let events = events(sor);
+    if !events.is_empty() {
+        let comments = sor.key_events.iter()
+            .flat_map(|ke| ke.key_events.iter().map(|e| &e.comment).chain(std::iter::once(&ke.last_key_event.comment)));
+        let datasets = vec![
+            ("number".to_owned(), Values::Int(events.iter().map(|e| e.number as i64).collect())),
+            ("distance_m".to_owned(), Values::Float(events.iter().map(|e| e.distance_m).collect())),
+            ("loss_db".to_owned(), Values::Float(events.iter().map(|e| e.loss_db).collect())),
+            ("reflectance_db".to_owned(), Values::Float(events.iter().map(|e| e.reflectance_db).collect())),
+            ("code".to_owned(), Values::Text(events.iter().map(|e| e.code.clone()).collect())),
+            ("comment".to_owned(), Values::Text(comments.map(|c| c.trim().to_owned()).collect())),
+        ];
+        group.groups.push(("events".to_owned(), Group { datasets, ..Group::default() }));
+    }
+    let blocks = vec![
+        ("general_parameters", sor.general_parameters.as_ref().map(fields)),
+        ("supplier_parameters", sor.supplier_parameters.as_ref().map(fields)),
+        ("fixed_parameters", sor.fixed_parameters.as_ref().map(fields)),
+    ];
+    for (name, attributes) in blocks {
+        if let Some(attributes) = attributes {
+            group.groups.push((name.to_owned(), Group { attributes, ..Group::default() }));
+        }
+    }
+    group
+}
+
+/// Write the given files, named by their filenames, as an HDF5 file grouped
+/// by wavelength. Files without general parameters are put under `0nm`. Two
+/// files at the same wavelength can't have the same file name.
+pub fn to_hdf5<I, N, S>(files: I) -> Result<Vec<u8>, String>
+    where I: IntoIterator<Item = (N, S)>, N: AsRef<str>, S: Borrow<SORFile> {
+    let mut wavelengths: BTreeMap<i16, Group> = BTreeMap::new();
+    for (filename, sor) in files {
+        let (filename, sor) = (filename.as_ref(), sor.borrow());
+        let name = Path::new(filename).file_name().and_then(|name| name.to_str())
+            .ok_or_else(|| format!("{} has no file name", filename))?;
+        let wavelength = sor.general_parameters.as_ref().map_or(0, |gp| gp.nominal_wavelength);
+        let group = wavelengths.entry(wavelength).or_insert_with(|| Group {
+            attributes: vec![Attribute { name: "wavelength_nm".to_owned(), values: Values::Int(vec![wavelength as i64]), scalar: true }],
+            ..Group::default()
+        });
+        if group.groups.iter().any(|(existing, _)| existing == name) {
+            return Err(format!("Two files at {}nm are named {}", wavelength, name));
+        }
+        group.groups.push((name.to_owned(), file_group(filename, sor)));
+    }
+    write(&Group {
+        groups: wavelengths.into_iter().map(|(wavelength, group)| (format!("{}nm", wavelength), group)).collect(),
+        ..Group::default()
+    })
+}
+
+/// An HDF5 file with the given root group
+fn write(root: &Group) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![0; SUPERBLOCK_SIZE];
+    let root = write_group(&mut bytes, root)?;
+    let mut superblock = SIGNATURE.to_vec();
+    // Version 2, with 8-byte addresses and lengths, and no flags
+    superblock.extend(&[2, 8, 8, 0]);
+    // The base address, the superblock extension (there is none), the end of
+    // the file and the root group
+    superblock.extend(&0u64.to_le_bytes());
+    superblock.extend(&UNDEFINED.to_le_bytes());
+    superblock.extend(&(bytes.len() as u64).to_le_bytes());
+    superblock.extend(&root.to_le_bytes());
+    let checksum = lookup3(&superblock);
+    superblock.extend(&checksum.to_le_bytes());
+    bytes[..SUPERBLOCK_SIZE].copy_from_slice(&superblock);
+    Ok(bytes)
+}
+
+/// Write a group's children and then its object header, returning its
+/// address
+fn write_group(bytes: &mut Vec<u8>, group: &Group) -> Result<u64, String> {
+    let mut messages = Vec::new();
+    // Link info, with no fractal heap or B-tree as the links are all held
+    // in the object header, and group info, with the limit on compact links
+    // raised to suit
+    let mut link_info = vec![0, 0];
+    link_info.extend(&UNDEFINED.to_le_bytes());
+    link_info.extend(&UNDEFINED.to_le_bytes());
+    message(&mut messages, LINK_INFO, 0, &link_info)?;
+    let mut group_info = vec![0, 1];
+    group_info.extend(phase_change(group.groups.len() + group.datasets.len())?);
+    message(&mut messages, GROUP_INFO, 0, &group_info)?;
+    for (name, child) in &group.groups {
+        let address = write_group(bytes, child)?;
+        message(&mut messages, LINK, 0, &link(name, address)?)?;
+    }
+    for (name, values) in &group.datasets {
+        let address = write_dataset(bytes, values)?;
+        message(&mut messages, LINK, 0, &link(name, address)?)?;
+    }
+    for attribute in &group.attributes {
+        message(&mut messages, ATTRIBUTE, 0, &attribute_message(attribute)?)?;
+    }
+    object_header(bytes, &messages, group.attributes.len())
+}
+
+/// Write a dataset's values and then its object header, returning its
+/// address
+fn write_dataset(bytes: &mut Vec<u8>, values: &Values) -> Result<u64, String> {
+    let raw = values.raw();
+    let address = if raw.is_empty() { UNDEFINED } else { bytes.len() as u64 };
+    bytes.extend(&raw);
+    let mut messages = Vec::new();
+    message(&mut messages, DATASPACE, 0, &dataspace(Some(values.len())))?;
+    // The datatype is constant
+    message(&mut messages, DATATYPE, 1, &values.datatype())?;
+    // Version 3, allocated late and filled if a fill value is set, which
+    // it isn't
+    message(&mut messages, FILL_VALUE, 0, &[3, 0x0A])?;
+    // Version 3, contiguous, at the address with the size
+    let mut layout = vec![3, 1];
+    layout.extend(&address.to_le_bytes());
+    layout.extend(&(raw.len() as u64).to_le_bytes());
+    message(&mut messages, DATA_LAYOUT, 0, &layout)?;
+    object_header(bytes, &messages, 0)
+}
+
+/// Append a version 2 object header holding the messages, of which some
+/// are attributes, returning its address
+fn object_header(bytes: &mut Vec<u8>, messages: &[u8], attributes: usize) -> Result<u64, String> {
+    let phase_change = phase_change(attributes)?;
+    let address = bytes.len();
+    bytes.extend(b"OHDR");
+    // Version 2, with the limits on compact attributes given, the size of
+    // the messages in four bytes and no times
+    bytes.extend(&[2, 0x12]);
+    bytes.extend(&phase_change);
+    bytes.extend(&(messages.len() as u32).to_le_bytes());
+    bytes.extend(messages);
+    let checksum = lookup3(&bytes[address..]);
+    bytes.extend(&checksum.to_le_bytes());
+    Ok(address as u64)
+}
+
+/// The most links or attributes to hold compactly, and the fewest to hold
+/// densely, for an object with the given number of them. HDF5's defaults
+/// are 8 and 6; the first is raised if need be, as nothing is written
+/// densely.
+fn phase_change(count: usize) -> Result<Vec<u8>, String> {
+    if count > u16::MAX as usize {
+        return Err("Too many files or fields for one HDF5 group".to_owned());
+    }
+    let mut limits = (count.max(8) as u16).to_le_bytes().to_vec();
+    limits.extend(&6u16.to_le_bytes());
+    Ok(limits)
+}
+
+fn message(messages: &mut Vec<u8>, kind: u8, flags: u8, data: &[u8]) -> Result<(), String> {
+    if data.len() > u16::MAX as usize {
+        return Err("An attribute is too large for an HDF5 object header".to_owned());
+    }
+    messages.push(kind);
+    messages.extend(&(data.len() as u16).to_le_bytes());
+    messages.push(flags);
+    messages.extend(data);
+    Ok(())
+}
+
+/// A scalar dataspace, or one of a single dimension
+fn dataspace(len: Option<usize>) -> Vec<u8> {
+    match len {
+        None => vec![2, 0, 0, 0],
+        Some(len) => {
+            let mut message = vec![2, 1, 0, 1];
+            message.extend(&(len as u64).to_le_bytes());
+            message
+        }
+    }
+}
+
+/// A hard link to the object at an address
+fn link(name: &str, address: u64) -> Result<Vec<u8>, String> {
+    if name.is_empty() || name == "." || name.len() > u16::MAX as usize {
+        return Err(format!("{:?} can't be the name of an HDF5 object", name));
+    }
+    // Version 1, with the name's character set given and its length in one
+    // byte or two
+    let long = name.len() > u8::MAX as usize;
+    let mut message = vec![1, 0x10 | long as u8, 1];
+    if long {
+        message.extend(&(name.len() as u16).to_le_bytes());
+    } else {
+        message.push(name.len() as u8);
+    }
+    message.extend(name.as_bytes());
+    message.extend(&address.to_le_bytes());
+    Ok(message)
+}
+
+fn attribute_message(attribute: &Attribute) -> Result<Vec<u8>, String> {
+    let datatype = attribute.values.datatype();
+    let dataspace = dataspace(Some(attribute.values.len()).filter(|_| !attribute.scalar));
+    // Version 3, with the sizes of the null-terminated UTF-8 name, the
+    // datatype and the dataspace
+    let mut message = vec![3, 0];
+    message.extend(&(attribute.name.len() as u16 + 1).to_le_bytes());
+    message.extend(&(datatype.len() as u16).to_le_bytes());
+    message.extend(&(dataspace.len() as u16).to_le_bytes());
+    message.push(1);
+    message.extend(attribute.name.as_bytes());
+    message.push(0);
+    message.extend(&datatype);
+    message.extend(&dataspace);
+    message.extend(&attribute.values.raw());
+    Ok(message)
+}
+
+/// Bob Jenkins' lookup3 hash, `hashlittle` with an initial value of 0, which
+/// HDF5 checksums its metadata with
+fn lookup3(key: &[u8]) -> u32 {
+    fn mix(a: &mut u32, b: &mut u32, c: &mut u32) {
+        *a = a.wrapping_sub(*c); *a ^= c.rotate_left(4); *c = c.wrapping_add(*b);
+        *b = b.wrapping_sub(*a); *b ^= a.rotate_left(6); *a = a.wrapping_add(*c);
+        *c = c.wrapping_sub(*b); *c ^= b.rotate_left(8); *b = b.wrapping_add(*a);
+        *a = a.wrapping_sub(*c); *a ^= c.rotate_left(16); *c = c.wrapping_add(*b);
+        *b = b.wrapping_sub(*a); *b ^= a.rotate_left(19); *a = a.wrapping_add(*c);
+        *c = c.wrapping_sub(*b); *c ^= b.rotate_left(4); *b = b.wrapping_add(*a);
+    }
+    fn finish(a: &mut u32, b: &mut u32, c: &mut u32) {
+        *c ^= *b; *c = c.wrapping_sub(b.rotate_left(14));
+        *a ^= *c; *a = a.wrapping_sub(c.rotate_left(11));
+        *b ^= *a; *b = b.wrapping_sub(a.rotate_left(25));
+        *c ^= *b; *c = c.wrapping_sub(b.rotate_left(16));
+        *a ^= *c; *a = a.wrapping_sub(c.rotate_left(4));
+        *b ^= *a; *b = b.wrapping_sub(a.rotate_left(14));
+        *c ^= *b; *c = c.wrapping_sub(b.rotate_left(24));
+    }
+    let word = |bytes: &[u8]| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    let mut a = 0xdeadbeefu32.wrapping_add(key.len() as u32);
+    let (mut b, mut c) = (a, a);
+    let mut key = key;
+    while key.len() > 12 {
+        a = a.wrapping_add(word(&key[0..4]));
+        b = b.wrapping_add(word(&key[4..8]));
+        c = c.wrapping_add(word(&key[8..12]));
+        mix(&mut a, &mut b, &mut c);
+        key = &key[12..];
+    }
+    if key.is_empty() {
+        return c;
+    }
+    // The last 1-12 bytes, padded with zeros
+    let mut tail = [0u8; 12];
+    tail[..key.len()].copy_from_slice(key);
+    a = a.wrapping_add(word(&tail[0..4]));
+    b = b.wrapping_add(word(&tail[4..8]));
+    c = c.wrapping_add(word(&tail[8..12]));
+    finish(&mut a, &mut b, &mut c);
+    c
+}
+
+/// What the test reads back of an object: its links, its attributes and, if
+/// it's a dataset, its values
+#[cfg(test)]
+#[derive(Debug, Default)]
+struct Object {
+    links: BTreeMap<String, u64>,
+    attributes: BTreeMap<String, Values>,
+    values: Option<Values>,
+}
+
+#[cfg(test)]
+fn read_object(bytes: &[u8], address: u64) -> Object {
+    use std::convert::TryInto;
+    let u16_at = |b: &[u8]| u16::from_le_bytes([b[0], b[1]]) as usize;
+    let u64_at = |b: &[u8]| u64::from_le_bytes(b[..8].try_into().unwrap());
+    let rank = |dataspace: &[u8]| if dataspace[1] == 0 { 1 } else { u64_at(&dataspace[4..]) as usize };
+    let decode = |datatype: &[u8], len: usize, raw: &[u8]| {
+        let size = u32::from_le_bytes(datatype[4..8].try_into().unwrap()) as usize;
+        assert_eq!(raw.len(), len * size);
+        match datatype[0] & 0x0f {
+            0 => Values::Int(raw.chunks(8).map(|v| i64::from_le_bytes(v.try_into().unwrap())).collect()),
+            1 => Values::Float(raw.chunks(8).map(|v| f64::from_le_bytes(v.try_into().unwrap())).collect()),
+            3 => Values::Text(raw.chunks(size).map(|s| std::str::from_utf8(s).unwrap().trim_end_matches('\0').to_owned()).collect()),
+            class => panic!("Unexpected datatype class {}", class),
+        }
+    };
+
+    let at = address as usize;
+    assert_eq!(&bytes[at..at + 6], b"OHDR\x02\x12");
+    let max_compact = u16_at(&bytes[at + 6..]);
+    let size = u32::from_le_bytes(bytes[at + 10..at + 14].try_into().unwrap()) as usize;
+    let end = at + 14 + size;
+    assert_eq!(lookup3(&bytes[at..end]).to_le_bytes(), bytes[end..end + 4]);
+    let mut object = Object::default();
+    let (mut datatype, mut len, mut data, mut max_links) = (None, 0, None, 8);
+    let mut messages = &bytes[at + 14..end];
+    while !messages.is_empty() {
+        let (kind, body) = (messages[0], &messages[4..4 + u16_at(&messages[1..])]);
+        match kind {
+            LINK => {
+                let (name_len, name_at) = if body[1] & 1 == 1 { (u16_at(&body[3..]), 5) } else { (body[3] as usize, 4) };
+                let name = std::str::from_utf8(&body[name_at..name_at + name_len]).unwrap().to_owned();
+                object.links.insert(name, u64_at(&body[name_at + name_len..]));
+            }
+            GROUP_INFO => max_links = u16_at(&body[2..]),
+            DATASPACE => len = rank(body),
+            DATATYPE => datatype = Some(body),
+            DATA_LAYOUT => data = Some((u64_at(&body[2..]) as usize, u64_at(&body[10..]) as usize)),
+            ATTRIBUTE => {
+                let (name_size, datatype_size, dataspace_size) = (u16_at(&body[2..]), u16_at(&body[4..]), u16_at(&body[6..]));
+                let name = std::str::from_utf8(&body[9..8 + name_size]).unwrap().to_owned();
+                let datatype = &body[9 + name_size..];
+                let dataspace = &datatype[datatype_size..];
+                let values = decode(datatype, rank(dataspace), &dataspace[dataspace_size..]);
+                object.attributes.insert(name, values);
+            }
+            _ => (),
+        }
+        messages = &messages[4 + body.len()..];
+    }
+    assert!(object.links.len() <= max_links);
+    assert!(object.attributes.len() <= max_compact);
+    if let (Some(datatype), Some((address, size))) = (datatype, data) {
+        let raw = if size == 0 { &[][..] } else { &bytes[address..address + size] };
+        object.values = Some(decode(datatype, len, raw));
+    }
+    object
+}
+
+#[test]
+fn test_lookup3() {
+    assert_eq!(lookup3(b""), 0xdeadbeef);
+    assert_eq!(lookup3(b"Four score and seven years ago"), 0x17770551);
+}
+
+#[test]
+fn test_to_hdf5() {
+    use std::convert::TryInto;
+    let paths = ["data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor", "data/example4-exfo-ftb4ftbx730c-mfdgainer-1550nm.sor",
+                 "data/example1-noyes-ofl280.sor"];
+    let files: Vec<(&str, SORFile)> = paths.iter()
+        .map(|&path| (path, crate::parser::parse_file(&std::fs::read(path).unwrap()).unwrap().1))
+        .collect();
+    let h5 = to_hdf5(files.iter().map(|(path, sor)| (path, sor))).unwrap();
+
+    assert_eq!(&h5[..12], b"\x89HDF\r\n\x1a\n\x02\x08\x08\x00");
+    assert_eq!(lookup3(&h5[..44]).to_le_bytes(), h5[44..48]);
+    let address_at = |at: usize| u64::from_le_bytes(h5[at..at + 8].try_into().unwrap());
+    assert_eq!(address_at(28), h5.len() as u64);
+    let root = read_object(&h5, address_at(36));
+    assert_eq!(root.links.keys().collect::<Vec<_>>(), ["1310nm", "1550nm"]);
+
+    let wavelength = read_object(&h5, root.links["1310nm"]);
+    assert_eq!(wavelength.attributes["wavelength_nm"], Values::Int(vec![1310]));
+    assert_eq!(wavelength.links.keys().collect::<Vec<_>>(), ["example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor"]);
+    let links = read_object(&h5, root.links["1550nm"]).links;
+    assert_eq!(links.keys().collect::<Vec<_>>(), ["example1-noyes-ofl280.sor", "example4-exfo-ftb4ftbx730c-mfdgainer-1550nm.sor"]);
+    let file = read_object(&h5, wavelength.links["example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor"]);
+    assert_eq!(file.attributes["filename"], Values::Text(vec![paths[0].to_owned()]));
+    let (_, sor) = &files[0];
+    let trace = Trace::new(sor).unwrap();
+    let level = read_object(&h5, file.links["level_db"]);
+    assert_eq!(level.values, Some(Values::Float(trace.points_db().to_vec())));
+    let distance = read_object(&h5, file.links["distance_m"]).values;
+    assert_eq!(distance, Some(Values::Float(trace.distance_m().iter().map(|d| d - trace.user_offset_m()).collect())));
+
+    let events_group = read_object(&h5, file.links["events"]);
+    let column = |name: &str| read_object(&h5, events_group.links[name]).values.unwrap();
+    let events = events(sor);
+    assert_eq!(column("number"), Values::Int(events.iter().map(|e| e.number as i64).collect()));
+    assert_eq!(column("loss_db"), Values::Float(events.iter().map(|e| e.loss_db).collect()));
+    assert_eq!(column("code"), Values::Text(events.iter().map(|e| e.code.clone()).collect()));
+    assert_eq!(column("comment").len(), events.len());
+
+    let parameters = |name: &str| read_object(&h5, file.links[name]).attributes;
+    assert_eq!(parameters("general_parameters")["fiber_id"], Values::Text(vec!["Fiber1".to_owned()]));
+    assert_eq!(parameters("general_parameters")["nominal_wavelength"], Values::Int(vec![1310]));
+    let sp = sor.supplier_parameters.as_ref().unwrap();
+    assert_eq!(parameters("supplier_parameters")["otdr_mainframe_id"], Values::Text(vec![sp.otdr_mainframe_id.trim().to_owned()]));
+    let fp = sor.fixed_parameters.as_ref().unwrap();
+    assert_eq!(parameters("fixed_parameters")["pulse_widths_used"], Values::Int(fp.pulse_widths_used.iter().map(|&pw| pw as i64).collect()));
+    assert_eq!(parameters("fixed_parameters")["group_index"], Values::Int(vec![fp.group_index as i64]));
+
+    let twice = [(paths[0], &files[0].1), ("elsewhere/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor", &files[0].1)];
+    assert!(to_hdf5(twice.iter().copied()).is_err());
+    assert_eq!(to_hdf5(Vec::<(&str, SORFile)>::new()).unwrap().len(), SUPERBLOCK_SIZE + 14 + (4 + 18) + (4 + 6) + 4);
+}
+
+/// A group with every kind of value the writer has, and more links and
+/// attributes than HDF5 holds compactly by default
+#[cfg(test)]
+fn golden() -> Group {
+    let scalar = |name: &str, values| Attribute { name: name.to_owned(), values, scalar: true };
+    let array = |name: &str, values| Attribute { name: name.to_owned(), values, scalar: false };
+    let mut attributes = vec![
+        Attribute::text("text", "Fibre \u{2116}1"),
+        Attribute::text("empty", ""),
+        scalar("int", Values::Int(vec![-1310])),
+        scalar("float", Values::Float(vec![1.4682])),
+        array("ints", Values::Int(vec![10, 30, 100])),
+        array("floats", Values::Float(vec![0.5, -0.25])),
+        array("texts", Values::Text(vec!["a".to_owned(), "bc".to_owned()])),
+    ];
+    attributes.extend((0..4).map(|i| scalar(&format!("extra_{}", i), Values::Int(vec![i]))));
+    let mut groups: Vec<(String, Group)> = (0..9).map(|i| (format!("group_{}", i), Group::default())).collect();
+    groups.push(("data".to_owned(), Group {
+        datasets: vec![
+            ("floats".to_owned(), Values::Float(vec![0.0, 1.5, -2.25])),
+            ("ints".to_owned(), Values::Int(vec![1, 2, 3])),
+            ("texts".to_owned(), Values::Text(vec!["1F9999".to_owned(), "0A9999LS".to_owned()])),
+            ("empty".to_owned(), Values::Float(Vec::new())),
+        ],
+        ..Group::default()
+    }));
+    Group { attributes, groups, datasets: Vec::new() }
+}
+
+#[test]
+fn test_golden() {
+    use std::convert::TryInto;
+    let h5 = write(&golden()).unwrap();
+    assert_eq!(h5, &include_bytes!("../../data/golden.h5")[..]);
+    let root = read_object(&h5, u64::from_le_bytes(h5[36..44].try_into().unwrap()));
+    assert_eq!(root.links.len(), 10);
+    assert_eq!(root.attributes.len(), 11);
+    assert_eq!(root.attributes["text"], Values::Text(vec!["Fibre \u{2116}1".to_owned()]));
+    assert_eq!(root.attributes["floats"], Values::Float(vec![0.5, -0.25]));
+    let data = read_object(&h5, root.links["data"]);
+    assert_eq!(read_object(&h5, data.links["texts"]).values, Some(Values::Text(vec!["1F9999".to_owned(), "0A9999LS".to_owned()])));
+    assert_eq!(read_object(&h5, data.links["empty"]).values, Some(Values::Float(Vec::new())));
+}
+
+/// Opens the golden file and an export of the sample files with h5dump, so
+/// the HDF5 library itself checks them. It needs the HDF5 tools installed,
+/// e.g. the hdf5-tools package, so is run with `cargo test -- --ignored`
+#[test]
+#[ignore]
+fn test_h5dump() {
+    let dump = |path: &Path| {
+        let output = std::process::Command::new("h5dump").arg(path).output().expect("h5dump is not installed");
+        assert!(output.status.success(), "h5dump failed: {}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let golden = dump(Path::new("data/golden.h5"));
+    for name in ["ATTRIBUTE \"extra_3\"", "GROUP \"group_8\"", "DATASET \"texts\"", "\"0A9999LS\""] {
+        assert!(golden.contains(name), "{} is missing from {}", name, golden);
+    }
+
+    let paths = ["data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor", "data/example1-noyes-ofl280.sor"];
+    let files: Vec<(&str, SORFile)> = paths.iter()
+        .map(|&path| (path, crate::parser::parse_file(&std::fs::read(path).unwrap()).unwrap().1))
+        .collect();
+    let path = std::env::temp_dir().join(format!("otdrs-hdf5-{}.h5", std::process::id()));
+    std::fs::write(&path, to_hdf5(files.iter().map(|(path, sor)| (path, sor))).unwrap()).unwrap();
+    let export = dump(&path);
+    std::fs::remove_file(&path).unwrap();
+    for name in ["GROUP \"1310nm\"", "GROUP \"example1-noyes-ofl280.sor\"", "DATASET \"level_db\"", "ATTRIBUTE \"pulse_widths_used\""] {
+        assert!(export.contains(name), "{} is missing from the export", name);
+    }
+}
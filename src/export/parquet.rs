@@ -0,0 +1,155 @@
+/// Parquet datasets of many files, so that large archives of traces can be
+/// loaded into Spark, DuckDB and the like without parsing SOR files there.
+///
+/// Two files are written into a directory: `samples.parquet`, with one row
+/// per data point, and `events.parquet`, with one row per key event. Every
+/// row carries its file's metadata, so the two can be queried on their own
+/// or joined on the filename. Each SOR file is written as its own row group,
+/// so only one file's data need be held in memory at a time.
+use std::borrow::Borrow;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+use parquet::basic::Compression;
+use parquet::data_type::{ByteArray, ByteArrayType, DoubleType, Int32Type, Int64Type};
+use parquet::errors::Result;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use crate::analysis::{events, Trace};
+use crate::types::SORFile;
+
+const METADATA_COLUMNS: &str = "
+    REQUIRED BYTE_ARRAY filename (UTF8);
+    REQUIRED BYTE_ARRAY cable_id (UTF8);
+    REQUIRED BYTE_ARRAY fiber_id (UTF8);
+    REQUIRED INT32 wavelength_nm;
+    REQUIRED INT32 pulse_width_ns;
+    REQUIRED INT64 acquired (TIMESTAMP(MILLIS, true));";
+
+const SAMPLES_SCHEMA: &str = "message samples {
+    METADATA
+    REQUIRED DOUBLE distance_m;
+    REQUIRED DOUBLE level_db;
+}";
+
+const EVENTS_SCHEMA: &str = "message events {
+    METADATA
+    REQUIRED INT32 event_number;
+    REQUIRED DOUBLE distance_m;
+    REQUIRED DOUBLE loss_db;
+    REQUIRED DOUBLE reflectance_db;
+    REQUIRED BYTE_ARRAY code (UTF8);
+    REQUIRED BYTE_ARRAY comment (UTF8);
+}";
+
+/// A column's values for one row group, in schema order
+enum Column {
+    Text(Vec<ByteArray>),
+    Int32(Vec<i32>),
+    Int64(Vec<i64>),
+    Double(Vec<f64>),
+}
+
+/// The metadata columns, repeated for each of a file's rows
+fn metadata_columns(filename: &str, sor: &SORFile, rows: usize) -> Vec<Column> {
+    let gp = sor.general_parameters.as_ref();
+    let fp = sor.fixed_parameters.as_ref();
+    let text = |s: &str| Column::Text(vec![ByteArray::from(s.trim()); rows]);
+    vec![
+        text(filename),
+        text(gp.map_or("", |gp| &*gp.cable_id)),
+        text(gp.map_or("", |gp| &*gp.fiber_id)),
+        Column::Int32(vec![gp.map_or(0, |gp| gp.nominal_wavelength as i32); rows]),
+        Column::Int32(vec![fp.and_then(|fp| fp.pulse_widths_used.first()).map_or(0, |&pw| pw as i32); rows]),
+        Column::Int64(vec![fp.map_or(0, |fp| fp.date_time_stamp as i64 * 1000); rows]),
+    ]
+}
+
+fn writer(path: &Path, schema: &str) -> Result<SerializedFileWriter<File>> {
+    let schema = parse_message_type(&schema.replace("METADATA", METADATA_COLUMNS))?;
+    let properties = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+    SerializedFileWriter::new(File::create(path)?, Arc::new(schema), Arc::new(properties))
+}
+
+fn write_row_group(writer: &mut SerializedFileWriter<File>, columns: Vec<Column>) -> Result<()> {
+    let mut row_group = writer.next_row_group()?;
+    let mut columns = columns.into_iter();
+    while let Some(mut column) = row_group.next_column()? {
+        match columns.next() {
+            Some(Column::Text(values)) => column.typed::<ByteArrayType>().write_batch(&values, None, None)?,
+            Some(Column::Int32(values)) => column.typed::<Int32Type>().write_batch(&values, None, None)?,
+            Some(Column::Int64(values)) => column.typed::<Int64Type>().write_batch(&values, None, None)?,
+            Some(Column::Double(values)) => column.typed::<DoubleType>().write_batch(&values, None, None)?,
+            None => unreachable!("Every column in the schema has values"),
+        };
+        column.close()?;
+    }
+    row_group.close()?;
+    Ok(())
+}
+
+/// Write `samples.parquet` and `events.parquet` for the given files, named
+/// by their filenames, into a directory, which is created if need be. Files
+/// without data points contribute only events, and vice versa. Distances
+/// are from each file's user offset.
+pub fn to_parquet<I, N, S>(files: I, path: &Path) -> Result<()>
+    where I: IntoIterator<Item = (N, S)>, N: AsRef<str>, S: Borrow<SORFile> {
+    std::fs::create_dir_all(path)?;
+    let mut samples = writer(&path.join("samples.parquet"), SAMPLES_SCHEMA)?;
+    let mut events_writer = writer(&path.join("events.parquet"), EVENTS_SCHEMA)?;
+    for (filename, sor) in files {
+        let (filename, sor) = (filename.as_ref(), sor.borrow());
+        if let Ok(trace) = Trace::new(sor) {
+            let user_offset_m = trace.user_offset_m();
+            let mut columns = metadata_columns(filename, sor, trace.points_db().len());
+            columns.push(Column::Double(trace.distance_m().iter().map(|d| d - user_offset_m).collect()));
+            columns.push(Column::Double(trace.points_db().to_vec()));
+            write_row_group(&mut samples, columns)?;
+        }
+        let events = events(sor);
+        if !events.is_empty() {
+            let mut columns = metadata_columns(filename, sor, events.len());
+            columns.push(Column::Int32(events.iter().map(|e| e.number as i32).collect()));
+            columns.push(Column::Double(events.iter().map(|e| e.distance_m).collect()));
+            columns.push(Column::Double(events.iter().map(|e| e.loss_db).collect()));
+            columns.push(Column::Double(events.iter().map(|e| e.reflectance_db).collect()));
+            columns.push(Column::Text(events.iter().map(|e| ByteArray::from(e.code.as_str())).collect()));
+            let comments = sor.key_events.iter()
+                .flat_map(|ke| ke.key_events.iter().map(|e| &e.comment).chain(std::iter::once(&ke.last_key_event.comment)));
+            columns.push(Column::Text(comments.map(|c| ByteArray::from(c.trim())).collect()));
+            write_row_group(&mut events_writer, columns)?;
+        }
+    }
+    samples.close()?;
+    events_writer.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_to_parquet() {
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::RowAccessor;
+    let parse = |data: &[u8]| crate::parser::parse_file(data).unwrap().1;
+    let files = [
+        ("1310.sor", parse(include_bytes!("../../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor"))),
+        ("1550.sor", parse(include_bytes!("../../data/example4-exfo-ftb4ftbx730c-mfdgainer-1550nm.sor"))),
+    ];
+    let dir = std::env::temp_dir().join(format!("otdrs-parquet-{}", std::process::id()));
+    to_parquet(files.iter().map(|(name, sor)| (*name, sor)), &dir).unwrap();
+
+    let read = |name: &str| SerializedFileReader::new(File::open(dir.join(name)).unwrap()).unwrap();
+    let samples = read("samples.parquet");
+    assert_eq!(samples.num_row_groups(), 2);
+    let points: usize = files.iter().map(|(_, sor)| Trace::new(sor).unwrap().points_db().len()).sum();
+    assert_eq!(samples.metadata().file_metadata().num_rows() as usize, points);
+    let events_file = read("events.parquet");
+    let n_events: usize = files.iter().map(|(_, sor)| events(sor).len()).sum();
+    assert_eq!(events_file.metadata().file_metadata().num_rows() as usize, n_events);
+    let first = events_file.get_row_iter(None).unwrap().next().unwrap().unwrap();
+    assert_eq!(first.get_string(0).unwrap(), "1310.sor");
+    assert_eq!(first.get_string(2).unwrap(), "Fiber1");
+    assert_eq!(first.get_int(3).unwrap(), 1310);
+    assert_eq!(first.get_int(6).unwrap(), 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,229 @@
+/// SQLite databases of the full content of many files, for ad-hoc SQL
+/// analysis. Where the catalogue holds just enough to find files, this holds
+/// everything needed to answer questions without the files themselves.
+///
+/// The tables are:
+///
+/// - `files`, one row per file with its identity and headline results
+/// - `parameters`, every field of the general, supplier and fixed parameter
+///   blocks as (block, name, value) rows, values keeping their SQL type
+/// - `events`, one row per key event
+/// - `landmarks`, one row per landmark in the link parameters
+/// - `samples`, optionally, each trace reduced to a limited number of points
+///
+/// Every other table refers to `files` by `file_id`. Exporting a file with
+/// the same filename again replaces it.
+use std::borrow::Borrow;
+use std::path::Path;
+use rusqlite::types::Value;
+use rusqlite::{params, Connection, Result, Transaction};
+use crate::analysis::{events, measured_orl, metres_per_100ps, Trace};
+use crate::engineering::iso8601;
+use crate::types::SORFile;
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS files (
+    file_id INTEGER PRIMARY KEY,
+    filename TEXT NOT NULL UNIQUE,
+    cable_id TEXT,
+    fiber_id TEXT,
+    originating_location TEXT,
+    terminating_location TEXT,
+    wavelength_nm INTEGER,
+    acquired TEXT,
+    supplier TEXT,
+    mainframe_sn TEXT,
+    pulse_width_ns INTEGER,
+    length_m REAL,
+    total_loss_db REAL,
+    orl_db REAL
+);
+CREATE TABLE IF NOT EXISTS parameters (
+    file_id INTEGER NOT NULL REFERENCES files,
+    block TEXT NOT NULL,
+    name TEXT NOT NULL,
+    value
+);
+CREATE TABLE IF NOT EXISTS events (
+    file_id INTEGER NOT NULL REFERENCES files,
+    event_number INTEGER NOT NULL,
+    distance_m REAL NOT NULL,
+    loss_db REAL NOT NULL,
+    reflectance_db REAL NOT NULL,
+    code TEXT NOT NULL,
+    comment TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS landmarks (
+    file_id INTEGER NOT NULL REFERENCES files,
+    landmark_number INTEGER NOT NULL,
+    code TEXT NOT NULL,
+    distance_m REAL NOT NULL,
+    related_event_number INTEGER,
+    latitude REAL,
+    longitude REAL,
+    sheath_marker_entering INTEGER NOT NULL,
+    sheath_marker_leaving INTEGER NOT NULL,
+    sheath_units TEXT NOT NULL,
+    comment TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS samples (
+    file_id INTEGER NOT NULL REFERENCES files,
+    distance_m REAL NOT NULL,
+    level_db REAL NOT NULL
+);
+CREATE INDEX IF NOT EXISTS parameters_file ON parameters (file_id);
+CREATE INDEX IF NOT EXISTS events_file ON events (file_id);
+CREATE INDEX IF NOT EXISTS landmarks_file ON landmarks (file_id);
+CREATE INDEX IF NOT EXISTS samples_file ON samples (file_id);";
+
+/// A block's fields as (name, value) pairs. Strings are trimmed, and arrays,
+/// such as the pulse widths used, are stored as JSON text.
+fn fields<T: serde::Serialize>(block: &T) -> Vec<(String, Value)> {
+    let object = match serde_json::to_value(block) {
+        Ok(serde_json::Value::Object(object)) => object,
+        _ => return Vec::new(),
+    };
+    object.into_iter().map(|(name, value)| {
+        let value = match value {
+            serde_json::Value::Null => Value::Null,
+            serde_json::Value::Bool(b) => Value::Integer(b as i64),
+            serde_json::Value::Number(n) => match n.as_i64() {
+                Some(i) => Value::Integer(i),
+                None => Value::Real(n.as_f64().unwrap_or(f64::NAN)),
+            },
+            serde_json::Value::String(s) => Value::Text(s.trim().to_owned()),
+            other => Value::Text(other.to_string()),
+        };
+        (name, value)
+    }).collect()
+}
+
+fn insert_file(tx: &Transaction, filename: &str, sor: &SORFile, trace_points: Option<usize>) -> Result<()> {
+    let existing: Option<i64> = tx.query_row("SELECT file_id FROM files WHERE filename = ?1", [filename], |row| row.get(0)).ok();
+    if let Some(file_id) = existing {
+        for table in ["parameters", "events", "landmarks", "samples", "files"].iter() {
+            tx.execute(&format!("DELETE FROM {} WHERE file_id = ?1", table), [file_id])?;
+        }
+    }
+    let gp = sor.general_parameters.as_ref();
+    let sp = sor.supplier_parameters.as_ref();
+    let fp = sor.fixed_parameters.as_ref();
+    let lke = sor.key_events.as_ref().map(|ke| &ke.last_key_event);
+    let m_per_100ps = metres_per_100ps(sor);
+    tx.execute(
+        "INSERT INTO files (filename, cable_id, fiber_id, originating_location, terminating_location, wavelength_nm,
+                            acquired, supplier, mainframe_sn, pulse_width_ns, length_m, total_loss_db, orl_db)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+        params![
+            filename,
+            gp.map(|gp| gp.cable_id.trim()),
+            gp.map(|gp| gp.fiber_id.trim()),
+            gp.map(|gp| gp.originating_location.trim()),
+            gp.map(|gp| gp.terminating_location.trim()),
+            gp.map(|gp| gp.nominal_wavelength),
+            fp.map(|fp| iso8601(fp.date_time_stamp)),
+            sp.map(|sp| sp.supplier_name.trim()),
+            sp.map(|sp| sp.otdr_mainframe_sn.trim()),
+            fp.and_then(|fp| fp.pulse_widths_used.first()),
+            lke.map(|lke| lke.event_propogation_time as f64 * m_per_100ps),
+            lke.map(|lke| lke.end_to_end_loss as f64 / 1000.0),
+            measured_orl(sor),
+        ],
+    )?;
+    let file_id = tx.last_insert_rowid();
+
+    let mut stmt = tx.prepare_cached("INSERT INTO parameters (file_id, block, name, value) VALUES (?1, ?2, ?3, ?4)")?;
+    let blocks = [
+        ("GenParams", gp.map(fields)),
+        ("SupParams", sp.map(fields)),
+        ("FxdParams", fp.map(fields)),
+    ];
+    for (block, fields) in blocks.iter() {
+        for (name, value) in fields.iter().flatten() {
+            stmt.execute(params![file_id, block, name, value])?;
+        }
+    }
+
+    let mut stmt = tx.prepare_cached("INSERT INTO events (file_id, event_number, distance_m, loss_db, reflectance_db, code, comment)
+                                      VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")?;
+    let comments = sor.key_events.iter()
+        .flat_map(|ke| ke.key_events.iter().map(|e| &e.comment).chain(std::iter::once(&ke.last_key_event.comment)));
+    for (e, comment) in events(sor).iter().zip(comments) {
+        stmt.execute(params![file_id, e.number, e.distance_m, e.loss_db, e.reflectance_db, e.code, comment.trim()])?;
+    }
+
+    let mut stmt = tx.prepare_cached("INSERT INTO landmarks (file_id, landmark_number, code, distance_m, related_event_number, latitude,
+                                                             longitude, sheath_marker_entering, sheath_marker_leaving, sheath_units, comment)
+                                      VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)")?;
+    for l in sor.link_parameters.iter().flat_map(|lp| lp.landmarks.iter()) {
+        let position = l.position();
+        stmt.execute(params![
+            file_id,
+            l.landmark_number,
+            l.landmark_code.trim(),
+            sor.landmark_distance_m(l),
+            Some(l.related_event_number).filter(|&n| n != 0),
+            position.map(|p| p.0),
+            position.map(|p| p.1),
+            l.sheath_marker_entering_landmark,
+            l.sheath_marker_leaving_landmark,
+            l.units_of_sheath_marks_leaving_landmark.trim(),
+            l.comment.trim(),
+        ])?;
+    }
+
+    if let (Some(max_points), Ok(trace)) = (trace_points, Trace::new(sor)) {
+        if let Ok(trace) = trace.decimate(max_points) {
+            let mut stmt = tx.prepare_cached("INSERT INTO samples (file_id, distance_m, level_db) VALUES (?1, ?2, ?3)")?;
+            for (d, level) in trace.distance_m().iter().zip(trace.points_db()) {
+                stmt.execute(params![file_id, d - trace.user_offset_m(), level])?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write the given files, named by their filenames, into a SQLite database,
+/// which is created if need be. If trace_points is given, each trace is also
+/// stored, reduced to at most that many points as by `Trace::decimate` so
+/// that reflections keep their height; distances are from each file's user
+/// offset. All the files are written in one transaction.
+pub fn to_sqlite<I, N, S>(files: I, path: &Path, trace_points: Option<usize>) -> Result<()>
+    where I: IntoIterator<Item = (N, S)>, N: AsRef<str>, S: Borrow<SORFile> {
+    let mut conn = Connection::open(path)?;
+    conn.execute_batch(SCHEMA)?;
+    let tx = conn.transaction()?;
+    for (filename, sor) in files {
+        insert_file(&tx, filename.as_ref(), sor.borrow(), trace_points)?;
+    }
+    tx.commit()
+}
+
+#[test]
+fn test_to_sqlite() {
+    let parse = |data: &[u8]| crate::parser::parse_file(data).unwrap().1;
+    let mut sor = parse(include_bytes!("../../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor"));
+    sor.add_landmark("MH", 500.0).unwrap().set_position(51.0, -1.0).unwrap();
+    let files = [
+        ("1310.sor", sor),
+        ("noyes.sor", parse(include_bytes!("../../data/example1-noyes-ofl280.sor"))),
+    ];
+    let path = std::env::temp_dir().join(format!("otdrs-sqlite-{}.sqlite", std::process::id()));
+    to_sqlite(files.iter().map(|(name, sor)| (*name, sor)), &path, Some(100)).unwrap();
+    // Exporting again replaces the files rather than adding to them
+    to_sqlite(files.iter().map(|(name, sor)| (*name, sor)), &path, Some(100)).unwrap();
+
+    let conn = Connection::open(&path).unwrap();
+    let count = |sql: &str| -> i64 { conn.query_row(sql, [], |row| row.get(0)).unwrap() };
+    assert_eq!(count("SELECT count(*) FROM files"), 2);
+    let n_events: usize = files.iter().map(|(_, sor)| events(sor).len()).sum();
+    assert_eq!(count("SELECT count(*) FROM events") as usize, n_events);
+    assert_eq!(count("SELECT count(*) FROM landmarks"), 1);
+    assert_eq!(count("SELECT count(*) FROM samples WHERE file_id = (SELECT file_id FROM files WHERE filename = '1310.sor')"), 100);
+    assert_eq!(count("SELECT value FROM parameters JOIN files USING (file_id)
+                      WHERE filename = 'noyes.sor' AND block = 'GenParams' AND name = 'nominal_wavelength'"), 1550);
+    let fiber_id: String = conn.query_row("SELECT fiber_id FROM files WHERE filename = '1310.sor'", [], |row| row.get(0)).unwrap();
+    assert_eq!(fiber_id, "Fiber1");
+    let latitude: f64 = conn.query_row("SELECT latitude FROM landmarks", [], |row| row.get(0)).unwrap();
+    assert!((latitude - 51.0).abs() < 1e-6);
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,178 @@
+/// This module defines the formats SOR files can be converted to. Each is an
+/// `OutputFormat`, looked up by name, so a new format - built in, or
+/// registered by an application embedding otdrs, e.g. GeoJSON - works with
+/// `otdrs::batch::convert_to` and the command line's `--format` alike.
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use serde::Serialize;
+use crate::types::SORFile;
+
+/// A format a SORFile can be written out in
+pub trait OutputFormat: Send + Sync {
+    /// The name `--format` knows the format by, e.g. msgpack
+    fn name(&self) -> &str;
+    /// Extension for files in this format, without the dot
+    fn extension(&self) -> &str;
+    fn serialize(&self, sor: &SORFile, options: &FormatOptions) -> Result<Vec<u8>, String>;
+}
+
+/// Options formats may honour where they apply
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct FormatOptions {
+    /// Give values in dB, metres, seconds and ISO-8601 timestamps rather than
+    /// raw SR-4731 encodings. Formats with a fixed encoding refuse this
+    pub engineering_units: bool,
+    /// Indent JSON and XML
+    pub pretty: bool,
+    /// Sort JSON object keys
+    pub canonical: bool,
+}
+
+/// The formats built in to otdrs
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Format {
+    #[default]
+    Json,
+    Cbor,
+    MessagePack,
+    Yaml,
+    Xml,
+    /// The otdrs interchange profile, see `otdrs::interchange`
+    Interchange,
+    /// Protocol Buffers, as described by `proto/otdrs.proto`
+    #[cfg(feature = "protobuf")]
+    Protobuf,
+}
+
+impl Format {
+    pub const ALL: &'static [Format] = &[
+        Format::Json,
+        Format::Cbor,
+        Format::MessagePack,
+        Format::Yaml,
+        Format::Xml,
+        Format::Interchange,
+        #[cfg(feature = "protobuf")]
+        Format::Protobuf,
+    ];
+
+    /// Serialise any value in this format, e.g. a selection of a file's
+    /// fields. Only the formats serde drives can do this
+    pub fn serialize_value<T: Serialize>(&self, value: &T, options: &FormatOptions) -> Result<Vec<u8>, String> {
+        let out = match self {
+            Format::Json if options.canonical => {
+                let value = serde_json::to_value(value).map_err(|err| err.to_string())?;
+                if options.pretty { serde_json::to_vec_pretty(&value) } else { serde_json::to_vec(&value) }.map_err(|err| err.to_string())?
+            }
+            Format::Json if options.pretty => serde_json::to_vec_pretty(value).map_err(|err| err.to_string())?,
+            Format::Json => serde_json::to_vec(value).map_err(|err| err.to_string())?,
+            Format::Cbor => serde_cbor::to_vec(value).map_err(|err| err.to_string())?,
+            Format::MessagePack => rmp_serde::to_vec_named(value).map_err(|err| err.to_string())?,
+            Format::Yaml => serde_yaml::to_string(value).map_err(|err| err.to_string())?.into_bytes(),
+            Format::Xml => crate::xml::to_xml(value, options.pretty).map_err(|err| err.to_string())?.into_bytes(),
+            _ => return Err(format!("The {} format can't be serialised from other values", self.name())),
+        };
+        Ok(out)
+    }
+}
+
+impl FromStr for Format {
+    type Err = &'static str;
+
+    /// Parse a format by the name the command line uses, e.g. msgpack
+    fn from_str(name: &str) -> Result<Format, &'static str> {
+        Format::ALL.iter().find(|format| format.name() == name).copied().ok_or("Unknown format")
+    }
+}
+
+impl OutputFormat for Format {
+    fn name(&self) -> &str {
+        match self {
+            Format::Json => "json",
+            Format::Cbor => "cbor",
+            Format::MessagePack => "msgpack",
+            Format::Yaml => "yaml",
+            Format::Xml => "xml",
+            Format::Interchange => "interchange",
+            #[cfg(feature = "protobuf")]
+            Format::Protobuf => "protobuf",
+        }
+    }
+
+    fn extension(&self) -> &str {
+        self.name()
+    }
+
+    fn serialize(&self, sor: &SORFile, options: &FormatOptions) -> Result<Vec<u8>, String> {
+        let encoded = match self {
+            Format::Interchange => sor.to_interchange(),
+            #[cfg(feature = "protobuf")]
+            Format::Protobuf => sor.to_protobuf(),
+            _ if options.engineering_units => {
+                return self.serialize_value(&crate::engineering::to_value(sor).map_err(|err| err.to_string())?, options);
+            }
+            _ => return self.serialize_value(sor, options),
+        };
+        if options.engineering_units {
+            return Err(format!("Engineering units can't be used with the {} format", self.name()));
+        }
+        Ok(encoded)
+    }
+}
+
+static REGISTERED: RwLock<Vec<Arc<dyn OutputFormat>>> = RwLock::new(Vec::new());
+
+/// Make a format available by name to `lookup`, for the rest of the
+/// process. A format registered under a name already taken, even a built-in
+/// one, replaces it
+pub fn register<F: OutputFormat + 'static>(format: F) {
+    REGISTERED.write().unwrap_or_else(|err| err.into_inner()).push(Arc::new(format));
+}
+
+/// Find a format by name, among those registered and then those built in
+pub fn lookup(name: &str) -> Option<Arc<dyn OutputFormat>> {
+    let registered = REGISTERED.read().unwrap_or_else(|err| err.into_inner());
+    registered.iter().rev().find(|format| format.name() == name).cloned()
+        .or_else(|| name.parse::<Format>().ok().map(|format| Arc::new(format) as Arc<dyn OutputFormat>))
+}
+
+/// The names of every format available, built in ones first
+pub fn names() -> Vec<String> {
+    let mut names: Vec<String> = Format::ALL.iter().map(|format| format.name().to_owned()).collect();
+    for format in REGISTERED.read().unwrap_or_else(|err| err.into_inner()).iter() {
+        if !names.iter().any(|name| name == format.name()) {
+            names.push(format.name().to_owned());
+        }
+    }
+    names
+}
+
+#[test]
+fn test_output_formats() {
+    struct EventCount;
+    impl OutputFormat for EventCount {
+        fn name(&self) -> &str {
+            "test-event-count"
+        }
+        fn extension(&self) -> &str {
+            "txt"
+        }
+        fn serialize(&self, sor: &SORFile, _options: &FormatOptions) -> Result<Vec<u8>, String> {
+            Ok(sor.key_events.as_ref().map_or(0, |ke| ke.key_events.len()).to_string().into_bytes())
+        }
+    }
+    let sor = crate::parser::parse_file(include_bytes!("../data/example1-noyes-ofl280.sor")).unwrap().1;
+    assert!(lookup("test-event-count").is_none());
+    register(EventCount);
+    let format = lookup("test-event-count").unwrap();
+    assert_eq!(format.extension(), "txt");
+    assert_eq!(format.serialize(&sor, &FormatOptions::default()).unwrap(), b"2");
+    assert!(names().contains(&"test-event-count".to_owned()));
+
+    let json = lookup("json").unwrap().serialize(&sor, &FormatOptions::default()).unwrap();
+    assert_eq!(serde_json::from_slice::<SORFile>(&json).unwrap(), sor);
+    let options = FormatOptions { engineering_units: true, ..FormatOptions::default() };
+    assert!(lookup("interchange").unwrap().serialize(&sor, &options).is_err());
+    assert!(lookup("yaml").unwrap().serialize(&sor, &options).is_ok());
+    assert!(lookup("geojson").is_none());
+}
@@ -0,0 +1,291 @@
+/// This module relates distances along the fibre to where they are in the
+/// field, using the landmarks in a file's link parameters: their GPS
+/// positions give coordinates, and the sheath markers printed on the cable
+/// where it enters and leaves each landmark give the marker reading a
+/// splicer would look for.
+///
+/// Between two landmarks both are interpolated linearly by optical
+/// distance, which allows for fibre helix and slack in between, so long as
+/// it's evenly spread. Beyond the first or last landmark, positions are
+/// extrapolated along the nearest stretch of route, and sheath markers from
+/// the nearest landmark using its fibre correction factor.
+///
+/// Landmarks can also be built from a route surveyed outside the OTDR, as
+/// CSV or GeoJSON from a GIS, to enrich field traces with it.
+use std::io::Read;
+use serde::Deserialize;
+use crate::analysis::Event;
+use crate::types::{Landmark, LinkParameters, SORFile};
+use crate::units;
+
+/// Where a point on the fibre is in the field
+#[derive(Debug, PartialEq, Clone)]
+pub struct GeoLocation {
+    /// Distance from the user offset, in metres
+    pub distance_m: f64,
+    /// WGS84 latitude and longitude in decimal degrees, if at least two
+    /// landmarks have GPS positions
+    pub position: Option<(f64, f64)>,
+    /// Sheath marker reading, if landmarks have sheath markers
+    pub sheath_marker: Option<f64>,
+    /// Units of the sheath markers, as a units_of_distance code, e.g. mt
+    pub sheath_units: Option<String>,
+    /// Number of the last landmark at or before the point, if any
+    pub previous_landmark: Option<i16>,
+}
+
+/// Positioned landmarks as (distance from the user offset in metres,
+/// latitude, longitude), in order of distance
+pub(crate) fn route(sor: &SORFile) -> Vec<(f64, f64, f64)> {
+    let mut route: Vec<(f64, f64, f64)> = sor.link_parameters.iter()
+        .flat_map(|lp| lp.landmarks.iter())
+        .filter_map(|l| l.position().map(|(lat, lon)| (sor.landmark_distance_m(l), lat, lon)))
+        .collect();
+    route.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    route
+}
+
+/// WGS84 latitude and longitude of a point a distance in metres from the
+/// user offset. None if fewer than two landmarks have positions
+pub fn position_at(sor: &SORFile, distance_m: f64) -> Option<(f64, f64)> {
+    let route = route(sor);
+    if route.len() < 2 {
+        return None;
+    }
+    let i = route.partition_point(|p| p.0 < distance_m).clamp(1, route.len() - 1);
+    let (a, b) = (route[i - 1], route[i]);
+    let t = if b.0 > a.0 { (distance_m - a.0) / (b.0 - a.0) } else { 0.0 };
+    Some((a.1 + t * (b.1 - a.1), a.2 + t * (b.2 - a.2)))
+}
+
+/// A landmark's sheath markers, with zero for both taken to mean there
+/// are none
+struct Marks {
+    distance_m: f64,
+    entering: f64,
+    leaving: f64,
+    /// Optical path length over cable length for the fibre leading in
+    helix: f64,
+    units: String,
+}
+
+/// Sheath marker reading and its units at a point a distance in metres from
+/// the user offset, if any landmarks have sheath markers
+pub fn sheath_marker_at(sor: &SORFile, distance_m: f64) -> Option<(f64, String)> {
+    let mut marks: Vec<Marks> = sor.link_parameters.iter()
+        .flat_map(|lp| lp.landmarks.iter())
+        .filter(|l| l.sheath_marker_entering_landmark != 0 || l.sheath_marker_leaving_landmark != 0)
+        .map(|l| Marks {
+            distance_m: sor.landmark_distance_m(l),
+            entering: l.sheath_marker_entering_landmark as f64,
+            leaving: l.sheath_marker_leaving_landmark as f64,
+            helix: 1.0 + l.fiber_correction_factor_lead_in_fiber as f64 / 10000.0,
+            units: l.units_of_sheath_marks_leaving_landmark.to_string(),
+        })
+        .collect();
+    marks.sort_by(|a, b| a.distance_m.partial_cmp(&b.distance_m).unwrap());
+    let i = marks.partition_point(|m| m.distance_m <= distance_m);
+    // Whether markers count up or down the route, from the first stretch
+    // between landmarks; up if there's only one landmark
+    let direction = match (marks.first(), marks.get(1)) {
+        (Some(a), Some(b)) if b.entering < a.leaving => -1.0,
+        _ => 1.0,
+    };
+    let extrapolate = |m: &Marks, from: f64, cable_m: f64| {
+        units::metres_per_unit(&m.units).map(|unit_m| (from + direction * cable_m / m.helix / unit_m, m.units.clone()))
+    };
+    match (i.checked_sub(1).and_then(|i| marks.get(i)), marks.get(i)) {
+        (Some(a), Some(b)) => {
+            let t = if b.distance_m > a.distance_m { (distance_m - a.distance_m) / (b.distance_m - a.distance_m) } else { 0.0 };
+            Some((a.leaving + t * (b.entering - a.leaving), a.units.clone()))
+        }
+        (Some(a), None) => extrapolate(a, a.leaving, distance_m - a.distance_m),
+        (None, Some(b)) => extrapolate(b, b.entering, distance_m - b.distance_m),
+        (None, None) => None,
+    }
+}
+
+/// Where a point a distance in metres from the user offset is in the field
+pub fn locate(sor: &SORFile, distance_m: f64) -> GeoLocation {
+    let previous_landmark = sor.link_parameters.iter()
+        .flat_map(|lp| lp.landmarks.iter())
+        .filter(|l| sor.landmark_distance_m(l) <= distance_m)
+        .max_by(|a, b| a.landmark_location.cmp(&b.landmark_location))
+        .map(|l| l.landmark_number);
+    let sheath = sheath_marker_at(sor, distance_m);
+    GeoLocation {
+        distance_m,
+        position: position_at(sor, distance_m),
+        sheath_marker: sheath.as_ref().map(|s| s.0),
+        sheath_units: sheath.map(|s| s.1),
+        previous_landmark,
+    }
+}
+
+/// Where an event, e.g. from `analysis::events`, is in the field
+pub fn locate_event(sor: &SORFile, event: &Event) -> GeoLocation {
+    locate(sor, event.distance_m)
+}
+
+/// One landmark of a route, as a CSV row or the properties of a GeoJSON
+/// point. Sheath markers and the fibre correction factor and mode field
+/// diameter are as SR-4731 encodes them
+#[derive(Debug, PartialEq, Deserialize)]
+struct RoutePoint {
+    /// Distance from the user offset, in metres
+    distance_m: f64,
+    /// Landmark code, e.g. MH for a manhole
+    code: String,
+    #[serde(default)]
+    latitude: Option<f64>,
+    #[serde(default)]
+    longitude: Option<f64>,
+    #[serde(default)]
+    sheath_entering: i32,
+    #[serde(default)]
+    sheath_leaving: i32,
+    #[serde(default)]
+    sheath_units: Option<String>,
+    #[serde(default)]
+    fiber_correction: i16,
+    #[serde(default)]
+    mode_field_diameter: i16,
+    #[serde(default)]
+    comment: String,
+}
+
+/// Build a link parameters block from a route, in order of distance
+fn link_parameters(sor: &SORFile, points: Vec<RoutePoint>) -> Result<LinkParameters, String> {
+    let group_index = sor.fixed_parameters.as_ref().map_or(0, |fp| fp.group_index);
+    let mut landmarks = Vec::with_capacity(points.len());
+    for point in points {
+        if point.code.len() != 2 || !point.code.is_ascii() {
+            return Err(format!("Landmark code {:?} at {}m is not two characters", point.code, point.distance_m));
+        }
+        let mut landmark = Landmark::new(&point.code, units::metres_to_time(point.distance_m, group_index));
+        match (point.latitude, point.longitude) {
+            (Some(latitude), Some(longitude)) => landmark.set_position(latitude, longitude)
+                .map_err(|err| format!("Landmark at {}m: {}", point.distance_m, err))?,
+            (None, None) => {}
+            _ => return Err(format!("Landmark at {}m has only one of a latitude and longitude", point.distance_m)),
+        }
+        landmark.sheath_marker_entering_landmark = point.sheath_entering;
+        landmark.sheath_marker_leaving_landmark = point.sheath_leaving;
+        if let Some(units) = point.sheath_units {
+            landmark.units_of_sheath_marks_leaving_landmark = units.into();
+        }
+        landmark.fiber_correction_factor_lead_in_fiber = point.fiber_correction;
+        landmark.mode_field_diameter_leaving_landmark = point.mode_field_diameter;
+        landmark.comment = point.comment.into();
+        landmarks.push(landmark);
+    }
+    landmarks.sort_by_key(|l| l.landmark_location);
+    for (n, l) in landmarks.iter_mut().enumerate() {
+        l.landmark_number = n as i16 + 1;
+    }
+    Ok(LinkParameters { number_of_landmarks: landmarks.len() as i16, landmarks })
+}
+
+/// Build landmarks from a CSV route with a header row. Each row is a
+/// landmark, with columns distance_m (from the user offset) and code, and
+/// optionally latitude and longitude in decimal degrees, sheath_entering,
+/// sheath_leaving, sheath_units, fiber_correction, mode_field_diameter and
+/// comment. Install the result with `SORFile::set_link_parameters`.
+pub fn landmarks_from_csv<R: Read>(sor: &SORFile, route: R) -> Result<LinkParameters, String> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(route);
+    let points = reader.deserialize().collect::<Result<Vec<RoutePoint>, _>>()
+        .map_err(|err| format!("Invalid route: {}", err))?;
+    link_parameters(sor, points)
+}
+
+/// Build landmarks from a GeoJSON FeatureCollection of points, whose
+/// properties are the CSV route's columns less latitude and longitude,
+/// which come from the point. Install the result with
+/// `SORFile::set_link_parameters`.
+pub fn landmarks_from_geojson<R: Read>(sor: &SORFile, route: R) -> Result<LinkParameters, String> {
+    let route: serde_json::Value = serde_json::from_reader(route).map_err(|err| format!("Invalid GeoJSON: {}", err))?;
+    if route["type"] != "FeatureCollection" {
+        return Err("The route must be a GeoJSON FeatureCollection".to_owned());
+    }
+    let features = route["features"].as_array().ok_or("The route has no features")?;
+    let mut points = Vec::with_capacity(features.len());
+    for (n, feature) in features.iter().enumerate() {
+        let geometry = &feature["geometry"];
+        if geometry["type"] != "Point" {
+            return Err(format!("Feature {} is not a point", n));
+        }
+        // GeoJSON positions are longitude first
+        let position = geometry["coordinates"].as_array().and_then(|c| Some((c.get(1)?.as_f64()?, c.first()?.as_f64()?)))
+            .ok_or(format!("Feature {} has no coordinates", n))?;
+        let mut point: RoutePoint = serde_json::from_value(feature["properties"].clone())
+            .map_err(|err| format!("Feature {}: {}", n, err))?;
+        point.latitude = Some(position.0);
+        point.longitude = Some(position.1);
+        points.push(point);
+    }
+    link_parameters(sor, points)
+}
+
+#[test]
+fn test_landmarks_from_route() {
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let mut sor = crate::parser::parse_file(data).unwrap().1;
+    let csv = "distance_m,code,latitude,longitude,sheath_entering,sheath_leaving,comment\n\
+               1100,MH,51.01,-1.0,4000,3990,\n\
+               100, MH, 51.0, -1.0, 5000, 4980, chamber 12\n";
+    let lp = landmarks_from_csv(&sor, csv.as_bytes()).unwrap();
+    assert_eq!(lp.number_of_landmarks, 2);
+    assert_eq!(&*lp.landmarks[0].comment, "chamber 12");
+    assert_eq!(lp.landmarks[1].landmark_number, 2);
+    assert!(landmarks_from_csv(&sor, "distance_m,code\n100,MAN\n".as_bytes()).is_err());
+
+    let geojson = r#"{"type": "FeatureCollection", "features": [
+        {"type": "Feature", "geometry": {"type": "Point", "coordinates": [-1.0, 51.0]},
+         "properties": {"distance_m": 100, "code": "MH", "sheath_entering": 5000, "sheath_leaving": 4980,
+                        "comment": "chamber 12", "owner": "ignored"}},
+        {"type": "Feature", "geometry": {"type": "Point", "coordinates": [-1.0, 51.01]},
+         "properties": {"distance_m": 1100, "code": "MH", "sheath_entering": 4000, "sheath_leaving": 3990}}]}"#;
+    assert_eq!(landmarks_from_geojson(&sor, geojson.as_bytes()).unwrap(), lp);
+
+    sor.set_link_parameters(lp);
+    let sor = crate::parser::parse_file(&sor.to_bytes().unwrap()).unwrap().1;
+    let location = locate(&sor, 600.0);
+    assert_eq!(location.previous_landmark, Some(1));
+    assert!((location.position.unwrap().0 - 51.005).abs() < 1e-4);
+}
+
+#[test]
+fn test_locate_event() {
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let mut sor = crate::parser::parse_file(data).unwrap().1;
+    let events = crate::analysis::events(&sor);
+    // Nothing is known without landmarks
+    let location = locate_event(&sor, &events[1]);
+    assert_eq!((location.position, location.sheath_marker, location.previous_landmark), (None, None, None));
+
+    let first = sor.add_landmark("MH", 100.0).unwrap();
+    first.set_position(51.0, -1.0).unwrap();
+    first.sheath_marker_entering_landmark = 5000;
+    first.sheath_marker_leaving_landmark = 4980;
+    let second = sor.add_landmark("MH", 1100.0).unwrap();
+    second.set_position(51.01, -1.0).unwrap();
+    second.sheath_marker_entering_landmark = 4000;
+    second.sheath_marker_leaving_landmark = 3990;
+    // 1% more fibre than cable
+    second.fiber_correction_factor_lead_in_fiber = 100;
+
+    // The gainer at 477.6m is 37.76% of the way between the landmarks
+    let location = locate_event(&sor, &events[1]);
+    assert_eq!(location.previous_landmark, Some(1));
+    assert_eq!(location.sheath_units.as_deref(), Some("mt"));
+    let t = (events[1].distance_m - 100.0) / 1000.0;
+    assert!((location.position.unwrap().0 - (51.0 + 0.01 * t)).abs() < 1e-6);
+    assert!((location.sheath_marker.unwrap() - (4980.0 - 980.0 * t)).abs() < 0.1);
+    // Markers count down, and 101m of fibre past the last landmark is 100m
+    // of cable
+    let beyond = locate(&sor, 1201.0).sheath_marker.unwrap();
+    assert!((beyond - 3890.0).abs() < 0.1, "{}", beyond);
+    let before = locate(&sor, 0.0);
+    assert_eq!(before.previous_landmark, None);
+    assert!((before.sheath_marker.unwrap() - 5100.0).abs() < 0.1);
+}
@@ -0,0 +1,345 @@
+/// This module defines the otdrs interchange profile: a versioned,
+/// deterministic CBOR encoding of a SORFile, for exchanging files between
+/// otdrs versions and bindings in other languages with compatibility
+/// guarantees that the plain `--format cbor` output, which follows whatever
+/// the struct fields are named, doesn't offer.
+///
+/// A document is a map of `0` to the profile version and `1` to the file.
+/// Within the file, fields are keyed by stable integer IDs rather than names,
+/// the same as the field numbers of `proto/otdrs.proto`. Values are the raw
+/// SR-4731 encodings, as in `otdrs::types`; a block missing from the file
+/// is left out, and proprietary block payloads are byte strings.
+///
+/// Encoding is deterministic, as in RFC 8949 section 4.2: map keys are in
+/// ascending order, and integers and lengths use their shortest form, so a
+/// file always encodes to the same bytes and documents can be compared or
+/// hashed directly.
+///
+/// Fields may be added without changing the version, taking new IDs, and
+/// readers ignore IDs they don't know. IDs are never reused or renumbered.
+/// The version only changes for changes older readers couldn't ignore, and
+/// readers refuse versions newer than their own.
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use serde_cbor::Value as Cbor;
+use serde_json::Value;
+use crate::types::SORFile;
+use self::Kind::*;
+
+/// The version of the profile written by this version of otdrs
+pub const INTERCHANGE_VERSION: u64 = 1;
+
+/// A field of a struct, with its stable ID
+struct Field {
+    id: u64,
+    name: &'static str,
+    kind: Kind,
+}
+
+/// How a field's value is encoded
+enum Kind {
+    /// Integers, strings, and lists of them, encoded as they are
+    Plain,
+    /// A list of bytes, encoded as a byte string
+    Bytes,
+    Struct(&'static [Field]),
+    List(&'static Kind),
+}
+
+const BLOCK_INFO: &[Field] = &[
+    Field { id: 1, name: "identifier", kind: Plain },
+    Field { id: 2, name: "revision_number", kind: Plain },
+    Field { id: 3, name: "size", kind: Plain },
+];
+
+const MAP_BLOCK: &[Field] = &[
+    Field { id: 1, name: "revision_number", kind: Plain },
+    Field { id: 2, name: "block_size", kind: Plain },
+    Field { id: 3, name: "block_count", kind: Plain },
+    Field { id: 4, name: "block_info", kind: List(&Struct(BLOCK_INFO)) },
+];
+
+const GENERAL_PARAMETERS_BLOCK: &[Field] = &[
+    Field { id: 1, name: "language_code", kind: Plain },
+    Field { id: 2, name: "cable_id", kind: Plain },
+    Field { id: 3, name: "fiber_id", kind: Plain },
+    Field { id: 4, name: "fiber_type", kind: Plain },
+    Field { id: 5, name: "nominal_wavelength", kind: Plain },
+    Field { id: 6, name: "originating_location", kind: Plain },
+    Field { id: 7, name: "terminating_location", kind: Plain },
+    Field { id: 8, name: "cable_code", kind: Plain },
+    Field { id: 9, name: "current_data_flag", kind: Plain },
+    Field { id: 10, name: "user_offset", kind: Plain },
+    Field { id: 11, name: "user_offset_distance", kind: Plain },
+    Field { id: 12, name: "operator", kind: Plain },
+    Field { id: 13, name: "comment", kind: Plain },
+];
+
+const SUPPLIER_PARAMETERS_BLOCK: &[Field] = &[
+    Field { id: 1, name: "supplier_name", kind: Plain },
+    Field { id: 2, name: "otdr_mainframe_id", kind: Plain },
+    Field { id: 3, name: "otdr_mainframe_sn", kind: Plain },
+    Field { id: 4, name: "optical_module_id", kind: Plain },
+    Field { id: 5, name: "optical_module_sn", kind: Plain },
+    Field { id: 6, name: "software_revision", kind: Plain },
+    Field { id: 7, name: "other", kind: Plain },
+];
+
+const FIXED_PARAMETERS_BLOCK: &[Field] = &[
+    Field { id: 1, name: "date_time_stamp", kind: Plain },
+    Field { id: 2, name: "units_of_distance", kind: Plain },
+    Field { id: 3, name: "actual_wavelength", kind: Plain },
+    Field { id: 4, name: "acquisition_offset", kind: Plain },
+    Field { id: 5, name: "acquisition_offset_distance", kind: Plain },
+    Field { id: 6, name: "total_n_pulse_widths_used", kind: Plain },
+    Field { id: 7, name: "pulse_widths_used", kind: Plain },
+    Field { id: 8, name: "data_spacing", kind: Plain },
+    Field { id: 9, name: "n_data_points_for_pulse_widths_used", kind: Plain },
+    Field { id: 10, name: "group_index", kind: Plain },
+    Field { id: 11, name: "backscatter_coefficient", kind: Plain },
+    Field { id: 12, name: "number_of_averages", kind: Plain },
+    Field { id: 13, name: "averaging_time", kind: Plain },
+    Field { id: 14, name: "acquisition_range", kind: Plain },
+    Field { id: 15, name: "acquisition_range_distance", kind: Plain },
+    Field { id: 16, name: "front_panel_offset", kind: Plain },
+    Field { id: 17, name: "noise_floor_level", kind: Plain },
+    Field { id: 18, name: "noise_floor_scale_factor", kind: Plain },
+    Field { id: 19, name: "power_offset_first_point", kind: Plain },
+    Field { id: 20, name: "loss_threshold", kind: Plain },
+    Field { id: 21, name: "reflectance_threshold", kind: Plain },
+    Field { id: 22, name: "end_of_fibre_threshold", kind: Plain },
+    Field { id: 23, name: "trace_type", kind: Plain },
+    Field { id: 24, name: "window_coordinate_1", kind: Plain },
+    Field { id: 25, name: "window_coordinate_2", kind: Plain },
+    Field { id: 26, name: "window_coordinate_3", kind: Plain },
+    Field { id: 27, name: "window_coordinate_4", kind: Plain },
+];
+
+const KEY_EVENT: &[Field] = &[
+    Field { id: 1, name: "event_number", kind: Plain },
+    Field { id: 2, name: "event_propogation_time", kind: Plain },
+    Field { id: 3, name: "attenuation_coefficient_lead_in_fiber", kind: Plain },
+    Field { id: 4, name: "event_loss", kind: Plain },
+    Field { id: 5, name: "event_reflectance", kind: Plain },
+    Field { id: 6, name: "event_code", kind: Plain },
+    Field { id: 7, name: "loss_measurement_technique", kind: Plain },
+    Field { id: 8, name: "marker_location_1", kind: Plain },
+    Field { id: 9, name: "marker_location_2", kind: Plain },
+    Field { id: 10, name: "marker_location_3", kind: Plain },
+    Field { id: 11, name: "marker_location_4", kind: Plain },
+    Field { id: 12, name: "marker_location_5", kind: Plain },
+    Field { id: 13, name: "comment", kind: Plain },
+];
+
+const LAST_KEY_EVENT: &[Field] = &[
+    Field { id: 1, name: "event_number", kind: Plain },
+    Field { id: 2, name: "event_propogation_time", kind: Plain },
+    Field { id: 3, name: "attenuation_coefficient_lead_in_fiber", kind: Plain },
+    Field { id: 4, name: "event_loss", kind: Plain },
+    Field { id: 5, name: "event_reflectance", kind: Plain },
+    Field { id: 6, name: "event_code", kind: Plain },
+    Field { id: 7, name: "loss_measurement_technique", kind: Plain },
+    Field { id: 8, name: "marker_location_1", kind: Plain },
+    Field { id: 9, name: "marker_location_2", kind: Plain },
+    Field { id: 10, name: "marker_location_3", kind: Plain },
+    Field { id: 11, name: "marker_location_4", kind: Plain },
+    Field { id: 12, name: "marker_location_5", kind: Plain },
+    Field { id: 13, name: "comment", kind: Plain },
+    Field { id: 14, name: "end_to_end_loss", kind: Plain },
+    Field { id: 15, name: "end_to_end_marker_position_1", kind: Plain },
+    Field { id: 16, name: "end_to_end_marker_position_2", kind: Plain },
+    Field { id: 17, name: "optical_return_loss", kind: Plain },
+    Field { id: 18, name: "optical_return_loss_marker_position_1", kind: Plain },
+    Field { id: 19, name: "optical_return_loss_marker_position_2", kind: Plain },
+];
+
+const KEY_EVENTS: &[Field] = &[
+    Field { id: 1, name: "number_of_key_events", kind: Plain },
+    Field { id: 2, name: "key_events", kind: List(&Struct(KEY_EVENT)) },
+    Field { id: 3, name: "last_key_event", kind: Struct(LAST_KEY_EVENT) },
+];
+
+const LANDMARK: &[Field] = &[
+    Field { id: 1, name: "landmark_number", kind: Plain },
+    Field { id: 2, name: "landmark_code", kind: Plain },
+    Field { id: 3, name: "landmark_location", kind: Plain },
+    Field { id: 4, name: "related_event_number", kind: Plain },
+    Field { id: 5, name: "gps_longitude", kind: Plain },
+    Field { id: 6, name: "gps_latitude", kind: Plain },
+    Field { id: 7, name: "fiber_correction_factor_lead_in_fiber", kind: Plain },
+    Field { id: 8, name: "sheath_marker_entering_landmark", kind: Plain },
+    Field { id: 9, name: "sheath_marker_leaving_landmark", kind: Plain },
+    Field { id: 10, name: "units_of_sheath_marks_leaving_landmark", kind: Plain },
+    Field { id: 11, name: "mode_field_diameter_leaving_landmark", kind: Plain },
+    Field { id: 12, name: "comment", kind: Plain },
+];
+
+const LINK_PARAMETERS: &[Field] = &[
+    Field { id: 1, name: "number_of_landmarks", kind: Plain },
+    Field { id: 2, name: "landmarks", kind: List(&Struct(LANDMARK)) },
+];
+
+const DATA_POINTS_AT_SCALE_FACTOR: &[Field] = &[
+    Field { id: 1, name: "n_points", kind: Plain },
+    Field { id: 2, name: "scale_factor", kind: Plain },
+    Field { id: 3, name: "data", kind: Plain },
+];
+
+const DATA_POINTS: &[Field] = &[
+    Field { id: 1, name: "number_of_data_points", kind: Plain },
+    Field { id: 2, name: "total_number_scale_factors_used", kind: Plain },
+    Field { id: 3, name: "scale_factors", kind: List(&Struct(DATA_POINTS_AT_SCALE_FACTOR)) },
+];
+
+const PROPRIETARY_BLOCK: &[Field] = &[
+    Field { id: 1, name: "header", kind: Plain },
+    Field { id: 2, name: "data", kind: Bytes },
+];
+
+const SOR_FILE: &[Field] = &[
+    Field { id: 1, name: "map", kind: Struct(MAP_BLOCK) },
+    Field { id: 2, name: "general_parameters", kind: Struct(GENERAL_PARAMETERS_BLOCK) },
+    Field { id: 3, name: "supplier_parameters", kind: Struct(SUPPLIER_PARAMETERS_BLOCK) },
+    Field { id: 4, name: "fixed_parameters", kind: Struct(FIXED_PARAMETERS_BLOCK) },
+    Field { id: 5, name: "key_events", kind: Struct(KEY_EVENTS) },
+    Field { id: 6, name: "link_parameters", kind: Struct(LINK_PARAMETERS) },
+    Field { id: 7, name: "data_points", kind: Struct(DATA_POINTS) },
+    Field { id: 8, name: "proprietary_blocks", kind: List(&Struct(PROPRIETARY_BLOCK)) },
+];
+
+fn plain_to_cbor(value: &Value) -> Cbor {
+    match value {
+        Value::Null => Cbor::Null,
+        Value::Bool(b) => Cbor::Bool(*b),
+        Value::Number(n) => match (n.as_i64(), n.as_u64()) {
+            (Some(i), _) => Cbor::Integer(i.into()),
+            (_, Some(u)) => Cbor::Integer(u.into()),
+            _ => Cbor::Float(n.as_f64().unwrap_or_default()),
+        },
+        Value::String(s) => Cbor::Text(s.clone()),
+        Value::Array(items) => Cbor::Array(items.iter().map(plain_to_cbor).collect()),
+        Value::Object(_) => Cbor::Null,
+    }
+}
+
+fn to_cbor(value: &Value, kind: &Kind) -> Cbor {
+    match (kind, value) {
+        (Plain, value) => plain_to_cbor(value),
+        (Bytes, Value::Array(items)) => Cbor::Bytes(items.iter().map(|b| b.as_u64().unwrap_or_default() as u8).collect()),
+        (Struct(fields), Value::Object(map)) => Cbor::Map(fields.iter()
+            .filter_map(|field| match map.get(field.name) {
+                None | Some(Value::Null) => None,
+                Some(value) => Some((Cbor::Integer(field.id.into()), to_cbor(value, &field.kind))),
+            })
+            .collect()),
+        (List(kind), Value::Array(items)) => Cbor::Array(items.iter().map(|item| to_cbor(item, kind)).collect()),
+        _ => Cbor::Null,
+    }
+}
+
+fn plain_from_cbor(value: &Cbor) -> Result<Value, &'static str> {
+    Ok(match value {
+        Cbor::Null => Value::Null,
+        Cbor::Bool(b) => Value::Bool(*b),
+        Cbor::Integer(i) => {
+            let n = i64::try_from(*i).map(Value::from).or_else(|_| u64::try_from(*i).map(Value::from));
+            n.map_err(|_| "Integer out of range")?
+        }
+        Cbor::Text(s) => Value::String(s.clone()),
+        Cbor::Array(items) => Value::Array(items.iter().map(plain_from_cbor).collect::<Result<_, _>>()?),
+        _ => return Err("Unexpected CBOR value"),
+    })
+}
+
+fn from_cbor(value: &Cbor, kind: &Kind) -> Result<Value, &'static str> {
+    match (kind, value) {
+        (Plain, value) => plain_from_cbor(value),
+        (Bytes, Cbor::Bytes(bytes)) => Ok(bytes.iter().map(|&b| Value::from(b)).collect()),
+        (Struct(fields), Cbor::Map(map)) => {
+            let mut object = serde_json::Map::new();
+            for field in fields.iter() {
+                let value = match map.get(&Cbor::Integer(field.id.into())) {
+                    Some(value) => from_cbor(value, &field.kind)?,
+                    None => Value::Null,
+                };
+                object.insert(field.name.to_owned(), value);
+            }
+            Ok(Value::Object(object))
+        }
+        (List(kind), Cbor::Array(items)) => items.iter().map(|item| from_cbor(item, kind)).collect(),
+        _ => Err("Unexpected CBOR value"),
+    }
+}
+
+impl SORFile {
+    /// Encode the file in the otdrs interchange profile
+    pub fn to_interchange(&self) -> Vec<u8> {
+        let file = serde_json::to_value(self).expect("SORFile always serialises");
+        let mut document = BTreeMap::new();
+        document.insert(Cbor::Integer(0), Cbor::Integer(INTERCHANGE_VERSION.into()));
+        document.insert(Cbor::Integer(1), to_cbor(&file, &Struct(SOR_FILE)));
+        serde_cbor::to_vec(&Cbor::Map(document)).expect("CBOR values always serialise")
+    }
+
+    /// Decode a file in the otdrs interchange profile, of this or an earlier
+    /// version
+    pub fn from_interchange(data: &[u8]) -> Result<SORFile, &'static str> {
+        let document: BTreeMap<Cbor, Cbor> = serde_cbor::from_slice(data).map_err(|_| "Not a CBOR map")?;
+        match document.get(&Cbor::Integer(0)) {
+            Some(Cbor::Integer(v)) if *v >= 1 && *v <= i128::from(INTERCHANGE_VERSION) => (),
+            Some(Cbor::Integer(_)) => return Err("Unsupported interchange profile version"),
+            _ => return Err("Not an otdrs interchange document"),
+        }
+        let file = document.get(&Cbor::Integer(1)).ok_or("Not an otdrs interchange document")?;
+        serde_json::from_value(from_cbor(file, &Struct(SOR_FILE))?).map_err(|_| "Invalid interchange document")
+    }
+}
+
+#[test]
+fn test_interchange() {
+    for name in ["example1-noyes-ofl280.sor", "example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor"].iter() {
+        let data = std::fs::read(format!("data/{}", name)).unwrap();
+        let sor = crate::parser::parse_file(&data).unwrap().1;
+        let encoded = sor.to_interchange();
+        assert_eq!(SORFile::from_interchange(&encoded).unwrap(), sor, "{}", name);
+        // Deterministic
+        assert_eq!(SORFile::from_interchange(&encoded).unwrap().to_interchange(), encoded);
+    }
+
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let sor = crate::parser::parse_file(data).unwrap().1;
+    let encoded = sor.to_interchange();
+    // {0: 1, 1: {1: {1: 200, ...
+    assert_eq!(&encoded[..8], &[0xa2, 0x00, 0x01, 0x01, 0xa7, 0x01, 0xa4, 0x01]);
+    let document: BTreeMap<Cbor, Cbor> = serde_cbor::from_slice(&encoded).unwrap();
+    let file = match &document[&Cbor::Integer(1)] { Cbor::Map(map) => map.clone(), _ => panic!() };
+    match &file[&Cbor::Integer(2)] {
+        Cbor::Map(gp) => assert_eq!(gp[&Cbor::Integer(3)], Cbor::Text("Fiber1".to_owned())),
+        _ => panic!(),
+    }
+    // The link parameters block is missing, so left out
+    assert!(!file.contains_key(&Cbor::Integer(6)));
+
+    // Every serialised field has an ID
+    fn check(value: &Value, kind: &Kind) {
+        match (kind, value) {
+            (Struct(fields), Value::Object(map)) => for (name, value) in map {
+                let field = fields.iter().find(|f| f.name == name).unwrap_or_else(|| panic!("{} has no ID", name));
+                check(value, &field.kind);
+            },
+            (List(kind), Value::Array(items)) => items.iter().for_each(|item| check(item, kind)),
+            _ => (),
+        }
+    }
+    check(&serde_json::to_value(&sor).unwrap(), &Struct(SOR_FILE));
+
+    // Unknown fields are ignored, but newer versions refused
+    let mut document = document;
+    if let Some(Cbor::Map(file)) = document.get_mut(&Cbor::Integer(1)) {
+        file.insert(Cbor::Integer(99), Cbor::Text("from the future".to_owned()));
+    }
+    assert_eq!(SORFile::from_interchange(&serde_cbor::to_vec(&document).unwrap()).unwrap(), sor);
+    document.insert(Cbor::Integer(0), Cbor::Integer(2));
+    assert_eq!(SORFile::from_interchange(&serde_cbor::to_vec(&document).unwrap()), Err("Unsupported interchange profile version"));
+    assert!(SORFile::from_interchange(b"not CBOR").is_err());
+}
@@ -0,0 +1,94 @@
+/// This module renders a file's landmarks, and optionally a fault location
+/// from the break locator, as KML, so that outside-plant teams can open them
+/// straight in Google Earth or a GIS during outage response.
+///
+/// Only landmarks with a GPS position are drawn. The fault is placed along
+/// the route as by `geo::locate`, which can't know of slack coiled at one
+/// spot in the cable, so the placemark says how far it is from the nearest
+/// landmark, and the sheath marker if known, too.
+use crate::analysis::BreakLocation;
+use crate::geo::{locate, route};
+use crate::types::{Landmark, SORFile};
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn placemark(name: &str, description: &str, style: &str, (latitude, longitude): (f64, f64)) -> String {
+    format!("<Placemark><name>{}</name><description>{}</description><styleUrl>#{}</styleUrl>\
+             <Point><coordinates>{:.6},{:.6},0</coordinates></Point></Placemark>\n",
+            escape_xml(name), escape_xml(description), style, longitude, latitude)
+}
+
+fn landmark_name(l: &Landmark) -> String {
+    match l.comment.trim() {
+        "" => format!("{} {}", l.landmark_code, l.landmark_number),
+        comment => comment.to_owned(),
+    }
+}
+
+/// Render a file's landmarks, the route between them and, if given, a fault
+/// location as a KML document
+pub fn to_kml(sor: &SORFile, fault: Option<&BreakLocation>) -> String {
+    let name = sor.general_parameters.as_ref()
+        .map_or(String::new(), |gp| format!("{} {}", gp.cable_id.trim(), gp.fiber_id.trim()).trim().to_owned());
+    let mut kml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<kml xmlns=\"http://www.opengis.net/kml/2.2\">\n<Document>\n");
+    kml += &format!("<name>{}</name>\n", escape_xml(&name));
+    kml += "<Style id=\"landmark\"><IconStyle><Icon><href>http://maps.google.com/mapfiles/kml/paddle/blu-circle.png</href></Icon></IconStyle></Style>\n";
+    kml += "<Style id=\"fault\"><IconStyle><Icon><href>http://maps.google.com/mapfiles/kml/paddle/red-stars.png</href></Icon></IconStyle></Style>\n";
+    kml += "<Style id=\"route\"><LineStyle><color>ffff0000</color><width>3</width></LineStyle></Style>\n";
+    for l in sor.link_parameters.iter().flat_map(|lp| lp.landmarks.iter()) {
+        if let Some(position) = l.position() {
+            let mut description = format!("Landmark {} ({}) at {:.1} m", l.landmark_number, l.landmark_code, sor.landmark_distance_m(l));
+            if l.related_event_number != 0 {
+                description += &format!(", event {}", l.related_event_number);
+            }
+            kml += &placemark(&landmark_name(l), &description, "landmark", position);
+        }
+    }
+    let route = route(sor);
+    if route.len() >= 2 {
+        let coordinates: Vec<String> = route.iter().map(|&(_, lat, lon)| format!("{:.6},{:.6},0", lon, lat)).collect();
+        kml += &format!("<Placemark><name>Route</name><styleUrl>#route</styleUrl><LineString><coordinates>{}</coordinates></LineString></Placemark>\n",
+                        coordinates.join(" "));
+    }
+    if let Some(fault) = fault {
+        let location = locate(sor, fault.distance_m);
+        if let Some(position) = location.position {
+            let mut description = format!("{} at {:.1} m +/- {:.1} m",
+                                          if fault.end_of_fibre { "Break" } else { "New loss" }, fault.distance_m, fault.uncertainty_m);
+            if let Some((l, from_m)) = &fault.landmark {
+                description += &format!(", {:.1} m {} {}", from_m.abs(), if *from_m >= 0.0 { "beyond" } else { "before" }, landmark_name(l));
+            }
+            if let (Some(marker), Some(units)) = (location.sheath_marker, &location.sheath_units) {
+                description += &format!(", sheath marker {:.0} {}", marker, units);
+            }
+            kml += &placemark("Fault", &description, "fault", position);
+        }
+    }
+    kml += "</Document>\n</kml>\n";
+    kml
+}
+
+#[test]
+fn test_to_kml() {
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let mut sor = crate::parser::parse_file(data).unwrap().1;
+    assert!(!to_kml(&sor, None).contains("<Placemark>"));
+    sor.add_landmark("MH", 0.0).unwrap().set_position(51.0, -1.0).unwrap();
+    let far = sor.add_landmark("MH", 1000.0).unwrap();
+    far.set_position(51.01, -1.02).unwrap();
+    far.comment = "Pole <7>".into();
+    // Without a position, this one is left off the map
+    sor.add_landmark("MH", 1500.0).unwrap();
+
+    let far = sor.link_parameters.as_ref().unwrap().landmarks[1].clone();
+    let fault = BreakLocation { distance_m: 1100.0, uncertainty_m: 1.2, end_of_fibre: true, landmark: Some((far, 100.0)) };
+    let kml = to_kml(&sor, Some(&fault));
+    assert_eq!(kml.matches("<Placemark>").count(), 4);
+    assert!(kml.contains("<name>Pole &lt;7&gt;</name>"));
+    assert!(kml.contains("<coordinates>-1.000000,51.000000,0 -1.020000,51.010000,0</coordinates>"));
+    assert!(kml.contains("Break at 1100.0 m +/- 1.2 m, 100.0 m beyond Pole &lt;7&gt;"));
+    // Beyond the last positioned landmark, the route is extrapolated
+    assert!(kml.contains("<coordinates>-1.022000,51.011000,0</coordinates>"));
+}
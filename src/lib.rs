@@ -1,7 +1,90 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+extern crate alloc;
+
 /// Base library for otdrs
 pub mod types;
+pub mod api;
+#[cfg(feature = "tokio")]
+pub mod aio;
+#[cfg(feature = "std")]
+pub mod analysis;
+#[cfg(feature = "zip")]
+pub mod archive;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+#[cfg(feature = "std")]
+pub mod batch;
+#[cfg(feature = "std")]
+pub mod builder;
 pub mod parser;
-use crc::{Crc, CRC_16_KERMIT};
+#[cfg(feature = "std")]
+pub mod patch;
+#[cfg(feature = "protobuf")]
+pub mod protobuf;
+pub mod checksum;
+#[cfg(feature = "std")]
+pub mod compare;
+#[cfg(feature = "sqlite")]
+pub mod catalogue;
+#[cfg(feature = "std")]
+pub mod edit;
+#[cfg(feature = "std")]
+pub mod engineering;
+#[cfg(feature = "std")]
+pub mod export;
+#[cfg(feature = "std")]
+pub mod format;
+#[cfg(feature = "std")]
+pub mod geo;
+#[cfg(feature = "std")]
+pub mod interchange;
+#[cfg(feature = "std")]
+pub mod kml;
+#[cfg(feature = "std")]
+pub mod lossless;
+#[cfg(feature = "plot")]
+pub mod plot;
+#[cfg(feature = "polars")]
+pub mod polars;
+#[cfg(feature = "std")]
+pub mod progress;
+#[cfg(feature = "std")]
+pub mod registry;
+#[cfg(feature = "std")]
+pub mod report;
+#[cfg(feature = "std")]
+pub mod requantize;
+#[cfg(feature = "std")]
+pub mod schema;
+#[cfg(feature = "serve")]
+pub mod serve;
+#[cfg(feature = "std")]
+pub mod set;
+pub mod stats;
+#[cfg(feature = "object_store")]
+pub mod store;
+/// This module converts between the units SR-4731 stores times and
+/// distances in and metres, as the one definition of these encodings that
+/// the rest of the crate builds on.
+///
+/// Times throughout a SOR file (propagation times, offsets, markers) are
+/// one-way, in units of 100ps; turning them into distances needs the
+/// fibre's group index, which is stored as the index x 100000. Distance
+/// fields, such as the user offset distance, are in tenths of the file's
+/// units_of_distance. Data spacing is the time taken to acquire 10,000
+/// points. Landmarks' GPS coordinates are WGS84 degrees x 1000000.
+#[cfg(feature = "std")]
+pub mod units;
+#[cfg(feature = "std")]
+pub mod validate;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "std")]
+pub mod xml;
+#[cfg(feature = "std")]
+pub use crate::lossless::verify_lossless;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 use crate::types::{BlockInfo, MapBlock, ProprietaryBlock, SORFile};
 
 // These macros are used to coherently and consistently produce all the binary encodings that we need
@@ -32,54 +115,150 @@ macro_rules! le_integer {
 }
 
 macro_rules! add_block {
-    ($b:expr, $m:expr, $nm:expr, $block:expr, $gen_block:expr, $block_id:expr) => {
-        if $block.is_some() {
-            let block_bytes = match $gen_block {
-                Ok(res) => res,
-                Err(err) => { return Err(err); }
-            };
-            let block_info = $m.block_info.iter().find(|&x| x.identifier == $block_id);
-            if block_info.is_none() {
-                return Err("BlockInfo block is missing for one of your blocks in the Map!");
-            }
-            let new_block_info = BlockInfo {
-                identifier: $block_id,
-                revision_number: block_info.unwrap().revision_number,
-                size: block_bytes.len() as i32
-            };
-            $nm.block_info.push(new_block_info);
-            $nm.block_count += 1;
-            // Per block: header string length + null terminating byte + 2-byte rev num + 4-byte size
-            $nm.block_size += ($block_id.len() + 1 + 2 + 4) as i32; 
-            $b.extend(block_bytes);
-        }
-    };
-    // FIXME: This is just the above without the is_some check for the vector form for proprietary blocks, best not duplicated but it's 1am
     ($b:expr, $m:expr, $nm:expr, $gen_block:expr, $block_id:expr) => {
-        let block_bytes = match $gen_block {
-            Ok(res) => res,
-            Err(err) => { return Err(err); }
-        };
-        let block_info = $m.block_info.iter().find(|&x| x.identifier == $block_id);
+        let block_info = $m.block_info.iter().find(|&x| *x.identifier == *$block_id);
         if block_info.is_none() {
             return Err("BlockInfo block is missing for one of your blocks in the Map!");
         }
+        let block_start = $b.len();
+        $gen_block?;
         let new_block_info = BlockInfo {
-            identifier: $block_id,
+            identifier: Arc::from(&*$block_id),
             revision_number: block_info.unwrap().revision_number,
-            size: block_bytes.len() as i32
+            size: ($b.len() - block_start) as i32
         };
         $nm.block_info.push(new_block_info);
         $nm.block_count += 1;
         // Per block: header string length + null terminating byte + 2-byte rev num + 4-byte size
-        $nm.block_size += ($block_id.len() + 1 + 2 + 4) as i32; 
-        $b.extend(block_bytes);
+        $nm.block_size += ($block_id.len() + 1 + 2 + 4) as i32;
     };
 }
 
+/// How the writer lays a file out
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct WriteOptions {
+    /// Write blocks in the order the file's map lists them, as they were in
+    /// the file it was parsed from, rather than the standard blocks followed
+    /// by the proprietary ones. Blocks the map doesn't list come last. The
+    /// checksum block also keeps its place and size, where some vendors'
+    /// readers expect it, rather than being written last in 8 bytes; any
+    /// bytes between its header and its value are written as zeros
+    pub preserve_block_order: bool,
+    /// The CRC-16 variant and coverage of the checksum block
+    pub checksum_algorithm: checksum::Algorithm,
+    pub checksum_strategy: checksum::Strategy,
+}
+
+impl Default for WriteOptions {
+    fn default() -> WriteOptions {
+        WriteOptions {
+            preserve_block_order: false,
+            checksum_algorithm: checksum::Algorithm::Kermit,
+            checksum_strategy: checksum::Strategy::PrecedingBlocks,
+        }
+    }
+}
+
+type GenBlock = fn(&SORFile, &mut Vec<u8>) -> Result<(), &'static str>;
+
+/// A block to be written after the map
+enum Block<'a> {
+    Standard(&'static str, GenBlock),
+    Proprietary(&'a ProprietaryBlock),
+    /// A checksum block kept where the map lists it, of the size it lists
+    Checksum(usize),
+}
+
+impl Block<'_> {
+    fn identifier(&self) -> &str {
+        match self {
+            Block::Standard(id, _) => id,
+            Block::Proprietary(pb) => &pb.header,
+            Block::Checksum(_) => parser::BLOCK_ID_CHECKSUM,
+        }
+    }
+}
+
 impl SORFile {
     pub fn to_bytes(&self) -> Result<Vec<u8>, &str> {
+        self.to_bytes_with(&WriteOptions::default())
+    }
+
+    /// Write the file out with a particular layout, e.g. to reproduce the
+    /// file it was parsed from as closely as possible
+    pub fn to_bytes_with(&self, options: &WriteOptions) -> Result<Vec<u8>, &'static str> {
         let mut bytes: Vec<u8> = Vec::new();
+        self.write_blocks(&mut bytes, 0, options)?;
+        Ok(bytes)
+    }
+
+    /// Write the file to the end of a buffer, which can be cleared and reused
+    /// between files to avoid allocating for each one. Space for the whole
+    /// file is reserved up front. If writing fails, the buffer is left as it
+    /// was
+    pub fn serialize_into(&self, bytes: &mut Vec<u8>) -> Result<(), &'static str> {
+        let start = bytes.len();
+        let result = self.write_blocks(bytes, start, &WriteOptions::default());
+        if result.is_err() {
+            bytes.truncate(start);
+        }
+        result
+    }
+
+    /// The blocks that will be written after the map, in order. The
+    /// checksum block is only among them if it keeps its original place,
+    /// and otherwise follows them
+    fn blocks(&self, options: &WriteOptions) -> Vec<Block<'_>> {
+        let standard: [(bool, &'static str, GenBlock); 6] = [
+            (self.general_parameters.is_some(), parser::BLOCK_ID_GENPARAMS, SORFile::gen_general_parameters),
+            (self.supplier_parameters.is_some(), parser::BLOCK_ID_SUPPARAMS, SORFile::gen_supplier_parameters),
+            (self.fixed_parameters.is_some(), parser::BLOCK_ID_FXDPARAMS, SORFile::gen_fixed_parameters),
+            (self.key_events.is_some(), parser::BLOCK_ID_KEYEVENTS, SORFile::gen_key_events),
+            (self.link_parameters.is_some(), parser::BLOCK_ID_LNKPARAMS, SORFile::gen_link_parameters),
+            (self.data_points.is_some(), parser::BLOCK_ID_DATAPTS, SORFile::gen_data_points),
+        ];
+        let mut blocks: Vec<Block> = standard.iter().filter(|(present, _, _)| *present).map(|&(_, id, gen)| Block::Standard(id, gen))
+            .chain(self.proprietary_blocks.iter().map(Block::Proprietary))
+            .collect();
+        if !options.preserve_block_order {
+            return blocks;
+        }
+        if let Some(bi) = self.map.block_info.iter().find(|bi| &*bi.identifier == parser::BLOCK_ID_CHECKSUM) {
+            // Too small to hold a header and value, it can't be reproduced
+            let min_size = parser::BLOCK_ID_CHECKSUM.len() + 1 + 2;
+            blocks.push(Block::Checksum((bi.size.max(0) as usize).max(min_size)));
+        }
+        // The nth block with an identifier goes where the map's nth block with
+        // that identifier was, since proprietary headers needn't be unique
+        let mut positioned: Vec<(usize, Block)> = Vec::with_capacity(blocks.len());
+        for block in blocks {
+            let occurrence = positioned.iter().filter(|(_, b)| b.identifier() == block.identifier()).count();
+            let position = self.map.block_info.iter().enumerate()
+                .filter(|(_, bi)| &*bi.identifier == block.identifier())
+                .nth(occurrence)
+                .map_or(usize::MAX, |(i, _)| i);
+            positioned.push((position, block));
+        }
+        positioned.sort_by_key(|&(position, _)| position);
+        positioned.into_iter().map(|(_, block)| block).collect()
+    }
+
+    /// Roughly how many bytes the file will take, counting the bulky parts
+    /// exactly - data points, key events and proprietary blocks - and
+    /// allowing generously for the rest
+    fn encoded_len_hint(&self) -> usize {
+        let data_points = self.data_points.as_ref()
+            .map_or(0, |dp| dp.scale_factors.iter().map(|sf| 6 + 2 * sf.data.len()).sum());
+        let key_events = self.key_events.as_ref()
+            .map_or(0, |ke| ke.key_events.iter().map(|e| 42 + e.comment.len()).sum::<usize>() + 64 + ke.last_key_event.comment.len());
+        let landmarks = self.link_parameters.as_ref()
+            .map_or(0, |lp| lp.landmarks.iter().map(|lm| 42 + lm.comment.len()).sum());
+        let proprietary: usize = self.proprietary_blocks.iter().map(|pb| pb.header.len() + 1 + pb.data.len()).sum();
+        1024 + data_points + key_events + landmarks + proprietary
+    }
+
+    fn write_blocks(&self, bytes: &mut Vec<u8>, start: usize, options: &WriteOptions) -> Result<(), &'static str> {
+        let blocks = self.blocks(options);
         // Basically, we're now going to generate everything from scratch from our internal state
         // We therefore need a new map block to describe the resulting blocks.
         let mut new_map = MapBlock{
@@ -88,44 +267,64 @@ impl SORFile {
             block_size: 0,
             block_info: Vec::new()
         };
+        // The map comes first, but its contents depend on the blocks' sizes. Its
+        // length doesn't, so we leave room for it and fill it in at the end.
+        let checksum_in_place = blocks.iter().any(|block| matches!(block, Block::Checksum(_)));
+        let map_len = parser::BLOCK_ID_MAP.len() + 1 + 2 + 4 + 2
+            + blocks.iter().map(|block| block.identifier().len() + 1 + 2 + 4).sum::<usize>()
+            + if checksum_in_place { 0 } else { parser::BLOCK_ID_CHECKSUM.len() + 1 + 2 + 4 };
+        bytes.reserve(map_len + self.encoded_len_hint());
+        bytes.resize(start + map_len, 0);
+
         // Then we add to this block for anything we have
         // FIXME: We should probably explode instead of producing non-compliant files, e.g. genparams is mandatory in spec
         // We are permissive in reading and parsing nonsense files but should be strict in production.
-        add_block!(bytes, self.map, new_map, self.general_parameters, self.gen_general_parameters(), parser::BLOCK_ID_GENPARAMS.to_string());
-        add_block!(bytes, self.map, new_map, self.supplier_parameters, self.gen_supplier_parameters(), parser::BLOCK_ID_SUPPARAMS.to_string());
-        add_block!(bytes, self.map, new_map, self.fixed_parameters, self.gen_fixed_parameters(), parser::BLOCK_ID_FXDPARAMS.to_string());
-        add_block!(bytes, self.map, new_map, self.key_events, self.gen_key_events(), parser::BLOCK_ID_KEYEVENTS.to_string());
-        add_block!(bytes, self.map, new_map, self.data_points, self.gen_data_points(), parser::BLOCK_ID_DATAPTS.to_string());
-        
-        // For each proprietary block, just write it out
-        for pb in &self.proprietary_blocks {
-            add_block!(bytes, self.map, new_map, self.gen_proprietary_block(pb), pb.header.clone());
+        // Proprietary blocks are just written out as they are
+        let mut checksum_block = None;
+        for block in &blocks {
+            match block {
+                Block::Standard(id, gen) => {
+                    add_block!(bytes, self.map, new_map, gen(self, bytes), *id);
+                }
+                Block::Proprietary(pb) => {
+                    add_block!(bytes, self.map, new_map, self.gen_proprietary_block(pb, bytes), pb.header);
+                }
+                Block::Checksum(size) => {
+                    // Its value is filled in once everything it covers is written
+                    checksum_block = Some(checksum::ChecksumBlock { offset: bytes.len(), size: *size, value: 0 });
+                    add_block!(bytes, self.map, new_map, self.gen_checksum_placeholder(*size, bytes), parser::BLOCK_ID_CHECKSUM);
+                }
+            }
+        }
+        // Now we want to generate our checksum block, unless it's been written in its place already - first we have to add the block to the map, before we bake it in, so we do this manually here...
+        if checksum_block.is_none() {
+            let original = self.map.block_info.iter().find(|bi| &*bi.identifier == parser::BLOCK_ID_CHECKSUM);
+            let new_block_info = BlockInfo {
+                identifier: Arc::from(parser::BLOCK_ID_CHECKSUM),
+                // We're hardcoding this because we can, unless asked to keep the file as it was
+                revision_number: original.filter(|_| options.preserve_block_order).map_or(200, |bi| bi.revision_number),
+                size: (parser::BLOCK_ID_CHECKSUM.len() + 1 + 2) as i32
+            };
+            new_map.block_info.push(new_block_info);
+            new_map.block_count += 1;
+            new_map.block_size += (parser::BLOCK_ID_CHECKSUM.len() + 1 + 2 + 4) as i32;
+        }
+
+        let map_bytes = self.gen_map(new_map)?;
+        debug_assert_eq!(map_bytes.len(), map_len);
+        bytes[start..start + map_len].copy_from_slice(&map_bytes);
+
+        // This is now the complete file - almost. We now gen the checksum block and tack it on the end, or fill in the one we left room for.
+        match checksum_block {
+            Some(block) => {
+                fill_checksum(bytes, start, &block, options);
+                Ok(())
+            }
+            None => self.gen_checksum_block(bytes, start, options),
         }
-        
-        // Now we want to generate our checksum block - first we have to add the block to the map, before we bake it in, so we do this manually here...
-        let new_block_info = BlockInfo {
-            identifier: parser::BLOCK_ID_CHECKSUM.to_string(),
-            revision_number: 200, // We're hardcoding this because we can
-            size: (parser::BLOCK_ID_CHECKSUM.len() + 1 + 2) as i32
-        };
-        new_map.block_info.push(new_block_info);
-        new_map.block_count += 1;
-        new_map.block_size += (parser::BLOCK_ID_CHECKSUM.len() + 1 + 2 + 4) as i32; 
-
-        // dbg!(&self.map);
-        // dbg!(&new_map);
-        
-        let mut map_bytes = self.gen_map(new_map).unwrap();
-        map_bytes.extend(bytes);
-
-        // This is now the complete file - almost. We now gen the checksum block and tack it on the end.
-        let cs_block = self.gen_checksum_block(&map_bytes).unwrap();
-        map_bytes.extend(cs_block);
-        
-        Ok(map_bytes)
     }
 
-    fn gen_map(&self, map: MapBlock) -> Result<Vec<u8>, &str> {
+    fn gen_map(&self, map: MapBlock) -> Result<Vec<u8>, &'static str> {
         let mut bytes: Vec<u8> = Vec::new();
         null_terminated_str!(bytes, parser::BLOCK_ID_MAP);
         le_integer!(bytes, map.revision_number);
@@ -141,8 +340,7 @@ impl SORFile {
         Ok(bytes)
     }
 
-    fn gen_general_parameters(&self) -> Result<Vec<u8>, &str> {
-        let mut bytes: Vec<u8> = Vec::new();
+    fn gen_general_parameters(&self, bytes: &mut Vec<u8>) -> Result<(), &'static str> {
         let gp = self.general_parameters.as_ref().unwrap();
         null_terminated_str!(bytes, parser::BLOCK_ID_GENPARAMS);
         fixed_length_str!(bytes, gp.language_code, 2);
@@ -158,11 +356,10 @@ impl SORFile {
         le_integer!(bytes, gp.user_offset_distance);
         null_terminated_str!(bytes, gp.operator); 
         null_terminated_str!(bytes, gp.comment); 
-        Ok(bytes)
+        Ok(())
     }
 
-    fn gen_supplier_parameters(&self) -> Result<Vec<u8>, &str> {
-        let mut bytes: Vec<u8> = Vec::new();
+    fn gen_supplier_parameters(&self, bytes: &mut Vec<u8>) -> Result<(), &'static str> {
         let sp = self.supplier_parameters.as_ref().unwrap();
         null_terminated_str!(bytes, parser::BLOCK_ID_SUPPARAMS);
         null_terminated_str!(bytes, sp.supplier_name);
@@ -172,11 +369,10 @@ impl SORFile {
         null_terminated_str!(bytes, sp.optical_module_sn);
         null_terminated_str!(bytes, sp.software_revision);
         null_terminated_str!(bytes, sp.other);
-        Ok(bytes)
+        Ok(())
     }
 
-    fn gen_fixed_parameters(&self) -> Result<Vec<u8>, &str> {
-        let mut bytes: Vec<u8> = Vec::new();
+    fn gen_fixed_parameters(&self, bytes: &mut Vec<u8>) -> Result<(), &'static str> {
         let fp = self.fixed_parameters.as_ref().unwrap();
         null_terminated_str!(bytes, parser::BLOCK_ID_FXDPARAMS);
         le_integer!(bytes, fp.date_time_stamp);
@@ -212,11 +408,10 @@ impl SORFile {
         le_integer!(bytes, fp.window_coordinate_2);
         le_integer!(bytes, fp.window_coordinate_3);
         le_integer!(bytes, fp.window_coordinate_4);
-        Ok(bytes)
+        Ok(())
     }
 
-    fn gen_key_events(&self) -> Result<Vec<u8>, &str> {
-        let mut bytes: Vec<u8> = Vec::new();
+    fn gen_key_events(&self, bytes: &mut Vec<u8>) -> Result<(), &'static str> {
         let events = self.key_events.as_ref().unwrap();
         null_terminated_str!(bytes, parser::BLOCK_ID_KEYEVENTS);
         le_integer!(bytes, events.number_of_key_events);
@@ -254,11 +449,31 @@ impl SORFile {
         le_integer!(bytes, events.last_key_event.optical_return_loss);
         le_integer!(bytes, events.last_key_event.optical_return_loss_marker_position_1);
         le_integer!(bytes, events.last_key_event.optical_return_loss_marker_position_2);
-        Ok(bytes)
+        Ok(())
     }
 
-    fn gen_data_points(&self) -> Result<Vec<u8>, &str> {
-        let mut bytes: Vec<u8> = Vec::new();
+    fn gen_link_parameters(&self, bytes: &mut Vec<u8>) -> Result<(), &'static str> {
+        let lp = self.link_parameters.as_ref().unwrap();
+        null_terminated_str!(bytes, parser::BLOCK_ID_LNKPARAMS);
+        le_integer!(bytes, lp.number_of_landmarks);
+        for lm in &lp.landmarks {
+            le_integer!(bytes, lm.landmark_number);
+            fixed_length_str!(bytes, lm.landmark_code, 2);
+            le_integer!(bytes, lm.landmark_location);
+            le_integer!(bytes, lm.related_event_number);
+            le_integer!(bytes, lm.gps_longitude);
+            le_integer!(bytes, lm.gps_latitude);
+            le_integer!(bytes, lm.fiber_correction_factor_lead_in_fiber);
+            le_integer!(bytes, lm.sheath_marker_entering_landmark);
+            le_integer!(bytes, lm.sheath_marker_leaving_landmark);
+            fixed_length_str!(bytes, lm.units_of_sheath_marks_leaving_landmark, 2);
+            le_integer!(bytes, lm.mode_field_diameter_leaving_landmark);
+            null_terminated_str!(bytes, lm.comment);
+        }
+        Ok(())
+    }
+
+    fn gen_data_points(&self, bytes: &mut Vec<u8>) -> Result<(), &'static str> {
         let dp = self.data_points.as_ref().unwrap();
         null_terminated_str!(bytes, parser::BLOCK_ID_DATAPTS);
         le_integer!(bytes, dp.number_of_data_points);
@@ -270,30 +485,48 @@ impl SORFile {
                 le_integer!(bytes, pt);
             }
         }
-        Ok(bytes)
+        Ok(())
     }
 
-    fn gen_proprietary_block(&self, pb: &ProprietaryBlock) -> Result<Vec<u8>, &str> {
-        let mut bytes: Vec<u8> = Vec::new();
+    fn gen_proprietary_block(&self, pb: &ProprietaryBlock, bytes: &mut Vec<u8>) -> Result<(), &'static str> {
         null_terminated_str!(bytes, pb.header);
-        bytes.extend(pb.data.iter());
-        Ok(bytes)
+        bytes.extend_from_slice(&pb.data);
+        Ok(())
     }
 
-    fn gen_checksum_block(&self, data: &Vec<u8>) -> Result<Vec<u8>, &str> {
-        let mut bytes: Vec<u8> = Vec::new();
-        null_terminated_str!(bytes, parser::BLOCK_ID_CHECKSUM);
-        let crc: Crc<u16> = Crc::<u16>::new(&CRC_16_KERMIT);
-        le_integer!(bytes, crc.checksum(data.as_slice()));
+    /// Append the checksum block, covering everything written from start and,
+    /// depending on the strategy, its own header
+    fn gen_checksum_block(&self, bytes: &mut Vec<u8>, start: usize, options: &WriteOptions) -> Result<(), &'static str> {
+        let block = checksum::ChecksumBlock { offset: bytes.len(), size: parser::BLOCK_ID_CHECKSUM.len() + 1 + 2, value: 0 };
+        self.gen_checksum_placeholder(block.size, bytes)?;
+        fill_checksum(bytes, start, &block, options);
+        Ok(())
+    }
 
-        Ok(bytes)
+    /// A checksum block of the given size, with zeros where the value goes
+    fn gen_checksum_placeholder(&self, size: usize, bytes: &mut Vec<u8>) -> Result<(), &'static str> {
+        let block_start = bytes.len();
+        null_terminated_str!(bytes, parser::BLOCK_ID_CHECKSUM);
+        bytes.resize(block_start + size, 0);
+        Ok(())
     }
 
 }
 
+/// Compute the checksum of a file written from start, and store it in the
+/// last two bytes of its checksum block
+fn fill_checksum(bytes: &mut [u8], start: usize, block: &checksum::ChecksumBlock, options: &WriteOptions) {
+    let value_at = block.offset + block.size - 2;
+    let covered_end = match options.checksum_strategy {
+        checksum::Strategy::PrecedingBlocks => block.offset,
+        checksum::Strategy::IncludingHeader => value_at,
+    };
+    let value = checksum::crc16(&bytes[start..covered_end], options.checksum_algorithm);
+    bytes[value_at..value_at + 2].copy_from_slice(&value.to_le_bytes());
+}
 
 #[cfg(test)]
-fn test_sor_load<'a>() -> SORFile {
+fn test_sor_load() -> SORFile {
     let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
     parser::parse_file(data).unwrap().1
 }
@@ -301,7 +534,8 @@ fn test_sor_load<'a>() -> SORFile {
 #[test]
 fn test_gen_general_parameters() {
     let in_sor = test_sor_load();
-    let _bytes = in_sor.gen_general_parameters();
+    let mut bytes = Vec::new();
+    in_sor.gen_general_parameters(&mut bytes).unwrap();
     // println!("{:#?}", bytes);
     // let mut file = std::fs::File::create("test_genparam.bin").unwrap();
     // file.write_all(bytes.as_slice()).unwrap();
@@ -310,7 +544,8 @@ fn test_gen_general_parameters() {
 #[test]
 fn test_gen_supplier_parameters() {
     let in_sor = test_sor_load();
-    let _bytes = in_sor.gen_supplier_parameters();
+    let mut bytes = Vec::new();
+    in_sor.gen_supplier_parameters(&mut bytes).unwrap();
     // println!("{:#?}", bytes);
     // let mut file = std::fs::File::create("test_supparam.bin").unwrap();
     // file.write_all(bytes.as_slice()).unwrap();
@@ -320,7 +555,8 @@ fn test_gen_supplier_parameters() {
 #[test]
 fn test_gen_fixed_parameters() {
     let in_sor = test_sor_load();
-    let _bytes = in_sor.gen_fixed_parameters();
+    let mut bytes = Vec::new();
+    in_sor.gen_fixed_parameters(&mut bytes).unwrap();
     // println!("{:#?}", bytes);
     // let mut file = std::fs::File::create("test_fixedparam.bin").unwrap();
     // file.write_all(bytes.as_slice()).unwrap();
@@ -330,12 +566,43 @@ fn test_gen_fixed_parameters() {
 #[test]
 fn test_gen_key_events() {
     let in_sor = test_sor_load();
-    let _bytes = in_sor.gen_key_events();
+    let mut bytes = Vec::new();
+    in_sor.gen_key_events(&mut bytes).unwrap();
     // println!("{:#?}", bytes);
     // let mut file = std::fs::File::create("test_keyevents.bin").unwrap();
     // file.write_all(bytes.as_slice()).unwrap();
     // dbg!(bytes);
 }
+#[test]
+fn test_preserve_checksum_block() {
+    let mut sor = test_sor_load();
+    // Move the checksum block up to follow the general parameters, and pad it
+    let i = sor.map.block_info.iter().position(|bi| &*bi.identifier == parser::BLOCK_ID_CHECKSUM).unwrap();
+    let mut cksum = sor.map.block_info.remove(i);
+    cksum.size = 12;
+    sor.map.block_info.insert(1, cksum);
+    let options = WriteOptions {
+        preserve_block_order: true,
+        checksum_algorithm: checksum::Algorithm::Ibm3740,
+        checksum_strategy: checksum::Strategy::IncludingHeader,
+    };
+    let bytes = sor.to_bytes_with(&options).unwrap();
+    let (_, map) = parser::map_block(&bytes).unwrap();
+    assert_eq!(map.block_info.iter().map(|bi| &bi.identifier).collect::<Vec<_>>(),
+               sor.map.block_info.iter().map(|bi| &bi.identifier).collect::<Vec<_>>());
+    let block = checksum::locate(&bytes).unwrap().unwrap();
+    assert_eq!((block.offset, block.size), (map.block_size as usize + map.block_info[0].size as usize, 12));
+    assert_eq!(&bytes[block.offset..block.offset + 6], b"Cksum\0");
+    assert_eq!(checksum::verify(&bytes).unwrap().matches, vec![(options.checksum_algorithm, options.checksum_strategy)]);
+    let reparsed = parser::parse_file(&bytes).unwrap().1;
+    assert_eq!((reparsed.key_events, reparsed.data_points), (sor.key_events.clone(), sor.data_points.clone()));
+
+    // Otherwise it is written last, in 8 bytes
+    let block = checksum::locate(&sor.to_bytes().unwrap()).unwrap().unwrap();
+    assert_eq!(block.size, 8);
+    assert_eq!(block.offset + 8, sor.to_bytes().unwrap().len());
+}
+
 #[test]
 fn test_roundtrip_sor() {
     let in_sor = test_sor_load();
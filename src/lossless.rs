@@ -0,0 +1,117 @@
+/// This module checks that otdrs can write a file back out exactly as it
+/// read it, so that archivists can show a pipeline built on otdrs doesn't
+/// alter the files passing through it.
+///
+/// Files are rewritten with their blocks in their original order and with
+/// the checksum convention they were stored with, as far as `otdrs::checksum`
+/// can identify it. Anything else that differs is reported by block.
+use std::ops::Range;
+use std::path::Path;
+use crate::checksum::{self, Algorithm, Strategy};
+use crate::{parser, WriteOptions};
+
+/// The first difference within one block of a file
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct BlockDifference {
+    pub identifier: String,
+    /// Offset in the source file of the first byte which differs, or of the
+    /// block if the rewritten file doesn't have it
+    pub offset: usize,
+}
+
+/// The outcome of rewriting a file
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct LosslessReport {
+    /// True if the rewritten file is byte-for-byte the same as the source
+    pub identical: bool,
+    pub source_len: usize,
+    pub written_len: usize,
+    /// The checksum convention of the source file, which the rewritten file
+    /// follows. If the source's checksum matches none otdrs knows, the
+    /// rewritten file uses the writer's default and its checksum differs
+    pub checksum: Option<(Algorithm, Strategy)>,
+    /// The blocks which differ, in the order the source file has them, with
+    /// the map first
+    pub differences: Vec<BlockDifference>,
+}
+
+/// Read, parse and rewrite a file, which may be an object store URL with
+/// the object_store feature or a path through a ZIP archive with the zip
+/// feature, and report whether the result is identical to it
+pub fn verify_lossless<P: AsRef<Path>>(path: P) -> Result<LosslessReport, String> {
+    let data = crate::batch::read(path.as_ref())?;
+    verify_lossless_bytes(&data).map_err(|err| err.to_owned())
+}
+
+/// Parse and rewrite a file already in memory, as for `verify_lossless`
+pub fn verify_lossless_bytes(data: &[u8]) -> Result<LosslessReport, &'static str> {
+    let (_, sor) = parser::parse_file(data).map_err(|_| "Could not parse SOR file")?;
+    let checksum = match checksum::locate(data)? {
+        Some(_) => checksum::verify(data)?.matches.first().copied(),
+        None => None,
+    };
+    let defaults = WriteOptions::default();
+    let (checksum_algorithm, checksum_strategy) = checksum.unwrap_or((defaults.checksum_algorithm, defaults.checksum_strategy));
+    let written = sor.to_bytes_with(&WriteOptions { preserve_block_order: true, checksum_algorithm, checksum_strategy })?;
+
+    let source_blocks = blocks(data)?;
+    let written_blocks = blocks(&written)?;
+    let mut differences = Vec::new();
+    for (i, (identifier, range)) in source_blocks.iter().enumerate() {
+        // Pair up the nth block with each identifier, since proprietary
+        // headers needn't be unique
+        let occurrence = source_blocks[..i].iter().filter(|(id, _)| id == identifier).count();
+        let counterpart = written_blocks.iter().filter(|(id, _)| id == identifier).nth(occurrence);
+        let offset = match counterpart {
+            Some((_, other)) => {
+                let (a, b) = (&data[range.clone()], &written[other.clone()]);
+                match a.iter().zip(b).position(|(x, y)| x != y) {
+                    Some(n) => range.start + n,
+                    None if a.len() != b.len() => range.start + a.len().min(b.len()),
+                    None => continue,
+                }
+            }
+            None => range.start,
+        };
+        differences.push(BlockDifference { identifier: identifier.clone(), offset });
+    }
+    Ok(LosslessReport {
+        identical: data == &written[..],
+        source_len: data.len(),
+        written_len: written.len(),
+        checksum,
+        differences,
+    })
+}
+
+/// Each block's identifier and extent, from the map, which is included
+fn blocks(data: &[u8]) -> Result<Vec<(String, Range<usize>)>, &'static str> {
+    let (_, map) = parser::map_block(data).map_err(|_| "Could not parse the map block")?;
+    let mut offset = map.block_size as usize;
+    let mut blocks = vec![(parser::BLOCK_ID_MAP.to_owned(), 0..offset.min(data.len()))];
+    for bi in map.block_info {
+        let end = offset.checked_add(bi.size as usize).ok_or("Block offsets in the map are incorrect")?;
+        blocks.push((bi.identifier.to_string(), offset.min(data.len())..end.min(data.len())));
+        offset = end;
+    }
+    Ok(blocks)
+}
+
+#[test]
+fn test_verify_lossless() {
+    // Noyes and Anritsu interleave their proprietary blocks with the
+    // standard ones, and use different checksum conventions
+    let report = verify_lossless("data/example1-noyes-ofl280.sor").unwrap();
+    assert_eq!(report.checksum, Some((Algorithm::Ibm3740, Strategy::IncludingHeader)));
+    assert_eq!(report.differences, vec![]);
+    assert!(report.identical);
+    assert!(verify_lossless("data/example3-anritsu-accessmastermt9085.sor").unwrap().identical);
+
+    // The EXFO samples' checksums match no convention we know
+    let report = verify_lossless("data/example2-exfo-maxtester730c.sor").unwrap();
+    assert!(!report.identical);
+    assert_eq!(report.checksum, None);
+    assert_eq!(report.differences, vec![BlockDifference { identifier: "Cksum".to_owned(), offset: 105761 }]);
+
+    assert!(verify_lossless("data/missing.sor").is_err());
+}
@@ -1,59 +1,2548 @@
 //!
 //! # otdrs
-//! 
+//!
 //! otdrs is a tool for parsing Telcordia SOR files into a neutral, open format
 //! for further processing.
-//! 
-//! The serde library is used for serialisation, and currently only JSON output 
-//! is supported.
-//! 
+//!
+//! The serde library is used for serialisation, and JSON, CBOR, MessagePack
+//! and YAML output are supported.
+//!
+//! ## Exit codes
+//!
+//! * 0 - success
+//! * 1 - any other error
+//! * 2 - invalid command line arguments
+//! * 3 - I/O error, e.g. an input file could not be read
+//! * 4 - an input file could not be parsed as a SOR file
+//! * 5 - a stored checksum does not match the file's contents
+//! * 6 - validation failure, e.g. a fibre failed its acceptance thresholds
+//!
 use std::fs::File;
 use std::io::prelude::*;
+use std::path::Path;
 // use anyhow::Error;
 // use thiserror::Error;
-use clap::Parser;
+use clap::{CommandFactory, Parser, Subcommand};
+use otdrs::analysis::acceptance::Profile;
+use otdrs::compare::ToleranceProfile;
+use otdrs::types::SORFile;
+use serde::Deserialize;
+use std::collections::HashMap;
 /// This doc string acts as a help message when the user runs '--help'
 /// as do all doc strings on fields
 #[derive(Parser)]
-#[clap(version = "0.4.2", author = "James Harrison <james@talkunafraid.co.uk>", about = "otdrs is a conversion utility to convert Telcordia SOR files, used by optical time-domain reflectometry testers, into open formats such as JSON")]
+#[clap(version = env!("CARGO_PKG_VERSION"), author = "James Harrison <james@talkunafraid.co.uk>", about = "otdrs is a conversion utility to convert Telcordia SOR files, used by optical time-domain reflectometry testers, into open formats such as JSON")]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Opts {
+    /// How to report errors - text, or json for a single JSON object on
+    /// stderr
+    #[clap(long, global = true, default_value = "text")]
+    error_format: String,
+    #[clap(subcommand)]
+    command: Option<Command>,
+    #[clap(flatten)]
+    convert: ConvertArgs,
+}
+
+/// Arguments for the default conversion behaviour, used when no subcommand is
+/// given
+#[derive(clap::Args)]
+struct ConvertArgs {
+    /// SOR file to convert; several may be given with --format ndjson
     #[clap(index=1, required=true)]
+    input_filenames: Vec<String>,
+    /// Output format - json, cbor, msgpack, yaml, xml, interchange, protobuf
+    /// (with the protobuf feature), any format registered with
+    /// otdrs::format, or ndjson [default: json]
+    #[clap(short, long)]
+    format: Option<String>,
+    /// Output file [default: stdout, or a file in the configured
+    /// output_directory]
+    #[clap(short, long)]
+    output_filename: Option<String>,
+    /// Indent JSON or XML output for readability
+    #[clap(long)]
+    pretty: bool,
+    /// Sort JSON object keys alphabetically, so output diffs cleanly
+    #[clap(long)]
+    canonical: bool,
+    /// Only output the given top-level fields, e.g. key_events,general_parameters
+    #[clap(long, value_delimiter = ',')]
+    select: Vec<String>,
+    /// Print the single value at a dotted path, e.g.
+    /// fixed_parameters.actual_wavelength
+    #[clap(long, conflicts_with = "select")]
+    get: Option<String>,
+    /// Give values in dB, metres, seconds and ISO-8601 timestamps rather
+    /// than as raw SR-4731 encoded integers
+    #[clap(long)]
+    engineering_units: bool,
+    /// Convert this many files at once with --format ndjson, keeping their
+    /// order
+    #[clap(short, long)]
+    jobs: Option<usize>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Convert a SOR file to another format - the same as giving no subcommand
+    Parse(ConvertArgs),
+    /// Summarise the size of each block and the number of samples, events
+    /// and landmarks in one or more files, with totals for capacity planning
+    Info(InfoArgs),
+    /// Dump the raw payload of one or all proprietary blocks
+    Extract(ExtractArgs),
+    /// Replace the payload of a proprietary block and write out a new SOR
+    Inject(InjectArgs),
+    /// Crop the trace to a span of distances, e.g. to remove launch and
+    /// receive leads, adjusting key events and offsets to match
+    Trim(TrimArgs),
+    /// Retag the wavelength a file was recorded at, and optionally its
+    /// backscatter coefficient, adjusting event reflectances to match
+    Retag(RetagArgs),
+    /// Copy event comments, and events added by hand, from an annotated
+    /// earlier test of the same fibre into a new one
+    MergeEvents(MergeEventsArgs),
+    /// Apply a JSON Patch (RFC 6902) of edits to the file, as serialised to
+    /// JSON, and write out a new SOR
+    Patch(PatchArgs),
+    /// Write SOR files from NDJSON documents, one per line, as a tar or ZIP
+    /// archive, e.g. from stdin to stdout with from-json - -o -
+    FromJson(FromJsonArgs),
+    /// Find events in the trace and replace the key events with them
+    DetectEvents(DetectEventsArgs),
+    /// Filter the trace to reduce noise, e.g. before detecting events in it
+    /// or plotting it
+    Smooth(SmoothArgs),
+    /// List key events which are ghosts of other reflections, optionally
+    /// noting them in the events' comments
+    Ghosts(GhostsArgs),
+    /// Re-measure each key event's loss from the trace and compare it with
+    /// the loss the instrument reported
+    EventLosses(EventLossesArgs),
+    /// Measure the event and attenuation dead zones of each reflective
+    /// event, optionally checking them against the instrument's
+    /// specification
+    DeadZones(DeadZonesArgs),
+    /// Score the quality of one or more acquisitions, failing if any scores
+    /// below a minimum
+    Assess(AssessArgs),
+    /// Check that one or more files make sense as measurements, failing if
+    /// any has an error
+    Validate(ValidateArgs),
+    /// Rewrite the cable, fibre, location and operator fields of many files
+    /// from a CSV worksheet
+    ApplySheet(ApplySheetArgs),
+    /// Verify, add, or repair the checksum block
+    #[clap(subcommand)]
+    Checksum(ChecksumCommand),
+    /// Render the trace and key events as an SVG or PNG chart
+    #[cfg(feature = "plot")]
+    Plot(PlotArgs),
+    /// Write the trace's data points with their distances, as CSV or NumPy
+    /// arrays
+    Trace(TraceArgs),
+    /// Show the trace and event table in the terminal
+    View(ViewArgs),
+    /// Produce an HTML or Markdown acceptance report for one or more files
+    Report(ReportArgs),
+    /// Compare a file against a baseline measurement of the same fibre and
+    /// report new or worsened events
+    Compare(CompareArgs),
+    /// Subtract a reference trace of the same fibre, e.g. from before a
+    /// repair, and report where the two differ
+    Diff(DiffArgs),
+    /// Compare event losses in two files of the same fibre at different
+    /// wavelengths to find macrobends
+    Macrobends(MacrobendsArgs),
+    /// Check whether a tested link's loss is within the power budget of a
+    /// pair of transceivers
+    Budget(BudgetArgs),
+    /// List where each key event is in the field, from the file's landmarks'
+    /// GPS positions and sheath markers
+    Locate(LocateArgs),
+    /// Replace a file's landmarks with a route from a GIS, as CSV or
+    /// GeoJSON points
+    Landmarks(LandmarksArgs),
+    /// Write a file's landmarks as KML, with the location of any break
+    /// since a baseline measurement, for opening in Google Earth
+    Kml(KmlArgs),
+    /// Extract a metric from many files of the same fibre as a CSV time
+    /// series, for trending its degradation
+    Timeseries(TimeseriesArgs),
+    /// Watch a directory and convert SOR files as they appear
+    #[cfg(feature = "watch")]
+    Watch(WatchArgs),
+    /// Build or update a searchable SQLite catalogue of the SOR files in a
+    /// directory tree
+    #[cfg(feature = "sqlite")]
+    Index(IndexArgs),
+    /// Search a catalogue built by the index subcommand
+    #[cfg(feature = "sqlite")]
+    Search(SearchArgs),
+    /// Export the samples and events of many files as Parquet datasets
+    #[cfg(feature = "parquet")]
+    Parquet(ParquetArgs),
+    /// Export the traces, events and parameters of many files to an HDF5
+    /// file, grouped by wavelength
+    #[cfg(feature = "hdf5")]
+    Hdf5(Hdf5Args),
+    /// Export the full content of many files to a SQLite database for
+    /// ad-hoc SQL analysis
+    #[cfg(feature = "sqlite")]
+    Sqlite(SqliteArgs),
+    /// Run an HTTP service converting and checking SOR files posted to it
+    #[cfg(feature = "serve")]
+    Serve(ServeArgs),
+    /// Print the JSON Schema describing the JSON and CBOR output, or the
+    /// XML Schema describing the XML output
+    Schema(SchemaArgs),
+    /// Print a shell completion script, e.g. otdrs completions bash
+    Completions {
+        #[clap(value_parser)]
+        shell: clap_complete::Shell,
+    },
+}
+
+#[cfg(feature = "serve")]
+#[derive(clap::Args)]
+struct ServeArgs {
+    /// Address to listen on
+    #[clap(long, default_value="127.0.0.1:8080")]
+    listen: std::net::SocketAddr,
+}
+
+#[derive(clap::Args)]
+struct SchemaArgs {
+    /// Print the XML Schema for --format xml instead
+    #[clap(long)]
+    xml: bool,
+    #[clap(short, long, default_value="stdout")]
+    output_filename: String,
+}
+
+#[derive(clap::Args)]
+struct InfoArgs {
+    #[clap(required = true)]
+    input_filenames: Vec<String>,
+}
+
+#[derive(clap::Args)]
+struct ExtractArgs {
+    input_filename: String,
+    /// Header of the proprietary block to extract, e.g. Fod02Params
+    #[clap(short, long, required_unless_present = "all", conflicts_with = "all")]
+    block: Option<String>,
+    /// Extract every proprietary block into the output directory, one file
+    /// per block
+    #[clap(long)]
+    all: bool,
+    /// Output file, or output directory when --all is used
+    #[clap(short, long, default_value="stdout")]
+    output_filename: String,
+}
+
+#[derive(clap::Args)]
+struct InjectArgs {
+    input_filename: String,
+    /// Header of the proprietary block to replace
+    #[clap(short, long)]
+    block: String,
+    /// File containing the new payload for the block
+    #[clap(short, long)]
+    data: String,
+    #[clap(short, long, default_value="stdout")]
+    output_filename: String,
+}
+
+#[derive(clap::Args)]
+struct PatchArgs {
+    input_filename: String,
+    /// JSON file containing an array of patch operations
+    patch_filename: String,
+    #[clap(short, long, default_value="stdout")]
+    output_filename: String,
+}
+
+#[derive(clap::Args)]
+struct FromJsonArgs {
+    /// File of SOR documents as JSON, one per line, or - for stdin. Lines
+    /// may be bare documents or as written by --format ndjson, whose
+    /// filenames are kept
+    #[clap(default_value="-")]
+    input_filename: String,
+    /// Archive to write, or - for stdout
+    #[clap(short, long, default_value="-")]
+    output_filename: String,
+    /// tar, or zip if built with the zip feature; zip by default if the
+    /// output filename ends in .zip, and tar otherwise
+    #[clap(long)]
+    archive: Option<String>,
+}
+
+#[derive(clap::Args)]
+struct TrimArgs {
+    input_filename: String,
+    /// Start of the span to keep, measured from the user offset, e.g. 0.5km
+    #[clap(long, value_parser = parse_distance)]
+    from: Option<f64>,
+    /// End of the span to keep, measured from the user offset, e.g. 24.3km
+    #[clap(long, value_parser = parse_distance)]
+    to: Option<f64>,
+    #[clap(short, long, default_value="stdout")]
+    output_filename: String,
+}
+
+#[derive(clap::Args)]
+struct RetagArgs {
+    input_filename: String,
+    /// Nominal wavelength in nm, e.g. 1550
+    #[clap(long)]
+    wavelength: Option<i16>,
+    /// Actual wavelength in nm, if not the nominal
+    #[clap(long)]
+    actual_wavelength: Option<i16>,
+    /// Backscatter coefficient for a 1ns pulse, e.g. -81.8dB
+    #[clap(long, value_parser = parse_loss, allow_hyphen_values = true)]
+    backscatter: Option<f64>,
+    #[clap(short, long, default_value="stdout")]
+    output_filename: String,
+}
+
+#[derive(clap::Args)]
+struct MergeEventsArgs {
+    input_filename: String,
+    /// Annotated file of the same fibre to copy from
+    #[clap(long)]
+    from: String,
+    /// How far apart events can be and still match, e.g. 2m
+    #[clap(long, default_value="2m", value_parser = parse_distance)]
+    tolerance: f64,
+    /// What to do where an event has a comment already - keep, replace or
+    /// append
+    #[clap(long, default_value="keep")]
+    comments: String,
+    /// Only copy comments, not events added by hand
+    #[clap(long)]
+    comments_only: bool,
+    #[clap(short, long, default_value="stdout")]
+    output_filename: String,
+}
+
+#[derive(clap::Args)]
+struct DetectEventsArgs {
+    input_filename: String,
+    /// Smallest loss reported as an event, e.g. 0.05dB - by default, the
+    /// threshold recorded in the file
+    #[clap(long, value_parser = parse_loss)]
+    loss_threshold: Option<f64>,
+    /// Smallest reflectance reported as a reflective event, e.g. -55dB
+    #[clap(long, value_parser = parse_loss, allow_hyphen_values = true)]
+    reflectance_threshold: Option<f64>,
+    /// Smallest loss taken to be the end of the fibre, e.g. 3dB
+    #[clap(long, value_parser = parse_loss)]
+    end_of_fibre_threshold: Option<f64>,
+    #[clap(short, long, default_value="stdout")]
+    output_filename: String,
+}
+
+#[derive(clap::Args)]
+struct SmoothArgs {
+    input_filename: String,
+    /// Filter - moving-average, median, or savitzky-golay
+    #[clap(long, default_value="moving-average")]
+    filter: String,
+    /// Width of the filter in points
+    #[clap(long, default_value_t = 5)]
+    window: usize,
+    /// Order of the polynomial fitted by savitzky-golay
+    #[clap(long, default_value_t = 2)]
+    order: usize,
+    #[clap(short, long, default_value="stdout")]
+    output_filename: String,
+}
+
+#[derive(clap::Args)]
+struct GhostsArgs {
+    input_filename: String,
+    /// Also write a copy of the file with the ghosts noted in their comments
+    #[clap(short, long)]
+    output_filename: Option<String>,
+}
+
+#[derive(clap::Args)]
+struct EventLossesArgs {
+    input_filename: String,
+    /// Length of fibre fitted either side of each event, e.g. 200m - by
+    /// default, chosen from the pulse width
+    #[clap(long, value_parser = parse_distance)]
+    fit_length: Option<f64>,
+    /// Length of fibre after each event left out of the fit, e.g. 10m - by
+    /// default, three pulse widths
+    #[clap(long, value_parser = parse_distance)]
+    gap: Option<f64>,
+    /// Also write a copy of the file with the re-measured losses
+    #[clap(short, long)]
+    output_filename: Option<String>,
+}
+
+#[derive(clap::Args)]
+struct DeadZonesArgs {
+    input_filename: String,
+    /// Specified event dead zone, e.g. 1m
+    #[clap(long, value_parser = parse_distance)]
+    event_spec: Option<f64>,
+    /// Specified attenuation dead zone, e.g. 4m
+    #[clap(long, value_parser = parse_distance)]
+    attenuation_spec: Option<f64>,
+}
+
+#[derive(clap::Args)]
+struct AssessArgs {
+    #[clap(required = true)]
+    input_filenames: Vec<String>,
+    /// Lowest acceptable score, out of 100
+    #[clap(long, default_value_t = 50)]
+    min_score: u8,
+}
+
+#[derive(clap::Args)]
+struct ValidateArgs {
+    #[clap(required = true)]
+    input_filenames: Vec<String>,
+    /// Report each finding against the SR-4731 block and field at fault,
+    /// with the value stored there
+    #[clap(long)]
+    conformance: bool,
+}
+
+#[derive(clap::Args)]
+struct ApplySheetArgs {
+    /// CSV file with a header row. The filename column is required; any of
+    /// cable_id, fiber_id, originating_location, terminating_location and
+    /// operator may be given, and empty cells leave the field unchanged
+    worksheet: String,
+    /// Directory which filenames in the worksheet are relative to
+    #[clap(short, long, default_value=".")]
+    dir: String,
+}
+
+/// One row of a worksheet for the apply-sheet subcommand
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SheetRow {
+    filename: String,
+    #[serde(default)]
+    cable_id: Option<String>,
+    #[serde(default)]
+    fiber_id: Option<String>,
+    #[serde(default)]
+    originating_location: Option<String>,
+    #[serde(default)]
+    terminating_location: Option<String>,
+    #[serde(default)]
+    operator: Option<String>,
+}
+
+#[derive(clap::Args)]
+struct CompareArgs {
+    baseline_filename: String,
+    current_filename: String,
+    /// Tolerance profile from the config file
+    #[clap(long)]
+    tolerances: Option<String>,
+    /// Increase in an event's loss above which it has worsened, e.g. 0.05dB
+    /// [default: the tolerance profile's loss_db]
+    #[clap(long, value_parser = parse_loss)]
+    loss_tolerance: Option<f64>,
+    /// Distance within which events in the two files are the same event,
+    /// e.g. 2m [default: the tolerance profile's distance_m]
+    #[clap(long, value_parser = parse_distance)]
+    distance_tolerance: Option<f64>,
+}
+
+#[derive(clap::Args)]
+struct DiffArgs {
+    reference_filename: String,
+    current_filename: String,
+    /// Tolerance profile from the config file
+    #[clap(long)]
+    tolerances: Option<String>,
+    /// Difference in level above which the traces differ, e.g. 0.3dB
+    /// [default: the tolerance profile's level_db]
+    #[clap(long, value_parser = parse_loss)]
+    delta: Option<f64>,
+    /// Also write the difference trace as a SOR file
+    #[clap(short, long)]
+    output_filename: Option<String>,
+}
+
+#[derive(clap::Args)]
+struct MacrobendsArgs {
+    /// Files of the same fibre at two wavelengths, in either order
+    first_filename: String,
+    second_filename: String,
+}
+
+#[derive(clap::Args)]
+struct BudgetArgs {
+    input_filename: String,
+    /// Minimum transmitter launch power in dBm
+    #[clap(long, allow_hyphen_values = true)]
+    tx_power: f64,
+    /// Receiver sensitivity in dBm
+    #[clap(long, allow_hyphen_values = true)]
+    rx_sensitivity: f64,
+    /// Receiver overload in dBm
+    #[clap(long, allow_hyphen_values = true)]
+    rx_overload: Option<f64>,
+    /// Minimum ORL the transmitter tolerates, in dB
+    #[clap(long)]
+    min_orl: Option<f64>,
+    /// Margin to keep in hand for repairs and ageing, e.g. 3dB
+    #[clap(long, default_value="3dB", value_parser = parse_loss)]
+    reserve: f64,
+}
+
+#[derive(clap::Args)]
+struct LocateArgs {
+    input_filename: String,
+}
+
+#[derive(clap::Args)]
+struct LandmarksArgs {
+    input_filename: String,
+    /// CSV or GeoJSON (.geojson or .json) route to build landmarks from
+    #[clap(long)]
+    route: String,
+    /// Relate each landmark to the nearest key event within this distance,
+    /// e.g. 5m
+    #[clap(long, value_parser = parse_distance)]
+    relate: Option<f64>,
+    #[clap(short, long, default_value="stdout")]
+    output_filename: String,
+}
+
+#[derive(clap::Args)]
+struct KmlArgs {
+    input_filename: String,
+    /// Baseline measurement of the same fibre, to locate a break against
+    #[clap(long)]
+    baseline: Option<String>,
+    #[clap(short, long, default_value="stdout")]
+    output_filename: String,
+}
+
+#[derive(clap::Args)]
+struct TimeseriesArgs {
+    #[clap(required = true)]
+    input_filenames: Vec<String>,
+    /// Metric to extract - total-loss, orl, or event-loss for the loss of
+    /// the event found --at a distance
+    #[clap(long, default_value="total-loss")]
+    metric: String,
+    /// Distance of the event for event-loss, measured from the user offset,
+    /// e.g. 4.2km
+    #[clap(long, value_parser = parse_distance)]
+    at: Option<f64>,
+    /// How far from --at the event may be found
+    #[clap(long, default_value="2m", value_parser = parse_distance)]
+    tolerance: f64,
+}
+
+#[derive(Subcommand)]
+enum ChecksumCommand {
+    /// Report which checksum algorithm and strategy (if any) matches the
+    /// stored checksum
+    Verify {
+        input_filename: String,
+    },
+    /// Append a checksum block to a file which lacks one
+    Add(ChecksumWriteArgs),
+    /// Recompute the value in an existing checksum block
+    Fix(ChecksumWriteArgs),
+}
+
+#[derive(clap::Args)]
+struct ChecksumWriteArgs {
+    input_filename: String,
+    /// CRC-16 variant - ibm-3740, kermit, or xmodem
+    #[clap(short, long, default_value="kermit")]
+    algorithm: otdrs::checksum::Algorithm,
+    /// Bytes covered by the checksum - preceding-blocks or including-header
+    #[clap(short, long, default_value="preceding-blocks")]
+    strategy: otdrs::checksum::Strategy,
+    /// Output file - the input file is modified in place if not given
+    #[clap(short, long)]
+    output_filename: Option<String>,
+}
+
+#[cfg(feature = "plot")]
+#[derive(clap::Args)]
+struct PlotArgs {
     input_filename: String,
-    #[clap(short, long, default_value="json")]
+    /// Output file - PNG if the name ends in .png, SVG otherwise
+    #[clap(short, long)]
+    output_filename: String,
+    #[clap(long, default_value="1200")]
+    width: u32,
+    #[clap(long, default_value="600")]
+    height: u32,
+}
+
+#[derive(clap::Args)]
+struct TraceArgs {
+    input_filename: String,
+    /// Output format - csv, npy for a 2xN array of distances and levels,
+    /// npz for distance_m and level_db arrays, or raw-u16 for the points as
+    /// stored, as little-endian u16s with no header
+    #[clap(short, long, default_value="csv")]
     format: String,
+    /// With raw-u16, write only the points of the nth scale factor, from 0
+    #[clap(long)]
+    run: Option<usize>,
+    #[clap(short, long, default_value="stdout")]
+    output_filename: String,
+}
+
+#[derive(clap::Args)]
+struct ViewArgs {
+    input_filename: String,
+    /// Width of the chart in characters
+    #[clap(long, default_value="78")]
+    width: usize,
+    /// Height of the chart in lines
+    #[clap(long, default_value="12")]
+    height: usize,
+}
+
+#[derive(clap::Args)]
+struct ReportArgs {
+    /// Files, or directories to report on every SOR file beneath
+    #[clap(required = true)]
+    input_filenames: Vec<String>,
+    /// Output file - Markdown if the name ends in .md, an Excel workbook if
+    /// it ends in .xlsx, HTML otherwise
     #[clap(short, long, default_value="stdout")]
     output_filename: String,
+    /// Report format - html, markdown or xlsx (with the xlsx feature);
+    /// inferred from the output filename if not given
+    #[clap(short, long)]
+    format: Option<String>,
+    /// Template file containing {{title}} and {{content}} placeholders
+    #[clap(long)]
+    template: Option<String>,
+    #[clap(long, default_value="OTDR Test Report")]
+    title: String,
+    /// Also write a CSV register of every fibre tested to this file
+    #[clap(long)]
+    register: Option<String>,
+    /// Acceptance profile from the config file to judge events against;
+    /// the options below override it
+    #[clap(long)]
+    profile: Option<String>,
+    /// Maximum non-reflective event loss in dB [default: 0.3]
+    #[clap(long)]
+    max_splice_loss: Option<f64>,
+    /// Maximum reflective event loss in dB [default: 0.75]
+    #[clap(long)]
+    max_connector_loss: Option<f64>,
+    /// Maximum reflectance in dB [default: -35]
+    #[clap(long, allow_hyphen_values = true)]
+    max_reflectance: Option<f64>,
+    /// Maximum end-to-end loss in dB
+    #[clap(long)]
+    max_total_loss: Option<f64>,
+    /// Minimum optical return loss in dB
+    #[clap(long)]
+    min_orl: Option<f64>,
+    /// Take non-reflective losses typical of a 1xN splitter to be splitters
+    /// rather than faults, as when testing a PON
+    #[clap(long)]
+    splitters: bool,
+    /// Directory of measurements from the far end, with the same filenames;
+    /// events found in both are judged by their bidirectional average loss
+    #[clap(long)]
+    backward_dir: Option<String>,
+    /// Tolerance profile from the config file, whose distance tolerance
+    /// matches events between the two ends
+    #[clap(long)]
+    tolerances: Option<String>,
+}
+
+#[cfg(feature = "watch")]
+#[derive(clap::Args)]
+struct WatchArgs {
+    /// Directory to watch for new SOR files
+    directory: String,
+    /// Output format - json, cbor, msgpack, yaml, or xml [default: json]
+    #[clap(short, long)]
+    format: Option<String>,
+    /// Directory to write converted files to, named after the input file;
+    /// defaults to the configured output_directory
+    #[clap(long)]
+    out_dir: Option<String>,
+    /// Indent JSON or XML output for readability
+    #[clap(long)]
+    pretty: bool,
+    /// Sort JSON object keys alphabetically, so output diffs cleanly
+    #[clap(long)]
+    canonical: bool,
+    /// Milliseconds a file must go unmodified before it is converted, so
+    /// that files are not read while an instrument is still writing them
+    #[clap(long, default_value="1000")]
+    settle_ms: u64,
+    /// Also convert SOR files already in the directory when starting
+    #[clap(long)]
+    existing: bool,
+    /// Give values in engineering units rather than raw encoded integers
+    #[clap(long)]
+    engineering_units: bool,
+}
+
+#[cfg(feature = "parquet")]
+#[derive(clap::Args)]
+struct ParquetArgs {
+    #[clap(required = true)]
+    input_filenames: Vec<String>,
+    /// Directory to write samples.parquet and events.parquet into
+    #[clap(short, long)]
+    output_directory: String,
+}
+
+#[cfg(feature = "hdf5")]
+#[derive(clap::Args)]
+struct Hdf5Args {
+    #[clap(required = true)]
+    input_filenames: Vec<String>,
+    /// HDF5 file to write
+    #[clap(short, long)]
+    output_filename: String,
+}
+
+#[cfg(feature = "sqlite")]
+#[derive(clap::Args)]
+struct SqliteArgs {
+    #[clap(required = true)]
+    input_filenames: Vec<String>,
+    /// Database to write to, created if need be
+    #[clap(short, long)]
+    output_filename: String,
+    /// Also store each trace, reduced to at most this many points
+    #[clap(long)]
+    trace_points: Option<usize>,
+}
+
+#[cfg(feature = "sqlite")]
+#[derive(clap::Args)]
+struct IndexArgs {
+    /// Directory to search for SOR files, including subdirectories
+    directory: String,
+    /// Catalogue file, which is created if need be and otherwise updated
+    #[clap(short, long, default_value="catalogue.sqlite")]
+    output_filename: String,
+}
+
+#[cfg(feature = "sqlite")]
+#[derive(clap::Args)]
+struct SearchArgs {
+    /// Catalogue file built by the index subcommand
+    catalogue: String,
+    /// Cable ID, which may contain * and ? wildcards
+    #[clap(long)]
+    cable: Option<String>,
+    /// Fibre ID, which may contain * and ? wildcards
+    #[clap(long)]
+    fiber: Option<String>,
+    /// Nominal wavelength in nm
+    #[clap(long)]
+    wavelength: Option<i16>,
+    /// Earliest acquisition date, e.g. 2019-09-01
+    #[clap(long)]
+    from: Option<String>,
+    /// Latest acquisition date, e.g. 2019-09 for anything up to the end of
+    /// September 2019
+    #[clap(long)]
+    to: Option<String>,
+    /// Minimum end-to-end loss in dB
+    #[clap(long)]
+    min_loss: Option<f64>,
+    /// Maximum end-to-end loss in dB
+    #[clap(long)]
+    max_loss: Option<f64>,
+    /// Output format - text or ndjson
+    #[clap(short, long, default_value="text")]
+    format: String,
+}
+
+/// Defaults read from ~/.config/otdrs/config.toml, e.g.
+///
+/// ```toml
+/// format = "yaml"
+/// output_directory = "converted"
+/// engineering_units = true
+///
+/// [profiles.carrier]
+/// max_splice_loss = 0.1
+/// max_connector_loss = 0.5
+/// min_orl = 27.0
+///
+/// [profiles.carrier.max_attenuation]
+/// 1550 = 0.25
+///
+/// [tolerances.monitoring]
+/// distance_m = 5.0
+/// loss_db = 0.1
+/// ```
+///
+/// Options given on the command line always take precedence.
+#[derive(Deserialize, Default, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+struct Config {
+    /// Default output format for conversion
+    format: Option<String>,
+    /// Directory converted files are written to when no output file is
+    /// given, named after the input file
+    output_directory: Option<String>,
+    /// Convert to engineering units by default
+    engineering_units: bool,
+    /// Named acceptance profiles for reports; any limits left out of a
+    /// profile take the defaults
+    profiles: HashMap<String, Profile>,
+    /// Named tolerance profiles for compare, diff and bidirectional reports;
+    /// any tolerances left out of a profile take the defaults
+    tolerances: HashMap<String, ToleranceProfile>,
+}
+
+impl Config {
+    /// Load the config file if there is one. $XDG_CONFIG_HOME is honoured
+    /// if set
+    fn load() -> Result<Config, Box<dyn std::error::Error>> {
+        let dir = match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) => std::path::PathBuf::from(dir),
+            None => match std::env::var_os("HOME") {
+                Some(home) => Path::new(&home).join(".config"),
+                None => return Ok(Config::default()),
+            },
+        };
+        let path = dir.join("otdrs").join("config.toml");
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        let text = std::fs::read_to_string(&path)?;
+        toml::from_str(&text).map_err(|e| format!("Could not read {}: {}", path.display(), e).into())
+    }
+
+    /// Fill in conversion options not given on the command line
+    fn apply(&self, args: &mut ConvertArgs) {
+        if args.format.is_none() {
+            args.format = self.format.clone();
+        }
+        args.engineering_units |= self.engineering_units;
+        // ndjson streams many files to one place, so it has no natural
+        // per-file output name
+        if let (None, Some(dir), [input]) = (&args.output_filename, &self.output_directory, args.input_filenames.as_slice()) {
+            if args.format() != "ndjson" {
+                args.output_filename = Some(output_path(Path::new(input), Path::new(dir), args.format()).to_string_lossy().into_owned());
+            }
+        }
+    }
+
+    /// Look up a named tolerance profile, or the defaults if none is named
+    fn tolerances(&self, name: Option<&str>) -> Result<ToleranceProfile, Box<dyn std::error::Error>> {
+        match name {
+            Some(name) => Ok(self.tolerances.get(name).ok_or(format!("No tolerance profile named {:?} in the config file", name))?.clone()),
+            None => Ok(ToleranceProfile::default()),
+        }
+    }
+
+    /// Resolve the report's acceptance profile from the command line, then
+    /// the named profile, then the defaults
+    fn profile(&self, args: &ReportArgs) -> Result<Profile, Box<dyn std::error::Error>> {
+        let profile = match &args.profile {
+            Some(name) => self.profiles.get(name).ok_or(format!("No acceptance profile named {:?} in the config file", name))?.clone(),
+            None => Profile::default(),
+        };
+        Ok(Profile {
+            max_splice_loss: args.max_splice_loss.unwrap_or(profile.max_splice_loss),
+            max_connector_loss: args.max_connector_loss.unwrap_or(profile.max_connector_loss),
+            max_reflectance: args.max_reflectance.unwrap_or(profile.max_reflectance),
+            max_total_loss: args.max_total_loss.or(profile.max_total_loss),
+            min_orl: args.min_orl.or(profile.min_orl),
+            splitters: args.splitters || profile.splitters,
+            ..profile
+        })
+    }
 }
 
-/// By default we simply read the file provided as the first argument, and 
+impl ConvertArgs {
+    fn format(&self) -> &str {
+        self.format.as_deref().unwrap_or("json")
+    }
+
+    fn output_filename(&self) -> &str {
+        self.output_filename.as_deref().unwrap_or("stdout")
+    }
+}
+
+/// Classes of failure, each with a documented exit code so that automation
+/// can tell them apart
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum ErrorKind {
+    Other,
+    Usage,
+    Io,
+    Parse,
+    ChecksumMismatch,
+    Validation,
+}
+
+impl ErrorKind {
+    fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Other => 1,
+            ErrorKind::Usage => 2,
+            ErrorKind::Io => 3,
+            ErrorKind::Parse => 4,
+            ErrorKind::ChecksumMismatch => 5,
+            ErrorKind::Validation => 6,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ErrorKind::Other => "other",
+            ErrorKind::Usage => "usage",
+            ErrorKind::Io => "io",
+            ErrorKind::Parse => "parse",
+            ErrorKind::ChecksumMismatch => "checksum_mismatch",
+            ErrorKind::Validation => "validation",
+        }
+    }
+
+    /// Create an error of this kind
+    fn error(self, message: impl Into<String>) -> Box<dyn std::error::Error> {
+        Box::new(CliError { kind: self, message: message.into() })
+    }
+
+    /// Work out the kind of an error returned from a subcommand
+    fn of(err: &(dyn std::error::Error + 'static)) -> ErrorKind {
+        if let Some(err) = err.downcast_ref::<CliError>() {
+            err.kind
+        } else if err.is::<std::io::Error>() {
+            ErrorKind::Io
+        } else {
+            ErrorKind::Other
+        }
+    }
+}
+
+/// An error whose kind isn't apparent from its type
+#[derive(Debug)]
+struct CliError {
+    kind: ErrorKind,
+    message: String,
+}
+
+impl std::fmt::Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for CliError {}
+
+/// Format an error for stderr, either as plain text or as a JSON object
+fn format_error(kind: ErrorKind, message: &str, json: bool) -> String {
+    if json {
+        serde_json::json!({ "error": kind.name(), "exit_code": kind.exit_code(), "message": message }).to_string()
+    } else {
+        format!("Error: {}", message)
+    }
+}
+
+fn main() {
+    let opts = match Opts::try_parse() {
+        Ok(opts) => opts,
+        Err(err) => {
+            // Help and version requests are "errors" too, but not ours
+            let json = std::env::args().collect::<Vec<_>>().windows(2).any(|w| w[0] == "--error-format" && w[1] == "json")
+                || std::env::args().any(|a| a == "--error-format=json");
+            if !json || !err.use_stderr() {
+                err.exit();
+            }
+            eprintln!("{}", format_error(ErrorKind::Usage, err.to_string().trim(), true));
+            std::process::exit(ErrorKind::Usage.exit_code());
+        }
+    };
+    let json = match opts.error_format.as_str() {
+        "text" => false,
+        "json" => true,
+        other => {
+            eprintln!("{}", format_error(ErrorKind::Usage, &format!("Unknown error format {:?}", other), false));
+            std::process::exit(ErrorKind::Usage.exit_code());
+        }
+    };
+    if let Err(err) = run(opts) {
+        let kind = ErrorKind::of(err.as_ref());
+        eprintln!("{}", format_error(kind, &err.to_string(), json));
+        std::process::exit(kind.exit_code());
+    }
+}
+
+/// By default we simply read the file provided as the first argument, and
 /// print the parsed file as JSON to stdout
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let opts: Opts = Opts::parse();
+fn run(opts: Opts) -> Result<(), Box<dyn std::error::Error>> {
+    let config = Config::load()?;
+    match opts.command {
+        Some(Command::Info(args)) => info(args),
+        Some(Command::Extract(args)) => extract(args),
+        Some(Command::Inject(args)) => inject(args),
+        Some(Command::Trim(args)) => trim(args),
+        Some(Command::Retag(args)) => retag(args),
+        Some(Command::MergeEvents(args)) => merge_events(args),
+        Some(Command::Patch(args)) => patch(args),
+        Some(Command::FromJson(args)) => from_json(args),
+        Some(Command::DetectEvents(args)) => detect_events(args),
+        Some(Command::Smooth(args)) => smooth(args),
+        Some(Command::Ghosts(args)) => ghosts(args),
+        Some(Command::EventLosses(args)) => event_losses(args),
+        Some(Command::DeadZones(args)) => dead_zones(args),
+        Some(Command::Assess(args)) => assess(args),
+        Some(Command::Validate(args)) => validate(args),
+        Some(Command::ApplySheet(args)) => apply_sheet(args),
+        Some(Command::Checksum(cmd)) => checksum(cmd),
+        #[cfg(feature = "plot")]
+        Some(Command::Plot(args)) => plot(args),
+        Some(Command::Parse(mut args)) => {
+            config.apply(&mut args);
+            convert(args)
+        }
+        Some(Command::Trace(args)) => trace(args),
+        Some(Command::View(args)) => view(args),
+        Some(Command::Report(args)) => report(args, &config),
+        Some(Command::Compare(args)) => compare(args, &config),
+        Some(Command::Diff(args)) => diff(args, &config),
+        Some(Command::Macrobends(args)) => macrobends(args),
+        Some(Command::Budget(args)) => budget(args),
+        Some(Command::Locate(args)) => locate(args),
+        Some(Command::Landmarks(args)) => landmarks(args),
+        Some(Command::Kml(args)) => kml(args),
+        Some(Command::Timeseries(args)) => timeseries(args),
+        #[cfg(feature = "watch")]
+        Some(Command::Watch(args)) => watch(args, &config),
+        #[cfg(feature = "sqlite")]
+        Some(Command::Index(args)) => index(args),
+        #[cfg(feature = "sqlite")]
+        Some(Command::Search(args)) => search(args),
+        #[cfg(feature = "parquet")]
+        Some(Command::Parquet(args)) => parquet(args),
+        #[cfg(feature = "hdf5")]
+        Some(Command::Hdf5(args)) => hdf5(args),
+        #[cfg(feature = "sqlite")]
+        Some(Command::Sqlite(args)) => sqlite(args),
+        #[cfg(feature = "serve")]
+        Some(Command::Serve(args)) => serve(args),
+        Some(Command::Schema(args)) => schema(args),
+        Some(Command::Completions { shell }) => {
+            clap_complete::generate(shell, &mut Opts::command(), "otdrs", &mut std::io::stdout());
+            Ok(())
+        }
+        None => {
+            let mut args = opts.convert;
+            config.apply(&mut args);
+            convert(args)
+        }
+    }
+}
+
+fn convert(opts: ConvertArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let input_filenames = expand_inputs(&opts.input_filenames)?;
+    if opts.format() == "ndjson" {
+        return convert_ndjson(&input_filenames, &opts);
+    }
+    if input_filenames.len() != 1 {
+        return Err("Multiple input files can only be converted with --format ndjson".into());
+    }
+    let res = parse_sor(&read_input(&input_filenames[0])?)?;
+    let mut out;
+    if let Some(path) = &opts.get {
+        let value = to_value(&res, &opts)?;
+        let found = get_path(&value, path).ok_or(format!("Nothing found at {:?}", path))?;
+        // Bare strings are more useful than quoted JSON strings in a shell
+        out = match found {
+            serde_json::Value::String(s) => s.clone().into_bytes(),
+            _ => to_json(found, opts.pretty, opts.canonical)?,
+        };
+        out.push(b'\n');
+    } else {
+        let format = output_format(opts.format())?;
+        let options = format_options(&opts);
+        out = if opts.select.is_empty() {
+            format.serialize(&res, &options).map_err(|err| ErrorKind::Usage.error(err))?
+        } else {
+            let format: otdrs::format::Format = opts.format().parse()
+                .map_err(|_| ErrorKind::Usage.error(format!("--select can't be used with --format {}", opts.format())))?;
+            format.serialize_value(&select_fields(to_value(&res, &opts)?, &opts.select)?, &options)?
+        };
+    }
+    write_output(opts.output_filename(), &out)
+}
+
+/// Look up an output format by name, built in or registered
+fn output_format(name: &str) -> Result<std::sync::Arc<dyn otdrs::format::OutputFormat>, Box<dyn std::error::Error>> {
+    otdrs::format::lookup(name).ok_or_else(|| ErrorKind::Usage.error(
+        format!("Unknown output format {:?} - use ndjson or one of {}", name, otdrs::format::names().join(", "))))
+}
+
+fn format_options(opts: &ConvertArgs) -> otdrs::format::FormatOptions {
+    otdrs::format::FormatOptions {
+        engineering_units: opts.engineering_units,
+        pretty: opts.pretty,
+        canonical: opts.canonical,
+    }
+}
+
+/// Stream one JSON document per line per input file, flushing as we go so
+/// that consumers can start work before the batch finishes. Files which fail
+/// to parse get a line with their error rather than stopping the batch
+fn convert_ndjson(input_filenames: &[String], opts: &ConvertArgs) -> Result<(), Box<dyn std::error::Error>> {
+    if opts.get.is_some() {
+        return Err("--get cannot be used with --format ndjson".into());
+    }
+    let stdout = std::io::stdout();
+    let mut out: Box<dyn Write> = if opts.output_filename() == "stdout" {
+        Box::new(stdout.lock())
+    } else {
+        Box::new(std::io::BufWriter::new(File::create(opts.output_filename())?))
+    };
+    if let Some(jobs) = opts.jobs {
+        if !opts.select.is_empty() {
+            return Err(ErrorKind::Usage.error("--select can't be used with --jobs"));
+        }
+        let options = otdrs::batch::ConvertOptions {
+            engineering_units: opts.engineering_units,
+            canonical: opts.canonical,
+            jobs: Some(jobs),
+            ..Default::default()
+        };
+        // Converted a chunk at a time, so that output starts promptly and
+        // memory use stays bounded however many files there are
+        for chunk in input_filenames.chunks(jobs.max(1) * 8) {
+            for conversion in otdrs::batch::convert(chunk, &options, &Default::default())? {
+                let filename = conversion.path.to_string_lossy();
+                let result = conversion.result.map_err(|err| format!("{}: {}", filename, err));
+                out.write_all(&ndjson_wrap(&filename, result)?)?;
+            }
+            out.flush()?;
+        }
+        return Ok(());
+    }
+    for filename in input_filenames {
+        out.write_all(&ndjson_line(filename, opts)?)?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+/// Build a line of NDJSON output for one file, e.g.
+/// {"filename":"a.sor","status":"ok","sor":{...}} or
+/// {"filename":"b.sor","status":"error","error":"..."}
+fn ndjson_line(filename: &str, opts: &ConvertArgs) -> Result<Vec<u8>, serde_json::Error> {
+    let document = || -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let res = parse_sor(&read_input(filename)?)?;
+        if !opts.select.is_empty() {
+            to_json(&select_fields(to_value(&res, opts)?, &opts.select)?, false, opts.canonical)
+        } else if opts.engineering_units {
+            to_json(&to_value(&res, opts)?, false, opts.canonical)
+        } else {
+            to_json(&res, false, opts.canonical)
+        }
+    };
+    ndjson_wrap(filename, document().map_err(|err| err.to_string()))
+}
+
+/// Wrap a file's JSON document, or the error converting it, in its line
+fn ndjson_wrap(filename: &str, document: Result<Vec<u8>, String>) -> Result<Vec<u8>, serde_json::Error> {
+    // Written by hand so that the document keeps its field order
+    let mut line = format!("{{\"filename\":{},", serde_json::to_string(filename)?).into_bytes();
+    match document {
+        Ok(doc) => {
+            line.extend(b"\"status\":\"ok\",\"sor\":");
+            line.extend(doc);
+        }
+        Err(err) => {
+            line.extend(format!("\"status\":\"error\",\"error\":{}", serde_json::to_string(&err)?).as_bytes());
+        }
+    }
+    line.extend(b"}\n");
+    Ok(line)
+}
+
+/// Convert to a JSON value, in engineering units if requested
+fn to_value(res: &SORFile, opts: &ConvertArgs) -> Result<serde_json::Value, serde_json::Error> {
+    if opts.engineering_units {
+        otdrs::engineering::to_value(res)
+    } else {
+        serde_json::to_value(res)
+    }
+}
+
+/// Keep only the named top-level fields of a serialised SORFile
+fn select_fields(value: serde_json::Value, fields: &[String]) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    let mut all = match value {
+        serde_json::Value::Object(map) => map,
+        _ => return Err("Can only select fields from an object".into()),
+    };
+    let mut selected = serde_json::Map::new();
+    for field in fields {
+        let v = all.remove(field).ok_or(format!("No field named {:?}", field))?;
+        selected.insert(field.clone(), v);
+    }
+    Ok(serde_json::Value::Object(selected))
+}
+
+/// Look up a dotted path such as fixed_parameters.actual_wavelength, where
+/// numeric components index into arrays
+fn get_path<'a>(value: &'a serde_json::Value, path: &str) -> Option<&'a serde_json::Value> {
+    path.split('.').try_fold(value, |v, key| match v {
+        serde_json::Value::Object(map) => map.get(key),
+        serde_json::Value::Array(arr) => arr.get(key.parse::<usize>().ok()?),
+        _ => None,
+    })
+}
+
+/// Serialise to JSON, optionally indented and with keys sorted. Keys are
+/// sorted by round-tripping through serde_json::Value, whose maps are ordered
+fn to_json<T: serde::Serialize>(value: &T, pretty: bool, canonical: bool) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let out = match (pretty, canonical) {
+        (false, false) => serde_json::to_vec(value)?,
+        (true, false) => serde_json::to_vec_pretty(value)?,
+        (false, true) => serde_json::to_vec(&serde_json::to_value(value)?)?,
+        (true, true) => serde_json::to_vec_pretty(&serde_json::to_value(value)?)?,
+    };
+    Ok(out)
+}
+
+/// Print the schema describing the output formats
+fn schema(args: SchemaArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let out = if args.xml {
+        otdrs::xml::XML_SCHEMA.to_owned()
+    } else {
+        serde_json::to_string_pretty(&otdrs::schema::json_schema())? + "\n"
+    };
+    write_output(&args.output_filename, out.as_bytes())
+}
+
+/// Print each file's block sizes and counts, and totals across them all
+fn info(args: InfoArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let (mut on_disk, mut written, mut in_memory, mut samples) = (0, 0, 0, 0);
+    for filename in &args.input_filenames {
+        let data = read_input(filename)?;
+        let stats = parse_sor(&data)?.stats().map_err(|e| e.to_string())?;
+        println!("{}: {} bytes, {} as written by otdrs, {} in memory", filename, data.len(), stats.serialized_bytes,
+                 stats.in_memory_bytes);
+        println!("  {:<20} {:>5} {:>9}", "Block", "Rev", "Bytes");
+        for block in &stats.blocks {
+            println!("  {:<20} {:>5} {:>9}", block.identifier, block.revision_number, block.bytes);
+        }
+        println!("  {} samples at {} scale factors, {} key events, {} landmarks", stats.samples, stats.scale_factors,
+                 stats.key_events, stats.landmarks);
+        on_disk += data.len();
+        written += stats.serialized_bytes;
+        in_memory += stats.in_memory_bytes;
+        samples += stats.samples;
+    }
+    if args.input_filenames.len() > 1 {
+        println!("Total: {} files, {} bytes, {} as written by otdrs, {} in memory, {} samples",
+                 args.input_filenames.len(), on_disk, written, in_memory, samples);
+    }
+    Ok(())
+}
+
+/// Write out proprietary block payloads without their header string, exactly
+/// as they appear in the file
+fn extract(args: ExtractArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let sor = parse_sor(&read_input(&args.input_filename)?)?;
+    if args.all {
+        let dir = Path::new(&args.output_filename);
+        std::fs::create_dir_all(dir)?;
+        for (n, pb) in sor.proprietary_blocks.iter().enumerate() {
+            // Block headers can contain spaces and other characters we don't
+            // want in a filename, and needn't be unique
+            let name: String = pb.header.trim().chars()
+                .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+                .collect();
+            let mut file = File::create(dir.join(format!("{:02}-{}.bin", n, name)))?;
+            file.write_all(&pb.data)?;
+        }
+        return Ok(());
+    }
+    let header = args.block.unwrap_or_default();
+    let pb = sor.proprietary_blocks.iter().find(|pb| *pb.header == header)
+        .ok_or(format!("No proprietary block named {:?} in this file", header))?;
+    write_output(&args.output_filename, &pb.data)
+}
+
+/// Swap a proprietary block's payload for the contents of another file and
+/// re-serialise
+fn inject(args: InjectArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut sor = parse_sor(&read_input(&args.input_filename)?)?;
+    let data = read_input(&args.data)?;
+    let pb = sor.proprietary_blocks.iter_mut().find(|pb| *pb.header == args.block)
+        .ok_or(format!("No proprietary block named {:?} in this file", args.block))?;
+    pb.data = data.into();
+    let bytes = sor.to_bytes().map_err(|e| e.to_string())?;
+    write_output(&args.output_filename, &bytes)
+}
+
+/// Crop a file to the requested span and re-serialise
+fn trim(args: TrimArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut sor = parse_sor(&read_input(&args.input_filename)?)?;
+    sor.crop(args.from, args.to).map_err(|e| ErrorKind::Validation.error(e))?;
+    let bytes = sor.to_bytes().map_err(|e| e.to_string())?;
+    write_output(&args.output_filename, &bytes)
+}
+
+fn retag(args: RetagArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut sor = parse_sor(&read_input(&args.input_filename)?)?;
+    let correction = otdrs::edit::WavelengthCorrection {
+        nominal_nm: args.wavelength,
+        actual_nm: args.actual_wavelength,
+        backscatter_db: args.backscatter,
+    };
+    sor.correct_wavelength(&correction).map_err(|e| ErrorKind::Validation.error(e))?;
+    let bytes = sor.to_bytes().map_err(|e| e.to_string())?;
+    write_output(&args.output_filename, &bytes)
+}
+
+fn merge_events(args: MergeEventsArgs) -> Result<(), Box<dyn std::error::Error>> {
+    use otdrs::edit::{CommentMerge, MergeStrategy};
+    let mut sor = parse_sor(&read_input(&args.input_filename)?)?;
+    let annotated = parse_sor(&read_input(&args.from)?)?;
+    let comments = match args.comments.as_str() {
+        "keep" => CommentMerge::Keep,
+        "replace" => CommentMerge::Replace,
+        "append" => CommentMerge::Append,
+        other => return Err(ErrorKind::Usage.error(format!("Unknown comment merge {:?} - use keep, replace or append", other))),
+    };
+    let strategy = MergeStrategy { tolerance_m: args.tolerance, comments, user_events: !args.comments_only };
+    let merged = sor.merge_events_from(&annotated, &strategy).map_err(|e| ErrorKind::Validation.error(e))?;
+    eprintln!("Merged {} comments and {} events", merged.comments, merged.events);
+    let bytes = sor.to_bytes().map_err(|e| e.to_string())?;
+    write_output(&args.output_filename, &bytes)
+}
+
+/// Apply a JSON Patch to a file and re-serialise. A patch which fails, e.g.
+/// on a test operation, is a validation error and nothing is written
+fn patch(args: PatchArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut sor = parse_sor(&read_input(&args.input_filename)?)?;
+    let patch: serde_json::Value = serde_json::from_slice(&read_input(&args.patch_filename)?)?;
+    sor.apply_json_patch(&patch).map_err(|e| ErrorKind::Validation.error(e))?;
+    let bytes = sor.to_bytes().map_err(|e| e.to_string())?;
+    write_output(&args.output_filename, &bytes)
+}
+
+/// Write each NDJSON document read to an archive as a SOR file, as soon as
+/// it is read, so that a tar stream can be unpacked while it's produced.
+/// Lines which can't be converted are reported and skipped, failing at the
+/// end, so that one bad message doesn't hold up the rest
+fn from_json(args: FromJsonArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let zip = match args.archive.as_deref() {
+        Some("tar") => false,
+        Some("zip") => true,
+        Some(other) => return Err(ErrorKind::Usage.error(format!("Unknown archive format {}, expected tar or zip", other))),
+        None => args.output_filename.to_ascii_lowercase().ends_with(".zip"),
+    };
+    let input: Box<dyn BufRead> = if args.input_filename == "-" {
+        Box::new(std::io::stdin().lock())
+    } else {
+        Box::new(std::io::BufReader::new(File::open(&args.input_filename)?))
+    };
+    let mut archive = SorArchive::new(&args.output_filename, zip)?;
+    let (mut lines, mut failed) = (0, 0);
+    for (i, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        lines += 1;
+        let converted = sor_from_ndjson(&line)
+            .and_then(|(name, sor)| Ok((name, sor.to_bytes().map_err(|e| e.to_string())?, sor)));
+        match converted {
+            Ok((name, bytes, sor)) => {
+                let mtime = sor.fixed_parameters.as_ref().map_or(0, |fp| fp.date_time_stamp as u64);
+                archive.add(&name.unwrap_or_else(|| format!("{:06}.sor", i + 1)), &bytes, mtime)?;
+            }
+            Err(err) => {
+                eprintln!("line {}: {}", i + 1, err);
+                failed += 1;
+            }
+        }
+    }
+    archive.finish()?;
+    if failed > 0 {
+        return Err(ErrorKind::Parse.error(format!("{} of {} lines could not be converted", failed, lines)));
+    }
+    Ok(())
+}
+
+/// Read a line of NDJSON, either a bare SOR document or one wrapped with its
+/// filename as by ndjson_line, giving the name the SOR file should have if
+/// there is one
+fn sor_from_ndjson(line: &str) -> Result<(Option<String>, SORFile), String> {
+    let mut value: serde_json::Value = serde_json::from_str(line).map_err(|e| e.to_string())?;
+    let filename = value.get("filename").and_then(|f| f.as_str()).map(|f| f.to_owned());
+    let document = match (&filename, value.get_mut("sor")) {
+        (Some(_), Some(sor)) => sor.take(),
+        (Some(filename), None) => {
+            let error = value.get("error").and_then(|e| e.as_str()).unwrap_or("no document");
+            return Err(format!("{}: {}", filename, error));
+        }
+        _ => value,
+    };
+    let sor = serde_json::from_value(document).map_err(|e| e.to_string())?;
+    let name = filename.and_then(|f| Path::new(&f).with_extension("sor").file_name().map(|n| n.to_string_lossy().into_owned()));
+    Ok((name, sor))
+}
+
+/// The archive from_json writes to. Tar archives are written out entry by
+/// entry; ZIP archives can only be written to something seekable, so are
+/// built in memory and written out at the end
+enum SorArchive {
+    Tar(tar::Builder<Box<dyn Write>>),
+    #[cfg(feature = "zip")]
+    Zip(Box<zip::ZipWriter<std::io::Cursor<Vec<u8>>>>, String),
+}
+
+impl SorArchive {
+    fn new(filename: &str, zip: bool) -> Result<SorArchive, Box<dyn std::error::Error>> {
+        if zip {
+            #[cfg(feature = "zip")]
+            return Ok(SorArchive::Zip(Box::new(zip::ZipWriter::new(std::io::Cursor::new(Vec::new()))), filename.to_owned()));
+            #[cfg(not(feature = "zip"))]
+            return Err(ErrorKind::Usage.error("ZIP archives need the zip feature"));
+        }
+        let out: Box<dyn Write> = if filename == "-" {
+            Box::new(std::io::stdout().lock())
+        } else {
+            Box::new(std::io::BufWriter::new(File::create(filename)?))
+        };
+        Ok(SorArchive::Tar(tar::Builder::new(out)))
+    }
+
+    /// Add a file, dated with its acquisition time where the format allows
+    fn add(&mut self, name: &str, data: &[u8], mtime: u64) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            SorArchive::Tar(builder) => {
+                let mut header = tar::Header::new_ustar();
+                header.set_size(data.len() as u64);
+                header.set_mode(0o644);
+                header.set_mtime(mtime);
+                builder.append_data(&mut header, name, data)?;
+                builder.get_mut().flush()?;
+            }
+            #[cfg(feature = "zip")]
+            SorArchive::Zip(zip, _) => {
+                zip.start_file(name, zip::write::SimpleFileOptions::default())?;
+                zip.write_all(data)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            SorArchive::Tar(builder) => builder.into_inner()?.flush()?,
+            #[cfg(feature = "zip")]
+            SorArchive::Zip(zip, filename) => {
+                let data = zip.finish()?.into_inner();
+                write_output(if filename == "-" { "stdout" } else { &filename }, &data)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compare a file to its baseline, failing with a validation error if
+/// anything has changed by more than the tolerances
+fn compare(args: CompareArgs, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    use otdrs::compare::Change;
+    let baseline = parse_sor(&read_input(&args.baseline_filename)?)?;
+    let current = parse_sor(&read_input(&args.current_filename)?)?;
+    let tolerances = config.tolerances(args.tolerances.as_deref())?;
+    let tolerances = ToleranceProfile {
+        loss_db: args.loss_tolerance.unwrap_or(tolerances.loss_db),
+        distance_m: args.distance_tolerance.unwrap_or(tolerances.distance_m),
+        ..tolerances
+    };
+    let result = otdrs::compare::compare(&baseline, &current, &tolerances);
+    println!("{} events matched, baseline offset {:.1} m", result.matched, result.offset_m);
+    for change in &result.changes {
+        match change {
+            Change::New(e) => println!("NEW      {:10.1} m  loss {:.3} dB  reflectance {:.3} dB  {}",
+                                       e.distance_m, e.loss_db, e.reflectance_db, e.code),
+            Change::Worsened { baseline, current } => println!("WORSENED {:10.1} m  loss {:.3} dB (was {:.3} dB)  reflectance {:.3} dB (was {:.3} dB)  {}",
+                                                              current.distance_m, current.loss_db, baseline.loss_db,
+                                                              current.reflectance_db, baseline.reflectance_db, current.code),
+            Change::Missing(e) => println!("MISSING  {:10.1} m  loss {:.3} dB  {} (baseline)",
+                                           e.distance_m + result.offset_m, e.loss_db, e.code),
+        }
+    }
+    if let Some(change) = result.total_loss_change_db {
+        println!("End-to-end loss change {:+.3} dB", change);
+    }
+    if let Some(change) = result.orl_change_db {
+        println!("ORL change {:+.3} dB", change);
+    }
+    if !result.pass {
+        return Err(ErrorKind::Validation.error(format!("{} differs from the baseline {}", args.current_filename, args.baseline_filename)));
+    }
+    println!("PASS");
+    Ok(())
+}
+
+/// Subtract the reference trace from the current one, failing with a
+/// validation error if they differ by more than the delta anywhere
+fn diff(args: DiffArgs, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    use otdrs::analysis::Trace;
+    let delta = args.delta.unwrap_or(config.tolerances(args.tolerances.as_deref())?.level_db);
+    let reference = Trace::new(&parse_sor(&read_input(&args.reference_filename)?)?)?;
+    let current_sor = parse_sor(&read_input(&args.current_filename)?)?;
+    let current = Trace::new(&current_sor)?;
+    let difference = otdrs::analysis::subtract(&reference, &current, delta)?;
+    let alignment = &difference.alignment;
+    println!("Reference offset {:.1} m, stretch {:.4}, correlation {:.2}", alignment.offset_m, alignment.scale, alignment.correlation);
+    println!("Compared {:.1} m to {:.1} m", difference.distance_m[0] - current.user_offset_m(),
+             difference.distance_m[difference.distance_m.len() - 1] - current.user_offset_m());
+    for deviation in &difference.deviations {
+        println!("{:10.1} m to {:10.1} m  {:+.3} dB", deviation.from_m - current.user_offset_m(),
+                 deviation.to_m - current.user_offset_m(), deviation.peak_db);
+    }
+    if let Some(output_filename) = &args.output_filename {
+        let bytes = difference.to_sor(&current_sor)?.to_bytes().map_err(|e| e.to_string())?;
+        write_output(output_filename, &bytes)?;
+    }
+    if !difference.deviations.is_empty() {
+        return Err(ErrorKind::Validation.error(format!("{} differs from the reference {}", args.current_filename, args.reference_filename)));
+    }
+    println!("PASS");
+    Ok(())
+}
+
+/// List each event's loss at both wavelengths, failing with a validation
+/// error if any looks like a macrobend
+fn macrobends(args: MacrobendsArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let first = parse_sor(&read_input(&args.first_filename)?)?;
+    let second = parse_sor(&read_input(&args.second_filename)?)?;
+    let losses = otdrs::analysis::find_macrobends(&first, &second)?;
+    let mut wavelengths = [&first, &second].map(|sor| sor.general_parameters.as_ref().map_or(0, |gp| gp.nominal_wavelength));
+    wavelengths.sort();
+    println!("{:>10} {:>10} {:>10} {:>8}", "Dist (m)", format!("{} nm", wavelengths[0]), format!("{} nm", wavelengths[1]), "Diff");
+    for loss in &losses {
+        println!("{:>10.1} {:>10.3} {:>10.3} {:>+8.3}{}", loss.distance_m, loss.short_db, loss.long_db, loss.long_db - loss.short_db,
+                 if loss.macrobend { "  MACROBEND" } else { "" });
+    }
+    let bends = losses.iter().filter(|l| l.macrobend).count();
+    if bends > 0 {
+        return Err(ErrorKind::Validation.error(format!("{} possible macrobends", bends)));
+    }
+    Ok(())
+}
+
+fn budget(args: BudgetArgs) -> Result<(), Box<dyn std::error::Error>> {
+    use otdrs::analysis::budget::{link_budget, Optics};
+    let sor = parse_sor(&read_input(&args.input_filename)?)?;
+    let optics = Optics {
+        tx_power_dbm: args.tx_power,
+        rx_sensitivity_dbm: args.rx_sensitivity,
+        rx_overload_dbm: args.rx_overload,
+        min_orl_db: args.min_orl,
+    };
+    let budget = link_budget(&sor, &optics, args.reserve)?;
+    println!("Budget:       {:.2} dB", budget.budget_db);
+    println!("Link loss:    {:.3} dB ({:.3} dB in events{})", budget.loss_db, budget.event_loss_db,
+             budget.worst_event.map_or(String::new(), |(n, loss)| format!(", worst {:.3} dB at event {}", loss, n)));
+    println!("Rx power:     {:.2} dBm", budget.rx_power_dbm);
+    println!("Margin:       {:.2} dB ({:.2} dB reserve)", budget.margin_db, args.reserve);
+    println!("ORL:          {:.3} dB", budget.orl_db);
+    let mut problems = Vec::new();
+    if budget.margin_db < args.reserve {
+        problems.push("insufficient margin");
+    }
+    if budget.overload {
+        problems.push("receiver overloaded");
+    }
+    if !budget.orl_pass {
+        problems.push("ORL too low");
+    }
+    if !problems.is_empty() {
+        return Err(ErrorKind::Validation.error(format!("Link fails its budget: {}", problems.join(", "))));
+    }
+    println!("PASS");
+    Ok(())
+}
+
+fn landmarks(args: LandmarksArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut sor = parse_sor(&read_input(&args.input_filename)?)?;
+    let route = read_input(&args.route)?;
+    let geojson = Path::new(&args.route).extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("geojson") || ext.eq_ignore_ascii_case("json"));
+    let lp = if geojson {
+        otdrs::geo::landmarks_from_geojson(&sor, route.as_slice())
+    } else {
+        otdrs::geo::landmarks_from_csv(&sor, route.as_slice())
+    }.map_err(|e| ErrorKind::Validation.error(e))?;
+    sor.set_link_parameters(lp);
+    if let Some(tolerance_m) = args.relate {
+        sor.relate_landmarks(tolerance_m);
+    }
+    let bytes = sor.to_bytes().map_err(|e| e.to_string())?;
+    write_output(&args.output_filename, &bytes)
+}
+
+fn locate(args: LocateArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let sor = parse_sor(&read_input(&args.input_filename)?)?;
+    if sor.link_parameters.is_none() {
+        return Err("File has no landmarks to locate events by".into());
+    }
+    println!("{:>5} {:>10} {:>8} {:>11} {:>12} {:>12}", "Event", "Dist (m)", "Landmark", "Latitude", "Longitude", "Sheath mark");
+    for event in otdrs::analysis::events(&sor) {
+        let location = otdrs::geo::locate_event(&sor, &event);
+        let (latitude, longitude) = location.position
+            .map_or((String::new(), String::new()), |(lat, lon)| (format!("{:.6}", lat), format!("{:.6}", lon)));
+        let sheath = match (location.sheath_marker, location.sheath_units) {
+            (Some(marker), Some(units)) => format!("{:.0} {}", marker, units),
+            _ => String::new(),
+        };
+        println!("{:>5} {:>10.1} {:>8} {:>11} {:>12} {:>12}", event.number, event.distance_m,
+                 location.previous_landmark.map_or(String::new(), |n| n.to_string()), latitude, longitude, sheath);
+    }
+    Ok(())
+}
+
+fn trace(args: TraceArgs) -> Result<(), Box<dyn std::error::Error>> {
+    use otdrs::export;
+    if args.run.is_some() && args.format != "raw-u16" {
+        return Err(ErrorKind::Usage.error("--run only applies to the raw-u16 format"));
+    }
+    let sor = parse_sor(&read_input(&args.input_filename)?)?;
+    let bytes = match args.format.as_str() {
+        "csv" => {
+            let trace = otdrs::analysis::Trace::new(&sor)?;
+            let mut csv = String::from("distance_m,level_db\n");
+            for (d, level) in trace.distance_m().iter().zip(trace.points_db()) {
+                csv += &format!("{:.3},{:.3}\n", d - trace.user_offset_m(), level);
+            }
+            csv.into_bytes()
+        }
+        "npy" => export::trace_to_npy(&sor)?,
+        "npz" => export::to_npz(&sor)?,
+        "raw-u16" => export::to_raw_u16(&sor, args.run)?,
+        other => return Err(format!("Unknown trace format {:?} - use csv, npy, npz or raw-u16", other).into()),
+    };
+    write_output(&args.output_filename, &bytes)
+}
+
+/// Write the landmarks as KML, placing the break on the route if a baseline
+/// is given. Not finding a break isn't an error, since the KML is still of use
+fn kml(args: KmlArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let sor = parse_sor(&read_input(&args.input_filename)?)?;
+    let fault = match &args.baseline {
+        Some(filename) => match otdrs::analysis::locate_break(&parse_sor(&read_input(filename)?)?, &sor) {
+            Ok(fault) => {
+                if otdrs::geo::position_at(&sor, fault.distance_m).is_none() {
+                    eprintln!("Break at {:.1} m can't be placed: fewer than two landmarks have GPS positions", fault.distance_m);
+                }
+                Some(fault)
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                None
+            }
+        },
+        None => None,
+    };
+    write_output(&args.output_filename, otdrs::kml::to_kml(&sor, fault.as_ref()).as_bytes())
+}
+
+/// Print a metric from each file as CSV, oldest first. Files without the
+/// metric are reported and skipped
+fn timeseries(args: TimeseriesArgs) -> Result<(), Box<dyn std::error::Error>> {
+    use otdrs::batch::Metric;
+    let input_filenames = expand_inputs(&args.input_filenames)?;
+    let metric = match args.metric.as_str() {
+        "total-loss" => Metric::TotalLoss,
+        "orl" => Metric::Orl,
+        "event-loss" => Metric::EventLoss {
+            distance_m: args.at.ok_or("The event-loss metric needs the event's distance --at")?,
+            tolerance_m: args.tolerance,
+        },
+        other => return Err(format!("Unknown metric {:?} - use total-loss, orl or event-loss", other).into()),
+    };
+    let series = otdrs::batch::timeseries(&input_filenames, &metric);
+    for (path, reason) in &series.skipped {
+        eprintln!("Skipping {}: {}", path.display(), reason);
+    }
+    println!("date,value_db,filename");
+    for point in &series.points {
+        println!("{},{:.3},{}", otdrs::engineering::iso8601(point.timestamp), point.value, point.path.display());
+    }
+    Ok(())
+}
+
+/// Parse a loss such as 0.05dB into dB; the unit is optional
+fn parse_loss(s: &str) -> Result<f64, String> {
+    let s = s.trim();
+    let number = s.strip_suffix("dB").or_else(|| s.strip_suffix("db")).unwrap_or(s);
+    number.trim().parse().map_err(|_| format!("{:?} is not a loss in dB", s))
+}
+
+/// Parse a distance such as 0.5km, 300m or 1000ft into metres; plain numbers
+/// are taken to be metres
+fn parse_distance(s: &str) -> Result<f64, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number.trim().parse().map_err(|_| format!("{:?} is not a distance", s))?;
+    let unit_m = match unit.to_ascii_lowercase().as_str() {
+        "" | "m" => 1.0,
+        "km" => 1000.0,
+        "ft" => 0.3048,
+        "kft" | "kf" => 304.8,
+        "mi" => 1609.344,
+        _ => return Err(format!("Unknown unit {:?} - use m, km, ft, kft or mi", unit)),
+    };
+    Ok(number * unit_m)
+}
+
+/// Replace a file's key events with those found in its trace
+fn detect_events(args: DetectEventsArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut sor = parse_sor(&read_input(&args.input_filename)?)?;
+    let options = otdrs::edit::ReanalyzeOptions {
+        loss_db: args.loss_threshold,
+        reflectance_db: args.reflectance_threshold,
+        end_of_fibre_db: args.end_of_fibre_threshold,
+        ..Default::default()
+    };
+    sor.reanalyze_events(&options)?;
+    let bytes = sor.to_bytes().map_err(|e| e.to_string())?;
+    write_output(&args.output_filename, &bytes)
+}
+
+fn smooth(args: SmoothArgs) -> Result<(), Box<dyn std::error::Error>> {
+    use otdrs::analysis::filter::Filter;
+    let filter = match args.filter.as_str() {
+        "moving-average" => Filter::MovingAverage { window: args.window },
+        "median" => Filter::Median { window: args.window },
+        "savitzky-golay" => Filter::SavitzkyGolay { window: args.window, order: args.order },
+        other => return Err(ErrorKind::Usage.error(format!("Unknown filter {:?}", other))),
+    };
+    let sor = parse_sor(&read_input(&args.input_filename)?)?;
+    let trace = otdrs::analysis::Trace::new(&sor)?.filter(&filter)?;
+    let bytes = trace.to_sor(&sor)?.to_bytes().map_err(|e| e.to_string())?;
+    write_output(&args.output_filename, &bytes)
+}
+
+/// Print the ghosts among the key events, optionally writing them back out
+/// with their comments noting them
+fn ghosts(args: GhostsArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut sor = parse_sor(&read_input(&args.input_filename)?)?;
+    for ghost in otdrs::analysis::flag_ghosts(&mut sor) {
+        let source = |n: i16| if n == 0 { "the front panel".to_owned() } else { format!("event {}", n) };
+        println!("Event {} is a ghost of event {}, from light bouncing between {} and {}", ghost.event_number,
+                 ghost.echo_of, source(ghost.between.0), source(ghost.between.1));
+    }
+    match args.output_filename {
+        Some(output_filename) => write_output(&output_filename, &sor.to_bytes().map_err(|e| e.to_string())?),
+        None => Ok(()),
+    }
+}
+
+/// Print reported and re-measured losses for each key event other than the
+/// end of the fibre, optionally writing the re-measured losses back out.
+/// Events without room for the fit either side keep their reported loss
+fn event_losses(args: EventLossesArgs) -> Result<(), Box<dyn std::error::Error>> {
+    use otdrs::analysis::{FitWindows, Trace};
+    let mut sor = parse_sor(&read_input(&args.input_filename)?)?;
+    let trace = Trace::new(&sor)?;
+    let default = FitWindows::for_trace(&trace);
+    let windows = FitWindows {
+        length_m: args.fit_length.unwrap_or(default.length_m),
+        gap_m: args.gap.unwrap_or(default.gap_m),
+    };
+    println!("Fitting {:.1} m either side of each event, leaving {:.1} m after it", windows.length_m, windows.gap_m);
+    println!("{:>3} {:>10} {:>10} {:>10} {:>8} {:>8}", "#", "Dist (m)", "Reported", "Measured", "Diff", "RMS");
+    let ke = sor.key_events.as_mut().ok_or_else(|| ErrorKind::Validation.error("File has no key events"))?;
+    for e in ke.key_events.iter_mut() {
+        let distance_m = trace.event_distance_m(e.event_propogation_time);
+        let reported = e.event_loss as f64 / 1000.0;
+        match trace.event_loss(distance_m, &windows) {
+            Ok(measured) => {
+                println!("{:>3} {:>10.1} {:>10.3} {:>10.3} {:>+8.3} {:>8.3}", e.event_number, distance_m - trace.user_offset_m(),
+                         reported, measured.loss_db, measured.loss_db - reported, measured.before.rms_db.max(measured.after.rms_db));
+                e.event_loss = (measured.loss_db * 1000.0).round() as i16;
+                e.loss_measurement_technique = "LS".into();
+            }
+            Err(err) => println!("{:>3} {:>10.1} {:>10.3} {:>10}   {}", e.event_number, distance_m - trace.user_offset_m(), reported, "-", err),
+        }
+    }
+    match args.output_filename {
+        Some(output_filename) => write_output(&output_filename, &sor.to_bytes().map_err(|e| e.to_string())?),
+        None => Ok(()),
+    }
+}
+
+/// Print each reflective event's dead zones, failing if any exceeds the
+/// specification given
+fn dead_zones(args: DeadZonesArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let sor = parse_sor(&read_input(&args.input_filename)?)?;
+    let zones = otdrs::analysis::dead_zones(&sor)?;
+    let over = |measured: f64, spec: Option<f64>| spec.is_some_and(|spec| measured > spec);
+    let mut failures = 0;
+    println!("{:>3} {:>10} {:>10}", "#", "Event (m)", "Atten. (m)");
+    for (number, dz) in &zones {
+        let attenuation = dz.attenuation_m.map_or("-".to_owned(), |m| format!("{:.2}", m));
+        let fail = over(dz.event_m, args.event_spec) || dz.attenuation_m.is_some_and(|m| over(m, args.attenuation_spec));
+        println!("{:>3} {:>10.2} {:>10}{}", number, dz.event_m, attenuation, if fail { "  FAIL" } else { "" });
+        if fail {
+            failures += 1;
+        }
+    }
+    if failures > 0 {
+        return Err(ErrorKind::Validation.error(format!("{} events exceed the specified dead zones", failures)));
+    }
+    Ok(())
+}
+
+/// Print the score and flags for each file, failing with a validation error
+/// if any file scores below the minimum
+fn assess(args: AssessArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let input_filenames = expand_inputs(&args.input_filenames)?;
+    let mut rejected = 0;
+    for filename in &input_filenames {
+        let sor = parse_sor(&read_input(filename)?)?;
+        let trace = otdrs::analysis::Trace::new(&sor)?;
+        let assessment = otdrs::analysis::assess(&trace)?;
+        let db = |v: Option<f64>| v.map_or("-".to_owned(), |v| format!("{:.1} dB", v));
+        println!("{}: score {}, noise floor {}, dynamic range {}, resolution {:.2} m{}", filename, assessment.score,
+                 db(assessment.noise_floor_db), db(assessment.dynamic_range_db), assessment.resolution.spatial_resolution_m,
+                 assessment.flags.iter().map(|f| format!(", {:?}", f)).collect::<String>());
+        if assessment.score < args.min_score {
+            rejected += 1;
+        }
+    }
+    if rejected > 0 {
+        return Err(ErrorKind::Validation.error(format!("{} of {} files scored below {}", rejected, input_filenames.len(), args.min_score)));
+    }
+    Ok(())
+}
+
+fn validate(args: ValidateArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let input_filenames = expand_inputs(&args.input_filenames)?;
+    let mut failed = 0;
+    for filename in &input_filenames {
+        let sor = parse_sor(&read_input(filename)?)?;
+        let report = sor.conformance_report();
+        if args.conformance {
+            print!("{}: {}", filename, report);
+        } else {
+            for finding in sor.validate() {
+                println!("{}: {} {}.{}: {} [{}]", filename, finding.severity, finding.block, finding.field, finding.message, finding.rule);
+            }
+        }
+        if !report.conforms {
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        return Err(ErrorKind::Validation.error(format!("{} of {} files have errors", failed, input_filenames.len())));
+    }
+    Ok(())
+}
+
+/// Rewrite the files named in a worksheet. Every file is read and updated
+/// before any is written, so a bad row leaves all the files untouched
+fn apply_sheet(args: ApplySheetArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let rows = read_sheet(File::open(&args.worksheet)?)?;
+    let mut updated = Vec::new();
+    for row in &rows {
+        let filename = Path::new(&args.dir).join(&row.filename).to_string_lossy().into_owned();
+        let mut sor = parse_sor(&read_input(&filename)?)?;
+        apply_row(&mut sor, row).map_err(|e| ErrorKind::Validation.error(format!("{}: {}", filename, e)))?;
+        let bytes = sor.to_bytes().map_err(|e| e.to_string())?;
+        updated.push((filename, bytes));
+    }
+    for (filename, bytes) in &updated {
+        write_output(filename, bytes)?;
+    }
+    eprintln!("Updated {} files", updated.len());
+    Ok(())
+}
+
+fn read_sheet(reader: impl Read) -> Result<Vec<SheetRow>, Box<dyn std::error::Error>> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(reader);
+    let rows = reader.deserialize().collect::<Result<Vec<SheetRow>, _>>()
+        .map_err(|e| ErrorKind::Validation.error(format!("Invalid worksheet: {}", e)))?;
+    Ok(rows)
+}
+
+/// Set the fields given in a worksheet row
+fn apply_row(sor: &mut SORFile, row: &SheetRow) -> Result<(), &'static str> {
+    let gp = sor.general_parameters.as_mut().ok_or("File has no general parameters block")?;
+    let fields = [
+        (&mut gp.cable_id, &row.cable_id),
+        (&mut gp.fiber_id, &row.fiber_id),
+        (&mut gp.originating_location, &row.originating_location),
+        (&mut gp.terminating_location, &row.terminating_location),
+        (&mut gp.operator, &row.operator),
+    ];
+    for (field, value) in fields {
+        if let Some(value) = value {
+            *field = value.as_str().into();
+        }
+    }
+    Ok(())
+}
+
+fn checksum(cmd: ChecksumCommand) -> Result<(), Box<dyn std::error::Error>> {
+    use otdrs::checksum;
+    match cmd {
+        ChecksumCommand::Verify { input_filename } => {
+            let data = read_input(&input_filename)?;
+            let res = checksum::verify(&data)?;
+            if res.matches.is_empty() {
+                return Err(ErrorKind::ChecksumMismatch.error(format!("Stored checksum {:#06x} does not match any known algorithm", res.block.value)));
+            }
+            for (algorithm, strategy) in res.matches {
+                println!("Stored checksum {:#06x} matches {} over {}", res.block.value, algorithm, strategy);
+            }
+            Ok(())
+        }
+        ChecksumCommand::Add(args) => {
+            let data = read_input(&args.input_filename)?;
+            let out = checksum::add(&data, args.algorithm, args.strategy)?;
+            write_output(&args.output_filename.unwrap_or(args.input_filename), &out)
+        }
+        ChecksumCommand::Fix(args) => {
+            let mut data = read_input(&args.input_filename)?;
+            checksum::fix(&mut data, args.algorithm, args.strategy)?;
+            write_output(&args.output_filename.unwrap_or(args.input_filename), &data)
+        }
+    }
+}
+
+#[cfg(feature = "plot")]
+fn plot(args: PlotArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let sor = parse_sor(&read_input(&args.input_filename)?)?;
+    let out = if args.output_filename.to_ascii_lowercase().ends_with(".png") {
+        otdrs::plot::render_png(&sor, args.width, args.height)?
+    } else {
+        otdrs::plot::render_svg(&sor, args.width, args.height)?.into_bytes()
+    };
+    write_output(&args.output_filename, &out)
+}
+
+fn view(args: ViewArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let sor = parse_sor(&read_input(&args.input_filename)?)?;
+    print!("{}", render_view(&sor, args.width.max(2), args.height.max(1))?);
+    Ok(())
+}
+
+/// Draw the trace as a unicode block chart, followed by a table of key events
+fn render_view(sor: &SORFile, width: usize, height: usize) -> Result<String, Box<dyn std::error::Error>> {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let trace = otdrs::analysis::Trace::new(sor)?;
+    let metres_per_100ps = trace.metres_per_100ps();
+    let start_m = trace.distance_m()[0];
+    let end_m = trace.distance_m()[trace.distance_m().len() - 1];
+
+    // Each column shows the highest power within it, so reflective peaks
+    // survive the downsampling
+    let db = trace.points_db();
+    let columns: Vec<f64> = (0..width).map(|c| {
+        let from = c * db.len() / width;
+        let to = ((c + 1) * db.len() / width).max(from + 1).min(db.len());
+        db[from..to].iter().cloned().fold(f64::NEG_INFINITY, f64::max)
+    }).collect();
+    let max = columns.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let min = columns.iter().cloned().fold(f64::INFINITY, f64::min);
+    let range = (max - min).max(1e-6);
+
+    let mut out = String::new();
+    if let Some(gp) = &sor.general_parameters {
+        out += &format!("{} {} - {} nm\n", gp.cable_id.trim(), gp.fiber_id.trim(), gp.nominal_wavelength);
+    }
+    // Heights in eighths of a line
+    let heights: Vec<usize> = columns.iter().map(|v| ((v - min) / range * (height * 8 - 1) as f64).round() as usize + 1).collect();
+    for row in (0..height).rev() {
+        let label = if row == height - 1 { format!("{:7.2} dB ", max) } else if row == 0 { format!("{:7.2} dB ", min) } else { " ".repeat(11) };
+        out += &label;
+        for h in &heights {
+            out.push(match h.saturating_sub(row * 8) {
+                0 => ' ',
+                n if n >= 8 => BARS[7],
+                n => BARS[n - 1],
+            });
+        }
+        out.push('\n');
+    }
+    let start_label = format!("{:.3} km", start_m / 1000.0);
+    let end_label = format!("{:.3} km", end_m / 1000.0);
+    out += &format!("{}{}{:>w$}\n\n", " ".repeat(11), start_label, end_label, w = width.saturating_sub(start_label.len()));
+
+    if let Some(ke) = &sor.key_events {
+        out += &format!("{:>3} {:>10} {:>8} {:>8} {:>6}  {}\n", "#", "Dist (m)", "Loss", "Refl", "Code", "Comment");
+        let events = ke.key_events.iter()
+            .map(|e| (e.event_number, e.event_propogation_time, e.event_loss, e.event_reflectance, &e.event_code, &e.comment))
+            .chain(std::iter::once((ke.last_key_event.event_number, ke.last_key_event.event_propogation_time, ke.last_key_event.event_loss, ke.last_key_event.event_reflectance, &ke.last_key_event.event_code, &ke.last_key_event.comment)));
+        for (number, time, loss, reflectance, code, comment) in events {
+            out += &format!("{:>3} {:>10.1} {:>8.3} {:>8.3} {:>6}  {}\n", number, time as f64 * metres_per_100ps,
+                loss as f64 / 1000.0, reflectance as f64 / 1000.0, code, comment.trim());
+        }
+        out += &format!("End-to-end loss {:.3} dB, ORL {:.3} dB\n",
+            ke.last_key_event.end_to_end_loss as f64 / 1000.0, ke.last_key_event.optical_return_loss as f64 / 1000.0);
+    }
+    Ok(out)
+}
+
+fn report(args: ReportArgs, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    use otdrs::report;
+    let mut input_filenames = Vec::new();
+    for filename in expand_inputs(&args.input_filenames)? {
+        if Path::new(&filename).is_dir() {
+            let mut paths = Vec::new();
+            find_sor_files(Path::new(&filename), &mut paths)?;
+            input_filenames.extend(paths.iter().map(|path| path.to_string_lossy().into_owned()));
+        } else {
+            input_filenames.push(filename);
+        }
+    }
+    let profile = config.profile(&args)?;
+    let tolerances = config.tolerances(args.tolerances.as_deref())?;
+    let mut reports = Vec::new();
+    for filename in &input_filenames {
+        let sor = parse_sor(&read_input(filename)?)?;
+        let backward = args.backward_dir.as_ref()
+            .map(|dir| Path::new(dir).join(Path::new(filename).file_name().unwrap_or_default()))
+            .filter(|path| path.exists());
+        match backward {
+            Some(path) => {
+                let backward = parse_sor(&read_input(&path.to_string_lossy())?)?;
+                reports.push(report::build_bidirectional(filename, &sor, &backward, &profile, tolerances.distance_m)?);
+            }
+            None => reports.push(report::build(filename, &sor, &profile)),
+        }
+    }
+    report::sort(&mut reports);
+    let output_filename = args.output_filename.to_ascii_lowercase();
+    let format = match args.format.as_deref() {
+        Some("md") => "markdown",
+        Some(format) => format,
+        None if output_filename.ends_with(".md") => "markdown",
+        None if output_filename.ends_with(".xlsx") => "xlsx",
+        None => "html",
+    };
+    let template = |default: &str| -> Result<String, Box<dyn std::error::Error>> {
+        match &args.template {
+            Some(filename) => Ok(String::from_utf8(read_input(filename)?)?),
+            None => Ok(default.to_owned()),
+        }
+    };
+    let out = match format {
+        "markdown" => report::to_markdown(&reports, &args.title, &template(report::DEFAULT_MARKDOWN_TEMPLATE)?).into_bytes(),
+        "html" => report::to_html(&reports, &args.title, &template(report::DEFAULT_HTML_TEMPLATE)?).into_bytes(),
+        #[cfg(feature = "xlsx")]
+        "xlsx" => report::to_xlsx(&reports, &args.title)?,
+        #[cfg(not(feature = "xlsx"))]
+        "xlsx" => return Err("XLSX reports need otdrs built with --features xlsx".into()),
+        other => return Err(format!("Unknown report format {:?}", other).into()),
+    };
+    write_output(&args.output_filename, &out)?;
+    if let Some(register) = &args.register {
+        write_output(register, report::to_register_csv(&reports)?.as_bytes())?;
+    }
+    let failed = reports.iter().filter(|r| !r.pass).count();
+    if failed > 0 {
+        return Err(ErrorKind::Validation.error(format!("{} of {} files failed acceptance", failed, reports.len())));
+    }
+    Ok(())
+}
 
-    let mut file = File::open(opts.input_filename)?;
+/// Convert SOR files as they are written into a directory. Instruments and
+/// file transfers write files in pieces, so a file is only converted once it
+/// has stopped changing for the settle time
+#[cfg(feature = "watch")]
+fn watch(args: WatchArgs, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    use notify::{EventKind, RecursiveMode, Watcher};
+    use std::sync::mpsc::{channel, RecvTimeoutError};
+    use std::time::{Duration, Instant};
+    let out_dir = args.out_dir.as_ref().or(config.output_directory.as_ref())
+        .ok_or("No output directory given, and none is configured")?;
+    let format = args.format.as_ref().or(config.format.as_ref()).map_or("json", |f| f.as_str());
+    std::fs::create_dir_all(out_dir)?;
+    let settle = Duration::from_millis(args.settle_ms);
+    let (tx, rx) = channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    watcher.watch(Path::new(&args.directory), RecursiveMode::NonRecursive)?;
+    let mut pending: HashMap<std::path::PathBuf, Instant> = HashMap::new();
+    if args.existing {
+        for entry in std::fs::read_dir(&args.directory)? {
+            let path = entry?.path();
+            if is_sor_filename(&path) {
+                // Already settled, unless the clock started too recently
+                pending.insert(path, Instant::now().checked_sub(settle).unwrap_or_else(Instant::now));
+            }
+        }
+    }
+    eprintln!("Watching {} for SOR files", args.directory);
+    loop {
+        match rx.recv_timeout(settle) {
+            Ok(event) => {
+                // One bad event shouldn't stop a watcher left running for days
+                let event = match event {
+                    Ok(event) => event,
+                    Err(err) => {
+                        eprintln!("Error watching {}: {}", args.directory, err);
+                        continue;
+                    }
+                };
+                if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                    for path in event.paths.into_iter().filter(|p| is_sor_filename(p)) {
+                        pending.insert(path, Instant::now());
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        let settled: Vec<_> = pending.iter().filter(|(_, t)| t.elapsed() >= settle).map(|(p, _)| p.clone()).collect();
+        for path in settled {
+            pending.remove(&path);
+            let output = output_path(&path, Path::new(out_dir), format);
+            let res = convert(ConvertArgs {
+                input_filenames: vec![path.to_string_lossy().into_owned()],
+                format: Some(format.to_owned()),
+                output_filename: Some(output.to_string_lossy().into_owned()),
+                pretty: args.pretty,
+                canonical: args.canonical,
+                select: Vec::new(),
+                get: None,
+                engineering_units: args.engineering_units || config.engineering_units,
+                jobs: None,
+            });
+            // A bad file shouldn't stop the rig, so report it and carry on
+            match res {
+                Ok(()) => eprintln!("Converted {} to {}", path.display(), output.display()),
+                Err(err) => eprintln!("Could not convert {}: {}", path.display(), err),
+            }
+        }
+    }
+}
+
+/// Catalogue every SOR file under a directory, reading only their metadata.
+/// Files which can't be read are reported and skipped
+#[cfg(feature = "sqlite")]
+fn index(args: IndexArgs) -> Result<(), Box<dyn std::error::Error>> {
+    use otdrs::catalogue;
+    let mut paths = Vec::new();
+    find_sor_files(Path::new(&args.directory), &mut paths)?;
+    paths.sort();
+    let mut conn = catalogue::open(Path::new(&args.output_filename))?;
+    let summary = catalogue::index(&mut conn, &paths, &Default::default())?.ok_or(otdrs::progress::Cancelled)?;
+    for (path, err) in &summary.skipped {
+        eprintln!("Skipping {}: {}", path.display(), err);
+    }
+    eprintln!("Indexed {} files into {}, {} skipped", summary.indexed, args.output_filename, summary.skipped.len());
+    Ok(())
+}
+
+fn find_sor_files(dir: &Path, paths: &mut Vec<std::path::PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_sor_files(&path, paths)?;
+        } else if is_sor_filename(&path) {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "sqlite")]
+fn search(args: SearchArgs) -> Result<(), Box<dyn std::error::Error>> {
+    use otdrs::catalogue;
+    if !Path::new(&args.catalogue).exists() {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("{}: no such catalogue", args.catalogue)).into());
+    }
+    let conn = catalogue::open(Path::new(&args.catalogue))?;
+    let entries = catalogue::search(&conn, &catalogue::Query {
+        cable_id: args.cable,
+        fiber_id: args.fiber,
+        wavelength: args.wavelength,
+        from: args.from,
+        to: args.to,
+        min_total_loss_db: args.min_loss,
+        max_total_loss_db: args.max_loss,
+    })?;
+    let mut out = String::new();
+    match args.format.as_str() {
+        "text" => {
+            for e in &entries {
+                let dash = || "-".to_owned();
+                out += &format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\n", e.path, e.cable_id, e.fiber_id, e.wavelength, e.date,
+                    e.length_m.map_or_else(dash, |l| format!("{:.1}", l)), e.total_loss_db.map_or_else(dash, |l| format!("{:.3}", l)));
+            }
+        }
+        "ndjson" => {
+            for e in &entries {
+                out += &serde_json::to_string(e)?;
+                out.push('\n');
+            }
+        }
+        other => return Err(ErrorKind::Usage.error(format!("Unknown search output format {:?}", other))),
+    }
+    write_output("stdout", out.as_bytes())
+}
+
+/// Export files to Parquet, reading them one at a time. Files which can't be
+/// read are reported and skipped
+#[cfg(feature = "parquet")]
+fn parquet(args: ParquetArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let input_filenames = expand_inputs(&args.input_filenames)?;
+    let mut failed = 0;
+    let files = input_filenames.iter().filter_map(|filename| {
+        match read_input(filename).and_then(|data| parse_sor(&data)) {
+            Ok(sor) => Some((filename, sor)),
+            Err(err) => {
+                eprintln!("Skipping {}: {}", filename, err);
+                failed += 1;
+                None
+            }
+        }
+    });
+    otdrs::export::to_parquet(files, Path::new(&args.output_directory))?;
+    eprintln!("Exported {} files to {}, {} skipped", input_filenames.len() - failed, args.output_directory, failed);
+    Ok(())
+}
+
+/// Export many files to an HDF5 file. Files which can't be read are
+/// reported and skipped
+#[cfg(feature = "hdf5")]
+fn hdf5(args: Hdf5Args) -> Result<(), Box<dyn std::error::Error>> {
+    let input_filenames = expand_inputs(&args.input_filenames)?;
+    let mut failed = 0;
+    let files = input_filenames.iter().filter_map(|filename| {
+        match read_input(filename).and_then(|data| parse_sor(&data)) {
+            Ok(sor) => Some((filename, sor)),
+            Err(err) => {
+                eprintln!("Skipping {}: {}", filename, err);
+                failed += 1;
+                None
+            }
+        }
+    });
+    let bytes = otdrs::export::to_hdf5(files).map_err(|err| ErrorKind::Validation.error(err))?;
+    write_output(&args.output_filename, &bytes)?;
+    eprintln!("Exported {} files to {}, {} skipped", input_filenames.len() - failed, args.output_filename, failed);
+    Ok(())
+}
+
+/// Export many files to a SQLite database. Files which can't be read are
+/// reported and skipped
+#[cfg(feature = "sqlite")]
+fn sqlite(args: SqliteArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let input_filenames = expand_inputs(&args.input_filenames)?;
+    let mut failed = 0;
+    let files = input_filenames.iter().filter_map(|filename| {
+        match read_input(filename).and_then(|data| parse_sor(&data)) {
+            Ok(sor) => Some((filename, sor)),
+            Err(err) => {
+                eprintln!("Skipping {}: {}", filename, err);
+                failed += 1;
+                None
+            }
+        }
+    });
+    otdrs::export::to_sqlite(files, Path::new(&args.output_filename), args.trace_points)?;
+    eprintln!("Exported {} files to {}, {} skipped", input_filenames.len() - failed, args.output_filename, failed);
+    Ok(())
+}
+
+#[cfg(feature = "serve")]
+fn serve(args: ServeArgs) -> Result<(), Box<dyn std::error::Error>> {
+    eprintln!("Listening on http://{}", args.listen);
+    tokio::runtime::Runtime::new()?.block_on(otdrs::serve::serve(args.listen))?;
+    Ok(())
+}
+
+fn is_sor_filename(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("sor"))
+}
+
+/// Output files take the input's name with the format's extension
+fn output_path(input: &Path, out_dir: &Path, format: &str) -> std::path::PathBuf {
+    let extension = otdrs::format::lookup(format).map_or(format.to_owned(), |format| format.extension().to_owned());
+    // Not with_extension, which would clobber dots within the stem
+    let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+    out_dir.join(format!("{}.{}", stem, extension))
+}
+
+fn parse_sor(data: &[u8]) -> Result<SORFile, Box<dyn std::error::Error>> {
+    otdrs::api::parse(data).map_err(|err| ErrorKind::Parse.error(err.to_string()))
+}
+
+fn read_input(filename: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    #[cfg(feature = "object_store")]
+    {
+        if otdrs::store::is_url(filename) {
+            return Ok(otdrs::store::read(filename)?);
+        }
+    }
+    #[cfg(feature = "zip")]
+    {
+        if otdrs::archive::split(Path::new(filename)).is_some() {
+            return Ok(otdrs::archive::read(Path::new(filename))?);
+        }
+    }
+    // Keep the io::Error, so that it's reported as such, but say which file
+    let with_name = |e: std::io::Error| std::io::Error::new(e.kind(), format!("{}: {}", filename, e));
+    let mut file = File::open(filename).map_err(with_name)?;
     let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer)?;
-    let parser = otdrs::parser::parse_file(buffer.as_slice());
-    let res = parser.unwrap().1;
-    let out;
-    // let output_file;
-    // 
-    // let mut output_file = File::open(opts.output_filename)?;
-    if opts.format == "json" {
-        out = (&serde_json::to_vec(&res).unwrap()).to_owned();
-    } else if opts.format == "cbor" {
-        out = (&serde_cbor::to_vec(&res).unwrap()).to_owned();
-    } else {
-        panic!("Unimplemented output format");
-    }
-    if opts.output_filename == "stdout" {
+    file.read_to_end(&mut buffer).map_err(with_name)?;
+    Ok(buffer)
+}
+
+/// Expand object store prefixes ending in a slash, e.g. s3://bucket/archive/,
+/// to the SOR files directly under them, and ZIP archives to the SOR files
+/// inside them
+fn expand_inputs(filenames: &[String]) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut expanded = Vec::new();
+    for filename in filenames {
+        #[cfg(feature = "object_store")]
+        {
+            if filename.ends_with('/') && otdrs::store::is_url(filename) {
+                expanded.extend(otdrs::store::list_sor_files(filename)?);
+                continue;
+            }
+        }
+        #[cfg(feature = "zip")]
+        {
+            if otdrs::archive::is_archive(Path::new(filename)) {
+                let entries = otdrs::archive::sor_entries(Path::new(filename))?;
+                expanded.extend(entries.iter().map(|entry| entry.to_string_lossy().into_owned()));
+                continue;
+            }
+        }
+        expanded.push(filename.clone());
+    }
+    Ok(expanded)
+}
+
+fn write_output(filename: &str, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    #[cfg(feature = "object_store")]
+    {
+        if otdrs::store::is_url(filename) {
+            return Ok(otdrs::store::write(filename, data.to_vec())?);
+        }
+    }
+    if filename == "stdout" {
         let stdout = std::io::stdout();
         let mut handle = stdout.lock();
-        handle.write_all(&out)?;
+        handle.write_all(data)?;
     } else {
-        let mut output_file = File::create(opts.output_filename).unwrap();
-        output_file.write_all(&out)?;
+        let mut output_file = File::create(filename)?;
+        output_file.write_all(data)?;
     }
-    
     Ok(())
 }
+
+#[test]
+fn test_inject_and_extract() {
+    let dir = std::env::temp_dir().join("otdrs-test-inject");
+    std::fs::create_dir_all(&dir).unwrap();
+    let payload = dir.join("payload.bin");
+    let injected = dir.join("injected.sor");
+    let extracted = dir.join("extracted.bin");
+    std::fs::write(&payload, [1u8, 2, 3, 4]).unwrap();
+    inject(InjectArgs {
+        input_filename: "data/example1-noyes-ofl280.sor".to_owned(),
+        block: "Fod02Params".to_owned(),
+        data: payload.to_str().unwrap().to_owned(),
+        output_filename: injected.to_str().unwrap().to_owned(),
+    }).unwrap();
+    extract(ExtractArgs {
+        input_filename: injected.to_str().unwrap().to_owned(),
+        block: Some("Fod02Params".to_owned()),
+        all: false,
+        output_filename: extracted.to_str().unwrap().to_owned(),
+    }).unwrap();
+    assert_eq!(std::fs::read(&extracted).unwrap(), vec![1u8, 2, 3, 4]);
+}
+
+#[test]
+fn test_parse_distance() {
+    assert_eq!(parse_distance("0.5km"), Ok(500.0));
+    assert_eq!(parse_distance("24.3 km"), Ok(24300.0));
+    assert_eq!(parse_distance("120"), Ok(120.0));
+    assert_eq!(parse_distance("1000ft"), Ok(304.8));
+    assert!(parse_distance("5 furlongs").is_err());
+    assert!(parse_distance("km").is_err());
+    assert_eq!(parse_loss("0.05dB"), Ok(0.05));
+    assert_eq!(parse_loss("0.1"), Ok(0.1));
+    assert!(parse_loss("0.1 dBm").is_err());
+}
+
+#[test]
+fn test_apply_sheet() {
+    let sheet = "filename,cable_id,fiber_id,operator\n\
+                 a.sor, C002 ,010,\n\
+                 b.sor,,,J Smith\n";
+    let rows = read_sheet(sheet.as_bytes()).unwrap();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0].cable_id.as_deref(), Some("C002"));
+    assert_eq!(rows[0].operator, None);
+    assert_eq!(rows[1].originating_location, None);
+    let mut sor = parse_sor(include_bytes!("../data/example1-noyes-ofl280.sor")).unwrap();
+    apply_row(&mut sor, &rows[0]).unwrap();
+    let gp = sor.general_parameters.as_ref().unwrap();
+    assert_eq!((&*gp.cable_id, &*gp.fiber_id), ("C002", "010"));
+    assert_eq!(&*gp.originating_location, "CAB000 ");
+    // A misspelt column is an error rather than silently ignored
+    assert!(read_sheet("filename,cable\na.sor,C002\n".as_bytes()).is_err());
+}
+
+#[test]
+fn test_to_json_canonical() {
+    #[derive(serde::Serialize)]
+    struct Unsorted { b: i32, a: i32 }
+    let value = Unsorted { b: 1, a: 2 };
+    assert_eq!(to_json(&value, false, false).unwrap(), b"{\"b\":1,\"a\":2}");
+    assert_eq!(to_json(&value, false, true).unwrap(), b"{\"a\":2,\"b\":1}");
+    assert_eq!(to_json(&value, true, true).unwrap(), b"{\n  \"a\": 2,\n  \"b\": 1\n}");
+}
+
+#[test]
+fn test_select_and_get() {
+    let value = serde_json::json!({"a": {"b": [1, {"c": "x"}]}, "d": 2});
+    assert_eq!(get_path(&value, "a.b.1.c"), Some(&serde_json::json!("x")));
+    assert_eq!(get_path(&value, "a.b.2"), None);
+    assert_eq!(get_path(&value, "d.e"), None);
+    let selected = select_fields(value, &["d".to_owned()]).unwrap();
+    assert_eq!(selected, serde_json::json!({"d": 2}));
+}
+
+#[test]
+fn test_render_view() {
+    let sor = parse_sor(include_bytes!("../data/example1-noyes-ofl280.sor")).unwrap();
+    let out = render_view(&sor, 40, 5).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines[0], "C001 009 - 1550 nm");
+    assert_eq!(lines[1].chars().count(), 51);
+    assert!(lines.iter().any(|l| l.starts_with("  3     3734.4")));
+}
+
+#[cfg(any(feature = "watch", feature = "sqlite"))]
+#[test]
+fn test_is_sor_filename() {
+    assert!(is_sor_filename(Path::new("/in/trace.1550.SOR")));
+    assert!(!is_sor_filename(Path::new("/in/trace.json")));
+}
+
+#[test]
+fn test_ndjson_line() {
+    let opts = Opts::parse_from(["otdrs", "a.sor", "b.sor", "--format", "ndjson", "--select", "general_parameters"]).convert;
+    assert_eq!(opts.input_filenames, vec!["a.sor", "b.sor"]);
+    let line = ndjson_line("data/example1-noyes-ofl280.sor", &opts).unwrap();
+    assert_eq!(line.iter().filter(|&&b| b == b'\n').count(), 1);
+    let value: serde_json::Value = serde_json::from_slice(&line).unwrap();
+    assert_eq!(value["status"], "ok");
+    assert_eq!(value["sor"]["general_parameters"]["cable_id"], "C001 ");
+    let line = ndjson_line("data/missing.sor", &opts).unwrap();
+    let value: serde_json::Value = serde_json::from_slice(&line).unwrap();
+    assert_eq!(value["filename"], "data/missing.sor");
+    assert_eq!(value["status"], "error");
+}
+
+#[test]
+fn test_from_json() {
+    let dir = std::env::temp_dir().join("otdrs-test-from-json");
+    std::fs::create_dir_all(&dir).unwrap();
+    let (input, output) = (dir.join("in.ndjson"), dir.join("out.tar"));
+    let opts = Opts::parse_from(["otdrs", "a.sor", "--format", "ndjson"]).convert;
+    let sor = parse_sor(&read_input("data/example2-exfo-maxtester730c.sor").unwrap()).unwrap();
+    let mut ndjson = ndjson_line("data/example1-noyes-ofl280.sor", &opts).unwrap();
+    ndjson.extend(serde_json::to_vec(&sor).unwrap());
+    ndjson.extend(b"\n\n");
+    ndjson.extend(ndjson_line("data/missing.sor", &opts).unwrap());
+    std::fs::write(&input, ndjson).unwrap();
+
+    let args = Opts::parse_from(["otdrs", "from-json", input.to_str().unwrap(), "-o", output.to_str().unwrap()]);
+    let err = match args.command {
+        Some(Command::FromJson(args)) => from_json(args).unwrap_err(),
+        _ => unreachable!(),
+    };
+    assert!(err.to_string().contains("1 of 3 lines"), "{}", err);
+    let mut archive = tar::Archive::new(File::open(&output).unwrap());
+    let entries: Vec<(String, SORFile)> = archive.entries().unwrap().map(|entry| {
+        let mut entry = entry.unwrap();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data).unwrap();
+        (entry.path().unwrap().to_string_lossy().into_owned(), parse_sor(&data).unwrap())
+    }).collect();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].0, "example1-noyes-ofl280.sor");
+    assert_eq!(entries[1], ("000002.sor".to_owned(), sor));
+}
+
+#[test]
+fn test_config() {
+    let config: Config = toml::from_str(r#"
+        format = "yaml"
+        output_directory = "/out"
+
+        [profiles.carrier]
+        max_splice_loss = 0.1
+
+        [profiles.carrier.max_attenuation]
+        1550 = 0.25
+    "#).unwrap();
+    let mut args = Opts::parse_from(["otdrs", "/in/trace.1550.SOR"]).convert;
+    config.apply(&mut args);
+    assert_eq!(args.format(), "yaml");
+    assert_eq!(args.output_filename(), "/out/trace.1550.yaml");
+    // The command line takes precedence
+    let mut args = Opts::parse_from(["otdrs", "/in/trace.sor", "-f", "json", "-o", "stdout"]).convert;
+    config.apply(&mut args);
+    assert_eq!(args.format(), "json");
+    assert_eq!(args.output_filename(), "stdout");
+
+    let report = |argv: &[&str]| match Opts::parse_from(argv).command {
+        Some(Command::Report(args)) => config.profile(&args),
+        _ => unreachable!(),
+    };
+    let profile = report(&["otdrs", "report", "a.sor", "--profile", "carrier", "--max-reflectance", "-40"]).unwrap();
+    assert_eq!(profile.max_splice_loss, 0.1);
+    assert_eq!(profile.max_connector_loss, 0.75);
+    assert_eq!(profile.max_reflectance, -40.0);
+    assert_eq!(profile.max_attenuation.get(&1550), Some(&0.25));
+    assert!(report(&["otdrs", "report", "a.sor", "--profile", "missing"]).is_err());
+    let config: Config = toml::from_str("[tolerances.monitoring]\ndistance_m = 5.0").unwrap();
+    assert_eq!(config.tolerances(Some("monitoring")).unwrap().distance_m, 5.0);
+    assert_eq!(config.tolerances(None).unwrap(), ToleranceProfile::default());
+    assert!(config.tolerances(Some("missing")).is_err());
+    assert!(toml::from_str::<Config>("colour = true").is_err());
+}
+
+#[test]
+fn test_error_kinds() {
+    let err = parse_sor(b"Map\0not a SOR file").unwrap_err();
+    assert_eq!(ErrorKind::of(err.as_ref()), ErrorKind::Parse);
+    assert!(err.to_string().starts_with("Could not parse SOR file"));
+    let err = read_input("data/missing.sor").unwrap_err();
+    assert_eq!(ErrorKind::of(err.as_ref()), ErrorKind::Io);
+    let err: Box<dyn std::error::Error> = "something else".into();
+    assert_eq!(ErrorKind::of(err.as_ref()), ErrorKind::Other);
+    assert_eq!(format_error(ErrorKind::Parse, "bad", true), r#"{"error":"parse","exit_code":4,"message":"bad"}"#);
+    assert_eq!(format_error(ErrorKind::Parse, "bad", false), "Error: bad");
+}
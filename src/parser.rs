@@ -13,7 +13,12 @@ use nom::{
     Err,
     error::{Error, ErrorKind}
 };
-use std::str;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::str;
 
 /// Block header string for the map block
 pub const BLOCK_ID_MAP: &str = "Map";
@@ -46,7 +51,7 @@ fn map_block_info(i: &[u8]) -> IResult<&[u8], BlockInfo> {
     Ok((
         i,
         BlockInfo {
-            identifier: String::from(header),
+            identifier: Arc::from(header),
             revision_number,
             size,
         },
@@ -61,7 +66,7 @@ pub fn map_block(i: &[u8]) -> IResult<&[u8], MapBlock> {
     let (i, block_size) = le_i32(i)?;
     let (i, block_count) = le_i16(i)?;
     let blocks_to_read= block_count.checked_sub(1);
-    if blocks_to_read == None {
+    if blocks_to_read.is_none() {
         return Err(Err::Failure(Error{input: i, code: ErrorKind::Fix}));
     }
     let (i, block_info) = count(map_block_info, blocks_to_read.unwrap() as usize)(i)?;
@@ -90,7 +95,6 @@ fn null_terminated_str(i: &[u8]) -> IResult<&[u8], &str> {
 }
 
 /// Parse a fixed-length string of the given number of bytes
-
 fn fixed_length_str(i: &[u8], n_bytes: usize) -> IResult<&[u8], &str> {
     #[allow(clippy::redundant_closure)]
     map_res(take(n_bytes * (1u8 as usize)),  |s|str::from_utf8(s))(i)
@@ -116,19 +120,19 @@ pub fn general_parameters_block(i: &[u8]) -> IResult<&[u8], GeneralParametersBlo
     Ok((
         i,
         GeneralParametersBlock {
-            language_code: String::from(language_code),
-            cable_id: String::from(cable_id),
-            fiber_id: String::from(fiber_id),
+            language_code: Arc::from(language_code),
+            cable_id: Arc::from(cable_id),
+            fiber_id: Arc::from(fiber_id),
             fiber_type,
             nominal_wavelength,
-            originating_location: String::from(originating_location),
-            terminating_location: String::from(terminating_location),
-            cable_code: String::from(cable_code),
-            current_data_flag: String::from(current_data_flag),
+            originating_location: Arc::from(originating_location),
+            terminating_location: Arc::from(terminating_location),
+            cable_code: Arc::from(cable_code),
+            current_data_flag: Arc::from(current_data_flag),
             user_offset,
             user_offset_distance,
-            operator: String::from(operator),
-            comment: String::from(comment),
+            operator: Arc::from(operator),
+            comment: Arc::from(comment),
         },
     ))
 }
@@ -147,13 +151,13 @@ pub fn supplier_parameters_block(i: &[u8]) -> IResult<&[u8], SupplierParametersB
     Ok((
         i,
         SupplierParametersBlock {
-            supplier_name: String::from(supplier_name),
-            otdr_mainframe_id: String::from(otdr_mainframe_id),
-            otdr_mainframe_sn: String::from(otdr_mainframe_sn),
-            optical_module_id: String::from(optical_module_id),
-            optical_module_sn: String::from(optical_module_sn),
-            software_revision: String::from(software_revision),
-            other: String::from(other),
+            supplier_name: Arc::from(supplier_name),
+            otdr_mainframe_id: Arc::from(otdr_mainframe_id),
+            otdr_mainframe_sn: Arc::from(otdr_mainframe_sn),
+            optical_module_id: Arc::from(optical_module_id),
+            optical_module_sn: Arc::from(optical_module_sn),
+            software_revision: Arc::from(software_revision),
+            other: Arc::from(other),
         },
     ))
 }
@@ -195,7 +199,7 @@ pub fn fixed_parameters_block(i: &[u8]) -> IResult<&[u8], FixedParametersBlock>
         i,
         FixedParametersBlock {
             date_time_stamp,
-            units_of_distance: String::from(units_of_distance),
+            units_of_distance: Arc::from(units_of_distance),
             actual_wavelength,
             acquisition_offset,
             acquisition_offset_distance,
@@ -216,7 +220,7 @@ pub fn fixed_parameters_block(i: &[u8]) -> IResult<&[u8], FixedParametersBlock>
             loss_threshold,
             reflectance_threshold,
             end_of_fibre_threshold,
-            trace_type: String::from(trace_type),
+            trace_type: Arc::from(trace_type),
             window_coordinate_1,
             window_coordinate_2,
             window_coordinate_3,
@@ -249,14 +253,14 @@ pub fn key_event(i: &[u8]) -> IResult<&[u8], KeyEvent> {
             attenuation_coefficient_lead_in_fiber,
             event_loss,
             event_reflectance,
-            event_code: String::from(event_code),
-            loss_measurement_technique: String::from(loss_measurement_technique),
+            event_code: Arc::from(event_code),
+            loss_measurement_technique: Arc::from(loss_measurement_technique),
             marker_location_1,
             marker_location_2,
             marker_location_3,
             marker_location_4,
             marker_location_5,
-            comment: String::from(comment),
+            comment: Arc::from(comment),
         },
     ))
 }
@@ -292,14 +296,14 @@ pub fn last_key_event(i: &[u8]) -> IResult<&[u8], LastKeyEvent> {
             attenuation_coefficient_lead_in_fiber,
             event_loss,
             event_reflectance,
-            event_code: String::from(event_code),
-            loss_measurement_technique: String::from(loss_measurement_technique),
+            event_code: Arc::from(event_code),
+            loss_measurement_technique: Arc::from(loss_measurement_technique),
             marker_location_1,
             marker_location_2,
             marker_location_3,
             marker_location_4,
             marker_location_5,
-            comment: String::from(comment),
+            comment: Arc::from(comment),
             end_to_end_loss,
             end_to_end_marker_position_1,
             end_to_end_marker_position_2,
@@ -330,10 +334,8 @@ pub fn key_events_block(i: &[u8]) -> IResult<&[u8], KeyEvents> {
     ))
 }
 
-// TODO: Test this, no test data to hand so this is probably correct
 /// Parse a landmark from the link parameters block
 pub fn landmark(i: &[u8]) -> IResult<&[u8], Landmark> {
-    let (i, _) = block_header(i, BLOCK_ID_LNKPARAMS)?;
     let (i, landmark_number) = le_i16(i)?;
     let (i, landmark_code) = fixed_length_str(i, 2)?;
     let (i, landmark_location) = le_i32(i)?;
@@ -350,7 +352,7 @@ pub fn landmark(i: &[u8]) -> IResult<&[u8], Landmark> {
         i,
         Landmark {
             landmark_number,
-            landmark_code: String::from(landmark_code),
+            landmark_code: Arc::from(landmark_code),
             landmark_location,
             related_event_number,
             gps_longitude,
@@ -358,14 +360,15 @@ pub fn landmark(i: &[u8]) -> IResult<&[u8], Landmark> {
             fiber_correction_factor_lead_in_fiber,
             sheath_marker_entering_landmark,
             sheath_marker_leaving_landmark,
-            units_of_sheath_marks_leaving_landmark: String::from(units_of_sheath_marks_leaving_landmark),
+            units_of_sheath_marks_leaving_landmark: Arc::from(units_of_sheath_marks_leaving_landmark),
             mode_field_diameter_leaving_landmark,
-            comment: String::from(comment),
+            comment: Arc::from(comment),
         },
     ))
 }
 
-// TODO: Test this, no test data to hand so this is probably correct
+// TODO: None of the sample files have link parameters, so this is only
+// tested against what we write ourselves
 /// Extract link parameters and encoded landmarks from the LinkParams block.
 pub fn link_parameters_block(i: &[u8]) -> IResult<&[u8], LinkParameters> {
     let (i, _) = block_header(i, BLOCK_ID_LNKPARAMS)?;
@@ -416,113 +419,543 @@ pub fn data_points_block(i: &[u8]) -> IResult<&[u8], DataPoints> {
 /// Parse the header string from a proprietary block, and return the remaining 
 /// data for external parsers.
 pub fn proprietary_block(i: &[u8]) -> IResult<&[u8], ProprietaryBlock> {
+    shared_proprietary_block(i, None)
+}
+
+fn shared_proprietary_block<'a>(i: &'a [u8], dedup: Option<&mut Dedup>) -> IResult<&'a [u8], ProprietaryBlock> {
     let (data, header) = null_terminated_str(i)?;
     Ok((
         &[],
         ProprietaryBlock {
-            header: String::from(header),
-            data: data.to_vec(),
+            header: Arc::from(header),
+            data: match dedup {
+                Some(dedup) => dedup.share(data),
+                None => data.into(),
+            },
         },
     ))
 }
 
+/// Proprietary block payloads and strings seen so far, by content, so that
+/// identical payloads - which some vendors repeat across blocks and files -
+/// and the strings which repeat between files from the same instruments
+/// (supplier, model, units, block names) are held in memory once. Pass the
+/// same Dedup to parse_file_dedup for every file; payloads and strings are
+/// kept until it is dropped, even if no file still uses them.
+#[derive(Debug, Default, Clone)]
+pub struct Dedup {
+    payloads: BTreeMap<u64, Vec<Arc<[u8]>>>,
+    strings: BTreeSet<Arc<str>>,
+}
+
+/// CRC-64 is plenty to tell payloads apart, and equal hashes are checked
+/// byte for byte anyway
+const DEDUP_HASH: crc::Crc<u64> = crc::Crc::<u64>::new(&crc::CRC_64_XZ);
+
+impl Dedup {
+    pub fn new() -> Dedup {
+        Dedup::default()
+    }
+
+    /// The shared copy of a payload, stored now if it hasn't been seen
+    pub fn share(&mut self, data: &[u8]) -> Arc<[u8]> {
+        let candidates = self.payloads.entry(DEDUP_HASH.checksum(data)).or_default();
+        if let Some(shared) = candidates.iter().find(|shared| shared[..] == *data) {
+            return shared.clone();
+        }
+        let shared: Arc<[u8]> = data.into();
+        candidates.push(shared.clone());
+        shared
+    }
+
+    /// The shared copy of a string, stored now if it hasn't been seen
+    pub fn intern(&mut self, s: &str) -> Arc<str> {
+        if let Some(shared) = self.strings.get(s) {
+            return shared.clone();
+        }
+        let shared: Arc<str> = s.into();
+        self.strings.insert(shared.clone());
+        shared
+    }
+
+    /// Share the payloads and strings of a file parsed some other way
+    pub fn dedup(&mut self, sor: &mut SORFile) {
+        for pb in &mut sor.proprietary_blocks {
+            pb.data = self.share(&pb.data);
+        }
+        self.intern_strings(sor);
+    }
+
+    fn intern_strings(&mut self, sor: &mut SORFile) {
+        let mut fields: Vec<&mut Arc<str>> = Vec::new();
+        fields.extend(sor.map.block_info.iter_mut().map(|bi| &mut bi.identifier));
+        if let Some(gp) = &mut sor.general_parameters {
+            fields.extend([
+                &mut gp.language_code, &mut gp.cable_id, &mut gp.fiber_id, &mut gp.originating_location,
+                &mut gp.terminating_location, &mut gp.cable_code, &mut gp.current_data_flag, &mut gp.operator,
+                &mut gp.comment,
+            ]);
+        }
+        if let Some(sp) = &mut sor.supplier_parameters {
+            fields.extend([
+                &mut sp.supplier_name, &mut sp.otdr_mainframe_id, &mut sp.otdr_mainframe_sn, &mut sp.optical_module_id,
+                &mut sp.optical_module_sn, &mut sp.software_revision, &mut sp.other,
+            ]);
+        }
+        if let Some(fp) = &mut sor.fixed_parameters {
+            fields.extend([&mut fp.units_of_distance, &mut fp.trace_type]);
+        }
+        if let Some(ke) = &mut sor.key_events {
+            for event in &mut ke.key_events {
+                fields.extend([&mut event.event_code, &mut event.loss_measurement_technique, &mut event.comment]);
+            }
+            let lke = &mut ke.last_key_event;
+            fields.extend([&mut lke.event_code, &mut lke.loss_measurement_technique, &mut lke.comment]);
+        }
+        if let Some(lp) = &mut sor.link_parameters {
+            for landmark in &mut lp.landmarks {
+                fields.extend([
+                    &mut landmark.landmark_code, &mut landmark.units_of_sheath_marks_leaving_landmark,
+                    &mut landmark.comment,
+                ]);
+            }
+        }
+        fields.extend(sor.proprietary_blocks.iter_mut().map(|pb| &mut pb.header));
+        for field in fields {
+            *field = self.intern(field);
+        }
+    }
+
+    /// Number of distinct payloads held
+    pub fn len(&self) -> usize {
+        self.payloads.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.payloads.is_empty()
+    }
+
+    /// Number of distinct strings held
+    pub fn strings(&self) -> usize {
+        self.strings.len()
+    }
+
+    /// Bytes of payload held, each distinct payload counted once
+    pub fn bytes(&self) -> usize {
+        self.payloads.values().flatten().map(|shared| shared.len()).sum()
+    }
+}
+
 
 /// Parse a complete SOR file, extracting all known and proprietary blocks to a 
 /// SORFile struct. 
-pub fn parse_file<'a>(i: &'a[u8]) -> IResult<&'a[u8], SORFile> {
+pub fn parse_file(i: &[u8]) -> IResult<&[u8], SORFile> {
+    parse_blocks(i, true, None, None, 0).map(|(i, outcome)| (i, outcome.file))
+}
+
+/// Parse a complete SOR file as parse_file does, sharing proprietary block
+/// payloads and strings with any identical ones already in dedup, to cut the
+/// memory taken by many parsed files from the same instruments. The file is
+/// written out just the same.
+pub fn parse_file_dedup<'a>(i: &'a [u8], dedup: &mut Dedup) -> IResult<&'a [u8], SORFile> {
+    let (i, outcome) = parse_blocks(i, true, None, Some(&mut *dedup), 0)?;
+    let mut file = outcome.file;
+    dedup.intern_strings(&mut file);
+    Ok((i, file))
+}
+
+/// Parse only the metadata of a SOR file, skipping the data points and 
+/// proprietary blocks, which make up the bulk of most files. This is much 
+/// faster when cataloguing large numbers of files.
+pub fn parse_metadata(i: &[u8]) -> IResult<&[u8], SORFile> {
+    parse_blocks(i, false, None, None, 0).map(|(i, outcome)| (i, outcome.file))
+}
+
+/// Parse a complete SOR file as parse_file does, but give up with a failure
+/// of kind ErrorKind::TooLarge once the parsed file holds more than budget
+/// bytes on the heap. This is for services parsing untrusted uploads, where
+/// a small file can claim to hold a great many events or points. The budget
+/// is checked after each block, so one block can take it over by its own
+/// size, which is bounded by the size of the input.
+pub fn parse_file_with_budget(i: &[u8], budget: usize) -> IResult<&[u8], SORFile> {
+    parse_blocks(i, true, Some(budget), None, 0).map(|(i, outcome)| (i, outcome.file))
+}
+
+/// Parse a complete SOR file as parse_file does, also reporting anything
+/// odd about it which the parser tolerated, including a stored checksum
+/// which doesn't match.
+pub fn parse_file_with_warnings(i: &[u8]) -> IResult<&[u8], ParseOutcome> {
+    parse_file_with_size_tolerance(i, 0)
+}
+
+/// Parse a complete SOR file as parse_file_with_warnings does, for files
+/// from writers whose map sizes are slightly off, e.g. leaving out the
+/// length of a block's header. Where a block's header isn't where the sizes
+/// in the map put it, but is within tolerance bytes of there, it is read
+/// from where it is and a BlockSizeMismatch warning is given for the size
+/// that was wrong. Writing the file out stores the right sizes.
+pub fn parse_file_with_size_tolerance(i: &[u8], tolerance: usize) -> IResult<&[u8], ParseOutcome> {
+    let (i, mut outcome) = parse_blocks(i, true, None, None, tolerance)?;
+    // A checksum block that can't be found is already warned about
+    if let Ok(verification) = crate::checksum::verify(i) {
+        if verification.matches.is_empty() {
+            outcome.warnings.push(ParseWarning::ChecksumMismatch { stored: verification.block.value });
+        }
+    }
+    Ok((i, outcome))
+}
+
+/// A parsed file and the warnings raised while parsing it
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParseOutcome {
+    pub file: SORFile,
+    pub warnings: Vec<ParseWarning>,
+}
+
+/// Something odd about a file which didn't stop it being parsed
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ParseWarning {
+    /// A standard block has a revision other than SR-4731 issue 2's, which
+    /// is the layout it was parsed with
+    UnknownRevision { block: String, revision_number: u16 },
+    /// The map lists a block more than once. Only the first listing of a
+    /// standard block is read; each of a proprietary block's is a block of
+    /// its own
+    DuplicateBlock { block: String },
+    /// The map puts a block outside the file, so it was parsed as empty
+    BadBlockPosition { block: String, reason: &'static str },
+    /// A standard block is longer than its contents, e.g. from padding
+    TrailingBytes { block: String, bytes: usize },
+    /// The stored checksum isn't reproduced by any known algorithm and range
+    /// of bytes; see `otdrs::checksum`
+    ChecksumMismatch { stored: u16 },
+    /// The map gives the wrong size for a block - or, for the map, its own
+    /// size - so the next block was found by its header; see
+    /// `parse_file_with_size_tolerance`
+    BlockSizeMismatch { block: String, stored: i32, actual: usize },
+    /// The link parameters don't fit the layout they were parsed with, so
+    /// were left out; none of the sample files have them to test against
+    BadLinkParameters,
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseWarning::UnknownRevision { block, revision_number } =>
+                write!(f, "{} block has unknown revision {}", block, revision_number),
+            ParseWarning::DuplicateBlock { block } => write!(f, "{} block is listed more than once in the map", block),
+            ParseWarning::BadBlockPosition { block, reason } => write!(f, "{} block could not be read: {}", block, reason),
+            ParseWarning::TrailingBytes { block, bytes } => write!(f, "{} block has {} unread bytes at its end", block, bytes),
+            ParseWarning::ChecksumMismatch { stored } => write!(f, "Stored checksum {:#06x} does not match the file", stored),
+            ParseWarning::BlockSizeMismatch { block, stored, actual } =>
+                write!(f, "{} block size is stored as {} bytes but is {}", block, stored, actual),
+            ParseWarning::BadLinkParameters => write!(f, "{} block could not be parsed, so was left out", BLOCK_ID_LNKPARAMS),
+        }
+    }
+}
+
+fn parse_blocks<'a>(i: &'a [u8], include_data: bool, budget: Option<usize>, mut dedup: Option<&mut Dedup>, tolerance: usize)
+                   -> IResult<&'a [u8], ParseOutcome> {
     let mut general_parameters: Option<GeneralParametersBlock> = None;
     let mut supplier_parameters: Option<SupplierParametersBlock> = None;
     let mut fixed_parameters: Option<FixedParametersBlock> = None;
     let mut key_events: Option<KeyEvents> = None;
-    let link_parameters: Option<LinkParameters> = None;
+    let mut link_parameters: Option<LinkParameters> = None;
     let mut data_points: Option<DataPoints> = None;
     let mut proprietary_blocks: Vec<ProprietaryBlock> = Vec::new();
+    let mut warnings: Vec<ParseWarning> = Vec::new();
     
     let (_, map) = map_block(i)?;
-    for block in &map.block_info {
+    if !is_known_revision(map.revision_number) {
+        warnings.push(ParseWarning::UnknownRevision { block: String::from(BLOCK_ID_MAP), revision_number: map.revision_number });
+    }
+    let mut allocated = map.heap_size();
+    let mut charge = |bytes: usize| {
+        allocated = allocated.saturating_add(bytes);
+        match budget {
+            Some(budget) if allocated > budget => Err(Err::Failure(Error{input: i, code: ErrorKind::TooLarge})),
+            _ => Ok(()),
+        }
+    };
+    charge(0)?;
+    let located = locate_blocks(i, &map, tolerance, &mut warnings);
+    for (n, block) in map.block_info.iter().enumerate() {
+        let duplicate = map.block_info[..n].iter().any(|b| b.identifier == block.identifier);
+        if duplicate {
+            warnings.push(ParseWarning::DuplicateBlock { block: block.identifier.to_string() });
+        }
+        if is_standard_block(&block.identifier) && !is_known_revision(block.revision_number) {
+            warnings.push(ParseWarning::UnknownRevision { block: block.identifier.to_string(), revision_number: block.revision_number });
+        }
+        if !include_data && !is_metadata_block(&block.identifier) {
+            continue;
+        }
+        // A file holds one of each standard block, from its first listing,
+        // but proprietary headers needn't be unique, so each of those
+        // listings is a block of its own
+        if duplicate && is_standard_block(&block.identifier) {
+            continue;
+        }
         // Load the block's data
         let default: &[u8] = &[0u8];
-        let data = extract_block_data(i, &block.identifier).unwrap_or(default);
+        let data = match located[n] {
+            Ok(data) => data,
+            Err(reason) => {
+                warnings.push(ParseWarning::BadBlockPosition { block: block.identifier.to_string(), reason });
+                default
+            }
+        };
         // Parse it
-        if block.identifier == BLOCK_ID_SUPPARAMS {
-            let (_, ret) = supplier_parameters_block(data)?;
+        let rest = if &*block.identifier == BLOCK_ID_SUPPARAMS {
+            let (rest, ret) = supplier_parameters_block(data)?;
+            charge(ret.heap_size())?;
             supplier_parameters = Some(ret);
-        } else if block.identifier == BLOCK_ID_GENPARAMS {
-            let (_, ret) = general_parameters_block(data)?;
+            rest
+        } else if &*block.identifier == BLOCK_ID_GENPARAMS {
+            let (rest, ret) = general_parameters_block(data)?;
+            charge(ret.heap_size())?;
             general_parameters = Some(ret);
-        } else if block.identifier == BLOCK_ID_FXDPARAMS {
-            let (_, ret) = fixed_parameters_block(data)?;
+            rest
+        } else if &*block.identifier == BLOCK_ID_FXDPARAMS {
+            let (rest, ret) = fixed_parameters_block(data)?;
+            charge(ret.heap_size())?;
             fixed_parameters = Some(ret);
-        } else if block.identifier == BLOCK_ID_KEYEVENTS {
-            let (_, ret) = key_events_block(data)?;
+            rest
+        } else if &*block.identifier == BLOCK_ID_KEYEVENTS {
+            let (rest, ret) = key_events_block(data)?;
+            charge(ret.heap_size())?;
             key_events = Some(ret);
-        } else if block.identifier == BLOCK_ID_LNKPARAMS {
-            // Unimplemented due to lack of test data
-        } else if block.identifier == BLOCK_ID_DATAPTS {
-            let (_, ret) = data_points_block(data)?;
+            rest
+        } else if &*block.identifier == BLOCK_ID_LNKPARAMS {
+            // Unlike the other blocks, this can't fail the file, as its
+            // layout is only tested against what we write ourselves
+            match link_parameters_block(data) {
+                Ok((rest, ret)) => {
+                    charge(ret.heap_size())?;
+                    link_parameters = Some(ret);
+                    rest
+                }
+                Err(_) => {
+                    warnings.push(ParseWarning::BadLinkParameters);
+                    &[]
+                }
+            }
+        } else if &*block.identifier == BLOCK_ID_DATAPTS {
+            let (rest, ret) = data_points_block(data)?;
+            charge(ret.heap_size())?;
             data_points = Some(ret);
-        } else if block.identifier == BLOCK_ID_CHECKSUM {
-            // TODO: Checksum checks should probably be handled elsewhere
+            rest
+        } else if &*block.identifier == BLOCK_ID_CHECKSUM {
+            // Checked by parse_file_with_warnings, or otdrs::checksum
+            &[]
         } else {
             // Handle proprietary blocks
-            let (_, ret) = proprietary_block(data)?;
+            let (rest, ret) = shared_proprietary_block(data, dedup.as_deref_mut())?;
+            charge(ret.heap_size())?;
             proprietary_blocks.push(ret);
+            rest
+        };
+        if !rest.is_empty() {
+            warnings.push(ParseWarning::TrailingBytes { block: block.identifier.to_string(), bytes: rest.len() });
         }
     }
     Ok((
         i,
-        SORFile {
-            map,
-            general_parameters,
-            supplier_parameters,
-            fixed_parameters,
-            key_events,
-            link_parameters,
-            data_points,
-            proprietary_blocks,
+        ParseOutcome {
+            file: SORFile {
+                map,
+                general_parameters,
+                supplier_parameters,
+                fixed_parameters,
+                key_events,
+                link_parameters,
+                data_points,
+                proprietary_blocks,
+            },
+            warnings,
         },
     ))
 }
 
+/// The bytes a parsed file holds on the heap, counting allocated capacity
+pub(crate) fn heap_size(sor: &SORFile) -> usize {
+    sor.heap_size()
+}
+
+/// The bytes a parsed value holds on the heap, for parse budgets
+trait HeapSize {
+    fn heap_size(&self) -> usize;
+}
+
+/// Shared strings and payloads count in full, though they may be held only
+/// once
+impl HeapSize for Arc<str> {
+    fn heap_size(&self) -> usize {
+        self.len()
+    }
+}
+
+impl HeapSize for Arc<[u8]> {
+    fn heap_size(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T: HeapSize> HeapSize for Option<T> {
+    fn heap_size(&self) -> usize {
+        self.as_ref().map_or(0, HeapSize::heap_size)
+    }
+}
+
+impl<T: HeapSize> HeapSize for Vec<T> {
+    fn heap_size(&self) -> usize {
+        self.capacity() * core::mem::size_of::<T>() + self.iter().map(HeapSize::heap_size).sum::<usize>()
+    }
+}
+
+macro_rules! heap_size {
+    ($($t:ty),*) => {
+        $(impl HeapSize for $t {
+            fn heap_size(&self) -> usize {
+                0
+            }
+        })*
+    };
+    ($t:ty { $($field:ident),* }) => {
+        impl HeapSize for $t {
+            fn heap_size(&self) -> usize {
+                0 $(+ self.$field.heap_size())*
+            }
+        }
+    };
+}
+
+heap_size!(u8, u16, i16, i32);
+heap_size!(BlockInfo { identifier });
+heap_size!(MapBlock { block_info });
+heap_size!(GeneralParametersBlock { language_code, cable_id, fiber_id, originating_location, terminating_location,
+                                    cable_code, current_data_flag, operator, comment });
+heap_size!(SupplierParametersBlock { supplier_name, otdr_mainframe_id, otdr_mainframe_sn, optical_module_id,
+                                     optical_module_sn, software_revision, other });
+heap_size!(FixedParametersBlock { units_of_distance, pulse_widths_used, data_spacing, n_data_points_for_pulse_widths_used,
+                                  trace_type });
+heap_size!(KeyEvent { event_code, loss_measurement_technique, comment });
+heap_size!(LastKeyEvent { event_code, loss_measurement_technique, comment });
+heap_size!(KeyEvents { key_events, last_key_event });
+heap_size!(Landmark { landmark_code, units_of_sheath_marks_leaving_landmark, comment });
+heap_size!(LinkParameters { landmarks });
+heap_size!(DataPointsAtScaleFactor { data });
+heap_size!(DataPoints { scale_factors });
+heap_size!(ProprietaryBlock { header, data });
+heap_size!(SORFile { map, general_parameters, supplier_parameters, fixed_parameters, key_events, link_parameters,
+                    data_points, proprietary_blocks });
+
+/// Whether a revision number is one of SR-4731 issue 2's, e.g. 200 or 210
+fn is_known_revision(revision_number: u16) -> bool {
+    (200..300).contains(&revision_number)
+}
+
+fn is_standard_block(identifier: &str) -> bool {
+    [BLOCK_ID_GENPARAMS, BLOCK_ID_SUPPARAMS, BLOCK_ID_FXDPARAMS, BLOCK_ID_KEYEVENTS, BLOCK_ID_LNKPARAMS, BLOCK_ID_DATAPTS,
+     BLOCK_ID_CHECKSUM].contains(&identifier)
+}
+
+fn is_metadata_block(identifier: &str) -> bool {
+    [BLOCK_ID_GENPARAMS, BLOCK_ID_SUPPARAMS, BLOCK_ID_FXDPARAMS, BLOCK_ID_KEYEVENTS, BLOCK_ID_LNKPARAMS].contains(&identifier)
+}
+
 /// Given an input file and a block header, extracts the bytes for that block 
 /// only using the map's description of the length of the block.
 /// This allows for the parsers in this file to work on a single block at a 
 /// time without strict ordering, as the SOR file does not require a specific 
 /// sequence of blocks.
-fn extract_block_data<'a>(data: &'a [u8], header: &String) -> Result<&'a [u8], &'a str> {
-    let res = map_block(data);
-    let map = res.unwrap().1;
-    let mut offset: usize = map.block_size as usize;
-    let mut len: usize = 0;
-    
-    for block in map.block_info {
-        len = block.size as usize;
-        if block.identifier == *header {
-            break;
-        }
-        let (offset_value, overflow) = offset.overflowing_add(block.size as usize);
-        offset = offset_value;
-        if overflow {
-            return Err("Error with block data - offset value is incorrect");
-        }
-    }
-    let (final_byte, overflow) = offset.overflowing_add(len);
-    if overflow {
-        return Err("Error with block data - final byte value is incorrect");
+/// Find each block in the map, in order, by adding up the sizes the map
+/// gives. With a tolerance, a block whose header isn't where the sizes put it
+/// is looked for up to that many bytes either side, for writers which get
+/// the sizes slightly wrong, and each block then ends where the next begins.
+fn locate_blocks<'a>(data: &'a [u8], map: &MapBlock, tolerance: usize, warnings: &mut Vec<ParseWarning>)
+                     -> Vec<Result<&'a [u8], &'static str>> {
+    let mut starts: Vec<Option<usize>> = Vec::with_capacity(map.block_info.len());
+    // The map is the first block, at the start of the file
+    let (mut previous, mut start, mut size) = (BLOCK_ID_MAP, Some(0usize), map.block_size);
+    for block in &map.block_info {
+        let expected = start.and_then(|start| start.checked_add(size as usize));
+        let found = match (start, expected) {
+            (Some(start), Some(expected)) => {
+                let found = find_block(data, &block.identifier, start, expected, tolerance);
+                if found != expected {
+                    warnings.push(ParseWarning::BlockSizeMismatch { block: String::from(previous), stored: size, actual: found - start });
+                }
+                Some(found)
+            }
+            _ => None,
+        };
+        starts.push(found);
+        previous = &block.identifier;
+        start = found;
+        size = block.size;
     }
-    if offset > data.len() {
-        return Err("Error with block data - reported block position is incorrect");
+    let mut located = Vec::with_capacity(starts.len());
+    for (n, block) in map.block_info.iter().enumerate() {
+        let offset = match starts[n] {
+            Some(offset) => offset,
+            None => {
+                located.push(Err("Error with block data - offset value is incorrect"));
+                continue;
+            }
+        };
+        let mut final_byte = match offset.checked_add(block.size as usize) {
+            Some(final_byte) => final_byte,
+            None => {
+                located.push(Err("Error with block data - final byte value is incorrect"));
+                continue;
+            }
+        };
+        if tolerance > 0 {
+            match starts.get(n + 1) {
+                Some(&Some(next)) => final_byte = next,
+                // The last block may run a little past the end of the file
+                None if final_byte > data.len() && final_byte - data.len() <= tolerance && offset <= data.len() => {
+                    warnings.push(ParseWarning::BlockSizeMismatch { block: block.identifier.to_string(), stored: block.size, actual: data.len() - offset });
+                    final_byte = data.len();
+                }
+                _ => {}
+            }
+        }
+        located.push(if offset > data.len() {
+            Err("Error with block data - reported block position is incorrect")
+        } else if final_byte > data.len() {
+            Err("Error with block data - reported block position or length is incorrect")
+        } else {
+            Ok(&data[offset..final_byte])
+        });
     }
-    if final_byte > data.len() {
-        return Err("Error with block data - reported block position or length is incorrect");
+    located
+}
+
+/// Where a block starts: where it is expected, unless its header is instead
+/// within tolerance bytes of there, nearest first, and after the previous
+/// block's start
+fn find_block(data: &[u8], identifier: &str, previous: usize, expected: usize, tolerance: usize) -> usize {
+    let has_header = |at: usize| {
+        data.get(at..).is_some_and(|rest| rest.starts_with(identifier.as_bytes()) && rest.get(identifier.len()) == Some(&0))
+    };
+    if tolerance == 0 || has_header(expected) {
+        return expected;
     }
-    Ok(&data[offset..final_byte])
+    (1..=tolerance)
+        .flat_map(|d| [expected.checked_sub(d), expected.checked_add(d)])
+        .flatten()
+        .find(|&at| at > previous && has_header(at))
+        .unwrap_or(expected)
 }
 
 #[cfg(test)]
 fn test_load_file_section<'a>(header: String) -> &'a[u8] {
     let data = include_bytes!("../data/example1-noyes-ofl280.sor");
-    return extract_block_data(data, &header).unwrap();
+    let map = map_block(data).unwrap().1;
+    let n = map.block_info.iter().position(|b| *b.identifier == *header).unwrap();
+    locate_blocks(data, &map, 0, &mut Vec::new()).remove(n).unwrap()
 }
 
 #[test]
@@ -541,6 +974,107 @@ fn test_parse_file() {
     assert_eq!(fp.number_of_averages, 2704);
 }
 
+#[test]
+fn test_parse_metadata() {
+    let data = include_bytes!("../data/example1-noyes-ofl280.sor");
+    let sor = parse_metadata(data).unwrap().1;
+    let full = parse_file(data).unwrap().1;
+    assert_eq!(sor.data_points, None);
+    assert!(sor.proprietary_blocks.is_empty());
+    assert_eq!(sor.fixed_parameters, full.fixed_parameters);
+    assert_eq!(sor.key_events, full.key_events);
+}
+
+#[test]
+fn test_parse_file_with_warnings() {
+    let data = include_bytes!("../data/example1-noyes-ofl280.sor");
+    let outcome = parse_file_with_warnings(data).unwrap().1;
+    assert_eq!(outcome.file, parse_file(data).unwrap().1);
+    assert_eq!(outcome.warnings, vec![]);
+    // EXFO's checksums match none of the known algorithms
+    let exfo = parse_file_with_warnings(include_bytes!("../data/example2-exfo-maxtester730c.sor")).unwrap().1;
+    assert!(matches!(exfo.warnings[..], [ParseWarning::ChecksumMismatch { .. }]));
+
+    // Give the general parameters an issue 1 revision in the map
+    let mut data = data.to_vec();
+    let at = data.windows(10).position(|w| w == b"GenParams\0").unwrap() + 10;
+    data[at..at + 2].copy_from_slice(&100u16.to_le_bytes());
+    let outcome = parse_file_with_warnings(&data).unwrap().1;
+    assert_eq!(outcome.warnings[0], ParseWarning::UnknownRevision { block: "GenParams".to_owned(), revision_number: 100 });
+    assert_eq!(outcome.warnings[0].to_string(), "GenParams block has unknown revision 100");
+    assert!(matches!(outcome.warnings[1], ParseWarning::ChecksumMismatch { .. }));
+}
+
+#[test]
+fn test_parse_file_with_size_tolerance() {
+    let data = include_bytes!("../data/example1-noyes-ofl280.sor");
+    let full = parse_file(data).unwrap().1;
+    // Store the map's size and the general parameters' without their
+    // headers, as some writers do
+    let mut data = data.to_vec();
+    let shrink = |data: &mut Vec<u8>, at: usize, by: i32| {
+        let size = i32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+        data[at..at + 4].copy_from_slice(&(size - by).to_le_bytes());
+        size
+    };
+    let map_size = shrink(&mut data, 6, 4);
+    let at = data.windows(10).position(|w| w == b"GenParams\0").unwrap() + 12;
+    let gen_params_size = shrink(&mut data, at, 10);
+    assert!(parse_file(&data).map_or(true, |(_, sor)| sor.general_parameters.is_none()));
+
+    let outcome = parse_file_with_size_tolerance(&data, 16).unwrap().1;
+    assert_eq!((&outcome.file.general_parameters, &outcome.file.data_points), (&full.general_parameters, &full.data_points));
+    assert_eq!(outcome.warnings[..2], [
+        ParseWarning::BlockSizeMismatch { block: "Map".to_owned(), stored: map_size - 4, actual: map_size as usize },
+        ParseWarning::BlockSizeMismatch { block: "GenParams".to_owned(), stored: gen_params_size - 10, actual: gen_params_size as usize },
+    ]);
+    assert_eq!(outcome.warnings[0].to_string(), format!("Map block size is stored as {} bytes but is {}", map_size - 4, map_size));
+    // The right sizes are written
+    let written = outcome.file.to_bytes().unwrap();
+    let reparsed = parse_file_with_warnings(&written).unwrap().1;
+    assert_eq!((reparsed.file.map.block_size, reparsed.warnings), (map_size, vec![]));
+}
+
+#[test]
+fn test_parse_duplicate_proprietary_blocks() {
+    let mut sor = parse_file(include_bytes!("../data/example1-noyes-ofl280.sor")).unwrap().1;
+    let header = sor.proprietary_blocks[0].header.clone();
+    sor.proprietary_blocks.push(ProprietaryBlock { header: header.clone(), data: vec![1, 2, 3].into() });
+    let data = sor.to_bytes().unwrap();
+    let outcome = parse_file_with_warnings(&data).unwrap().1;
+    assert_eq!(outcome.warnings, vec![ParseWarning::DuplicateBlock { block: header.to_string() }]);
+    assert_eq!(outcome.file.proprietary_blocks, sor.proprietary_blocks);
+    assert!(crate::lossless::verify_lossless_bytes(&data).unwrap().identical);
+}
+
+#[test]
+fn test_parse_bad_link_parameters() {
+    let mut sor = parse_file(include_bytes!("../data/example1-noyes-ofl280.sor")).unwrap().1;
+    sor.add_landmark("MH", 100.0).unwrap();
+    let mut data = sor.to_bytes().unwrap();
+    // Claim more landmarks than the block holds
+    let at = data.windows(10).rposition(|w| w == b"LnkParams\0").unwrap() + 10;
+    data[at..at + 2].copy_from_slice(&100i16.to_le_bytes());
+    let outcome = parse_file_with_warnings(&data).unwrap().1;
+    assert_eq!(outcome.file.link_parameters, None);
+    assert_eq!(outcome.file.data_points, sor.data_points);
+    assert_eq!(outcome.warnings[0], ParseWarning::BadLinkParameters);
+    assert_eq!(outcome.warnings[0].to_string(), "LnkParams block could not be parsed, so was left out");
+}
+
+#[test]
+fn test_parse_file_with_budget() {
+    let data = include_bytes!("../data/example1-noyes-ofl280.sor");
+    let full = parse_file(data).unwrap().1;
+    // The data points alone need two bytes per point
+    let points = full.data_points.as_ref().unwrap().number_of_data_points as usize;
+    assert_eq!(parse_file_with_budget(data, points * 4).unwrap().1, full);
+    match parse_file_with_budget(data, points) {
+        Err(Err::Failure(e)) => assert_eq!(e.code, ErrorKind::TooLarge),
+        _ => panic!("the budget should be exceeded"),
+    }
+}
+
 #[test]
 fn test_parse_anritsu_file() {
     let data = include_bytes!("../data/example3-anritsu-accessmastermt9085.sor");
@@ -623,14 +1157,14 @@ fn test_key_events_block() {
                     attenuation_coefficient_lead_in_fiber: 0,
                     event_loss: -215,
                     event_reflectance: -46671,
-                    event_code: "1F9999".to_owned(),
-                    loss_measurement_technique: "LS".to_owned(),
+                    event_code: "1F9999".into(),
+                    loss_measurement_technique: "LS".into(),
                     marker_location_1: 0,
                     marker_location_2: 0,
                     marker_location_3: 0,
                     marker_location_4: 0,
                     marker_location_5: 0,
-                    comment: " ".to_owned()
+                    comment: " ".into()
                 },
                 KeyEvent {
                     event_number: 2,
@@ -638,14 +1172,14 @@ fn test_key_events_block() {
                     attenuation_coefficient_lead_in_fiber: 0,
                     event_loss: 374,
                     event_reflectance: 0,
-                    event_code: "0F9999".to_owned(),
-                    loss_measurement_technique: "LS".to_owned(),
+                    event_code: "0F9999".into(),
+                    loss_measurement_technique: "LS".into(),
                     marker_location_1: 0,
                     marker_location_2: 0,
                     marker_location_3: 0,
                     marker_location_4: 0,
                     marker_location_5: 0,
-                    comment: " ".to_owned()
+                    comment: " ".into()
                 }
             ],
             last_key_event: LastKeyEvent {
@@ -654,14 +1188,14 @@ fn test_key_events_block() {
                 attenuation_coefficient_lead_in_fiber: 185,
                 event_loss: -950,
                 event_reflectance: -23027,
-                event_code: "2E9999".to_owned(),
-                loss_measurement_technique: "LS".to_owned(),
+                event_code: "2E9999".into(),
+                loss_measurement_technique: "LS".into(),
                 marker_location_1: 0,
                 marker_location_2: 0,
                 marker_location_3: 0,
                 marker_location_4: 0,
                 marker_location_5: 0,
-                comment: " ".to_owned(),
+                comment: " ".into(),
                 end_to_end_loss: 576,
                 end_to_end_marker_position_1: 0,
                 end_to_end_marker_position_2: 182809,
@@ -681,7 +1215,7 @@ fn test_fixparam_block() {
         res.unwrap().1,
         FixedParametersBlock {
             date_time_stamp: 1569835674,
-            units_of_distance: "mt".to_owned(),
+            units_of_distance: "mt".into(),
             actual_wavelength: 1550,
             acquisition_offset: -2147,
             acquisition_offset_distance: -42,
@@ -702,7 +1236,7 @@ fn test_fixparam_block() {
             loss_threshold: 50,
             reflectance_threshold: 65000,
             end_of_fibre_threshold: 3000,
-            trace_type: "ST".to_owned(),
+            trace_type: "ST".into(),
             window_coordinate_1: 0,
             window_coordinate_2: 0,
             window_coordinate_3: 0,
@@ -718,13 +1252,13 @@ fn test_supparam_block() {
     assert_eq!(
         res.unwrap().1,
         SupplierParametersBlock {
-            supplier_name: "Noyes".to_owned(),
-            otdr_mainframe_id: "OFL280C-100".to_owned(),
-            otdr_mainframe_sn: "2G14PT7552     ".to_owned(),
-            optical_module_id: "0.0.43 ".to_owned(),
-            optical_module_sn: " ".to_owned(),
-            software_revision: "1.2.04b1011F ".to_owned(),
-            other: "Last Calibration Date:  2019-03-25 ".to_owned()
+            supplier_name: "Noyes".into(),
+            otdr_mainframe_id: "OFL280C-100".into(),
+            otdr_mainframe_sn: "2G14PT7552     ".into(),
+            optical_module_id: "0.0.43 ".into(),
+            optical_module_sn: " ".into(),
+            software_revision: "1.2.04b1011F ".into(),
+            other: "Last Calibration Date:  2019-03-25 ".into()
         }
     );
 }
@@ -736,19 +1270,19 @@ fn test_genparam_block() {
     assert_eq!(
         res.unwrap().1,
         GeneralParametersBlock {
-            language_code: "EN".to_owned(),
-            cable_id: "C001 ".to_owned(),
-            fiber_id: "009".to_owned(),
+            language_code: "EN".into(),
+            cable_id: "C001 ".into(),
+            fiber_id: "009".into(),
             fiber_type: 652,
             nominal_wavelength: 1550,
-            originating_location: "CAB000 ".to_owned(),
-            terminating_location: "CLS007 ".to_owned(),
-            cable_code: " ".to_owned(),
-            current_data_flag: "NC".to_owned(),
+            originating_location: "CAB000 ".into(),
+            terminating_location: "CLS007 ".into(),
+            cable_code: " ".into(),
+            current_data_flag: "NC".into(),
             user_offset: 24641,
             user_offset_distance: 503,
-            operator: " ".to_owned(),
-            comment: " ".to_owned()
+            operator: " ".into(),
+            comment: " ".into()
         }
     );
 }
@@ -766,52 +1300,52 @@ fn test_map_block() {
             block_count: 11,
             block_info: vec![
                 BlockInfo {
-                    identifier: "GenParams".to_owned(),
+                    identifier: "GenParams".into(),
                     revision_number: 200,
                     size: 58
                 },
                 BlockInfo {
-                    identifier: "SupParams".to_owned(),
+                    identifier: "SupParams".into(),
                     revision_number: 200,
                     size: 104
                 },
                 BlockInfo {
-                    identifier: "FxdParams".to_owned(),
+                    identifier: "FxdParams".into(),
                     revision_number: 200,
                     size: 92
                 },
                 BlockInfo {
-                    identifier: "FodParams".to_owned(),
+                    identifier: "FodParams".into(),
                     revision_number: 200,
                     size: 266
                 },
                 BlockInfo {
-                    identifier: "KeyEvents".to_owned(),
+                    identifier: "KeyEvents".into(),
                     revision_number: 200,
                     size: 166
                 },
                 BlockInfo {
-                    identifier: "Fod02Params".to_owned(),
+                    identifier: "Fod02Params".into(),
                     revision_number: 200,
                     size: 38
                 },
                 BlockInfo {
-                    identifier: "Fod04Params".to_owned(),
+                    identifier: "Fod04Params".into(),
                     revision_number: 200,
                     size: 166
                 },
                 BlockInfo {
-                    identifier: "Fod03Params".to_owned(),
+                    identifier: "Fod03Params".into(),
                     revision_number: 200,
                     size: 26
                 },
                 BlockInfo {
-                    identifier: "DataPts".to_owned(),
+                    identifier: "DataPts".into(),
                     revision_number: 200,
                     size: 60020
                 },
                 BlockInfo {
-                    identifier: "Cksum".to_owned(),
+                    identifier: "Cksum".into(),
                     revision_number: 200,
                     size: 8
                 }
@@ -828,3 +1362,36 @@ fn test_null_terminated_chunk() {
     assert_eq!(data.0, "".as_bytes()); // make sure we've consumed the null
     assert_eq!(data.1, "abcdef".as_bytes());
 }
+#[test]
+fn test_parse_file_dedup() {
+    let data = include_bytes!("../data/example1-noyes-ofl280.sor");
+    let mut dedup = Dedup::new();
+    let first = parse_file_dedup(data, &mut dedup).unwrap().1;
+    let second = parse_file_dedup(data, &mut dedup).unwrap().1;
+    assert_eq!(dedup.len(), 4);
+    assert_eq!(dedup.bytes(), first.proprietary_blocks.iter().map(|pb| pb.data.len()).sum::<usize>());
+    for (a, b) in first.proprietary_blocks.iter().zip(&second.proprietary_blocks) {
+        assert!(Arc::ptr_eq(&a.data, &b.data));
+    }
+    // Deduplication doesn't change the file as parsed or written
+    let plain = parse_file(data).unwrap().1;
+    assert_eq!(first, plain);
+    assert_eq!(first.to_bytes().unwrap(), plain.to_bytes().unwrap());
+
+    let mut other = parse_file(include_bytes!("../data/example2-exfo-maxtester730c.sor")).unwrap().1;
+    other.proprietary_blocks.push(plain.proprietary_blocks[0].clone());
+    other.proprietary_blocks[1].data = plain.proprietary_blocks[0].data.to_vec().into();
+    dedup.dedup(&mut other);
+    assert_eq!(dedup.len(), 5);
+    assert!(Arc::ptr_eq(&other.proprietary_blocks[1].data, &first.proprietary_blocks[0].data));
+
+    // Strings are shared too, both between files and within one
+    let (a, b) = (first.supplier_parameters.as_ref().unwrap(), second.supplier_parameters.as_ref().unwrap());
+    assert!(Arc::ptr_eq(&a.supplier_name, &b.supplier_name));
+    assert!(Arc::ptr_eq(&first.map.block_info[0].identifier, &second.map.block_info[0].identifier));
+    let ke = first.key_events.as_ref().unwrap();
+    assert!(Arc::ptr_eq(&ke.key_events[0].loss_measurement_technique, &ke.last_key_event.loss_measurement_technique));
+    let strings = dedup.strings();
+    assert!(Arc::ptr_eq(&dedup.intern("Noyes"), &a.supplier_name));
+    assert_eq!(dedup.strings(), strings);
+}
@@ -0,0 +1,192 @@
+/// This module applies JSON Patch (RFC 6902) documents to a SORFile, so that
+/// bulk edits can be written declaratively and reviewed like any other diff,
+/// e.g. `[{"op": "replace", "path": "/general_parameters/cable_id", "value":
+/// "C042"}]`.
+///
+/// Paths address the file as serialised to JSON, with values as raw SR-4731
+/// encodings rather than engineering units. The map block is rebuilt when a
+/// file is written, so needn't be patched to match other changes.
+use serde_json::Value;
+use crate::types::SORFile;
+
+/// Split a JSON Pointer (RFC 6901) into its unescaped reference tokens
+fn tokens(pointer: &str) -> Result<Vec<String>, String> {
+    if pointer.is_empty() {
+        return Ok(Vec::new());
+    }
+    if !pointer.starts_with('/') {
+        return Err(format!("{:?} is not a JSON Pointer", pointer));
+    }
+    Ok(pointer[1..].split('/').map(|token| token.replace("~1", "/").replace("~0", "~")).collect())
+}
+
+/// An array index, which must be a plain decimal number
+fn index(token: &str, len: usize) -> Option<usize> {
+    if token.is_empty() || (token.len() > 1 && token.starts_with('0')) || !token.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    token.parse().ok().filter(|&i| i < len)
+}
+
+fn get<'a>(doc: &'a Value, path: &[String]) -> Option<&'a Value> {
+    path.iter().try_fold(doc, |value, token| match value {
+        Value::Object(map) => map.get(token),
+        Value::Array(items) => items.get(index(token, items.len())?),
+        _ => None,
+    })
+}
+
+fn get_mut<'a>(doc: &'a mut Value, path: &[String]) -> Option<&'a mut Value> {
+    path.iter().try_fold(doc, |value, token| match value {
+        Value::Object(map) => map.get_mut(token),
+        Value::Array(items) => {
+            let i = index(token, items.len())?;
+            items.get_mut(i)
+        }
+        _ => None,
+    })
+}
+
+fn add(doc: &mut Value, path: &[String], value: Value) -> Result<(), &'static str> {
+    let (last, parent) = match path.split_last() {
+        Some(split) => split,
+        None => {
+            *doc = value;
+            return Ok(());
+        }
+    };
+    match get_mut(doc, parent) {
+        Some(Value::Object(map)) => {
+            map.insert(last.clone(), value);
+            Ok(())
+        }
+        Some(Value::Array(items)) => {
+            let i = if last == "-" { items.len() } else { index(last, items.len() + 1).ok_or("array index is out of range")? };
+            items.insert(i, value);
+            Ok(())
+        }
+        _ => Err("the parent of the path does not exist"),
+    }
+}
+
+fn remove(doc: &mut Value, path: &[String]) -> Result<Value, &'static str> {
+    let (last, parent) = path.split_last().ok_or("the whole document can't be removed")?;
+    match get_mut(doc, parent) {
+        Some(Value::Object(map)) => map.remove(last).ok_or("the path does not exist"),
+        Some(Value::Array(items)) => {
+            let i = index(last, items.len()).ok_or("array index is out of range")?;
+            Ok(items.remove(i))
+        }
+        _ => Err("the path does not exist"),
+    }
+}
+
+fn apply_operation(doc: &mut Value, operation: &Value) -> Result<(), String> {
+    let field = |name: &str| operation.get(name).ok_or(format!("missing {:?}", name));
+    let pointer = |name: &str| -> Result<Vec<String>, String> {
+        tokens(field(name)?.as_str().ok_or(format!("{:?} must be a string", name))?)
+    };
+    let path = pointer("path")?;
+    match field("op")?.as_str() {
+        Some("add") => add(doc, &path, field("value")?.clone())?,
+        Some("remove") => {
+            remove(doc, &path)?;
+        }
+        Some("replace") => *get_mut(doc, &path).ok_or("the path does not exist")? = field("value")?.clone(),
+        Some("move") => {
+            let from = pointer("from")?;
+            if path.len() > from.len() && path.starts_with(&from) {
+                return Err("a value can't be moved into itself".to_owned());
+            }
+            let value = remove(doc, &from)?;
+            add(doc, &path, value)?;
+        }
+        Some("copy") => {
+            let value = get(doc, &pointer("from")?).ok_or("the from path does not exist")?.clone();
+            add(doc, &path, value)?;
+        }
+        Some("test") => {
+            if get(doc, &path) != Some(field("value")?) {
+                return Err("the value is not as expected".to_owned());
+            }
+        }
+        _ => return Err("op must be add, remove, replace, move, copy or test".to_owned()),
+    }
+    Ok(())
+}
+
+/// Apply a JSON Patch to a JSON document. Either every operation is applied
+/// or, if any fails, the document is left untouched
+pub fn apply(doc: &mut Value, patch: &Value) -> Result<(), String> {
+    let operations = patch.as_array().ok_or("A JSON Patch must be an array of operations")?;
+    let mut patched = doc.clone();
+    for (i, operation) in operations.iter().enumerate() {
+        apply_operation(&mut patched, operation).map_err(|err| {
+            let path = operation.get("path").and_then(Value::as_str).unwrap_or_default();
+            format!("Operation {} ({:?}) failed: {}", i, path, err)
+        })?;
+    }
+    *doc = patched;
+    Ok(())
+}
+
+impl SORFile {
+    /// Apply a JSON Patch to the file, as serialised to JSON. If any
+    /// operation fails, or the result is no longer a valid SORFile, the file
+    /// is left untouched
+    pub fn apply_json_patch(&mut self, patch: &Value) -> Result<(), String> {
+        let mut doc = serde_json::to_value(&*self).map_err(|err| err.to_string())?;
+        apply(&mut doc, patch)?;
+        *self = serde_json::from_value(doc).map_err(|err| format!("The patched file is not valid: {}", err))?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_apply() {
+    use serde_json::json;
+    // The examples from RFC 6902's appendix
+    let mut doc = json!({"foo": ["bar", "baz"], "a/b": 1, "m~n": 2});
+    apply(&mut doc, &json!([
+        {"op": "add", "path": "/foo/1", "value": "qux"},
+        {"op": "test", "path": "/a~1b", "value": 1},
+        {"op": "move", "from": "/m~0n", "path": "/foo/-"},
+        {"op": "copy", "from": "/foo/0", "path": "/copied"},
+        {"op": "replace", "path": "/a~1b", "value": [1]},
+        {"op": "remove", "path": "/foo/2"},
+    ])).unwrap();
+    assert_eq!(doc, json!({"foo": ["bar", "qux", 2], "a/b": [1], "copied": "bar"}));
+
+    let before = doc.clone();
+    let err = apply(&mut doc, &json!([
+        {"op": "remove", "path": "/copied"},
+        {"op": "test", "path": "/foo/0", "value": "baz"},
+    ])).unwrap_err();
+    assert_eq!(err, "Operation 1 (\"/foo/0\") failed: the value is not as expected");
+    assert_eq!(doc, before);
+    assert!(apply(&mut doc, &json!([{"op": "replace", "path": "/missing", "value": 1}])).is_err());
+    assert!(apply(&mut doc, &json!([{"op": "add", "path": "/foo/01", "value": 1}])).is_err());
+    assert!(apply(&mut doc, &json!([{"op": "move", "from": "/foo", "path": "/foo/0"}])).is_err());
+    assert!(apply(&mut doc, &json!([{"op": "frobnicate", "path": "/foo"}])).is_err());
+}
+
+#[test]
+fn test_apply_json_patch() {
+    use serde_json::json;
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let mut sor = crate::parser::parse_file(data).unwrap().1;
+    sor.apply_json_patch(&json!([
+        {"op": "test", "path": "/general_parameters/fiber_id", "value": "Fiber1"},
+        {"op": "replace", "path": "/general_parameters/cable_id", "value": "C042"},
+        {"op": "replace", "path": "/key_events/key_events/1/comment", "value": "Splice tray 3"},
+    ])).unwrap();
+    let written = crate::parser::parse_file(&sor.to_bytes().unwrap()).unwrap().1;
+    assert_eq!(&*written.general_parameters.unwrap().cable_id, "C042");
+    assert_eq!(&*written.key_events.unwrap().key_events[1].comment, "Splice tray 3");
+
+    // Patches which would make the file invalid are refused
+    let before = sor.clone();
+    let err = sor.apply_json_patch(&json!([{"op": "replace", "path": "/general_parameters/nominal_wavelength", "value": "1310nm"}])).unwrap_err();
+    assert!(err.starts_with("The patched file is not valid"), "{}", err);
+    assert_eq!(sor, before);
+}
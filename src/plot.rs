@@ -0,0 +1,106 @@
+/// This module renders the backscatter trace of a SOR file as a chart, with
+/// key events marked, using plotters. The trace is normalized to 0 dB at the
+/// start of the fibre and clipped at the noise floor. It is only available with the `plot`
+/// feature.
+use plotters::coord::Shift;
+use plotters::prelude::*;
+use crate::analysis::Trace;
+use crate::types::SORFile;
+
+/// Render the trace as an SVG document
+pub fn render_svg(sor: &SORFile, width: u32, height: u32) -> Result<String, Box<dyn std::error::Error>> {
+    let mut svg = String::new();
+    {
+        let root = SVGBackend::with_string(&mut svg, (width, height)).into_drawing_area();
+        draw(sor, &root)?;
+        root.present()?;
+    }
+    Ok(svg)
+}
+
+/// Render the trace as a PNG image
+pub fn render_png(sor: &SORFile, width: u32, height: u32) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut rgb = vec![0u8; (width * height * 3) as usize];
+    {
+        let root = BitMapBackend::with_buffer(&mut rgb, (width, height)).into_drawing_area();
+        draw(sor, &root)?;
+        root.present()?;
+    }
+    let mut png = Vec::new();
+    image::ImageEncoder::write_image(image::codecs::png::PngEncoder::new(&mut png), &rgb, width, height, image::ColorType::Rgb8)?;
+    Ok(png)
+}
+
+/// Compute (distance in km, power in dB) for each point in the trace
+fn trace_points(trace: &Trace) -> Vec<(f64, f64)> {
+    trace.distance_m().iter().zip(trace.points_db()).map(|(&m, &db)| (m / 1000.0, db)).collect()
+}
+
+/// Draw the trace, with a vertical marker and label for each key event
+fn draw<DB: DrawingBackend>(sor: &SORFile, root: &DrawingArea<DB, Shift>) -> Result<(), Box<dyn std::error::Error>>
+where
+    DB::ErrorType: 'static,
+{
+    // There's no use in drawing more than a couple of points per pixel
+    let trace = Trace::new(sor)?.normalized(true)?.decimate(2 * root.dim_in_pixel().0 as usize)?;
+    let points = trace_points(&trace);
+    let (x_min, x_max) = (points[0].0, points[points.len() - 1].0);
+    let y_min = points.iter().map(|p| p.1).fold(f64::INFINITY, f64::min);
+    let y_max = points.iter().map(|p| p.1).fold(f64::NEG_INFINITY, f64::max);
+    let y_pad = ((y_max - y_min) * 0.05).max(0.5);
+
+    let mut caption = String::new();
+    if let Some(gp) = &sor.general_parameters {
+        caption = format!("{} {} - {} nm", gp.cable_id.trim(), gp.fiber_id.trim(), gp.nominal_wavelength);
+    }
+    root.fill(&WHITE)?;
+    let mut chart = ChartBuilder::on(root)
+        .caption(caption, ("sans-serif", 20))
+        .margin(10)
+        .x_label_area_size(40)
+        .y_label_area_size(50)
+        .build_cartesian_2d(x_min..x_max, (y_min - y_pad)..(y_max + y_pad))?;
+    chart.configure_mesh()
+        .x_desc("Distance (km)")
+        .y_desc("Relative power (dB)")
+        .draw()?;
+    chart.draw_series(LineSeries::new(points, &BLUE))?;
+
+    if let Some(ke) = &sor.key_events {
+        let events = ke.key_events.iter()
+            .map(|e| (e.event_number, e.event_propogation_time, e.event_loss))
+            .chain(std::iter::once((ke.last_key_event.event_number, ke.last_key_event.event_propogation_time, ke.last_key_event.event_loss)));
+        for (n, (number, time, loss)) in events.enumerate() {
+            let x = trace.event_distance_m(time) / 1000.0;
+            chart.draw_series(LineSeries::new(vec![(x, y_min - y_pad), (x, y_max + y_pad)], &RED))?;
+            // Stagger labels so that closely spaced events stay legible
+            let y = y_max + y_pad - (n % 3) as f64 * y_pad;
+            chart.draw_series(std::iter::once(Text::new(
+                format!("{} ({:.3} dB)", number, loss as f64 / 1000.0),
+                (x, y),
+                ("sans-serif", 12).into_font().color(&RED),
+            )))?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_render_svg() {
+    let data = include_bytes!("../data/example1-noyes-ofl280.sor");
+    let sor = crate::parser::parse_file(data).unwrap().1;
+    let svg = render_svg(&sor, 800, 600).unwrap();
+    assert!(svg.starts_with("<svg"));
+    assert!(svg.contains("Distance (km)"));
+}
+
+#[test]
+fn test_trace_points() {
+    let data = include_bytes!("../data/example1-noyes-ofl280.sor");
+    let sor = crate::parser::parse_file(data).unwrap().1;
+    let points = trace_points(&Trace::new(&sor).unwrap());
+    assert_eq!(points.len(), 30000);
+    assert_eq!(points[0].1, -22.153);
+    // The first data point is 214.7ns behind the front panel
+    assert!((points[0].0 + 0.04386).abs() < 0.0001);
+}
@@ -0,0 +1,62 @@
+/// This module converts a SORFile's key events and trace to polars
+/// DataFrames, for analytics services built on polars. It is only available
+/// with the `polars` feature.
+///
+/// The columns are those of the events and samples record batches of
+/// `otdrs::arrow`: distances in metres from the user offset, and levels and
+/// losses in dB.
+use polars::prelude::{Column, DataFrame, PolarsResult};
+use crate::analysis::{events, Trace};
+use crate::types::SORFile;
+
+impl SORFile {
+    /// One row per key event, with its number, distance, loss, reflectance,
+    /// code and comment
+    pub fn events_df(&self) -> PolarsResult<DataFrame> {
+        let events = events(self);
+        let comments: Vec<&str> = self.key_events.iter()
+            .flat_map(|ke| ke.key_events.iter().map(|e| e.comment.trim()).chain(std::iter::once(ke.last_key_event.comment.trim())))
+            .collect();
+        DataFrame::new(vec![
+            Column::new("event_number".into(), events.iter().map(|e| e.number).collect::<Vec<i16>>()),
+            Column::new("distance_m".into(), events.iter().map(|e| e.distance_m).collect::<Vec<f64>>()),
+            Column::new("loss_db".into(), events.iter().map(|e| e.loss_db).collect::<Vec<f64>>()),
+            Column::new("reflectance_db".into(), events.iter().map(|e| e.reflectance_db).collect::<Vec<f64>>()),
+            Column::new("code".into(), events.iter().map(|e| e.code.trim()).collect::<Vec<&str>>()),
+            Column::new("comment".into(), comments),
+        ])
+    }
+
+    /// One row per data point, with its index, distance and level; empty if
+    /// the file has no data points
+    pub fn trace_df(&self) -> PolarsResult<DataFrame> {
+        let (distance_m, level_db): (Vec<f64>, Vec<f64>) = match Trace::new(self) {
+            Ok(trace) => (trace.distance_m().iter().map(|d| d - trace.user_offset_m()).collect(), trace.points_db().to_vec()),
+            Err(_) => (Vec::new(), Vec::new()),
+        };
+        DataFrame::new(vec![
+            Column::new("index".into(), (0..distance_m.len() as i32).collect::<Vec<i32>>()),
+            Column::new("distance_m".into(), distance_m),
+            Column::new("level_db".into(), level_db),
+        ])
+    }
+}
+
+#[test]
+fn test_dataframes() {
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let sor = crate::parser::parse_file(data).unwrap().1;
+    let df = sor.events_df().unwrap();
+    assert_eq!(df.height(), events(&sor).len());
+    assert_eq!(df.column("loss_db").unwrap().f64().unwrap().get(1), Some(-0.336));
+    assert_eq!(df.get_column_names(), ["event_number", "distance_m", "loss_db", "reflectance_db", "code", "comment"]);
+    let df = sor.trace_df().unwrap();
+    assert_eq!(df.height(), Trace::new(&sor).unwrap().points_db().len());
+    assert_eq!(df.column("distance_m").unwrap().f64().unwrap().get(0), Some(-Trace::new(&sor).unwrap().user_offset_m()));
+
+    // Without data points or key events, the frames are empty
+    let mut metadata = crate::parser::parse_metadata(include_bytes!("../data/example1-noyes-ofl280.sor")).unwrap().1;
+    assert_eq!(metadata.trace_df().unwrap().shape(), (0, 3));
+    metadata.key_events = None;
+    assert_eq!(metadata.events_df().unwrap().shape(), (0, 6));
+}
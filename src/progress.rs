@@ -0,0 +1,103 @@
+/// This module lets applications embedding otdrs follow and abort long
+/// operations over many files, such as batch conversion, indexing and report
+/// generation, e.g. to show a progress bar with a cancel button.
+///
+/// Operations take a `Hooks`, which carries an optional progress callback
+/// and a `CancellationToken`. Cancelling the token from another thread stops
+/// the operation before its next file, and the operation returns `Cancelled`
+/// (or says so in its result) rather than a partial result.
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A flag for cancelling an operation from elsewhere, e.g. a GUI's cancel
+/// button. Clones share the flag
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask the operations holding this token to stop
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Progress reporting and cancellation for an operation. The default does
+/// neither
+#[derive(Default)]
+pub struct Hooks<'a> {
+    progress: Option<Box<dyn Fn(usize, usize) + Send + Sync + 'a>>,
+    token: CancellationToken,
+}
+
+impl<'a> Hooks<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Call a function with the number of files done and the total as each
+    /// file is finished. It may be called from several threads at once
+    pub fn with_progress(mut self, progress: impl Fn(usize, usize) + Send + Sync + 'a) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Stop when a token is cancelled
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.token = token;
+        self
+    }
+
+    pub(crate) fn progress(&self, done: usize, total: usize) {
+        if let Some(progress) = &self.progress {
+            progress(done, total);
+        }
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+}
+
+/// The error from an operation which was cancelled
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The operation was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+#[test]
+fn test_hooks() {
+    use std::sync::atomic::AtomicUsize;
+    let token = CancellationToken::new();
+    let calls = AtomicUsize::new(0);
+    let hooks = Hooks::new()
+        .with_progress(|done, total| {
+            assert_eq!(total, 10);
+            calls.fetch_add(done, Ordering::Relaxed);
+        })
+        .with_cancellation(token.clone());
+    hooks.progress(1, 10);
+    hooks.progress(2, 10);
+    assert!(!hooks.is_cancelled());
+    token.cancel();
+    assert!(hooks.is_cancelled());
+    drop(hooks);
+    assert_eq!(calls.into_inner(), 3);
+    // The default does nothing
+    Hooks::default().progress(1, 1);
+    assert_eq!(Cancelled.to_string(), "The operation was cancelled");
+}
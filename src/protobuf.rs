@@ -0,0 +1,805 @@
+/// This module represents a SORFile as Protocol Buffers messages, for
+/// streaming results through gRPC or Kafka pipelines. It is only available
+/// with the `protobuf` feature.
+///
+/// The messages are those of `proto/otdrs.proto`, package `otdrs.v1`, so
+/// other languages can generate their own types from it; they're written out
+/// here, rather than generated at build time, so that building otdrs doesn't
+/// need protoc. Fields hold raw SR-4731 values as in `otdrs::types`, widened
+/// to 32 bits.
+use std::convert::{TryFrom, TryInto};
+use prost::Message;
+use crate::types as sor;
+
+/// A block listed in the map
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct BlockInfo {
+    #[prost(string, tag = "1")]
+    pub identifier: String,
+    #[prost(uint32, tag = "2")]
+    pub revision_number: u32,
+    #[prost(sint32, tag = "3")]
+    pub size: i32,
+}
+
+/// The map of the blocks in the file
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MapBlock {
+    #[prost(uint32, tag = "1")]
+    pub revision_number: u32,
+    #[prost(sint32, tag = "2")]
+    pub block_size: i32,
+    #[prost(sint32, tag = "3")]
+    pub block_count: i32,
+    #[prost(message, repeated, tag = "4")]
+    pub block_info: Vec<BlockInfo>,
+}
+
+/// The GenParams block
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GeneralParametersBlock {
+    #[prost(string, tag = "1")]
+    pub language_code: String,
+    #[prost(string, tag = "2")]
+    pub cable_id: String,
+    #[prost(string, tag = "3")]
+    pub fiber_id: String,
+    #[prost(sint32, tag = "4")]
+    pub fiber_type: i32,
+    #[prost(sint32, tag = "5")]
+    pub nominal_wavelength: i32,
+    #[prost(string, tag = "6")]
+    pub originating_location: String,
+    #[prost(string, tag = "7")]
+    pub terminating_location: String,
+    #[prost(string, tag = "8")]
+    pub cable_code: String,
+    #[prost(string, tag = "9")]
+    pub current_data_flag: String,
+    #[prost(sint32, tag = "10")]
+    pub user_offset: i32,
+    #[prost(sint32, tag = "11")]
+    pub user_offset_distance: i32,
+    #[prost(string, tag = "12")]
+    pub operator: String,
+    #[prost(string, tag = "13")]
+    pub comment: String,
+}
+
+/// The SupParams block
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SupplierParametersBlock {
+    #[prost(string, tag = "1")]
+    pub supplier_name: String,
+    #[prost(string, tag = "2")]
+    pub otdr_mainframe_id: String,
+    #[prost(string, tag = "3")]
+    pub otdr_mainframe_sn: String,
+    #[prost(string, tag = "4")]
+    pub optical_module_id: String,
+    #[prost(string, tag = "5")]
+    pub optical_module_sn: String,
+    #[prost(string, tag = "6")]
+    pub software_revision: String,
+    #[prost(string, tag = "7")]
+    pub other: String,
+}
+
+/// The FxdParams block
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct FixedParametersBlock {
+    #[prost(uint32, tag = "1")]
+    pub date_time_stamp: u32,
+    #[prost(string, tag = "2")]
+    pub units_of_distance: String,
+    #[prost(sint32, tag = "3")]
+    pub actual_wavelength: i32,
+    #[prost(sint32, tag = "4")]
+    pub acquisition_offset: i32,
+    #[prost(sint32, tag = "5")]
+    pub acquisition_offset_distance: i32,
+    #[prost(sint32, tag = "6")]
+    pub total_n_pulse_widths_used: i32,
+    #[prost(sint32, repeated, tag = "7")]
+    pub pulse_widths_used: Vec<i32>,
+    #[prost(sint32, repeated, tag = "8")]
+    pub data_spacing: Vec<i32>,
+    #[prost(sint32, repeated, tag = "9")]
+    pub n_data_points_for_pulse_widths_used: Vec<i32>,
+    #[prost(sint32, tag = "10")]
+    pub group_index: i32,
+    #[prost(sint32, tag = "11")]
+    pub backscatter_coefficient: i32,
+    #[prost(sint32, tag = "12")]
+    pub number_of_averages: i32,
+    #[prost(uint32, tag = "13")]
+    pub averaging_time: u32,
+    #[prost(sint32, tag = "14")]
+    pub acquisition_range: i32,
+    #[prost(sint32, tag = "15")]
+    pub acquisition_range_distance: i32,
+    #[prost(sint32, tag = "16")]
+    pub front_panel_offset: i32,
+    #[prost(uint32, tag = "17")]
+    pub noise_floor_level: u32,
+    #[prost(sint32, tag = "18")]
+    pub noise_floor_scale_factor: i32,
+    #[prost(uint32, tag = "19")]
+    pub power_offset_first_point: u32,
+    #[prost(uint32, tag = "20")]
+    pub loss_threshold: u32,
+    #[prost(uint32, tag = "21")]
+    pub reflectance_threshold: u32,
+    #[prost(uint32, tag = "22")]
+    pub end_of_fibre_threshold: u32,
+    #[prost(string, tag = "23")]
+    pub trace_type: String,
+    #[prost(sint32, tag = "24")]
+    pub window_coordinate_1: i32,
+    #[prost(sint32, tag = "25")]
+    pub window_coordinate_2: i32,
+    #[prost(sint32, tag = "26")]
+    pub window_coordinate_3: i32,
+    #[prost(sint32, tag = "27")]
+    pub window_coordinate_4: i32,
+}
+
+/// A key event
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct KeyEvent {
+    #[prost(sint32, tag = "1")]
+    pub event_number: i32,
+    #[prost(sint32, tag = "2")]
+    pub event_propogation_time: i32,
+    #[prost(sint32, tag = "3")]
+    pub attenuation_coefficient_lead_in_fiber: i32,
+    #[prost(sint32, tag = "4")]
+    pub event_loss: i32,
+    #[prost(sint32, tag = "5")]
+    pub event_reflectance: i32,
+    #[prost(string, tag = "6")]
+    pub event_code: String,
+    #[prost(string, tag = "7")]
+    pub loss_measurement_technique: String,
+    #[prost(sint32, tag = "8")]
+    pub marker_location_1: i32,
+    #[prost(sint32, tag = "9")]
+    pub marker_location_2: i32,
+    #[prost(sint32, tag = "10")]
+    pub marker_location_3: i32,
+    #[prost(sint32, tag = "11")]
+    pub marker_location_4: i32,
+    #[prost(sint32, tag = "12")]
+    pub marker_location_5: i32,
+    #[prost(string, tag = "13")]
+    pub comment: String,
+}
+
+/// The last key event, with the link's end-to-end loss and ORL
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct LastKeyEvent {
+    #[prost(sint32, tag = "1")]
+    pub event_number: i32,
+    #[prost(sint32, tag = "2")]
+    pub event_propogation_time: i32,
+    #[prost(sint32, tag = "3")]
+    pub attenuation_coefficient_lead_in_fiber: i32,
+    #[prost(sint32, tag = "4")]
+    pub event_loss: i32,
+    #[prost(sint32, tag = "5")]
+    pub event_reflectance: i32,
+    #[prost(string, tag = "6")]
+    pub event_code: String,
+    #[prost(string, tag = "7")]
+    pub loss_measurement_technique: String,
+    #[prost(sint32, tag = "8")]
+    pub marker_location_1: i32,
+    #[prost(sint32, tag = "9")]
+    pub marker_location_2: i32,
+    #[prost(sint32, tag = "10")]
+    pub marker_location_3: i32,
+    #[prost(sint32, tag = "11")]
+    pub marker_location_4: i32,
+    #[prost(sint32, tag = "12")]
+    pub marker_location_5: i32,
+    #[prost(string, tag = "13")]
+    pub comment: String,
+    #[prost(sint32, tag = "14")]
+    pub end_to_end_loss: i32,
+    #[prost(sint32, tag = "15")]
+    pub end_to_end_marker_position_1: i32,
+    #[prost(sint32, tag = "16")]
+    pub end_to_end_marker_position_2: i32,
+    #[prost(uint32, tag = "17")]
+    pub optical_return_loss: u32,
+    #[prost(sint32, tag = "18")]
+    pub optical_return_loss_marker_position_1: i32,
+    #[prost(sint32, tag = "19")]
+    pub optical_return_loss_marker_position_2: i32,
+}
+
+/// The KeyEvents block
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct KeyEvents {
+    #[prost(sint32, tag = "1")]
+    pub number_of_key_events: i32,
+    #[prost(message, repeated, tag = "2")]
+    pub key_events: Vec<KeyEvent>,
+    #[prost(message, optional, tag = "3")]
+    pub last_key_event: Option<LastKeyEvent>,
+}
+
+/// A landmark on the route
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Landmark {
+    #[prost(sint32, tag = "1")]
+    pub landmark_number: i32,
+    #[prost(string, tag = "2")]
+    pub landmark_code: String,
+    #[prost(sint32, tag = "3")]
+    pub landmark_location: i32,
+    #[prost(sint32, tag = "4")]
+    pub related_event_number: i32,
+    #[prost(sint32, tag = "5")]
+    pub gps_longitude: i32,
+    #[prost(sint32, tag = "6")]
+    pub gps_latitude: i32,
+    #[prost(sint32, tag = "7")]
+    pub fiber_correction_factor_lead_in_fiber: i32,
+    #[prost(sint32, tag = "8")]
+    pub sheath_marker_entering_landmark: i32,
+    #[prost(sint32, tag = "9")]
+    pub sheath_marker_leaving_landmark: i32,
+    #[prost(string, tag = "10")]
+    pub units_of_sheath_marks_leaving_landmark: String,
+    #[prost(sint32, tag = "11")]
+    pub mode_field_diameter_leaving_landmark: i32,
+    #[prost(string, tag = "12")]
+    pub comment: String,
+}
+
+/// The LnkParams block
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct LinkParameters {
+    #[prost(sint32, tag = "1")]
+    pub number_of_landmarks: i32,
+    #[prost(message, repeated, tag = "2")]
+    pub landmarks: Vec<Landmark>,
+}
+
+/// Data points at one scale factor
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DataPointsAtScaleFactor {
+    #[prost(sint32, tag = "1")]
+    pub n_points: i32,
+    #[prost(sint32, tag = "2")]
+    pub scale_factor: i32,
+    #[prost(uint32, repeated, tag = "3")]
+    pub data: Vec<u32>,
+}
+
+/// The DataPts block
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DataPoints {
+    #[prost(sint32, tag = "1")]
+    pub number_of_data_points: i32,
+    #[prost(sint32, tag = "2")]
+    pub total_number_scale_factors_used: i32,
+    #[prost(message, repeated, tag = "3")]
+    pub scale_factors: Vec<DataPointsAtScaleFactor>,
+}
+
+/// A vendor's proprietary block, with its payload as it appears in the file
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ProprietaryBlock {
+    #[prost(string, tag = "1")]
+    pub header: String,
+    #[prost(bytes = "vec", tag = "2")]
+    pub data: Vec<u8>,
+}
+
+/// A whole SOR file; blocks missing from the file are unset
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SorFile {
+    #[prost(message, optional, tag = "1")]
+    pub map: Option<MapBlock>,
+    #[prost(message, optional, tag = "2")]
+    pub general_parameters: Option<GeneralParametersBlock>,
+    #[prost(message, optional, tag = "3")]
+    pub supplier_parameters: Option<SupplierParametersBlock>,
+    #[prost(message, optional, tag = "4")]
+    pub fixed_parameters: Option<FixedParametersBlock>,
+    #[prost(message, optional, tag = "5")]
+    pub key_events: Option<KeyEvents>,
+    #[prost(message, optional, tag = "6")]
+    pub link_parameters: Option<LinkParameters>,
+    #[prost(message, optional, tag = "7")]
+    pub data_points: Option<DataPoints>,
+    #[prost(message, repeated, tag = "8")]
+    pub proprietary_blocks: Vec<ProprietaryBlock>,
+}
+
+impl From<&sor::BlockInfo> for BlockInfo {
+    fn from(x: &sor::BlockInfo) -> BlockInfo {
+        BlockInfo {
+            identifier: x.identifier.to_string(),
+            revision_number: x.revision_number.into(),
+            size: x.size,
+        }
+    }
+}
+
+impl From<&sor::MapBlock> for MapBlock {
+    fn from(x: &sor::MapBlock) -> MapBlock {
+        MapBlock {
+            revision_number: x.revision_number.into(),
+            block_size: x.block_size,
+            block_count: x.block_count.into(),
+            block_info: x.block_info.iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<&sor::GeneralParametersBlock> for GeneralParametersBlock {
+    fn from(x: &sor::GeneralParametersBlock) -> GeneralParametersBlock {
+        GeneralParametersBlock {
+            language_code: x.language_code.to_string(),
+            cable_id: x.cable_id.to_string(),
+            fiber_id: x.fiber_id.to_string(),
+            fiber_type: x.fiber_type.into(),
+            nominal_wavelength: x.nominal_wavelength.into(),
+            originating_location: x.originating_location.to_string(),
+            terminating_location: x.terminating_location.to_string(),
+            cable_code: x.cable_code.to_string(),
+            current_data_flag: x.current_data_flag.to_string(),
+            user_offset: x.user_offset,
+            user_offset_distance: x.user_offset_distance,
+            operator: x.operator.to_string(),
+            comment: x.comment.to_string(),
+        }
+    }
+}
+
+impl From<&sor::SupplierParametersBlock> for SupplierParametersBlock {
+    fn from(x: &sor::SupplierParametersBlock) -> SupplierParametersBlock {
+        SupplierParametersBlock {
+            supplier_name: x.supplier_name.to_string(),
+            otdr_mainframe_id: x.otdr_mainframe_id.to_string(),
+            otdr_mainframe_sn: x.otdr_mainframe_sn.to_string(),
+            optical_module_id: x.optical_module_id.to_string(),
+            optical_module_sn: x.optical_module_sn.to_string(),
+            software_revision: x.software_revision.to_string(),
+            other: x.other.to_string(),
+        }
+    }
+}
+
+impl From<&sor::FixedParametersBlock> for FixedParametersBlock {
+    fn from(x: &sor::FixedParametersBlock) -> FixedParametersBlock {
+        FixedParametersBlock {
+            date_time_stamp: x.date_time_stamp,
+            units_of_distance: x.units_of_distance.to_string(),
+            actual_wavelength: x.actual_wavelength.into(),
+            acquisition_offset: x.acquisition_offset,
+            acquisition_offset_distance: x.acquisition_offset_distance,
+            total_n_pulse_widths_used: x.total_n_pulse_widths_used.into(),
+            pulse_widths_used: x.pulse_widths_used.iter().map(|&v| v.into()).collect(),
+            data_spacing: x.data_spacing.clone(),
+            n_data_points_for_pulse_widths_used: x.n_data_points_for_pulse_widths_used.clone(),
+            group_index: x.group_index,
+            backscatter_coefficient: x.backscatter_coefficient.into(),
+            number_of_averages: x.number_of_averages,
+            averaging_time: x.averaging_time.into(),
+            acquisition_range: x.acquisition_range,
+            acquisition_range_distance: x.acquisition_range_distance,
+            front_panel_offset: x.front_panel_offset,
+            noise_floor_level: x.noise_floor_level.into(),
+            noise_floor_scale_factor: x.noise_floor_scale_factor.into(),
+            power_offset_first_point: x.power_offset_first_point.into(),
+            loss_threshold: x.loss_threshold.into(),
+            reflectance_threshold: x.reflectance_threshold.into(),
+            end_of_fibre_threshold: x.end_of_fibre_threshold.into(),
+            trace_type: x.trace_type.to_string(),
+            window_coordinate_1: x.window_coordinate_1,
+            window_coordinate_2: x.window_coordinate_2,
+            window_coordinate_3: x.window_coordinate_3,
+            window_coordinate_4: x.window_coordinate_4,
+        }
+    }
+}
+
+impl From<&sor::KeyEvent> for KeyEvent {
+    fn from(x: &sor::KeyEvent) -> KeyEvent {
+        KeyEvent {
+            event_number: x.event_number.into(),
+            event_propogation_time: x.event_propogation_time,
+            attenuation_coefficient_lead_in_fiber: x.attenuation_coefficient_lead_in_fiber.into(),
+            event_loss: x.event_loss.into(),
+            event_reflectance: x.event_reflectance,
+            event_code: x.event_code.to_string(),
+            loss_measurement_technique: x.loss_measurement_technique.to_string(),
+            marker_location_1: x.marker_location_1,
+            marker_location_2: x.marker_location_2,
+            marker_location_3: x.marker_location_3,
+            marker_location_4: x.marker_location_4,
+            marker_location_5: x.marker_location_5,
+            comment: x.comment.to_string(),
+        }
+    }
+}
+
+impl From<&sor::LastKeyEvent> for LastKeyEvent {
+    fn from(x: &sor::LastKeyEvent) -> LastKeyEvent {
+        LastKeyEvent {
+            event_number: x.event_number.into(),
+            event_propogation_time: x.event_propogation_time,
+            attenuation_coefficient_lead_in_fiber: x.attenuation_coefficient_lead_in_fiber.into(),
+            event_loss: x.event_loss.into(),
+            event_reflectance: x.event_reflectance,
+            event_code: x.event_code.to_string(),
+            loss_measurement_technique: x.loss_measurement_technique.to_string(),
+            marker_location_1: x.marker_location_1,
+            marker_location_2: x.marker_location_2,
+            marker_location_3: x.marker_location_3,
+            marker_location_4: x.marker_location_4,
+            marker_location_5: x.marker_location_5,
+            comment: x.comment.to_string(),
+            end_to_end_loss: x.end_to_end_loss,
+            end_to_end_marker_position_1: x.end_to_end_marker_position_1,
+            end_to_end_marker_position_2: x.end_to_end_marker_position_2,
+            optical_return_loss: x.optical_return_loss.into(),
+            optical_return_loss_marker_position_1: x.optical_return_loss_marker_position_1,
+            optical_return_loss_marker_position_2: x.optical_return_loss_marker_position_2,
+        }
+    }
+}
+
+impl From<&sor::KeyEvents> for KeyEvents {
+    fn from(x: &sor::KeyEvents) -> KeyEvents {
+        KeyEvents {
+            number_of_key_events: x.number_of_key_events.into(),
+            key_events: x.key_events.iter().map(Into::into).collect(),
+            last_key_event: Some((&x.last_key_event).into()),
+        }
+    }
+}
+
+impl From<&sor::Landmark> for Landmark {
+    fn from(x: &sor::Landmark) -> Landmark {
+        Landmark {
+            landmark_number: x.landmark_number.into(),
+            landmark_code: x.landmark_code.to_string(),
+            landmark_location: x.landmark_location,
+            related_event_number: x.related_event_number.into(),
+            gps_longitude: x.gps_longitude,
+            gps_latitude: x.gps_latitude,
+            fiber_correction_factor_lead_in_fiber: x.fiber_correction_factor_lead_in_fiber.into(),
+            sheath_marker_entering_landmark: x.sheath_marker_entering_landmark,
+            sheath_marker_leaving_landmark: x.sheath_marker_leaving_landmark,
+            units_of_sheath_marks_leaving_landmark: x.units_of_sheath_marks_leaving_landmark.to_string(),
+            mode_field_diameter_leaving_landmark: x.mode_field_diameter_leaving_landmark.into(),
+            comment: x.comment.to_string(),
+        }
+    }
+}
+
+impl From<&sor::LinkParameters> for LinkParameters {
+    fn from(x: &sor::LinkParameters) -> LinkParameters {
+        LinkParameters {
+            number_of_landmarks: x.number_of_landmarks.into(),
+            landmarks: x.landmarks.iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<&sor::DataPointsAtScaleFactor> for DataPointsAtScaleFactor {
+    fn from(x: &sor::DataPointsAtScaleFactor) -> DataPointsAtScaleFactor {
+        DataPointsAtScaleFactor {
+            n_points: x.n_points,
+            scale_factor: x.scale_factor.into(),
+            data: x.data.iter().map(|&v| v.into()).collect(),
+        }
+    }
+}
+
+impl From<&sor::DataPoints> for DataPoints {
+    fn from(x: &sor::DataPoints) -> DataPoints {
+        DataPoints {
+            number_of_data_points: x.number_of_data_points,
+            total_number_scale_factors_used: x.total_number_scale_factors_used.into(),
+            scale_factors: x.scale_factors.iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<&sor::ProprietaryBlock> for ProprietaryBlock {
+    fn from(x: &sor::ProprietaryBlock) -> ProprietaryBlock {
+        ProprietaryBlock {
+            header: x.header.to_string(),
+            data: x.data.to_vec(),
+        }
+    }
+}
+
+impl From<&sor::SORFile> for SorFile {
+    fn from(x: &sor::SORFile) -> SorFile {
+        SorFile {
+            map: Some((&x.map).into()),
+            general_parameters: x.general_parameters.as_ref().map(Into::into),
+            supplier_parameters: x.supplier_parameters.as_ref().map(Into::into),
+            fixed_parameters: x.fixed_parameters.as_ref().map(Into::into),
+            key_events: x.key_events.as_ref().map(Into::into),
+            link_parameters: x.link_parameters.as_ref().map(Into::into),
+            data_points: x.data_points.as_ref().map(Into::into),
+            proprietary_blocks: x.proprietary_blocks.iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl TryFrom<BlockInfo> for sor::BlockInfo {
+    type Error = &'static str;
+    fn try_from(x: BlockInfo) -> Result<sor::BlockInfo, &'static str> {
+        Ok(sor::BlockInfo {
+            identifier: x.identifier.into(),
+            revision_number: narrow(x.revision_number)?,
+            size: x.size,
+        })
+    }
+}
+
+impl TryFrom<MapBlock> for sor::MapBlock {
+    type Error = &'static str;
+    fn try_from(x: MapBlock) -> Result<sor::MapBlock, &'static str> {
+        Ok(sor::MapBlock {
+            revision_number: narrow(x.revision_number)?,
+            block_size: x.block_size,
+            block_count: narrow(x.block_count)?,
+            block_info: x.block_info.into_iter().map(TryFrom::try_from).collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl TryFrom<GeneralParametersBlock> for sor::GeneralParametersBlock {
+    type Error = &'static str;
+    fn try_from(x: GeneralParametersBlock) -> Result<sor::GeneralParametersBlock, &'static str> {
+        Ok(sor::GeneralParametersBlock {
+            language_code: x.language_code.into(),
+            cable_id: x.cable_id.into(),
+            fiber_id: x.fiber_id.into(),
+            fiber_type: narrow(x.fiber_type)?,
+            nominal_wavelength: narrow(x.nominal_wavelength)?,
+            originating_location: x.originating_location.into(),
+            terminating_location: x.terminating_location.into(),
+            cable_code: x.cable_code.into(),
+            current_data_flag: x.current_data_flag.into(),
+            user_offset: x.user_offset,
+            user_offset_distance: x.user_offset_distance,
+            operator: x.operator.into(),
+            comment: x.comment.into(),
+        })
+    }
+}
+
+impl TryFrom<SupplierParametersBlock> for sor::SupplierParametersBlock {
+    type Error = &'static str;
+    fn try_from(x: SupplierParametersBlock) -> Result<sor::SupplierParametersBlock, &'static str> {
+        Ok(sor::SupplierParametersBlock {
+            supplier_name: x.supplier_name.into(),
+            otdr_mainframe_id: x.otdr_mainframe_id.into(),
+            otdr_mainframe_sn: x.otdr_mainframe_sn.into(),
+            optical_module_id: x.optical_module_id.into(),
+            optical_module_sn: x.optical_module_sn.into(),
+            software_revision: x.software_revision.into(),
+            other: x.other.into(),
+        })
+    }
+}
+
+impl TryFrom<FixedParametersBlock> for sor::FixedParametersBlock {
+    type Error = &'static str;
+    fn try_from(x: FixedParametersBlock) -> Result<sor::FixedParametersBlock, &'static str> {
+        Ok(sor::FixedParametersBlock {
+            date_time_stamp: x.date_time_stamp,
+            units_of_distance: x.units_of_distance.into(),
+            actual_wavelength: narrow(x.actual_wavelength)?,
+            acquisition_offset: x.acquisition_offset,
+            acquisition_offset_distance: x.acquisition_offset_distance,
+            total_n_pulse_widths_used: narrow(x.total_n_pulse_widths_used)?,
+            pulse_widths_used: x.pulse_widths_used.into_iter().map(narrow).collect::<Result<_, _>>()?,
+            data_spacing: x.data_spacing,
+            n_data_points_for_pulse_widths_used: x.n_data_points_for_pulse_widths_used,
+            group_index: x.group_index,
+            backscatter_coefficient: narrow(x.backscatter_coefficient)?,
+            number_of_averages: x.number_of_averages,
+            averaging_time: narrow(x.averaging_time)?,
+            acquisition_range: x.acquisition_range,
+            acquisition_range_distance: x.acquisition_range_distance,
+            front_panel_offset: x.front_panel_offset,
+            noise_floor_level: narrow(x.noise_floor_level)?,
+            noise_floor_scale_factor: narrow(x.noise_floor_scale_factor)?,
+            power_offset_first_point: narrow(x.power_offset_first_point)?,
+            loss_threshold: narrow(x.loss_threshold)?,
+            reflectance_threshold: narrow(x.reflectance_threshold)?,
+            end_of_fibre_threshold: narrow(x.end_of_fibre_threshold)?,
+            trace_type: x.trace_type.into(),
+            window_coordinate_1: x.window_coordinate_1,
+            window_coordinate_2: x.window_coordinate_2,
+            window_coordinate_3: x.window_coordinate_3,
+            window_coordinate_4: x.window_coordinate_4,
+        })
+    }
+}
+
+impl TryFrom<KeyEvent> for sor::KeyEvent {
+    type Error = &'static str;
+    fn try_from(x: KeyEvent) -> Result<sor::KeyEvent, &'static str> {
+        Ok(sor::KeyEvent {
+            event_number: narrow(x.event_number)?,
+            event_propogation_time: x.event_propogation_time,
+            attenuation_coefficient_lead_in_fiber: narrow(x.attenuation_coefficient_lead_in_fiber)?,
+            event_loss: narrow(x.event_loss)?,
+            event_reflectance: x.event_reflectance,
+            event_code: x.event_code.into(),
+            loss_measurement_technique: x.loss_measurement_technique.into(),
+            marker_location_1: x.marker_location_1,
+            marker_location_2: x.marker_location_2,
+            marker_location_3: x.marker_location_3,
+            marker_location_4: x.marker_location_4,
+            marker_location_5: x.marker_location_5,
+            comment: x.comment.into(),
+        })
+    }
+}
+
+impl TryFrom<LastKeyEvent> for sor::LastKeyEvent {
+    type Error = &'static str;
+    fn try_from(x: LastKeyEvent) -> Result<sor::LastKeyEvent, &'static str> {
+        Ok(sor::LastKeyEvent {
+            event_number: narrow(x.event_number)?,
+            event_propogation_time: x.event_propogation_time,
+            attenuation_coefficient_lead_in_fiber: narrow(x.attenuation_coefficient_lead_in_fiber)?,
+            event_loss: narrow(x.event_loss)?,
+            event_reflectance: x.event_reflectance,
+            event_code: x.event_code.into(),
+            loss_measurement_technique: x.loss_measurement_technique.into(),
+            marker_location_1: x.marker_location_1,
+            marker_location_2: x.marker_location_2,
+            marker_location_3: x.marker_location_3,
+            marker_location_4: x.marker_location_4,
+            marker_location_5: x.marker_location_5,
+            comment: x.comment.into(),
+            end_to_end_loss: x.end_to_end_loss,
+            end_to_end_marker_position_1: x.end_to_end_marker_position_1,
+            end_to_end_marker_position_2: x.end_to_end_marker_position_2,
+            optical_return_loss: narrow(x.optical_return_loss)?,
+            optical_return_loss_marker_position_1: x.optical_return_loss_marker_position_1,
+            optical_return_loss_marker_position_2: x.optical_return_loss_marker_position_2,
+        })
+    }
+}
+
+impl TryFrom<KeyEvents> for sor::KeyEvents {
+    type Error = &'static str;
+    fn try_from(x: KeyEvents) -> Result<sor::KeyEvents, &'static str> {
+        Ok(sor::KeyEvents {
+            number_of_key_events: narrow(x.number_of_key_events)?,
+            key_events: x.key_events.into_iter().map(TryFrom::try_from).collect::<Result<_, _>>()?,
+            last_key_event: x.last_key_event.ok_or("The last key event is missing")?.try_into()?,
+        })
+    }
+}
+
+impl TryFrom<Landmark> for sor::Landmark {
+    type Error = &'static str;
+    fn try_from(x: Landmark) -> Result<sor::Landmark, &'static str> {
+        Ok(sor::Landmark {
+            landmark_number: narrow(x.landmark_number)?,
+            landmark_code: x.landmark_code.into(),
+            landmark_location: x.landmark_location,
+            related_event_number: narrow(x.related_event_number)?,
+            gps_longitude: x.gps_longitude,
+            gps_latitude: x.gps_latitude,
+            fiber_correction_factor_lead_in_fiber: narrow(x.fiber_correction_factor_lead_in_fiber)?,
+            sheath_marker_entering_landmark: x.sheath_marker_entering_landmark,
+            sheath_marker_leaving_landmark: x.sheath_marker_leaving_landmark,
+            units_of_sheath_marks_leaving_landmark: x.units_of_sheath_marks_leaving_landmark.into(),
+            mode_field_diameter_leaving_landmark: narrow(x.mode_field_diameter_leaving_landmark)?,
+            comment: x.comment.into(),
+        })
+    }
+}
+
+impl TryFrom<LinkParameters> for sor::LinkParameters {
+    type Error = &'static str;
+    fn try_from(x: LinkParameters) -> Result<sor::LinkParameters, &'static str> {
+        Ok(sor::LinkParameters {
+            number_of_landmarks: narrow(x.number_of_landmarks)?,
+            landmarks: x.landmarks.into_iter().map(TryFrom::try_from).collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl TryFrom<DataPointsAtScaleFactor> for sor::DataPointsAtScaleFactor {
+    type Error = &'static str;
+    fn try_from(x: DataPointsAtScaleFactor) -> Result<sor::DataPointsAtScaleFactor, &'static str> {
+        Ok(sor::DataPointsAtScaleFactor {
+            n_points: x.n_points,
+            scale_factor: narrow(x.scale_factor)?,
+            data: x.data.into_iter().map(narrow).collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl TryFrom<DataPoints> for sor::DataPoints {
+    type Error = &'static str;
+    fn try_from(x: DataPoints) -> Result<sor::DataPoints, &'static str> {
+        Ok(sor::DataPoints {
+            number_of_data_points: x.number_of_data_points,
+            total_number_scale_factors_used: narrow(x.total_number_scale_factors_used)?,
+            scale_factors: x.scale_factors.into_iter().map(TryFrom::try_from).collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl TryFrom<ProprietaryBlock> for sor::ProprietaryBlock {
+    type Error = &'static str;
+    fn try_from(x: ProprietaryBlock) -> Result<sor::ProprietaryBlock, &'static str> {
+        Ok(sor::ProprietaryBlock {
+            header: x.header.into(),
+            data: x.data.into(),
+        })
+    }
+}
+
+impl TryFrom<SorFile> for sor::SORFile {
+    type Error = &'static str;
+    fn try_from(x: SorFile) -> Result<sor::SORFile, &'static str> {
+        Ok(sor::SORFile {
+            map: x.map.ok_or("The map is missing")?.try_into()?,
+            general_parameters: x.general_parameters.map(TryFrom::try_from).transpose()?,
+            supplier_parameters: x.supplier_parameters.map(TryFrom::try_from).transpose()?,
+            fixed_parameters: x.fixed_parameters.map(TryFrom::try_from).transpose()?,
+            key_events: x.key_events.map(TryFrom::try_from).transpose()?,
+            link_parameters: x.link_parameters.map(TryFrom::try_from).transpose()?,
+            data_points: x.data_points.map(TryFrom::try_from).transpose()?,
+            proprietary_blocks: x.proprietary_blocks.into_iter().map(TryFrom::try_from).collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// Narrow a value decoded from a message to the width of its field
+fn narrow<T: TryFrom<U>, U>(value: U) -> Result<T, &'static str> {
+    T::try_from(value).map_err(|_| "A value is out of range for its field")
+}
+
+impl sor::SORFile {
+    /// Encode the file as an `otdrs.v1.SORFile` message
+    pub fn to_protobuf(&self) -> Vec<u8> {
+        SorFile::from(self).encode_to_vec()
+    }
+
+    /// Decode a file from an `otdrs.v1.SORFile` message
+    pub fn from_protobuf(data: &[u8]) -> Result<sor::SORFile, &'static str> {
+        SorFile::decode(data).map_err(|_| "Could not decode the message")?.try_into()
+    }
+}
+
+#[test]
+fn test_protobuf_round_trip() {
+    for data in [
+        &include_bytes!("../data/example1-noyes-ofl280.sor")[..],
+        &include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor")[..],
+    ].iter() {
+        let sor = crate::parser::parse_file(data).unwrap().1;
+        let encoded = sor.to_protobuf();
+        assert_eq!(sor::SORFile::from_protobuf(&encoded).unwrap(), sor);
+    }
+    let sor = crate::parser::parse_metadata(include_bytes!("../data/example1-noyes-ofl280.sor")).unwrap().1;
+    let mut message = SorFile::from(&sor);
+    assert!(message.data_points.is_none());
+    message.general_parameters.as_mut().unwrap().nominal_wavelength = 40000;
+    assert_eq!(sor::SORFile::try_from(message.clone()), Err("A value is out of range for its field"));
+    message.map = None;
+    assert!(sor::SORFile::from_protobuf(&message.encode_to_vec()).is_err());
+    assert!(sor::SORFile::from_protobuf(b"not a message").is_err());
+}
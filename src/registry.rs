@@ -0,0 +1,133 @@
+/// This module keeps a registry of OTDRs - their supplier, model, software
+/// and calibration - so that files generated by converters and simulators
+/// carry the provenance of the instrument they stand in for.
+///
+/// Registries can be loaded from TOML, keyed by mainframe model, e.g.
+///
+/// ```toml
+/// [devices.MAX-730C]
+/// supplier_name = "EXFO"
+/// optical_module_id = "MAX-730C-SM1"
+/// software_revision = "6.20.0.2"
+///
+/// [devices.MAX-730C.instruments.881234]
+/// optical_module_sn = "881235"
+/// calibrated = "2024-03-01"
+/// calibration_due = "2025-03-01"
+/// ```
+///
+/// with an entry under `instruments` for each serial number whose module
+/// or calibration is known.
+use std::collections::BTreeMap;
+use serde::Deserialize;
+use crate::types::SupplierParametersBlock;
+
+/// OTDR models by mainframe model number
+#[derive(Deserialize, Debug, PartialEq, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeviceRegistry {
+    pub devices: BTreeMap<String, Device>,
+}
+
+/// The defaults for a model of OTDR
+#[derive(Deserialize, Debug, PartialEq, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Device {
+    pub supplier_name: String,
+    /// Optical module model number, if the model has a fixed module
+    pub optical_module_id: String,
+    pub software_revision: String,
+    /// Free text for the supplier parameters block's other field, ahead of
+    /// any calibration details
+    pub other: String,
+    /// Individual instruments, by mainframe serial number
+    pub instruments: BTreeMap<String, Instrument>,
+}
+
+/// What is known about one instrument
+#[derive(Deserialize, Debug, PartialEq, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Instrument {
+    /// Optical module model number, if it differs from the model's
+    pub optical_module_id: Option<String>,
+    pub optical_module_sn: String,
+    /// Software revision, if it differs from the model's
+    pub software_revision: Option<String>,
+    /// Date of the last calibration, e.g. 2024-03-01
+    pub calibrated: Option<String>,
+    /// Date the next calibration is due
+    pub calibration_due: Option<String>,
+    /// Calibration certificate number
+    pub certificate: Option<String>,
+}
+
+impl DeviceRegistry {
+    pub fn device(&self, model: &str) -> Option<&Device> {
+        self.devices.get(model)
+    }
+}
+
+impl SupplierParametersBlock {
+    /// Supplier parameters for the instrument of the given model and
+    /// mainframe serial number. Serial numbers not in the registry get the
+    /// model's defaults, with no module serial number or calibration; models
+    /// not in the registry are an error, as files claiming to come from an
+    /// unknown instrument are worse than useless for provenance
+    pub fn from_registry(registry: &DeviceRegistry, model: &str, serial: &str) -> Result<SupplierParametersBlock, String> {
+        let device = registry.device(model).ok_or_else(|| format!("Unknown OTDR model {}", model))?;
+        let instrument = device.instruments.get(serial).cloned().unwrap_or_default();
+        let mut other = vec![];
+        if !device.other.is_empty() {
+            other.push(device.other.clone());
+        }
+        if let Some(date) = &instrument.calibrated {
+            other.push(format!("Calibrated {}", date));
+        }
+        if let Some(date) = &instrument.calibration_due {
+            other.push(format!("Calibration due {}", date));
+        }
+        if let Some(certificate) = &instrument.certificate {
+            other.push(format!("Certificate {}", certificate));
+        }
+        Ok(SupplierParametersBlock {
+            supplier_name: device.supplier_name.as_str().into(),
+            otdr_mainframe_id: model.into(),
+            otdr_mainframe_sn: serial.into(),
+            optical_module_id: instrument.optical_module_id.unwrap_or_else(|| device.optical_module_id.clone()).into(),
+            optical_module_sn: instrument.optical_module_sn.into(),
+            software_revision: instrument.software_revision.unwrap_or_else(|| device.software_revision.clone()).into(),
+            other: other.join("; ").into(),
+        })
+    }
+}
+
+#[test]
+fn test_from_registry() {
+    let registry: DeviceRegistry = toml::from_str(r#"
+        [devices.MAX-730C]
+        supplier_name = "EXFO"
+        optical_module_id = "MAX-730C-SM1"
+        software_revision = "6.20.0.2"
+
+        [devices.MAX-730C.instruments.881234]
+        optical_module_sn = "881235"
+        software_revision = "6.21.0.1"
+        calibrated = "2024-03-01"
+        calibration_due = "2025-03-01"
+    "#).unwrap();
+    let sp = SupplierParametersBlock::from_registry(&registry, "MAX-730C", "881234").unwrap();
+    assert_eq!(sp, SupplierParametersBlock {
+        supplier_name: "EXFO".into(),
+        otdr_mainframe_id: "MAX-730C".into(),
+        otdr_mainframe_sn: "881234".into(),
+        optical_module_id: "MAX-730C-SM1".into(),
+        optical_module_sn: "881235".into(),
+        software_revision: "6.21.0.1".into(),
+        other: "Calibrated 2024-03-01; Calibration due 2025-03-01".into(),
+    });
+
+    let sp = SupplierParametersBlock::from_registry(&registry, "MAX-730C", "990000").unwrap();
+    assert_eq!((&*sp.software_revision, &*sp.optical_module_sn, &*sp.other), ("6.20.0.2", "", ""));
+    assert!(SupplierParametersBlock::from_registry(&registry, "OFL280", "1").is_err());
+    assert!(toml::from_str::<DeviceRegistry>("[devices.X]\nsupplier = \"EXFO\"").is_err());
+}
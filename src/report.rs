@@ -0,0 +1,588 @@
+/// This module builds per-fibre acceptance reports from parsed SOR files and
+/// renders them as HTML or Markdown, or, with the `xlsx` feature, as an
+/// Excel workbook.
+///
+/// Rendering fills in a template containing `{{title}}` and `{{content}}`
+/// placeholders, so that contractors can supply their own branding.
+use crate::analysis::acceptance::{evaluate, Profile};
+use crate::analysis::metres_per_100ps;
+use crate::engineering::iso8601;
+use crate::progress::{Cancelled, Hooks};
+use crate::types::SORFile;
+use std::path::Path;
+
+/// The template used for HTML reports if none is supplied
+pub const DEFAULT_HTML_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{{title}}</title>
+<style>
+body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; margin-bottom: 1em; }
+th, td { border: 1px solid #ccc; padding: 0.2em 0.6em; text-align: right; }
+th { background: #eee; }
+.pass { color: #070; }
+.fail { color: #b00; font-weight: bold; }
+.gainer { color: #a60; }
+</style>
+</head>
+<body>
+<h1>{{title}}</h1>
+{{content}}
+</body>
+</html>
+"#;
+
+/// The template used for Markdown reports if none is supplied
+pub const DEFAULT_MARKDOWN_TEMPLATE: &str = "# {{title}}\n\n{{content}}";
+
+/// One row in a report's event table
+#[derive(Debug, PartialEq, Clone)]
+pub struct EventRow {
+    pub number: i16,
+    /// Distance from the user offset, in metres
+    pub distance_m: f64,
+    pub loss_db: f64,
+    pub reflectance_db: f64,
+    pub code: String,
+    pub comment: String,
+    /// True for a non-reflective event with negative loss, as where the mode
+    /// field diameter increases; its true loss needs measuring from both ends
+    pub gainer: bool,
+    /// Mean of the losses measured from each end, if the event was found in
+    /// a measurement from the far end
+    pub bidirectional_loss_db: Option<f64>,
+    /// The N of a probable 1xN splitter, if splitters are being looked for
+    pub splitter: Option<u32>,
+    pub pass: bool,
+}
+
+/// The report for a single SOR file
+#[derive(Debug, PartialEq, Clone)]
+pub struct FibreReport {
+    pub filename: String,
+    pub cable_id: String,
+    pub fiber_id: String,
+    pub wavelength: i16,
+    /// Acquisition time as an ISO-8601 timestamp
+    pub date: String,
+    /// Distance to the last event, in metres
+    pub length_m: f64,
+    pub total_loss_db: f64,
+    pub orl_db: f64,
+    pub events: Vec<EventRow>,
+    /// True if the link as a whole - its total loss, ORL and fibre
+    /// attenuation - is within the profile
+    pub link_pass: bool,
+    /// True if every event and the link as a whole are within the profile
+    pub pass: bool,
+    /// Chart of the trace, if the plot feature is available
+    pub chart_svg: Option<String>,
+}
+
+/// Build the report for one file, judging it against the given profile
+pub fn build(filename: &str, sor: &SORFile, profile: &Profile) -> FibreReport {
+    let metres_per_100ps = metres_per_100ps(sor);
+    let evaluation = evaluate(sor, profile);
+    let mut events = Vec::new();
+    let mut length_m = 0.0;
+    if let Some(ke) = &sor.key_events {
+        let lke = &ke.last_key_event;
+        let all = ke.key_events.iter()
+            .map(|e| (e.event_number, e.event_propogation_time, e.event_loss, e.event_reflectance, &e.event_code, &e.comment))
+            .chain(std::iter::once((lke.event_number, lke.event_propogation_time, lke.event_loss, lke.event_reflectance, &lke.event_code, &lke.comment)));
+        for ((number, time, loss, reflectance, code, comment), result) in all.zip(&evaluation.events) {
+            let loss_db = loss as f64 / 1000.0;
+            let reflective = code.starts_with('1') || code.starts_with('2');
+            let end_of_fibre = code.chars().nth(1) == Some('E');
+            events.push(EventRow {
+                number,
+                distance_m: time as f64 * metres_per_100ps,
+                loss_db,
+                reflectance_db: reflectance as f64 / 1000.0,
+                code: code.to_string(),
+                comment: comment.trim().to_owned(),
+                gainer: !reflective && !end_of_fibre && loss_db < 0.0,
+                bidirectional_loss_db: None,
+                splitter: result.splitter,
+                pass: result.pass,
+            });
+        }
+        length_m = lke.event_propogation_time as f64 * metres_per_100ps;
+    }
+    let gp = sor.general_parameters.as_ref();
+    FibreReport {
+        filename: filename.to_owned(),
+        cable_id: gp.map_or(String::new(), |gp| gp.cable_id.trim().to_owned()),
+        fiber_id: gp.map_or(String::new(), |gp| gp.fiber_id.trim().to_owned()),
+        wavelength: gp.map_or(0, |gp| gp.nominal_wavelength),
+        date: sor.fixed_parameters.as_ref().map_or(String::new(), |fp| iso8601(fp.date_time_stamp)),
+        length_m,
+        total_loss_db: evaluation.total_loss_db,
+        orl_db: evaluation.orl_db,
+        events,
+        link_pass: evaluation.link_pass,
+        pass: evaluation.pass,
+        chart_svg: chart(sor),
+    }
+}
+
+/// Build the report for a file measured from one end, using a measurement
+/// from the far end to judge events by their bidirectional average loss
+/// where they appear in both. Events are matched within tolerance_m.
+/// Splitters lose differently in each direction, so are left as they are.
+pub fn build_bidirectional(filename: &str, forward: &SORFile, backward: &SORFile, profile: &Profile,
+                           tolerance_m: f64) -> Result<FibreReport, &'static str> {
+    let mut report = build(filename, forward, profile);
+    let losses = crate::analysis::bidirectional_losses(forward, backward, tolerance_m)?;
+    for e in report.events.iter_mut() {
+        if e.splitter.is_some() {
+            continue;
+        }
+        if let Some(loss) = losses.iter().find(|l| l.event_number == e.number) {
+            e.bidirectional_loss_db = Some(loss.average_db);
+            e.pass = profile.judge(loss.average_db, e.reflectance_db, &e.code);
+        }
+    }
+    report.pass = report.link_pass && report.events.iter().all(|e| e.pass);
+    Ok(report)
+}
+
+/// Build the reports for many files, which may be object store URLs or
+/// paths through ZIP archives as for `batch::timeseries`, in the order
+/// given. A file which can't be read or parsed gets an error in place of its
+/// report
+pub fn build_many<P: AsRef<Path>>(paths: &[P], profile: &Profile, hooks: &Hooks)
+                                  -> Result<Vec<Result<FibreReport, String>>, Cancelled> {
+    let mut reports = Vec::with_capacity(paths.len());
+    for (i, path) in paths.iter().enumerate() {
+        if hooks.is_cancelled() {
+            return Err(Cancelled);
+        }
+        let path = path.as_ref();
+        let report = crate::batch::read(path).and_then(|data| {
+            let sor = crate::parser::parse_file(&data).map_err(|_| "Could not parse SOR file".to_string())?.1;
+            Ok(build(&path.to_string_lossy(), &sor, profile))
+        });
+        reports.push(report);
+        hooks.progress(i + 1, paths.len());
+    }
+    Ok(reports)
+}
+
+/// Put reports in the order of a consolidated report: by cable, then
+/// wavelength, then fibre, with numbers in fibre IDs in numeric order so
+/// that fibre 2 comes before fibre 10
+pub fn sort(reports: &mut [FibreReport]) {
+    reports.sort_by_cached_key(|r| (natural_key(&r.cable_id), r.wavelength, natural_key(&r.fiber_id), r.filename.clone()));
+}
+
+/// Split a string into runs of digits, as numbers, and runs of anything
+/// else
+fn natural_key(s: &str) -> Vec<Result<u64, String>> {
+    let mut key = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        let digits = c.is_ascii_digit();
+        let mut run = String::new();
+        while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit() == digits) {
+            run.push(c);
+            chars.next();
+        }
+        key.push(if digits { Ok(run.parse().unwrap_or(u64::MAX)) } else { Err(run) });
+    }
+    key
+}
+
+/// Reports of the fibres in one cable at one wavelength
+#[derive(Debug, PartialEq, Clone)]
+pub struct Group<'a> {
+    pub cable_id: &'a str,
+    pub wavelength: i16,
+    pub reports: &'a [FibreReport],
+}
+
+impl Group<'_> {
+    /// Number of fibres which pass
+    pub fn passed(&self) -> usize {
+        self.reports.iter().filter(|r| r.pass).count()
+    }
+}
+
+/// Group runs of reports sharing a cable and wavelength, as `sort` leaves
+/// them
+pub fn groups(reports: &[FibreReport]) -> Vec<Group<'_>> {
+    let mut groups = Vec::new();
+    let mut start = 0;
+    for end in 1..=reports.len() {
+        if end == reports.len() || (&reports[end].cable_id, reports[end].wavelength) != (&reports[start].cable_id, reports[start].wavelength) {
+            groups.push(Group { cable_id: &reports[start].cable_id, wavelength: reports[start].wavelength, reports: &reports[start..end] });
+            start = end;
+        }
+    }
+    groups
+}
+
+/// A CSV register of every fibre tested, one row per report
+pub fn to_register_csv(reports: &[FibreReport]) -> Result<String, csv::Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(["cable_id", "fiber_id", "wavelength_nm", "date", "length_m", "total_loss_db", "orl_db",
+                         "events", "failed_events", "result", "filename"])?;
+    for r in reports {
+        writer.write_record([
+            r.cable_id.clone(),
+            r.fiber_id.clone(),
+            r.wavelength.to_string(),
+            r.date.clone(),
+            format!("{:.1}", r.length_m),
+            format!("{:.3}", r.total_loss_db),
+            format!("{:.3}", r.orl_db),
+            r.events.len().to_string(),
+            r.events.iter().filter(|e| !e.pass).count().to_string(),
+            pass_fail(r.pass).to_owned(),
+            r.filename.clone(),
+        ])?;
+    }
+    let bytes = writer.into_inner().map_err(|err| err.into_error())?;
+    Ok(String::from_utf8(bytes).expect("CSV of strings is UTF-8"))
+}
+
+#[cfg(feature = "plot")]
+fn chart(sor: &SORFile) -> Option<String> {
+    crate::plot::render_svg(sor, 900, 400).ok()
+}
+
+#[cfg(not(feature = "plot"))]
+fn chart(_sor: &SORFile) -> Option<String> {
+    None
+}
+
+/// An event's result: a gainer passes, but can't be judged properly without
+/// a measurement from the far end, so is called out
+fn event_result(e: &EventRow) -> &'static str {
+    if e.pass && e.gainer && e.bidirectional_loss_db.is_none() {
+        "GAINER"
+    } else {
+        pass_fail(e.pass)
+    }
+}
+
+/// An event's loss, with the bidirectional average if there is one
+fn event_loss(e: &EventRow) -> String {
+    match e.bidirectional_loss_db {
+        Some(average) => format!("{:.3} (bidir. {:.3})", e.loss_db, average),
+        None => format!("{:.3}", e.loss_db),
+    }
+}
+
+/// An event's comment, noting it if it's a probable splitter
+fn event_comment(e: &EventRow) -> String {
+    match (e.splitter, e.comment.is_empty()) {
+        (Some(n), true) => format!("probable 1x{} splitter", n),
+        (Some(n), false) => format!("{}; probable 1x{} splitter", e.comment, n),
+        (None, _) => e.comment.clone(),
+    }
+}
+
+fn pass_fail(pass: bool) -> &'static str {
+    if pass { "PASS" } else { "FAIL" }
+}
+
+fn cable_name(cable_id: &str) -> &str {
+    if cable_id.is_empty() { "No cable ID" } else { cable_id }
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Escape text for a Markdown table cell or heading, where a `|` would end
+/// the cell and a line break the row
+fn escape_markdown(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('|', "\\|").replace("\r\n", "<br>").replace(['\r', '\n'], "<br>")
+}
+
+fn fill_template(template: &str, title: &str, content: &str) -> String {
+    template.replace("{{title}}", title).replace("{{content}}", content)
+}
+
+/// Render reports as an HTML document using the given template. Reports
+/// for more than one cable or wavelength are summarised by group, as for
+/// `groups`
+pub fn to_html(reports: &[FibreReport], title: &str, template: &str) -> String {
+    let mut content = String::new();
+    let groups = groups(reports);
+    for group in &groups {
+        if groups.len() > 1 {
+            content += &format!("<h2>{} - {} nm</h2>\n<p>{} of {} fibres pass</p>\n",
+                escape_html(cable_name(group.cable_id)), group.wavelength, group.passed(), group.reports.len());
+        }
+        content += "<table>\n<tr><th>File</th><th>Cable</th><th>Fibre</th><th>Wavelength (nm)</th><th>Date</th><th>Length (m)</th><th>Loss (dB)</th><th>ORL (dB)</th><th>Result</th></tr>\n";
+        for r in group.reports {
+            content += &format!("<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:.1}</td><td>{:.3}</td><td>{:.3}</td><td class=\"{}\">{}</td></tr>\n",
+                escape_html(&r.filename), escape_html(&r.cable_id), escape_html(&r.fiber_id), r.wavelength, r.date,
+                r.length_m, r.total_loss_db, r.orl_db, pass_fail(r.pass).to_lowercase(), pass_fail(r.pass));
+        }
+        content += "</table>\n";
+    }
+    for r in reports {
+        content += &format!("<h2>{} {} - {} nm</h2>\n<p>{}</p>\n", escape_html(&r.cable_id), escape_html(&r.fiber_id), r.wavelength, escape_html(&r.filename));
+        if let Some(svg) = &r.chart_svg {
+            content += svg;
+            content += "\n";
+        }
+        content += "<table>\n<tr><th>#</th><th>Distance (m)</th><th>Loss (dB)</th><th>Reflectance (dB)</th><th>Code</th><th>Comment</th><th>Result</th></tr>\n";
+        for e in &r.events {
+            content += &format!("<tr><td>{}</td><td>{:.1}</td><td>{}</td><td>{:.3}</td><td>{}</td><td>{}</td><td class=\"{}\">{}</td></tr>\n",
+                e.number, e.distance_m, event_loss(e), e.reflectance_db, escape_html(&e.code), escape_html(&event_comment(e)),
+                event_result(e).to_lowercase(), event_result(e));
+        }
+        content += "</table>\n";
+    }
+    fill_template(template, &escape_html(title), &content)
+}
+
+/// Render reports as a Markdown document using the given template,
+/// summarised by group as for `to_html`
+pub fn to_markdown(reports: &[FibreReport], title: &str, template: &str) -> String {
+    let mut content = String::new();
+    let groups = groups(reports);
+    for (i, group) in groups.iter().enumerate() {
+        if groups.len() > 1 {
+            content += &format!("{}## {} - {} nm\n\n{} of {} fibres pass\n\n", if i > 0 { "\n" } else { "" },
+                escape_markdown(cable_name(group.cable_id)), group.wavelength, group.passed(), group.reports.len());
+        }
+        content += "| File | Cable | Fibre | Wavelength (nm) | Date | Length (m) | Loss (dB) | ORL (dB) | Result |\n";
+        content += "|---|---|---|---:|---|---:|---:|---:|---|\n";
+        for r in group.reports {
+            content += &format!("| {} | {} | {} | {} | {} | {:.1} | {:.3} | {:.3} | {} |\n",
+                escape_markdown(&r.filename), escape_markdown(&r.cable_id), escape_markdown(&r.fiber_id), r.wavelength, r.date,
+                r.length_m, r.total_loss_db, r.orl_db, pass_fail(r.pass));
+        }
+    }
+    for r in reports {
+        content += &format!("\n## {} {} - {} nm\n\n{}\n\n",
+            escape_markdown(&r.cable_id), escape_markdown(&r.fiber_id), r.wavelength, escape_markdown(&r.filename));
+        content += "| # | Distance (m) | Loss (dB) | Reflectance (dB) | Code | Comment | Result |\n";
+        content += "|---:|---:|---:|---:|---|---|---|\n";
+        for e in &r.events {
+            content += &format!("| {} | {:.1} | {} | {:.3} | {} | {} | {} |\n",
+                e.number, e.distance_m, event_loss(e), e.reflectance_db, escape_markdown(&e.code), escape_markdown(&event_comment(e)), event_result(e));
+        }
+    }
+    fill_template(template, title, &content)
+}
+
+/// A worksheet name for a report, numbered so that names are unique: Excel
+/// allows at most 31 characters, and none of `[]:*?/\`
+#[cfg(feature = "xlsx")]
+fn sheet_name(i: usize, r: &FibreReport) -> String {
+    let name = format!("{} {} {}", i + 1, r.fiber_id, r.wavelength);
+    name.chars().map(|c| if "[]:*?/\\".contains(c) { '_' } else { c }).take(31).collect()
+}
+
+/// Render reports as an Excel workbook, with a summary sheet and a sheet of
+/// events for each file. Numbers are written as numbers, so that they can
+/// be sorted and summed.
+#[cfg(feature = "xlsx")]
+pub fn to_xlsx(reports: &[FibreReport], title: &str) -> Result<Vec<u8>, rust_xlsxwriter::XlsxError> {
+    use rust_xlsxwriter::{Format, Workbook, Worksheet, XlsxError};
+    let bold = Format::new().set_bold();
+    let fail = Format::new().set_bold().set_font_color("#C00000");
+    let metres = Format::new().set_num_format("0.0");
+    let db = Format::new().set_num_format("0.000");
+    let header = |sheet: &mut Worksheet, row: u32, headings: &[&str]| -> Result<(), XlsxError> {
+        for (col, heading) in headings.iter().enumerate() {
+            sheet.write_string_with_format(row, col as u16, *heading, &bold)?;
+        }
+        sheet.set_freeze_panes(row + 1, 0)?;
+        Ok(())
+    };
+    let result = |sheet: &mut Worksheet, row: u32, col: u16, result: &str| -> Result<(), XlsxError> {
+        match result {
+            "FAIL" => sheet.write_string_with_format(row, col, result, &fail)?,
+            _ => sheet.write_string(row, col, result)?,
+        };
+        Ok(())
+    };
+
+    let mut workbook = Workbook::new();
+    let summary = workbook.add_worksheet().set_name("Summary")?;
+    summary.write_string_with_format(0, 0, title, &bold)?;
+    header(summary, 2, &["File", "Cable", "Fibre", "Wavelength (nm)", "Date", "Length (m)", "Loss (dB)", "ORL (dB)", "Result"])?;
+    for (i, r) in reports.iter().enumerate() {
+        let row = 3 + i as u32;
+        summary.write_string(row, 0, &r.filename)?;
+        summary.write_string(row, 1, &r.cable_id)?;
+        summary.write_string(row, 2, &r.fiber_id)?;
+        summary.write_number(row, 3, r.wavelength)?;
+        summary.write_string(row, 4, &r.date)?;
+        summary.write_number_with_format(row, 5, r.length_m, &metres)?;
+        summary.write_number_with_format(row, 6, r.total_loss_db, &db)?;
+        summary.write_number_with_format(row, 7, r.orl_db, &db)?;
+        result(summary, row, 8, pass_fail(r.pass))?;
+    }
+    summary.set_column_width(0, 40)?;
+    summary.set_column_width(4, 22)?;
+
+    for (i, r) in reports.iter().enumerate() {
+        let sheet = workbook.add_worksheet().set_name(sheet_name(i, r))?;
+        sheet.write_string_with_format(0, 0, format!("{} {} - {} nm", r.cable_id, r.fiber_id, r.wavelength), &bold)?;
+        sheet.write_string(1, 0, &r.filename)?;
+        header(sheet, 3, &["#", "Distance (m)", "Loss (dB)", "Bidir. loss (dB)", "Reflectance (dB)", "Code", "Comment", "Result"])?;
+        for (j, e) in r.events.iter().enumerate() {
+            let row = 4 + j as u32;
+            sheet.write_number(row, 0, e.number)?;
+            sheet.write_number_with_format(row, 1, e.distance_m, &metres)?;
+            sheet.write_number_with_format(row, 2, e.loss_db, &db)?;
+            if let Some(average) = e.bidirectional_loss_db {
+                sheet.write_number_with_format(row, 3, average, &db)?;
+            }
+            sheet.write_number_with_format(row, 4, e.reflectance_db, &db)?;
+            sheet.write_string(row, 5, &e.code)?;
+            sheet.write_string(row, 6, event_comment(e))?;
+            result(sheet, row, 7, event_result(e))?;
+        }
+        sheet.set_column_width(6, 40)?;
+    }
+    workbook.save_to_buffer()
+}
+
+#[test]
+fn test_build_report() {
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let sor = crate::parser::parse_file(data).unwrap().1;
+    let report = build("test.sor", &sor, &Profile::default());
+    assert_eq!(report.events.len(), 9);
+    // Event 4 is a 0.342 dB splice, over the default 0.3 dB limit
+    assert!(!report.events[3].pass);
+    assert!(!report.pass);
+    let relaxed = Profile { max_splice_loss: 0.5, ..Profile::default() };
+    assert!(build("test.sor", &sor, &relaxed).pass);
+    let md = to_markdown(&[report], "Acceptance", DEFAULT_MARKDOWN_TEMPLATE);
+    assert!(md.starts_with("# Acceptance\n"));
+    assert!(md.contains("| 4 | 778.6 | 0.342 | 0.000 | 0F9999 |  | FAIL |"));
+    // Event 2 is a gainer
+    assert!(md.contains("| 2 | 477.6 | -0.336 | 0.000 | 0F9999 |  | GAINER |"));
+    // Vendor comments can hold anything, including what would break a table
+    let mut awkward = build("test.sor", &sor, &relaxed);
+    awkward.cable_id = "C|3".to_owned();
+    awkward.events[0].comment = "splice\nclosure | 4".to_owned();
+    let md = to_markdown(&[awkward], "Acceptance", DEFAULT_MARKDOWN_TEMPLATE);
+    assert!(md.contains("\n## C\\|3 "));
+    assert!(md.contains("| splice<br>closure \\| 4 |"));
+}
+
+#[test]
+fn test_build_many() {
+    use crate::progress::CancellationToken;
+    let paths = ["data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor", "data/missing.sor", "Cargo.toml"];
+    let token = CancellationToken::new();
+    let hooks = Hooks::new().with_cancellation(token.clone()).with_progress(|done, total| {
+        assert_eq!(total, 3);
+        // Stop after the second file
+        if done == 2 {
+            token.cancel();
+        }
+    });
+    assert_eq!(build_many(&paths, &Profile::default(), &hooks).unwrap_err(), Cancelled);
+    let reports = build_many(&paths, &Profile::default(), &Hooks::default()).unwrap();
+    assert_eq!(reports[0].as_ref().unwrap().filename, paths[0]);
+    assert!(reports[1].is_err());
+    assert_eq!(reports[2].as_ref().unwrap_err(), "Could not parse SOR file");
+}
+
+#[test]
+fn test_build_bidirectional() {
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let forward = crate::parser::parse_file(data).unwrap().1;
+    // From the far end, the gainer shows as extra loss and event 4 as less
+    let mut backward = forward.clone();
+    let ke = backward.key_events.as_mut().unwrap();
+    let length = ke.last_key_event.event_propogation_time;
+    for e in ke.key_events.iter_mut() {
+        e.event_propogation_time = length - e.event_propogation_time;
+    }
+    ke.key_events[1].event_loss = 900;
+    ke.key_events[3].event_loss = 200;
+    let report = build_bidirectional("test.sor", &forward, &backward, &Profile::default(), 2.0).unwrap();
+    assert!(report.events[1].gainer);
+    assert!((report.events[1].bidirectional_loss_db.unwrap() - 0.282).abs() < 1e-9);
+    assert!(report.events[1].pass);
+    // Event 4 passes on its 0.271 dB average
+    assert!(report.events[3].pass);
+    assert!(report.pass);
+    let md = to_markdown(&[report], "Acceptance", DEFAULT_MARKDOWN_TEMPLATE);
+    assert!(md.contains("| 2 | 477.6 | -0.336 (bidir. 0.282) | 0.000 | 0F9999 |  | PASS |"));
+}
+
+#[test]
+fn test_splitters() {
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let mut sor = crate::parser::parse_file(data).unwrap().1;
+    // Make event 6 look like a 1x8 splitter
+    sor.key_events.as_mut().unwrap().key_events[5].event_loss = 10500;
+    let profile = Profile { max_splice_loss: 0.5, ..Profile::default() };
+    let report = build("test.sor", &sor, &profile);
+    assert!(!report.events[5].pass);
+    assert_eq!(report.events[5].splitter, None);
+    let report = build("test.sor", &sor, &Profile { splitters: true, ..profile });
+    assert_eq!(report.events[5].splitter, Some(8));
+    assert!(report.pass);
+    let md = to_markdown(&[report], "Acceptance", DEFAULT_MARKDOWN_TEMPLATE);
+    assert!(md.contains("| 6 | 1155.2 | 10.500 | 0.000 | 0F9999 | probable 1x8 splitter | PASS |"));
+}
+
+#[test]
+fn test_consolidated_report() {
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let sor = crate::parser::parse_file(data).unwrap().1;
+    let relaxed = Profile { max_splice_loss: 0.5, ..Profile::default() };
+    let report = |cable: &str, fibre: &str, wavelength: i16| FibreReport {
+        cable_id: cable.to_owned(),
+        fiber_id: fibre.to_owned(),
+        wavelength,
+        ..build(&format!("{}-{}-{}.sor", cable, fibre, wavelength), &sor, &relaxed)
+    };
+    let mut reports = vec![report("C2", "10", 1310), report("C1", "F10", 1550), report("C2", "2", 1310),
+                           report("C1", "F2", 1550), report("C1", "F1", 1310)];
+    reports[0].pass = false;
+    sort(&mut reports);
+    let order: Vec<_> = reports.iter().map(|r| r.filename.as_str()).collect();
+    assert_eq!(order, ["C1-F1-1310.sor", "C1-F2-1550.sor", "C1-F10-1550.sor", "C2-2-1310.sor", "C2-10-1310.sor"]);
+    let groups = groups(&reports);
+    assert_eq!(groups.iter().map(|g| (g.cable_id, g.wavelength, g.reports.len(), g.passed())).collect::<Vec<_>>(),
+               [("C1", 1310, 1, 1), ("C1", 1550, 2, 2), ("C2", 1310, 2, 1)]);
+    let md = to_markdown(&reports, "Acceptance", DEFAULT_MARKDOWN_TEMPLATE);
+    assert!(md.starts_with("# Acceptance\n\n## C1 - 1310 nm\n\n1 of 1 fibres pass\n\n| File |"));
+    assert!(md.contains("\n\n## C2 - 1310 nm\n\n1 of 2 fibres pass\n\n"));
+    let mut awkward = reports[..2].to_vec();
+    awkward[1].cable_id = "C|3".to_owned();
+    let md = to_markdown(&awkward, "Acceptance", DEFAULT_MARKDOWN_TEMPLATE);
+    assert!(md.contains("\n## C\\|3 - 1550 nm\n"));
+    assert!(md.contains("| C1-F2-1550.sor | C\\|3 |"));
+
+    let csv = to_register_csv(&reports).unwrap();
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(lines.len(), 6);
+    assert_eq!(lines[0], "cable_id,fiber_id,wavelength_nm,date,length_m,total_loss_db,orl_db,events,failed_events,result,filename");
+    assert!(lines[5].starts_with("C2,10,1310,"));
+    assert!(lines[5].ends_with(",9,0,FAIL,C2-10-1310.sor"));
+}
+
+#[cfg(feature = "xlsx")]
+#[test]
+fn test_to_xlsx() {
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let sor = crate::parser::parse_file(data).unwrap().1;
+    let mut report = build("test.sor", &sor, &Profile::default());
+    report.fiber_id = "A/B: 1".to_owned();
+    assert_eq!(sheet_name(0, &report), "1 A_B_ 1 1310");
+    report.fiber_id = "x".repeat(40);
+    assert_eq!(sheet_name(0, &report).len(), 31);
+    // Even truncated, the names of the two sheets differ
+    let xlsx = to_xlsx(&[report.clone(), report], "Test").unwrap();
+    // An XLSX file is a zip archive
+    assert_eq!(&xlsx[..4], b"PK\x03\x04");
+}
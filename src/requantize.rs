@@ -0,0 +1,159 @@
+/// This module re-quantizes data points to a different scale factor, so that
+/// traces stored with different scale factors - or a trace whose runs of
+/// points use several - can be merged or differenced point for point.
+///
+/// A point stores its level as -dB*1000 divided by the scale factor/1000, so
+/// a larger scale factor reaches further down in coarser steps. Going to a
+/// coarser scale factor loses resolution, which rounding turns into steps in
+/// the trace; dithering trades those for noise, which averages out.
+use crate::types::DataPoints;
+
+/// How points are rounded to the new scale factor's steps
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Dither {
+    /// Round to the nearest step
+    #[default]
+    None,
+    /// Add uniform noise of up to half a step either way before rounding
+    Rectangular { seed: u64 },
+    /// Add triangular noise of up to a step either way before rounding,
+    /// which leaves the error independent of the level
+    Triangular { seed: u64 },
+}
+
+/// What re-quantizing changed
+#[derive(Debug, PartialEq, Clone)]
+pub struct Requantized {
+    /// The scale factor the points are now stored with, as 1000*SF
+    pub scale_factor: i16,
+    /// Largest change to any point, in dB
+    pub max_error_db: f64,
+    /// Points too low to store at the new scale factor, which were clipped
+    /// to the lowest level it can
+    pub clipped: usize,
+}
+
+impl DataPoints {
+    /// Store every point with the given scale factor, as 1000*SF, keeping
+    /// the runs of points as they are. Runs already at the scale factor are
+    /// left alone, dithered or not.
+    pub fn requantize(&mut self, scale_factor: i16, dither: Dither) -> Result<Requantized, &'static str> {
+        if scale_factor <= 0 || self.scale_factors.iter().any(|sf| sf.scale_factor <= 0) {
+            return Err("Scale factors must be positive");
+        }
+        let mut noise = Noise::new(dither);
+        let mut max_error: f64 = 0.0;
+        let mut clipped = 0;
+        for sf in self.scale_factors.iter_mut().filter(|sf| sf.scale_factor != scale_factor) {
+            let ratio = sf.scale_factor as f64 / scale_factor as f64;
+            for point in sf.data.iter_mut() {
+                let exact = *point as f64 * ratio;
+                let rounded = (exact + noise.next()).round().max(0.0);
+                let new = if rounded > u16::MAX as f64 {
+                    clipped += 1;
+                    u16::MAX
+                } else {
+                    rounded as u16
+                };
+                max_error = max_error.max((new as f64 - exact).abs());
+                *point = new;
+            }
+            sf.scale_factor = scale_factor;
+        }
+        Ok(Requantized { scale_factor, max_error_db: max_error * scale_factor as f64 / 1e6, clipped })
+    }
+
+    /// Store every point with the finest scale factor which reaches the
+    /// lowest point, or with the finest of those already used if that is
+    /// coarser, so that nothing is clipped and - if the points were stored
+    /// with one scale factor - nothing changes
+    pub fn requantize_auto(&mut self, dither: Dither) -> Result<Requantized, &'static str> {
+        let lowest = self.scale_factors.iter()
+            .flat_map(|sf| sf.data.iter().map(move |&point| point as i64 * sf.scale_factor as i64))
+            .max()
+            .unwrap_or(0);
+        let finest = self.scale_factors.iter().map(|sf| sf.scale_factor).filter(|&sf| sf > 0).min().unwrap_or(1000);
+        let needed = (lowest + u16::MAX as i64 - 1) / u16::MAX as i64;
+        if needed > i16::MAX as i64 {
+            return Err("The lowest point is too low for any scale factor");
+        }
+        self.requantize(finest.max(needed as i16), dither)
+    }
+}
+
+/// Deterministic dither noise in steps of the new scale factor, from a
+/// xorshift generator, so that re-quantizing the same file twice gives the
+/// same result
+struct Noise {
+    dither: Dither,
+    state: u64,
+}
+
+impl Noise {
+    fn new(dither: Dither) -> Noise {
+        let seed = match dither {
+            Dither::None => 0,
+            Dither::Rectangular { seed } | Dither::Triangular { seed } => seed,
+        };
+        // xorshift never leaves zero
+        Noise { dither, state: seed.max(1) }
+    }
+
+    /// Uniform in [-0.5, 0.5)
+    fn uniform(&mut self) -> f64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 11) as f64 / (1u64 << 53) as f64 - 0.5
+    }
+
+    fn next(&mut self) -> f64 {
+        match self.dither {
+            Dither::None => 0.0,
+            Dither::Rectangular { .. } => self.uniform(),
+            Dither::Triangular { .. } => self.uniform() + self.uniform(),
+        }
+    }
+}
+
+#[test]
+fn test_requantize() {
+    use crate::types::DataPointsAtScaleFactor;
+    let data = include_bytes!("../data/example1-noyes-ofl280.sor");
+    let original = crate::parser::parse_file(data).unwrap().1.data_points.unwrap();
+    assert_eq!(original.scale_factors[0].scale_factor, 1000);
+
+    // Doubling the scale factor halves the points, to within a step
+    let mut dp = original.clone();
+    let result = dp.requantize(2000, Dither::None).unwrap();
+    assert_eq!((result.scale_factor, result.clipped), (2000, 0));
+    assert!(result.max_error_db <= 0.001 + 1e-9);
+    for (new, old) in dp.scale_factors[0].data.iter().zip(&original.scale_factors[0].data) {
+        assert!((*new as f64 * 2.0 - *old as f64).abs() <= 1.0);
+    }
+    // Dithering is repeatable, and keeps within a step either way
+    let mut dithered = original.clone();
+    let result = dithered.requantize(2000, Dither::Triangular { seed: 7 }).unwrap();
+    assert!(result.max_error_db <= 0.003 + 1e-9);
+    let mut again = original.clone();
+    again.requantize(2000, Dither::Triangular { seed: 7 }).unwrap();
+    assert_eq!(again, dithered);
+    assert_ne!(dithered, dp);
+
+    // Two runs at different scale factors come together at one just coarse
+    // enough to reach the lowest point, which the finer can't
+    let mut dp = DataPoints {
+        number_of_data_points: 4,
+        total_number_scale_factors_used: 2,
+        scale_factors: vec![
+            DataPointsAtScaleFactor { n_points: 2, scale_factor: 1000, data: vec![10000, 20000] },
+            DataPointsAtScaleFactor { n_points: 2, scale_factor: 2000, data: vec![40000, 50000] },
+        ],
+    };
+    assert_eq!(dp.clone().requantize(1000, Dither::None).unwrap().clipped, 2);
+    let result = dp.requantize_auto(Dither::None).unwrap();
+    assert_eq!((result.scale_factor, result.clipped), (1526, 0));
+    assert!(result.max_error_db <= 0.001526 / 2.0 + 1e-9);
+    assert!(dp.scale_factors.iter().all(|sf| sf.scale_factor == 1526));
+    assert!(dp.requantize(0, Dither::None).is_err());
+}
@@ -0,0 +1,37 @@
+/// This module publishes a JSON Schema describing a serialised SORFile, as
+/// output in JSON, CBOR, MessagePack or YAML, so that downstream consumers
+/// can generate types for it and validate payloads. The field descriptions
+/// are taken from the documentation of `otdrs::types`.
+///
+/// The schema describes the raw SR-4731 encoding; output with engineering
+/// units has other field names and isn't covered.
+use schemars::schema_for;
+use crate::types::SORFile;
+
+/// The JSON Schema (draft 2020-12) for a serialised SORFile
+pub fn json_schema() -> serde_json::Value {
+    schema_for!(SORFile).to_value()
+}
+
+#[test]
+fn test_json_schema() {
+    let schema = json_schema();
+    assert_eq!(schema["title"], "SORFile");
+    assert_eq!(schema["$schema"], "https://json-schema.org/draft/2020-12/schema");
+    let required = schema["required"].as_array().unwrap();
+    assert!(required.contains(&"map".into()));
+    assert!(!required.contains(&"link_parameters".into()));
+    let key_event = &schema["$defs"]["KeyEvent"]["properties"];
+    assert_eq!(key_event["event_loss"]["type"], "integer");
+    assert_eq!(key_event["event_loss"]["description"], "Loss in dB*1000 for the event");
+
+    // Every field of a parsed file is described
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let sor = serde_json::to_value(crate::parser::parse_file(data).unwrap().1).unwrap();
+    for (name, _) in sor.as_object().unwrap() {
+        assert!(schema["properties"].get(name).is_some(), "{} isn't in the schema", name);
+    }
+    for (name, _) in sor["fixed_parameters"].as_object().unwrap() {
+        assert!(schema["$defs"]["FixedParametersBlock"]["properties"].get(name).is_some(), "{} isn't in the schema", name);
+    }
+}
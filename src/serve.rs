@@ -0,0 +1,251 @@
+/// This module is a small HTTP service for converting and checking SOR
+/// files, for teams who would rather call a service than embed the library.
+/// It is only available with the `serve` feature, and is run by
+/// `otdrs serve`.
+///
+/// Each endpoint takes a SOR file as the raw body of a POST request, e.g.
+/// `curl --data-binary @fibre.sor localhost:8080/json`:
+///
+/// - `/json` returns the parsed file, in engineering units with
+///   `?engineering=true`
+/// - `/validate` checks the stored checksum and judges the file against an
+///   acceptance profile, whose limits can be given as query parameters as
+///   in the config file, e.g. `?max_splice_loss=0.2&min_orl=40`
+/// - `/summary` returns the file's identity and headline results
+/// - `/events` returns the key event table in dB and metres
+/// - `/preview` returns the trace reduced to at most 1000 points, or
+///   `?points=n`, keeping the peaks of reflections, as `distance_m` and
+///   `level_db` arrays with distances measured as for events; with
+///   `?normalized=true` levels are relative to the start of the fibre and
+///   clipped at the noise floor, as in plots
+/// - `/plot` renders the trace as SVG, or PNG with `?format=png`, if built
+///   with the `plot` feature
+///
+/// Errors are returned as JSON, e.g. `{"error": "Could not parse SOR file"}`,
+/// with a 422 status for files which can't be parsed.
+use std::net::SocketAddr;
+use axum::body::Bytes;
+use axum::extract::{DefaultBodyLimit, Query};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use crate::analysis::acceptance::{evaluate, Profile};
+use crate::checksum;
+use crate::types::SORFile;
+
+/// Largest SOR file accepted, in bytes
+const MAX_FILE_SIZE: usize = 64 * 1024 * 1024;
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+fn parse(body: &[u8]) -> Option<SORFile> {
+    crate::parser::parse_file(body).map(|(_, sor)| sor).ok()
+}
+
+fn unparsable() -> Response {
+    error(StatusCode::UNPROCESSABLE_ENTITY, "Could not parse SOR file")
+}
+
+#[derive(Deserialize)]
+struct JsonParams {
+    #[serde(default)]
+    engineering: bool,
+}
+
+async fn to_json(Query(params): Query<JsonParams>, body: Bytes) -> Response {
+    let sor = match parse(&body) {
+        Some(sor) => sor,
+        None => return unparsable(),
+    };
+    let value = if params.engineering { crate::engineering::to_value(&sor) } else { serde_json::to_value(&sor) };
+    match value {
+        Ok(value) => Json(value).into_response(),
+        Err(err) => error(StatusCode::INTERNAL_SERVER_ERROR, &err.to_string()),
+    }
+}
+
+async fn validate(Query(profile): Query<Profile>, body: Bytes) -> Response {
+    let sor = match parse(&body) {
+        Some(sor) => sor,
+        None => return unparsable(),
+    };
+    let checksum = match checksum::verify(&body) {
+        Ok(v) => json!({
+            "stored": v.block.value,
+            "valid": !v.matches.is_empty(),
+            "matches": v.matches.iter().map(|(a, s)| json!({ "algorithm": a.to_string(), "strategy": s.to_string() })).collect::<Vec<_>>(),
+        }),
+        // Files needn't have a checksum
+        Err(_) => Value::Null,
+    };
+    let evaluation = evaluate(&sor, &profile);
+    Json(json!({
+        "checksum": checksum,
+        "acceptance": {
+            "pass": evaluation.pass,
+            "link_pass": evaluation.link_pass,
+            "total_loss_db": evaluation.total_loss_db,
+            "total_loss_pass": evaluation.total_loss_pass,
+            "orl_db": evaluation.orl_db,
+            "orl_pass": evaluation.orl_pass,
+            "failed_events": evaluation.events.iter().filter(|e| !e.pass).map(|e| e.event_number).collect::<Vec<_>>(),
+            "failed_sections": evaluation.sections.iter().filter(|s| !s.pass)
+                .map(|s| json!({ "from_event": s.from_event, "to_event": s.to_event, "db_per_km": s.db_per_km }))
+                .collect::<Vec<_>>(),
+        },
+    })).into_response()
+}
+
+async fn summary(body: Bytes) -> Response {
+    let sor = match parse(&body) {
+        Some(sor) => sor,
+        None => return unparsable(),
+    };
+    Json(crate::api::summary(&sor)).into_response()
+}
+
+async fn events(body: Bytes) -> Response {
+    let sor = match parse(&body) {
+        Some(sor) => sor,
+        None => return unparsable(),
+    };
+    let report = crate::report::build("", &sor, &Profile::default());
+    let events: Vec<Value> = report.events.iter().map(|e| json!({
+        "number": e.number,
+        "distance_m": e.distance_m,
+        "loss_db": e.loss_db,
+        "reflectance_db": e.reflectance_db,
+        "code": e.code,
+        "comment": e.comment,
+    })).collect();
+    Json(events).into_response()
+}
+
+#[derive(Deserialize)]
+struct PreviewParams {
+    points: Option<usize>,
+    #[serde(default)]
+    normalized: bool,
+}
+
+async fn preview(Query(params): Query<PreviewParams>, body: Bytes) -> Response {
+    let sor = match parse(&body) {
+        Some(sor) => sor,
+        None => return unparsable(),
+    };
+    let points = crate::analysis::Trace::new(&sor)
+        .and_then(|trace| if params.normalized { trace.normalized(true) } else { Ok(trace) })
+        .and_then(|trace| trace.preview(params.points.unwrap_or(1000)));
+    match points {
+        Ok(points) => {
+            let (distance_m, level_db): (Vec<f64>, Vec<f64>) = points.into_iter().unzip();
+            Json(json!({ "distance_m": distance_m, "level_db": level_db })).into_response()
+        }
+        Err(err) => error(StatusCode::UNPROCESSABLE_ENTITY, err),
+    }
+}
+
+#[cfg(feature = "plot")]
+#[derive(Deserialize)]
+struct PlotParams {
+    format: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+}
+
+#[cfg(feature = "plot")]
+async fn plot(Query(params): Query<PlotParams>, body: Bytes) -> Response {
+    let sor = match parse(&body) {
+        Some(sor) => sor,
+        None => return unparsable(),
+    };
+    let (width, height) = (params.width.unwrap_or(900).clamp(100, 4000), params.height.unwrap_or(400).clamp(100, 4000));
+    let rendered = match params.format.as_deref() {
+        Some("png") => crate::plot::render_png(&sor, width, height).map(|png| ("image/png", png)),
+        None | Some("svg") => crate::plot::render_svg(&sor, width, height).map(|svg| ("image/svg+xml", svg.into_bytes())),
+        Some(_) => return error(StatusCode::BAD_REQUEST, "The format must be svg or png"),
+    };
+    match rendered {
+        Ok((content_type, data)) => ([(axum::http::header::CONTENT_TYPE, content_type)], data).into_response(),
+        Err(err) => error(StatusCode::UNPROCESSABLE_ENTITY, &err.to_string()),
+    }
+}
+
+#[cfg(not(feature = "plot"))]
+async fn plot() -> Response {
+    error(StatusCode::NOT_IMPLEMENTED, "Plots need otdrs built with the plot feature")
+}
+
+/// The service's routes, for serving or nesting in another axum app
+pub fn router() -> Router {
+    Router::new()
+        .route("/health", get(|| async { "ok" }))
+        .route("/json", post(to_json))
+        .route("/validate", post(validate))
+        .route("/summary", post(summary))
+        .route("/events", post(events))
+        .route("/preview", post(preview))
+        .route("/plot", post(plot))
+        .layer(DefaultBodyLimit::max(MAX_FILE_SIZE))
+}
+
+/// Serve the routes on an address until the process is stopped
+pub async fn serve(addr: SocketAddr) -> std::io::Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    axum::serve(listener, router()).await
+}
+
+#[test]
+fn test_router() {
+    use std::io::{Read, Write};
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let listener = runtime.block_on(tokio::net::TcpListener::bind("127.0.0.1:0")).unwrap();
+    let addr = listener.local_addr().unwrap();
+    runtime.spawn(async move { axum::serve(listener, router()).await });
+
+    let request = |path: &str, body: &[u8]| -> (String, Vec<u8>) {
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        write!(stream, "POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", path, body.len()).unwrap();
+        stream.write_all(body).unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        let split = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        (String::from_utf8_lossy(&response[..split]).into_owned(), response[split + 4..].to_vec())
+    };
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let (head, body) = request("/json?engineering=true", data);
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    let value: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(value["general_parameters"]["fiber_id"], "Fiber1");
+    assert!(value["key_events"]["last_key_event"]["end_to_end_loss_db"].is_number());
+
+    let (_, body) = request("/summary", data);
+    let value: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(value["wavelength_nm"], 1310);
+    let (_, body) = request("/events", data);
+    let value: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(value[1]["loss_db"], -0.336);
+    let (_, body) = request("/validate?max_splice_loss=0.01", data);
+    let value: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(value["acceptance"]["pass"], false);
+    assert!(!value["acceptance"]["failed_events"].as_array().unwrap().is_empty());
+    let (_, body) = request("/preview?points=200", data);
+    let value: Value = serde_json::from_slice(&body).unwrap();
+    assert!(value["level_db"].as_array().unwrap().len() <= 200);
+    assert_eq!(value["distance_m"].as_array().unwrap().len(), value["level_db"].as_array().unwrap().len());
+    let (_, body) = request("/preview?points=200&normalized=true", data);
+    let normalized: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(normalized["distance_m"][0], value["distance_m"][0]);
+    assert_ne!(normalized["level_db"], value["level_db"]);
+
+    let (head, body) = request("/json", b"not a SOR file");
+    assert!(head.starts_with("HTTP/1.1 422"), "{}", head);
+    assert_eq!(serde_json::from_slice::<Value>(&body).unwrap()["error"], "Could not parse SOR file");
+    let (head, _) = request("/validate?max_splice_loss=lots", data);
+    assert!(head.starts_with("HTTP/1.1 400"), "{}", head);
+}
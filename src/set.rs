@@ -0,0 +1,231 @@
+/// This module groups the SOR files of one fibre - typically measured at
+/// several wavelengths, e.g. 1310/1550/1625nm, and from both ends - into a
+/// set, since that is how tests are almost always captured in practice, and
+/// reports on the set as a whole.
+use crate::analysis::acceptance::Profile;
+use crate::analysis::{find_macrobends, WavelengthLoss};
+use crate::report::{build, build_bidirectional, FibreReport};
+use crate::types::SORFile;
+
+/// Distance in metres within which events measured from each end are taken
+/// to be the same event
+const BIDIRECTIONAL_TOLERANCE_M: f64 = 2.0;
+
+/// Which end of the fibre a file was measured from, relative to the first
+/// file added to its set
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+/// One file in a set
+#[derive(Debug, PartialEq, Clone)]
+pub struct Member {
+    pub filename: String,
+    pub wavelength: i16,
+    pub direction: Direction,
+    pub sor: SORFile,
+}
+
+/// The files measuring one fibre, kept in order of wavelength and then
+/// direction. Every file shares the same cable and fibre IDs, and there is
+/// at most one file for each wavelength and direction.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct TraceSet {
+    members: Vec<Member>,
+    /// Originating and terminating locations in the forward direction, as
+    /// set by the first file added
+    forward: Option<(String, String)>,
+}
+
+/// The report for a set of files
+#[derive(Debug, PartialEq, Clone)]
+pub struct SetReport {
+    pub cable_id: String,
+    pub fiber_id: String,
+    /// A report for each file, in the set's order. Where a file has a
+    /// counterpart measured from the other end at the same wavelength, its
+    /// events are judged by their bidirectional average loss
+    pub reports: Vec<FibreReport>,
+    /// Events in the forward direction whose loss is much greater at the
+    /// longest wavelength than at the shortest; empty if there is only one
+    /// wavelength or the traces can't be compared
+    pub macrobends: Vec<WavelengthLoss>,
+    /// True if every file passes and no macrobends were found
+    pub pass: bool,
+}
+
+fn ids(sor: &SORFile) -> (String, String) {
+    sor.general_parameters.as_ref().map_or((String::new(), String::new()),
+                                           |gp| (gp.cable_id.trim().to_owned(), gp.fiber_id.trim().to_owned()))
+}
+
+fn locations(sor: &SORFile) -> (String, String) {
+    sor.general_parameters.as_ref().map_or((String::new(), String::new()),
+                                           |gp| (gp.originating_location.trim().to_owned(), gp.terminating_location.trim().to_owned()))
+}
+
+impl TraceSet {
+    pub fn new() -> Self {
+        TraceSet::default()
+    }
+
+    /// Add a file, working out its direction from its originating and
+    /// terminating locations: a file whose locations are those of the first
+    /// file swapped over is Backward, and anything else Forward. Files with
+    /// no locations recorded should be added with add_with_direction.
+    pub fn add(&mut self, filename: &str, sor: SORFile) -> Result<Direction, &'static str> {
+        // Compared with the first file added rather than the first member,
+        // which may be a backward file at a shorter wavelength
+        let direction = match &self.forward {
+            Some((first_from, first_to)) => {
+                let (from, to) = locations(&sor);
+                if !from.is_empty() && !to.is_empty() && &from == first_to && &to == first_from {
+                    Direction::Backward
+                } else {
+                    Direction::Forward
+                }
+            }
+            None => Direction::Forward,
+        };
+        self.add_with_direction(filename, sor, direction)?;
+        Ok(direction)
+    }
+
+    /// Add a file measured in the given direction, checking that it belongs
+    /// to the set
+    pub fn add_with_direction(&mut self, filename: &str, sor: SORFile, direction: Direction) -> Result<(), &'static str> {
+        let wavelength = sor.general_parameters.as_ref().map(|gp| gp.nominal_wavelength)
+            .ok_or("File has no general parameters block")?;
+        if let Some(first) = self.members.first() {
+            if ids(&sor) != ids(&first.sor) {
+                return Err("File's cable or fibre ID differs from the rest of the set");
+            }
+        }
+        if self.get(wavelength, direction).is_some() {
+            return Err("Set already has a file for this wavelength and direction");
+        }
+        if self.forward.is_none() {
+            let (from, to) = locations(&sor);
+            self.forward = Some(if direction == Direction::Forward { (from, to) } else { (to, from) });
+        }
+        let at = self.members.partition_point(|m| (m.wavelength, m.direction) < (wavelength, direction));
+        self.members.insert(at, Member { filename: filename.to_owned(), wavelength, direction, sor });
+        Ok(())
+    }
+
+    pub fn members(&self) -> &[Member] {
+        &self.members
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// The file at a wavelength in nm, measured in a direction
+    pub fn get(&self, wavelength: i16, direction: Direction) -> Option<&SORFile> {
+        self.members.iter().find(|m| m.wavelength == wavelength && m.direction == direction).map(|m| &m.sor)
+    }
+
+    /// The wavelengths in the set, shortest first
+    pub fn wavelengths(&self) -> Vec<i16> {
+        let mut wavelengths: Vec<i16> = self.members.iter().map(|m| m.wavelength).collect();
+        wavelengths.dedup();
+        wavelengths
+    }
+
+    /// The files measured in a direction, shortest wavelength first
+    pub fn direction(&self, direction: Direction) -> impl Iterator<Item = &Member> {
+        self.members.iter().filter(move |m| m.direction == direction)
+    }
+
+    /// Report on every file in the set, judged against the given profile
+    pub fn report(&self, profile: &Profile) -> Result<SetReport, &'static str> {
+        let first = self.members.first().ok_or("Set has no files")?;
+        let (cable_id, fiber_id) = ids(&first.sor);
+        let mut reports = Vec::new();
+        for m in &self.members {
+            let other = match m.direction {
+                Direction::Forward => Direction::Backward,
+                Direction::Backward => Direction::Forward,
+            };
+            reports.push(match self.get(m.wavelength, other) {
+                Some(far) => build_bidirectional(&m.filename, &m.sor, far, profile, BIDIRECTIONAL_TOLERANCE_M)?,
+                None => build(&m.filename, &m.sor, profile),
+            });
+        }
+        let forward: Vec<&Member> = self.direction(Direction::Forward).collect();
+        let macrobends = match (forward.first(), forward.last()) {
+            (Some(short), Some(long)) if short.wavelength != long.wavelength => {
+                find_macrobends(&short.sor, &long.sor).unwrap_or_default().into_iter().filter(|l| l.macrobend).collect()
+            }
+            _ => Vec::new(),
+        };
+        let pass = reports.iter().all(|r| r.pass) && macrobends.is_empty();
+        Ok(SetReport { cable_id, fiber_id, reports, macrobends, pass })
+    }
+}
+
+#[test]
+fn test_trace_set() {
+    let parse = |data: &[u8]| crate::parser::parse_file(data).unwrap().1;
+    let sor_1310 = parse(include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor"));
+    let sor_1550 = parse(include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1550nm.sor"));
+    let mut set = TraceSet::new();
+    assert!(set.report(&Profile::default()).is_err());
+    assert_eq!(set.add("1550.sor", sor_1550.clone()), Ok(Direction::Forward));
+    // No locations are recorded, so both files are taken as forward
+    assert_eq!(set.add("1310.sor", sor_1310.clone()), Ok(Direction::Forward));
+    assert_eq!(set.wavelengths(), vec![1310, 1550]);
+    assert_eq!(set.members()[0].filename, "1310.sor");
+    assert_eq!(set.get(1550, Direction::Forward), Some(&sor_1550));
+    assert!(set.get(1550, Direction::Backward).is_none());
+    // A second forward 1310nm file, or a different fibre, doesn't belong
+    assert!(set.add("again.sor", sor_1310.clone()).is_err());
+    assert!(set.add("other.sor", parse(include_bytes!("../data/example1-noyes-ofl280.sor"))).is_err());
+    // From the far end, the events appear in mirror image
+    let mut backward = sor_1310;
+    let ke = backward.key_events.as_mut().unwrap();
+    let length = ke.last_key_event.event_propogation_time;
+    for e in ke.key_events.iter_mut() {
+        e.event_propogation_time = length - e.event_propogation_time;
+    }
+    set.add_with_direction("1310-back.sor", backward, Direction::Backward).unwrap();
+    assert_eq!(set.direction(Direction::Forward).count(), 2);
+
+    let report = set.report(&Profile::default()).unwrap();
+    assert_eq!(report.fiber_id, "Fiber1");
+    let filenames: Vec<&str> = report.reports.iter().map(|r| r.filename.as_str()).collect();
+    assert_eq!(filenames, vec!["1310.sor", "1310-back.sor", "1550.sor"]);
+    // The 1310nm files are judged by their bidirectional losses
+    assert!(report.reports[0].events.iter().any(|e| e.bidirectional_loss_db.is_some()));
+    assert!(report.reports[2].events.iter().all(|e| e.bidirectional_loss_db.is_none()));
+    assert_eq!(report.pass, report.reports.iter().all(|r| r.pass) && report.macrobends.is_empty());
+}
+
+#[test]
+fn test_direction_from_locations() {
+    let sor = crate::parser::parse_file(include_bytes!("../data/example1-noyes-ofl280.sor")).unwrap().1;
+    let mut backward = sor.clone();
+    let gp = backward.general_parameters.as_mut().unwrap();
+    std::mem::swap(&mut gp.originating_location, &mut gp.terminating_location);
+    let mut set = TraceSet::new();
+    assert_eq!(set.add("a.sor", sor), Ok(Direction::Forward));
+    assert_eq!(set.add("b.sor", backward), Ok(Direction::Backward));
+
+    // A backward file at a shorter wavelength sorts first, but directions
+    // are still judged against the first file added
+    let at = |sor: &SORFile, wavelength: i16| {
+        let mut sor = sor.clone();
+        sor.general_parameters.as_mut().unwrap().nominal_wavelength = wavelength;
+        sor
+    };
+    let forward = set.members()[0].sor.clone();
+    let backward = set.members()[1].sor.clone();
+    let mut set = TraceSet::new();
+    assert_eq!(set.add("1550.sor", at(&forward, 1550)), Ok(Direction::Forward));
+    assert_eq!(set.add("1310-back.sor", at(&backward, 1310)), Ok(Direction::Backward));
+    assert_eq!(set.members()[0].direction, Direction::Backward);
+    assert_eq!(set.add("1625.sor", at(&forward, 1625)), Ok(Direction::Forward));
+}
@@ -0,0 +1,80 @@
+/// This module reports how big a SORFile is, on disk and in memory, for
+/// sizing archives and spotting unusually bulky files.
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use serde::Serialize;
+use crate::parser;
+use crate::types::SORFile;
+
+/// Size of one block as the writer lays it out
+#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+pub struct BlockStats {
+    pub identifier: String,
+    pub revision_number: u16,
+    /// Size in bytes, including the block's header string
+    pub bytes: usize,
+}
+
+/// Sizes and counts describing a whole file
+#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+pub struct FileStats {
+    /// Every block in file order, starting with the map
+    pub blocks: Vec<BlockStats>,
+    /// Data points across all scale factors
+    pub samples: usize,
+    pub scale_factors: usize,
+    pub key_events: usize,
+    pub landmarks: usize,
+    /// Bytes the file takes when written out by otdrs, which may differ
+    /// from the file it was parsed from - see SORFile::to_bytes
+    pub serialized_bytes: usize,
+    /// Bytes the parsed file holds in memory, counting allocated capacity
+    pub in_memory_bytes: usize,
+}
+
+impl SORFile {
+    /// Measure the file. Block sizes come from writing it out, so this
+    /// fails wherever to_bytes would
+    pub fn stats(&self) -> Result<FileStats, &'static str> {
+        let mut bytes = Vec::new();
+        self.serialize_into(&mut bytes)?;
+        let (_, map) = parser::map_block(&bytes).map_err(|_| "Could not read back the written map block")?;
+        let mut blocks = Vec::with_capacity(map.block_info.len() + 1);
+        blocks.push(BlockStats {
+            identifier: parser::BLOCK_ID_MAP.into(),
+            revision_number: map.revision_number,
+            bytes: map.block_size as usize,
+        });
+        blocks.extend(map.block_info.iter().map(|bi| BlockStats {
+            identifier: bi.identifier.to_string(),
+            revision_number: bi.revision_number,
+            bytes: bi.size as usize,
+        }));
+        let scale_factors = self.data_points.as_ref().map_or(&[][..], |dp| &dp.scale_factors[..]);
+        Ok(FileStats {
+            blocks,
+            samples: scale_factors.iter().map(|sf| sf.data.len()).sum(),
+            scale_factors: scale_factors.len(),
+            key_events: self.key_events.as_ref().map_or(0, |ke| ke.key_events.len()),
+            landmarks: self.link_parameters.as_ref().map_or(0, |lp| lp.landmarks.len()),
+            serialized_bytes: bytes.len(),
+            in_memory_bytes: core::mem::size_of::<SORFile>() + parser::heap_size(self),
+        })
+    }
+}
+
+#[test]
+fn test_stats() {
+    let data = include_bytes!("../data/example1-noyes-ofl280.sor");
+    let sor = parser::parse_file(data).unwrap().1;
+    let stats = sor.stats().unwrap();
+    assert_eq!(stats.blocks[0].identifier, parser::BLOCK_ID_MAP);
+    assert_eq!(stats.blocks.last().unwrap().identifier, parser::BLOCK_ID_CHECKSUM);
+    assert_eq!(stats.blocks.iter().map(|b| b.bytes).sum::<usize>(), stats.serialized_bytes);
+    assert_eq!(stats.serialized_bytes, sor.to_bytes().unwrap().len());
+    assert_eq!(stats.samples, sor.data_points.as_ref().unwrap().number_of_data_points as usize);
+    assert_eq!(stats.key_events, sor.key_events.as_ref().unwrap().key_events.len());
+    let datapts = stats.blocks.iter().find(|b| b.identifier == parser::BLOCK_ID_DATAPTS).unwrap();
+    assert!(datapts.bytes > 2 * stats.samples);
+    assert!(stats.in_memory_bytes > 2 * stats.samples);
+}
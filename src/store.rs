@@ -0,0 +1,82 @@
+/// This module reads and writes files in object stores - Amazon S3, Google
+/// Cloud Storage and Azure Blob Storage - by URL, e.g.
+/// `s3://bucket/archive/fibre.sor`, since that's where large trace archives
+/// are usually kept. It is only available with the `object_store` feature.
+///
+/// Credentials and other settings come from the environment variables each
+/// store's own tools use, e.g. `AWS_ACCESS_KEY_ID` and `AWS_REGION`,
+/// `GOOGLE_SERVICE_ACCOUNT`, or `AZURE_STORAGE_ACCOUNT_NAME`. Calls block
+/// until the transfer is done, so they can be used like `std::fs`.
+use std::future::Future;
+use object_store::path::Path;
+use object_store::{parse_url_opts, Error, ObjectStore, ObjectStoreScheme, Result};
+use url::Url;
+
+/// True if a path is a URL for an object store, rather than a local path
+pub fn is_url(path: &str) -> bool {
+    Url::parse(path).ok()
+        .and_then(|url| ObjectStoreScheme::parse(&url).ok())
+        .is_some_and(|(scheme, _)| scheme != ObjectStoreScheme::Memory)
+}
+
+fn open(url: &str) -> Result<(Url, Box<dyn ObjectStore>, Path)> {
+    let url = Url::parse(url).map_err(|err| Error::Generic { store: "URL", source: Box::new(err) })?;
+    // Settings are matched by lowercase name, e.g. aws_region
+    let options = std::env::vars().map(|(key, value)| (key.to_ascii_lowercase(), value));
+    let (store, path) = parse_url_opts(&url, options)?;
+    Ok((url, store, path))
+}
+
+fn block_on<F: Future<Output = Result<T>>, T>(future: F) -> Result<T> {
+    tokio::runtime::Builder::new_current_thread().enable_all().build()
+        .map_err(|err| Error::Generic { store: "runtime", source: Box::new(err) })?
+        .block_on(future)
+}
+
+/// Read a whole object
+pub fn read(url: &str) -> Result<Vec<u8>> {
+    let (_, store, path) = open(url)?;
+    block_on(async { Ok(store.get(&path).await?.bytes().await?.to_vec()) })
+}
+
+/// Write a whole object, replacing any existing object of the same name
+pub fn write(url: &str, data: Vec<u8>) -> Result<()> {
+    let (_, store, path) = open(url)?;
+    block_on(async { store.put(&path, data.into()).await.map(|_| ()) })
+}
+
+/// URLs of the SOR files directly under a prefix, e.g. `s3://bucket/archive/`,
+/// in order of name
+pub fn list_sor_files(url: &str) -> Result<Vec<String>> {
+    let (url, store, prefix) = open(url)?;
+    let listing = block_on(store.list_with_delimiter(Some(&prefix)))?;
+    let base = &url[..url::Position::BeforePath];
+    let mut urls: Vec<String> = listing.objects.iter()
+        .filter(|object| object.location.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("sor")))
+        .map(|object| format!("{}/{}", base, object.location))
+        .collect();
+    urls.sort();
+    Ok(urls)
+}
+
+#[test]
+fn test_store() {
+    assert!(is_url("s3://bucket/archive/fibre.sor"));
+    assert!(is_url("gs://bucket/fibre.sor"));
+    assert!(is_url("az://container/fibre.sor"));
+    assert!(!is_url("archive/fibre.sor"));
+    assert!(!is_url("C:\\archive\\fibre.sor"));
+
+    // Local files stand in for a bucket
+    let dir = std::env::temp_dir().join(format!("otdrs-store-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let base = format!("file://{}", dir.display());
+    let data = include_bytes!("../data/example1-noyes-ofl280.sor").to_vec();
+    write(&format!("{}/b.sor", base), data.clone()).unwrap();
+    write(&format!("{}/a.SOR", base), data.clone()).unwrap();
+    write(&format!("{}/notes.txt", base), b"not a trace".to_vec()).unwrap();
+    assert_eq!(read(&format!("{}/b.sor", base)).unwrap(), data);
+    assert!(read(&format!("{}/missing.sor", base)).is_err());
+    assert_eq!(list_sor_files(&format!("{}/", base)).unwrap(), vec![format!("{}/a.SOR", base), format!("{}/b.sor", base)]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
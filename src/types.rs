@@ -1,13 +1,20 @@
 /// This module contains all of the struct definitions for the various types
 /// we're pulling from OTDR files.
-use serde::Serialize;
+///
+/// Strings are held as `Arc<str>`, so that files parsed with a
+/// `parser::Dedup` can share the values which repeat between them; set them
+/// from a `&str` or `String` with `.into()`.
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
 /// A BlockInfo struct contains information about a specific block later in the
 /// file, and appears in the MapBlock
-#[derive(Debug, PartialEq, Eq, Hash, Serialize, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct BlockInfo {
     /// Name of the block
-    pub identifier: String,
+    pub identifier: Arc<str>,
     /// Revision number - major (3 digits), minor, cosmetic
     pub revision_number: u16,
     /// Size in bytes of the block
@@ -16,7 +23,7 @@ pub struct BlockInfo {
 }
 
 /// Every SOR file has a MapBlock which acts as a map to the file's contents
-#[derive(Debug, PartialEq, Eq, Hash, Serialize, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct MapBlock {
     /// Revision number - major (3 digits), minor, cosmetic - for the file as a
     /// whole
@@ -32,27 +39,27 @@ pub struct MapBlock {
 /// The GeneralParametersBlock is mandatory for the format and contains 
 /// test-identifying information as well as generic information about the test
 /// being run such as the nominal wavelength
-#[derive(Debug, PartialEq, Eq, Hash, Serialize, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct GeneralParametersBlock {
     /// Language code - EN, CN, JP, etc.
-    pub language_code: String, 
+    pub language_code: Arc<str>, 
     /// Cable identifier
-    pub cable_id: String, 
+    pub cable_id: Arc<str>, 
     /// Fibre identifier
-    pub fiber_id: String, 
+    pub fiber_id: Arc<str>, 
     /// Fibre type - this is generally coded as the ITU-T standard definition,
     /// sans letters, e.g. 657, 655.
     pub fiber_type: i16, 
     /// Nominal test wavelength in nm
     pub nominal_wavelength: i16, 
     /// Start location for the test
-    pub originating_location: String, 
+    pub originating_location: Arc<str>, 
     /// End location for the test
-    pub terminating_location: String, 
+    pub terminating_location: Arc<str>, 
     /// Cable code - free field
-    pub cable_code: String, 
+    pub cable_code: Arc<str>, 
     ///  NC for new condition, RC for as-repaired, OT as something else
-    pub current_data_flag: String, 
+    pub current_data_flag: Arc<str>, 
     /// User offset - This is essentially the launch lead length from the front 
     /// panel offset (provided in the fixed parameters block), in 100ps 
     /// increments
@@ -61,42 +68,42 @@ pub struct GeneralParametersBlock {
     /// in FixedParametersBlock.units_of_distance
     pub user_offset_distance: i32,
     /// Operator of the unit for the test
-    pub operator: String,
+    pub operator: Arc<str>,
     /// Free comment field
-    pub comment: String,
+    pub comment: Arc<str>,
 }
 
 /// Supplier parameters describe the OTDR unit itself, such as the optical 
 /// module ID/serial number. Often this block also contains information about 
 /// calibration dates in the "other" field.
-#[derive(Debug, PartialEq, Serialize, Clone)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct SupplierParametersBlock {
     /// Manufacturer of the OTDR
-    pub supplier_name: String,
+    pub supplier_name: Arc<str>,
     /// Mainframe model number
-    pub otdr_mainframe_id: String,
+    pub otdr_mainframe_id: Arc<str>,
     /// Mainframe serial number
-    pub otdr_mainframe_sn: String,
+    pub otdr_mainframe_sn: Arc<str>,
     /// Optical module model number
-    pub optical_module_id: String,
+    pub optical_module_id: Arc<str>,
     /// Optical module serial number
-    pub optical_module_sn: String,
+    pub optical_module_sn: Arc<str>,
     /// Software revision
-    pub software_revision: String,
+    pub software_revision: Arc<str>,
     /// Free text
-    pub other: String,
+    pub other: Arc<str>,
 }
 
 /// Fixed parameters block contains key information for interpreting the test 
 /// data
-#[derive(Debug, PartialEq, Serialize, Clone)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct FixedParametersBlock {
     /// Datestamp - unix epoch seconds, 32-bit. Remember not to do any OTDR 
     /// tests after 2038.
     pub date_time_stamp: u32,
     /// Units of distance - km, mt, ft, kf, mi, etc. Typically mt (in civilised 
     /// nations)
-    pub units_of_distance: String,
+    pub units_of_distance: Arc<str>,
     /// Actual wavelength used - normally the factory-calibrated wavelength in 
     /// nm, or nominal wavelength
     pub actual_wavelength: i16,
@@ -152,7 +159,7 @@ pub struct FixedParametersBlock {
     /// Trace type - identifies if this is a standard one-way trace, a 
     /// bidirectional trace, reference trace, difference trace, or reversed 
     /// trace
-    pub trace_type: String,
+    pub trace_type: Arc<str>,
     /// Window coordinate for the upper right window corner
     pub window_coordinate_1: i32,
     /// Power coordinate for the upper right window corner
@@ -164,7 +171,7 @@ pub struct FixedParametersBlock {
 }
 
 /// KeyEvents describe a single event along the fibre path detected by the OTDR
-#[derive(Debug, PartialEq, Serialize, Clone)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct KeyEvent {
     /// Event number - this is from 0 to n
     pub event_number: i16,
@@ -185,10 +192,10 @@ pub struct KeyEvent {
     ///     A = added by user, M = moved by user, E = end of fibre, F = found 
     ///     by software, O = out of range, D = modified end of fibre
     /// Remaining bytes are the Landmark number if used - 9s otherwise
-    pub event_code: String,
+    pub event_code: Arc<str>,
     /// Loss measurement technique - 2P for two point, LS for least squares, OT 
     /// for other
-    pub loss_measurement_technique: String,
+    pub loss_measurement_technique: Arc<str>,
     /// Marker location - ML1 is the OTDR side for 2P/LS/OT measurements
     pub marker_location_1: i32,
     /// Marker location - ML2 is the OTDR side for LS measurements, and bounds 
@@ -203,26 +210,26 @@ pub struct KeyEvent {
     /// Marker location - ML5 is the reflectance calculation position
     pub marker_location_5: i32,
     /// Free comment on the event
-    pub comment: String,
+    pub comment: Arc<str>,
 }
 
 /// The last key event is as the KeyEvent, with some additional fields; see 
 /// KeyEvent for the documentation of other fields
-#[derive(Debug, PartialEq, Serialize, Clone)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct LastKeyEvent {
     pub event_number: i16,
     pub event_propogation_time: i32,
     pub attenuation_coefficient_lead_in_fiber: i16,
     pub event_loss: i16,
     pub event_reflectance: i32,
-    pub event_code: String,
-    pub loss_measurement_technique: String,
+    pub event_code: Arc<str>,
+    pub loss_measurement_technique: Arc<str>,
     pub marker_location_1: i32,
     pub marker_location_2: i32,
     pub marker_location_3: i32,
     pub marker_location_4: i32,
     pub marker_location_5: i32,
-    pub comment: String,
+    pub comment: Arc<str>,
     /// End to end loss is in dB*1000 and measures the loss between the two 
     /// markers defined below
     pub end_to_end_loss: i32,
@@ -239,7 +246,7 @@ pub struct LastKeyEvent {
 }
 
 /// List of key events and a pointer to the last key event
-#[derive(Debug, PartialEq, Serialize, Clone)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct KeyEvents {
     pub number_of_key_events: i16,
     pub key_events: Vec<KeyEvent>,
@@ -249,12 +256,12 @@ pub struct KeyEvents {
 /// Landmarks are a slightly esoteric feature not often used in SOR files for 
 /// field test equipment. They act to relate OTDR events to real-world 
 /// information such as WGS84 GPS data, known fibre MFDs, metre markers, etc
-#[derive(Debug, PartialEq, Serialize, Clone)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct Landmark {
     pub landmark_number: i16,
     /// Landmark code identifies the landmark - see page 27 of the standard for 
     /// the list
-    pub landmark_code: String,
+    pub landmark_code: Arc<str>,
     /// Location in 100ps from user offset to the landmark
     pub landmark_location: i32,
     pub related_event_number: i16,
@@ -265,14 +272,14 @@ pub struct Landmark {
     pub fiber_correction_factor_lead_in_fiber: i16,
     pub sheath_marker_entering_landmark: i32,
     pub sheath_marker_leaving_landmark: i32,
-    pub units_of_sheath_marks_leaving_landmark: String,
+    pub units_of_sheath_marks_leaving_landmark: Arc<str>,
     pub mode_field_diameter_leaving_landmark: i16,
-    pub comment: String,
+    pub comment: Arc<str>,
 }
 
 /// DataPointsAtScaleFactor is the struct that actually contains the data 
 /// points of the measurements for a given scale factor
-#[derive(Debug, PartialEq, Serialize, Clone)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct DataPointsAtScaleFactor {
     /// Number of points in this block
     pub n_points: i32,
@@ -284,7 +291,7 @@ pub struct DataPointsAtScaleFactor {
 
 /// DataPoints holds all the different datasets in this file - one per scale 
 /// factor
-#[derive(Debug, PartialEq, Serialize, Clone)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct DataPoints {
     pub number_of_data_points: i32,
     pub total_number_scale_factors_used: i16,
@@ -295,7 +302,7 @@ pub struct DataPoints {
 /// more the likes of network management systems.
 /// Contains a set of landmarks which describe the physical fibre path and may 
 /// relate this to described KeyEvents
-#[derive(Debug, PartialEq, Serialize, Clone)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct LinkParameters {
     pub number_of_landmarks: i16,
     pub landmarks: Vec<Landmark>,
@@ -305,16 +312,19 @@ pub struct LinkParameters {
 /// This is mostly used for vendor-specific special sauce, extra data, extra 
 /// analysis, etc.
 /// otdrs extracts the header, and stores the data as an array of bytes.
-#[derive(Debug, PartialEq, Serialize, Clone)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct ProprietaryBlock {
-    pub header: String,
-    pub data: Vec<u8>,
+    pub header: Arc<str>,
+    /// The block's contents after the header. This is shared, so that files
+    /// parsed with a `parser::Dedup` hold identical payloads only once; set
+    /// it from a Vec with `.into()`
+    pub data: Arc<[u8]>,
 }
 
 /// SORFile describes a full SOR file. All blocks except MapBlock are Option 
 /// types as we cannot guarantee the parser will find them, but many blocks are 
 /// in fact mandatory in the specification so compliant files will provide them.
-#[derive(Debug, PartialEq, Serialize, Clone)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct SORFile {
     pub map: MapBlock,
     pub general_parameters: Option<GeneralParametersBlock>,
@@ -0,0 +1,104 @@
+/// Speed of light in a vacuum, in m/s
+pub const SPEED_OF_LIGHT: f64 = 299_792_458.0;
+/// Group index used when a file doesn't specify one, as stored
+pub const DEFAULT_GROUP_INDEX: i32 = 146800;
+
+/// One-way distance in metres covered by a time of 100ps, given a group
+/// index as stored; zero or negative indices take the default
+pub fn metres_per_100ps(group_index: i32) -> f64 {
+    let group_index = if group_index > 0 { group_index } else { DEFAULT_GROUP_INDEX };
+    1e-10 * SPEED_OF_LIGHT / (group_index as f64 / 100000.0)
+}
+
+/// Convert a time in 100ps units to a one-way distance in metres
+pub fn time_to_metres(time: f64, group_index: i32) -> f64 {
+    time * metres_per_100ps(group_index)
+}
+
+/// Convert a one-way distance in metres to the nearest time in 100ps units
+pub fn metres_to_time(metres: f64, group_index: i32) -> i32 {
+    (metres / metres_per_100ps(group_index)).round() as i32
+}
+
+/// Distance in metres between adjacent data points
+pub fn spacing_m(data_spacing: i32, group_index: i32) -> f64 {
+    time_to_metres(data_spacing as f64 / 10000.0, group_index)
+}
+
+/// Data spacing giving points spacing_m metres apart
+pub fn data_spacing(spacing_m: f64, group_index: i32) -> i32 {
+    (spacing_m / metres_per_100ps(group_index) * 10000.0).round() as i32
+}
+
+/// Length in metres of the fibre a pulse of light pulse_ns long occupies
+pub fn pulse_width_m(pulse_ns: f64, group_index: i32) -> f64 {
+    time_to_metres(pulse_ns * 10.0, group_index)
+}
+
+/// How close together, in metres, two events can be and still be told apart
+/// with a pulse pulse_ns long: half its length, as light makes the round
+/// trip
+pub fn spatial_resolution_m(pulse_ns: f64, group_index: i32) -> f64 {
+    pulse_width_m(pulse_ns, group_index) / 2.0
+}
+
+/// Convert WGS84 decimal degrees to a landmark's GPS encoding
+pub fn degrees_to_gps(degrees: f64) -> i32 {
+    (degrees * 1e6).round() as i32
+}
+
+/// Convert a landmark's GPS encoding to WGS84 decimal degrees
+pub fn gps_to_degrees(gps: i32) -> f64 {
+    gps as f64 / 1e6
+}
+
+/// Length in metres of the units_of_distance codes in SR-4731
+pub fn metres_per_unit(units: &str) -> Option<f64> {
+    match units {
+        "mt" => Some(1.0),
+        "km" => Some(1000.0),
+        "ft" => Some(0.3048),
+        "kf" => Some(304.8),
+        "mi" => Some(1609.344),
+        _ => None,
+    }
+}
+
+/// Convert a distance field to metres, if the units are recognised
+pub fn distance_to_metres(distance: i32, units: &str) -> Option<f64> {
+    metres_per_unit(units).map(|unit_m| distance as f64 / 10.0 * unit_m)
+}
+
+/// Convert metres to the nearest value of a distance field, if the units
+/// are recognised
+pub fn metres_to_distance(metres: f64, units: &str) -> Option<i32> {
+    metres_per_unit(units).map(|unit_m| (metres / unit_m * 10.0).round() as i32)
+}
+
+/// How much a distance field grows for each 100ps of time, if the units are
+/// recognised
+pub fn distance_per_100ps(units: &str, group_index: i32) -> Option<f64> {
+    metres_per_unit(units).map(|unit_m| metres_per_100ps(group_index) / unit_m * 10.0)
+}
+
+#[test]
+fn test_units() {
+    // Light covers about 2cm of fibre in 100ps
+    let m = metres_per_100ps(146800);
+    assert!((m - 0.020422).abs() < 1e-6);
+    assert_eq!(metres_per_100ps(0), m);
+    assert_eq!(metres_to_time(time_to_metres(24641.0, 146800), 146800), 24641);
+    // A data spacing of 100000 puts points about 0.2m apart
+    assert!((spacing_m(100000, 146800) - 0.204219).abs() < 1e-6);
+    assert_eq!(data_spacing(spacing_m(100000, 146800), 146800), 100000);
+    // A 10ns pulse is about 2m long
+    assert!((pulse_width_m(10.0, 146800) - 2.04219).abs() < 1e-5);
+    assert_eq!(spatial_resolution_m(10.0, 146800), pulse_width_m(10.0, 146800) / 2.0);
+    assert_eq!(distance_to_metres(5034, "mt"), Some(503.4));
+    assert_eq!(distance_to_metres(5034, "xx"), None);
+    assert_eq!(metres_to_distance(1.0, "km"), Some(0));
+    assert_eq!(metres_to_distance(1609.344, "mi"), Some(10));
+    assert_eq!(distance_per_100ps("mt", 146800), Some(m * 10.0));
+    assert_eq!(degrees_to_gps(-0.127758), -127758);
+    assert_eq!(gps_to_degrees(51507351), 51.507351);
+}
@@ -0,0 +1,383 @@
+/// This module checks that a SOR file makes sense as a measurement, beyond
+/// parsing: that values describing the same physical quantity agree with
+/// one another and are plausible for the fibre and wavelength, e.g. that
+/// the backscatter coefficient is near that of single-mode fibre at the
+/// wavelength tested. Files which break these rules parse, but analysis of
+/// them, and conversions to engineering units, may well be wrong.
+use core::fmt;
+use serde::Serialize;
+use crate::analysis::aggregates::{check_aggregates, Aggregate, AggregateTolerances};
+use crate::types::{FixedParametersBlock, SORFile};
+use crate::units;
+
+/// How much a finding matters
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize)]
+pub enum Severity {
+    /// Unusual but harmless, e.g. a vendor convention
+    Info,
+    /// Probably wrong, and likely to mislead analysis
+    Warning,
+    /// Inconsistent, so that the file can't be interpreted as it stands
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+/// One rule broken by a file
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Finding {
+    pub severity: Severity,
+    /// The rule broken, e.g. physics.backscatter
+    pub rule: &'static str,
+    /// Block and field at fault, e.g. FxdParams and backscatter_coefficient
+    pub block: &'static str,
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl SORFile {
+    /// Check the file against every rule set, most severe findings first
+    pub fn validate(&self) -> Vec<Finding> {
+        let mut findings = physics(self);
+        findings.extend(aggregates(self));
+        findings.sort_by_key(|finding| core::cmp::Reverse(finding.severity));
+        findings
+    }
+}
+
+/// Typical backscatter coefficient in dB for a 1ns pulse, by nominal
+/// wavelength: multimode fibre at 850 and 1300nm, single-mode otherwise
+const BACKSCATTER_DB: &[(i16, f64)] = &[
+    (850, -68.0),
+    (1300, -76.0),
+    (1310, -79.4),
+    (1383, -80.0),
+    (1490, -81.0),
+    (1550, -81.7),
+    (1625, -82.3),
+    (1650, -82.5),
+];
+/// How far a backscatter coefficient may be from the typical value before
+/// it is implausible, in dB
+const BACKSCATTER_TOLERANCE_DB: f64 = 4.0;
+
+/// The physics rule set: that the fixed parameters are consistent with one
+/// another, with the wavelength and with the data points
+pub fn physics(sor: &SORFile) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let fp = match &sor.fixed_parameters {
+        Some(fp) => fp,
+        None => return findings,
+    };
+    let mut find = |severity, rule, field, message: String| {
+        findings.push(Finding { severity, rule, block: "FxdParams", field, message })
+    };
+
+    // Vendors store the actual wavelength in nm or tenths of a nm
+    let nominal = sor.general_parameters.as_ref().map(|gp| gp.nominal_wavelength).filter(|&nm| nm > 0);
+    let actual = match fp.actual_wavelength {
+        nm if nm > 3000 => Some(nm as f64 / 10.0),
+        nm if nm > 0 => Some(nm as f64),
+        _ => None,
+    };
+    if let (Some(nominal), Some(actual)) = (nominal, actual) {
+        if (actual - nominal as f64).abs() > 30.0 {
+            find(Severity::Warning, "physics.wavelength", "actual_wavelength",
+                 format!("Actual wavelength {}nm is far from the nominal {}nm", actual, nominal));
+        }
+    }
+
+    let group_index = fp.group_index as f64 / 100000.0;
+    if fp.group_index == 0 {
+        find(Severity::Info, "physics.group_index", "group_index",
+             format!("No group index is given, so {} is assumed", units::DEFAULT_GROUP_INDEX as f64 / 100000.0));
+    } else if !(1.4..=1.6).contains(&group_index) {
+        find(Severity::Warning, "physics.group_index", "group_index",
+             format!("Group index {} is implausible for silica fibre", group_index));
+    }
+
+    let backscatter_db = -(fp.backscatter_coefficient as f64) / 10.0;
+    let wavelength = nominal.map(|nm| nm as f64).or(actual);
+    let typical = wavelength.and_then(|nm| {
+        BACKSCATTER_DB.iter().map(|&(typical_nm, db)| ((typical_nm as f64 - nm).abs(), typical_nm, db))
+            .filter(|&(off, _, _)| off <= 20.0)
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, typical_nm, db)| (typical_nm, db))
+    });
+    if fp.backscatter_coefficient == 0 {
+        find(Severity::Info, "physics.backscatter", "backscatter_coefficient", "No backscatter coefficient is given".to_owned());
+    } else if let Some((nm, typical_db)) = typical {
+        if (backscatter_db - typical_db).abs() > BACKSCATTER_TOLERANCE_DB {
+            find(Severity::Warning, "physics.backscatter", "backscatter_coefficient",
+                 format!("Backscatter coefficient {:.1}dB is implausible at {}nm, where {:.1}dB is typical", backscatter_db, nm, typical_db));
+        }
+    } else if !(-90.0..=-60.0).contains(&backscatter_db) {
+        find(Severity::Warning, "physics.backscatter", "backscatter_coefficient",
+             format!("Backscatter coefficient {:.1}dB is implausible for any fibre", backscatter_db));
+    }
+
+    if fp.noise_floor_scale_factor <= 0 {
+        find(Severity::Warning, "physics.noise_floor", "noise_floor_scale_factor",
+             format!("Noise floor scale factor {} leaves the noise floor meaningless", fp.noise_floor_scale_factor));
+    } else if fp.noise_floor_level as f64 * fp.noise_floor_scale_factor as f64 / 1e6 > 100.0 {
+        find(Severity::Warning, "physics.noise_floor", "noise_floor_scale_factor",
+             format!("Noise floor scale factor {} puts the noise floor below -100dB", fp.noise_floor_scale_factor));
+    }
+
+    // Every average needs a pulse's round trip over the whole range
+    let minimum_s = fp.acquisition_range as f64 * 2e-10 * fp.number_of_averages as f64;
+    let averaging_s = fp.averaging_time as f64 / 10.0;
+    if fp.averaging_time > 0 && fp.number_of_averages > 0 && averaging_s < minimum_s {
+        find(Severity::Warning, "physics.averaging", "averaging_time",
+             format!("{} averages over the acquisition range take at least {:.2}s, not {:.1}s", fp.number_of_averages, minimum_s, averaging_s));
+    } else if fp.averaging_time == 0 && fp.number_of_averages <= 0 {
+        find(Severity::Info, "physics.averaging", "number_of_averages", "Neither the number of averages nor the averaging time is given".to_owned());
+    }
+
+    check_pulse_widths(fp, &mut find);
+
+    if let Some(dp) = &sor.data_points {
+        if dp.scale_factors.iter().any(|sf| sf.scale_factor == 0) {
+            findings.push(Finding {
+                severity: Severity::Error,
+                rule: "physics.scale_factor",
+                block: "DataPts",
+                field: "scale_factor",
+                message: "A data points scale factor of 0 makes every point 0dB".to_owned(),
+            });
+        }
+    }
+    findings
+}
+
+/// The aggregates rule set: that the end-to-end loss and ORL stored with
+/// the last key event agree with the trace between their markers, within the
+/// default tolerances. Files without key events or data points pass
+pub fn aggregates(sor: &SORFile) -> Vec<Finding> {
+    let check = match check_aggregates(sor, &AggregateTolerances::default()) {
+        Ok(check) => check,
+        Err(_) => return Vec::new(),
+    };
+    let find = |aggregate: &Aggregate, rule, field, name| match (aggregate.consistent, aggregate.recomputed_db) {
+        (Some(false), Some(recomputed)) => Some(Finding {
+            severity: Severity::Warning,
+            rule,
+            block: "KeyEvents",
+            field,
+            message: format!("Stored {} of {:.3}dB doesn't match the {:.3}dB measured from the trace",
+                             name, aggregate.stored_db, recomputed),
+        }),
+        _ => None,
+    };
+    find(&check.end_to_end_loss, "aggregates.end_to_end_loss", "end_to_end_loss", "end-to-end loss").into_iter()
+        .chain(find(&check.optical_return_loss, "aggregates.orl", "optical_return_loss", "ORL"))
+        .collect()
+}
+
+/// Check that each pulse width has a spacing and number of points, that
+/// they span the acquisition range, and that the points are close enough
+/// together to resolve the pulse
+fn check_pulse_widths<F: FnMut(Severity, &'static str, &'static str, String)>(fp: &FixedParametersBlock, find: &mut F) {
+    let n = fp.total_n_pulse_widths_used as usize;
+    if fp.pulse_widths_used.len() != n || fp.data_spacing.len() != n || fp.n_data_points_for_pulse_widths_used.len() != n {
+        find(Severity::Error, "physics.pulse_widths", "total_n_pulse_widths_used",
+             format!("{} pulse widths are used, but {} pulse widths, {} spacings and {} point counts are given", n,
+                     fp.pulse_widths_used.len(), fp.data_spacing.len(), fp.n_data_points_for_pulse_widths_used.len()));
+        return;
+    }
+    for ((&pulse_ns, &spacing), &points) in fp.pulse_widths_used.iter().zip(&fp.data_spacing).zip(&fp.n_data_points_for_pulse_widths_used) {
+        if spacing <= 0 || pulse_ns <= 0 || points <= 0 {
+            find(Severity::Error, "physics.pulse_widths", "data_spacing",
+                 format!("The {}ns pulse width has a spacing of {} and {} points", pulse_ns, spacing, points));
+            continue;
+        }
+        // Ranges may include or exclude the last point's spacing
+        let span = spacing as f64 / 10000.0 * (points - 1) as f64;
+        let range = fp.acquisition_range as f64;
+        if fp.acquisition_range > 0 && (range - span).abs() > (range * 0.01).max(spacing as f64 / 10000.0) {
+            find(Severity::Info, "physics.range", "acquisition_range",
+                 format!("The {}ns pulse width's {} points span {:.0}, not the acquisition range of {}", pulse_ns, points, span, range));
+        }
+        // A pulse occupies half its width in one-way time, in 100ps
+        let spacing_m = units::spacing_m(spacing, fp.group_index);
+        let pulse_m = units::time_to_metres(pulse_ns as f64 * 10.0 / 2.0, fp.group_index);
+        if spacing_m > pulse_m * 4.0 {
+            find(Severity::Warning, "physics.resolution", "data_spacing",
+                 format!("Points {:.2}m apart can't resolve a {}ns pulse, {:.2}m long", spacing_m, pulse_ns, pulse_m));
+        } else if spacing_m > pulse_m {
+            find(Severity::Info, "physics.resolution", "data_spacing",
+                 format!("Points {:.2}m apart undersample a {}ns pulse, {:.2}m long", spacing_m, pulse_ns, pulse_m));
+        }
+    }
+}
+
+/// A field as SR-4731 identifies it: by block, and by the field's name and
+/// mnemonic in the block's table. Section numbers aren't given, as they
+/// differ between issues of SR-4731
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize)]
+pub struct Field {
+    pub block: &'static str,
+    /// e.g. BC
+    pub mnemonic: &'static str,
+    /// e.g. Backscatter Coefficient
+    pub name: &'static str,
+}
+
+/// SR-4731's mnemonics and names for the fields rules refer to, by block and
+/// otdrs field name
+const FIELDS: &[(&str, &str, &str, &str)] = &[
+    ("FxdParams", "actual_wavelength", "AW", "Actual Wavelength"),
+    ("FxdParams", "total_n_pulse_widths_used", "TPW", "Total Number of Pulse Widths Used"),
+    ("FxdParams", "data_spacing", "DS", "Data Spacing"),
+    ("FxdParams", "group_index", "GI", "Group Index"),
+    ("FxdParams", "backscatter_coefficient", "BC", "Backscatter Coefficient"),
+    ("FxdParams", "number_of_averages", "NAV", "Number of Averages"),
+    ("FxdParams", "averaging_time", "AT", "Averaging Time"),
+    ("FxdParams", "acquisition_range", "AR", "Acquisition Range"),
+    ("FxdParams", "noise_floor_scale_factor", "NFSF", "Noise Floor Scale Factor"),
+    ("KeyEvents", "end_to_end_loss", "EEL", "End-to-End Loss"),
+    ("KeyEvents", "optical_return_loss", "ORL", "Optical Return Loss"),
+    ("DataPts", "scale_factor", "SF", "Scale Factor"),
+];
+
+impl Field {
+    /// SR-4731's identity for an otdrs field, falling back to the field's own
+    /// name
+    pub fn of(block: &'static str, field: &'static str) -> Field {
+        FIELDS.iter().find(|(b, f, _, _)| *b == block && *f == field)
+            .map_or(Field { block, mnemonic: "", name: field }, |&(block, _, mnemonic, name)| Field { block, mnemonic, name })
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.mnemonic.is_empty() {
+            write!(f, "{} {}", self.block, self.name)
+        } else {
+            write!(f, "{} {} ({})", self.block, self.name, self.mnemonic)
+        }
+    }
+}
+
+/// A finding, with the field at fault as SR-4731 identifies it and the
+/// value stored there
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct ConformanceItem {
+    pub field: Field,
+    pub severity: Severity,
+    pub rule: &'static str,
+    /// The stored value, as JSON, if the field holds a single value
+    pub value: Option<String>,
+    pub message: String,
+}
+
+/// Findings for a file keyed to SR-4731's fields, for handing to the team
+/// which wrote the software that produced it
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct ConformanceReport {
+    /// True unless an error was found
+    pub conforms: bool,
+    /// Most severe first
+    pub items: Vec<ConformanceItem>,
+}
+
+impl SORFile {
+    /// Validate the file, as for `validate`, and key each finding to the
+    /// SR-4731 block and field at fault
+    pub fn conformance_report(&self) -> ConformanceReport {
+        let blocks = [
+            ("FxdParams", self.fixed_parameters.as_ref().and_then(|fp| serde_json::to_value(fp).ok())),
+            ("KeyEvents", self.key_events.as_ref().and_then(|ke| serde_json::to_value(&ke.last_key_event).ok())),
+            ("DataPts", self.data_points.as_ref().and_then(|dp| serde_json::to_value(dp).ok())),
+        ];
+        let value = |block: &str, field: &str| {
+            let (_, values) = blocks.iter().find(|(b, _)| *b == block)?;
+            values.as_ref()?.get(field).map(|value| value.to_string())
+        };
+        let items: Vec<ConformanceItem> = self.validate().into_iter().map(|finding| ConformanceItem {
+            field: Field::of(finding.block, finding.field),
+            severity: finding.severity,
+            rule: finding.rule,
+            value: value(finding.block, finding.field),
+            message: finding.message,
+        }).collect();
+        ConformanceReport { conforms: items.iter().all(|item| item.severity < Severity::Error), items }
+    }
+}
+
+impl fmt::Display for ConformanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let count = |severity| self.items.iter().filter(|item| item.severity == severity).count();
+        writeln!(f, "SR-4731 conformance: {} ({} errors, {} warnings, {} notes)",
+                 if self.conforms { "conforms" } else { "does not conform" },
+                 count(Severity::Error), count(Severity::Warning), count(Severity::Info))?;
+        for item in &self.items {
+            write!(f, "{:<8}{}", item.severity, item.field)?;
+            if let Some(value) = &item.value {
+                write!(f, " = {}", value)?;
+            }
+            writeln!(f, ": {} [{}]", item.message, item.rule)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_physics() {
+    let parse = |data: &[u8]| crate::parser::parse_file(data).unwrap().1;
+    let rules = |sor: &SORFile| sor.validate().iter().map(|f| (f.severity, f.rule)).collect::<Vec<_>>();
+    // The OFL280's ORL isn't that of its own trace
+    assert_eq!(rules(&parse(include_bytes!("../data/example1-noyes-ofl280.sor"))), vec![(Severity::Warning, "aggregates.orl")]);
+    // The re-saved file's acquisition range is shorter than its points span,
+    // and its events are shifted against the trace, so its loss is too
+    assert_eq!(rules(&parse(include_bytes!("../data/example1-noyes-ofl280-fastreporter-save.sor"))),
+               vec![(Severity::Warning, "aggregates.end_to_end_loss"), (Severity::Info, "physics.range")]);
+    assert_eq!(rules(&parse(include_bytes!("../data/example2-exfo-maxtester730c.sor"))), vec![]);
+    // Anritsu stores -60dB, which no single-mode fibre has
+    let sor = parse(include_bytes!("../data/example3-anritsu-accessmastermt9085.sor"));
+    let findings = sor.validate();
+    assert_eq!(findings.len(), 1);
+    assert_eq!((findings[0].severity, findings[0].rule, findings[0].field), (Severity::Warning, "physics.backscatter", "backscatter_coefficient"));
+
+    let mut sor = parse(include_bytes!("../data/example1-noyes-ofl280.sor"));
+    let fp = sor.fixed_parameters.as_mut().unwrap();
+    fp.averaging_time = 1;
+    fp.data_spacing = vec![10000000];
+    fp.n_data_points_for_pulse_widths_used = vec![300];
+    fp.total_n_pulse_widths_used = 1;
+    fp.noise_floor_scale_factor = 0;
+    sor.data_points.as_mut().unwrap().scale_factors[0].scale_factor = 0;
+    assert_eq!(rules(&sor), vec![(Severity::Error, "physics.scale_factor"), (Severity::Warning, "physics.noise_floor"),
+                                 (Severity::Warning, "physics.averaging"), (Severity::Warning, "physics.resolution"),
+                                 (Severity::Warning, "aggregates.orl")]);
+    sor.fixed_parameters.as_mut().unwrap().total_n_pulse_widths_used = 2;
+    assert!(rules(&sor).contains(&(Severity::Error, "physics.pulse_widths")));
+}
+
+#[test]
+fn test_conformance_report() {
+    let sor = crate::parser::parse_file(include_bytes!("../data/example3-anritsu-accessmastermt9085.sor")).unwrap().1;
+    let report = sor.conformance_report();
+    assert!(report.conforms);
+    assert_eq!(report.items.len(), 1);
+    let item = &report.items[0];
+    assert_eq!(item.field, Field { block: "FxdParams", mnemonic: "BC", name: "Backscatter Coefficient" });
+    assert_eq!(item.value.as_deref(), Some("600"));
+    let text = report.to_string();
+    assert!(text.starts_with("SR-4731 conformance: conforms (0 errors, 1 warnings, 0 notes)\n"));
+    assert!(text.contains("warning FxdParams Backscatter Coefficient (BC) = 600: "));
+
+    let mut sor = sor;
+    sor.data_points.as_mut().unwrap().scale_factors[0].scale_factor = 0;
+    let report = sor.conformance_report();
+    assert!(!report.conforms);
+    assert_eq!((report.items[0].field.mnemonic, report.items[0].value.as_deref()), ("SF", None));
+}
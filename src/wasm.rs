@@ -0,0 +1,103 @@
+/// This module exposes the parser to JavaScript through wasm-bindgen, so that
+/// web applications can parse SOR files dropped onto a page entirely in the
+/// browser, without uploading them anywhere. It is only available with the
+/// `wasm` feature, e.g. `wasm-pack build --target web -- --features wasm`.
+///
+/// Parsed files cross into JavaScript as JSON strings, for `JSON.parse`, in
+/// the same form as the command line's output; traces come across as
+/// `Float64Array`s, ready for a charting library.
+use wasm_bindgen::prelude::*;
+use crate::analysis::Trace;
+use crate::types::SORFile;
+
+/// A parsed SOR file, held on the WebAssembly side
+#[wasm_bindgen]
+pub struct SorFile {
+    sor: SORFile,
+}
+
+fn parse(data: &[u8]) -> Result<SORFile, &'static str> {
+    crate::parser::parse_file(data).map(|(_, sor)| sor).map_err(|_| "Could not parse SOR file")
+}
+
+/// Parse the bytes of a SOR file, e.g. from `File.arrayBuffer()`
+#[wasm_bindgen(js_name = parseBytes)]
+pub fn parse_bytes(data: &[u8]) -> Result<SorFile, JsError> {
+    parse(data).map(|sor| SorFile { sor }).map_err(JsError::new)
+}
+
+/// Parse the bytes of a SOR file straight to JSON
+#[wasm_bindgen(js_name = sorToJson)]
+pub fn sor_to_json(data: &[u8], pretty: bool) -> Result<String, JsError> {
+    parse_bytes(data)?.to_json(pretty)
+}
+
+#[wasm_bindgen]
+impl SorFile {
+    /// The file as JSON, with values as raw SR-4731 encodings
+    #[wasm_bindgen(js_name = toJson)]
+    pub fn to_json(&self, pretty: bool) -> Result<String, JsError> {
+        let json = if pretty { serde_json::to_string_pretty(&self.sor)? } else { serde_json::to_string(&self.sor)? };
+        Ok(json)
+    }
+
+    /// The file as JSON, with values in dB, metres, seconds and ISO-8601
+    /// timestamps
+    #[wasm_bindgen(js_name = toEngineeringJson)]
+    pub fn to_engineering_json(&self) -> Result<String, JsError> {
+        Ok(serde_json::to_string(&crate::engineering::to_value(&self.sor)?)?)
+    }
+
+    #[wasm_bindgen(getter, js_name = cableId)]
+    pub fn cable_id(&self) -> String {
+        self.sor.general_parameters.as_ref().map_or(String::new(), |gp| gp.cable_id.trim().to_owned())
+    }
+
+    #[wasm_bindgen(getter, js_name = fiberId)]
+    pub fn fiber_id(&self) -> String {
+        self.sor.general_parameters.as_ref().map_or(String::new(), |gp| gp.fiber_id.trim().to_owned())
+    }
+
+    /// Nominal wavelength in nm, or 0 if the file has no general parameters
+    #[wasm_bindgen(getter)]
+    pub fn wavelength(&self) -> i16 {
+        self.sor.general_parameters.as_ref().map_or(0, |gp| gp.nominal_wavelength)
+    }
+
+    /// Distance of each data point from the user offset, in metres; empty if
+    /// the file has no data points
+    pub fn distances(&self) -> Vec<f64> {
+        Trace::new(&self.sor).map_or(Vec::new(), |trace| {
+            trace.distance_m().iter().map(|d| d - trace.user_offset_m()).collect()
+        })
+    }
+
+    /// Level of each data point in dB; empty if the file has no data points
+    pub fn levels(&self) -> Vec<f64> {
+        Trace::new(&self.sor).map_or(Vec::new(), |trace| trace.points_db().to_vec())
+    }
+
+    /// The file written back out as SOR bytes, e.g. after parsing a file
+    /// from another source
+    #[wasm_bindgen(js_name = toBytes)]
+    pub fn to_bytes(&self) -> Result<Vec<u8>, JsError> {
+        self.sor.to_bytes().map_err(JsError::new)
+    }
+}
+
+#[test]
+fn test_wasm_bindings() {
+    // Errors can only be built in a browser, so only the happy path is
+    // tested here
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let sor = parse_bytes(data).ok().unwrap();
+    assert_eq!((sor.fiber_id().as_str(), sor.wavelength()), ("Fiber1", 1310));
+    let json: serde_json::Value = serde_json::from_str(&sor.to_json(false).ok().unwrap()).unwrap();
+    assert_eq!(json["general_parameters"]["fiber_id"], "Fiber1");
+    let json: serde_json::Value = serde_json::from_str(&sor.to_engineering_json().ok().unwrap()).unwrap();
+    assert!(json["key_events"]["last_key_event"]["end_to_end_loss_db"].is_number());
+    assert_eq!(sor.distances().len(), sor.levels().len());
+    assert_eq!(sor.distances().len(), Trace::new(&sor.sor).unwrap().points_db().len());
+    assert_eq!(parse(&sor.to_bytes().ok().unwrap()).unwrap(), sor.sor);
+    assert!(parse(b"not a SOR file").is_err());
+}
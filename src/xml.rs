@@ -0,0 +1,53 @@
+/// This module serialises a SORFile as XML, for operations and billing
+/// systems which only ingest XML. The document's layout is described by the
+/// XML Schema in `schema/sor.xsd`, also available as `XML_SCHEMA`.
+///
+/// The layout follows the JSON output: a `SORFile` root element containing
+/// an element for each field, named as the field is, with values as raw
+/// SR-4731 encoded integers. Lists, such as key events and data points, are
+/// written as one element per item, named after the list. A block which is
+/// missing from the file is written as an empty element.
+use quick_xml::se::Serializer;
+use quick_xml::SeError;
+use serde::Serialize;
+
+/// The XML Schema describing the documents written by to_xml
+pub const XML_SCHEMA: &str = include_str!("../schema/sor.xsd");
+
+/// Serialise a value, such as a SORFile, as an XML document with a
+/// `SORFile` root element, optionally indented for readability
+pub fn to_xml<T: Serialize>(value: &T, indent: bool) -> Result<String, SeError> {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let mut serializer = Serializer::with_root(&mut xml, Some("SORFile"))?;
+    if indent {
+        serializer.indent(' ', 2);
+    }
+    value.serialize(serializer)?;
+    xml.push('\n');
+    Ok(xml)
+}
+
+#[test]
+fn test_to_xml() {
+    let data = include_bytes!("../data/example4-exfo-ftb4ftbx730c-mfdgainer-1310nm.sor");
+    let sor = crate::parser::parse_file(data).unwrap().1;
+    let xml = to_xml(&sor, false).unwrap();
+    assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<SORFile><map><revision_number>200</revision_number>"));
+    assert!(xml.contains("<fiber_id>Fiber1</fiber_id><fiber_type>652</fiber_type><nominal_wavelength>1310</nominal_wavelength>"));
+    assert!(xml.contains("<pulse_widths_used>10</pulse_widths_used>"));
+    assert_eq!(xml.matches("<key_events><event_number>").count(), 8);
+    assert!(xml.contains("<link_parameters/>"));
+    assert!(xml.ends_with("</SORFile>\n"));
+    assert!(to_xml(&sor, true).unwrap().contains("\n  <general_parameters>\n    <language_code>EN</language_code>"));
+
+    // Every element written is declared in the schema
+    let mut names: Vec<&str> = xml.split('<').skip(2)
+        .filter(|tag| !tag.starts_with('/'))
+        .map(|tag| tag.split(['>', '/']).next().unwrap())
+        .collect();
+    names.sort_unstable();
+    names.dedup();
+    for name in names {
+        assert!(XML_SCHEMA.contains(&format!("name=\"{}\"", name)), "{} isn't in the schema", name);
+    }
+}